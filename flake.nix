@@ -34,8 +34,13 @@
           inherit src;
           strictDeps = true;
 
+          nativeBuildInputs = [
+            pkgs.pkg-config
+          ];
+
           buildInputs = [
-            # Add additional build inputs here
+            # for IMAP over TLS
+            pkgs.openssl
           ] ++ lib.optionals pkgs.stdenv.isDarwin [
             # Additional darwin specific inputs can be set here
             pkgs.libiconv
@@ -85,11 +90,20 @@
           checks = self.checks.${system};
 
           packages = with pkgs; [
+            age
+            awscli2
+            fzf
             ghostscript
+            git
             imagemagick
+            minisign
+            ocrmypdf
+            qpdf
             rclone
             ripgrep
+            sane-backends
             tesseract4
+            zbar
           ];
         };
       });
@@ -0,0 +1,107 @@
+//! An append-only log of everything kartka has done to documents, kept at `.kartka/audit.log`
+//! as one JSON object a line, so there's a record to go back to when something goes missing.
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::Kartka;
+
+const AUDIT_LOG: &str = ".kartka/audit.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Scan,
+    Upload,
+    Delete,
+    Rename,
+    Hydrate,
+    Rebuild,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Scan => "scan",
+            Operation::Upload => "upload",
+            Operation::Delete => "delete",
+            Operation::Rename => "rename",
+            Operation::Hydrate => "hydrate",
+            Operation::Rebuild => "rebuild",
+        };
+        f.pad(name)
+    }
+}
+
+/// One line of the log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: jiff::Timestamp,
+    pub operation: Operation,
+    pub id: String,
+    /// Anything else worth knowing, like what a document was renamed to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Why the operation failed, or `None` if it succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Kartka {
+    /// Records that `operation` was done to `id`, and the `error` it failed with if it did.
+    /// Not being able to write the log is only warned about, since the operation itself has
+    /// already happened.
+    pub(crate) fn audit(
+        &self,
+        operation: Operation,
+        id: &str,
+        detail: Option<String>,
+        error: Option<&eyre::Report>,
+    ) {
+        let entry = Entry {
+            time: jiff::Timestamp::now(),
+            operation,
+            id: id.to_string(),
+            detail,
+            error: error.map(|e| format!("{e:#}")),
+        };
+        if let Err(e) = self.append_audit(&entry) {
+            warn!("couldn't record the {operation} of {id} in the audit log: {e:#}");
+        }
+    }
+
+    fn append_audit(&self, entry: &Entry) -> Result<()> {
+        let path = self.index().join(AUDIT_LOG);
+        fs::create_dir_all(self.index().join(".kartka"))?;
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // written in one go, so lines from processes and threads logging at once don't mix
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut it| it.write_all(line.as_bytes()))
+            .context(format!("writing {path:?}"))
+    }
+
+    /// Everything in the audit log, oldest first.
+    pub fn audit_log(&self) -> Result<Vec<Entry>> {
+        let path = self.index().join(AUDIT_LOG);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let contents = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+        Ok(contents
+            .lines()
+            // a torn last line from a crash shouldn't hide the rest
+            .filter_map(|it| serde_json::from_str(it).ok())
+            .collect())
+    }
+}
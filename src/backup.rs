@@ -0,0 +1,164 @@
+//! `kartka backup` and `kartka restore`: snapshots of the whole index - text, metadata, tags,
+//! thumbnails - kept on the remote, so losing the laptop means downloading one file rather than
+//! hydrating everything again and redoing the tags by hand.
+
+use std::{fs, path::Path, process::Command};
+
+use eyre::{bail, Context, Result};
+use tracing::info;
+
+use crate::{
+    encryption,
+    index::Backend,
+    pdf::run,
+    sqlite::{self, DATABASE},
+    store, Kartka,
+};
+
+/// The folder on the remote that backups go in, out of the way of the documents.
+const BACKUP_DIR: &str = "kartka-backups";
+const PREFIX: &str = "index-";
+
+impl Kartka {
+    fn backup_name(name: &str) -> String {
+        format!("{BACKUP_DIR}/{name}")
+    }
+
+    /// The backups on the remote, oldest first.
+    pub fn backups(&self) -> Result<Vec<String>> {
+        // filtered from the top, since listing the folder itself fails until the first backup
+        let output = self.rclone(
+            [
+                "lsf",
+                "--recursive",
+                "--files-only",
+                "--include",
+                &format!("/{BACKUP_DIR}/{PREFIX}*"),
+                &self.remote_path(""),
+            ],
+            "listing backups",
+        )?;
+        let mut backups: Vec<_> = String::from_utf8(output.stdout)?
+            .lines()
+            .filter_map(|it| it.strip_prefix(&format!("{BACKUP_DIR}/")))
+            .map(str::to_string)
+            .collect();
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// Packs the index into a tarball and uploads it to the remote, encrypted if encryption is
+    /// set up, returning its name.
+    pub fn backup(&self) -> Result<String> {
+        let stamp = jiff::Zoned::now().strftime("%Y_%m_%d_%H_%M_%S");
+        let mut name = format!("{PREFIX}{stamp}.tar.gz");
+        if self.encryption.is_some() {
+            name.push_str(".age");
+        }
+        if self.dry_run {
+            println!(
+                "would back up {:?} to {}",
+                self.index(),
+                self.remote_path(&Self::backup_name(&name))
+            );
+            return Ok(name);
+        }
+
+        info!("packing up {:?}..", self.index());
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("index.tar.gz");
+        let mut command = Command::new("tar");
+        command.arg("-czf").arg(&tarball);
+        // the database can't be copied as a file while it's in use, so it's snapshotted instead,
+        // and the OCR cache is only ever a shortcut, so isn't worth the space
+        for excluded in [DATABASE, "kartka.db-*", "lock", "*.tmp", "ocr-cache"] {
+            command.arg(format!("--exclude={excluded}"));
+        }
+        command.arg("-C").arg(self.index()).arg(".");
+        if self.index_backend == Backend::Sqlite {
+            let snapshot_dir = temp_dir.path().join("db");
+            fs::create_dir(&snapshot_dir)?;
+            sqlite::open(self.index())?.snapshot(&snapshot_dir.join(DATABASE))?;
+            command.arg("-C").arg(&snapshot_dir).arg(DATABASE);
+        }
+        {
+            // so no metadata changes halfway through
+            let _transaction = store::transaction(self.index())?;
+            run(&mut command, "packing up the index")?;
+        }
+
+        let upload = match &self.encryption {
+            Some(encryption) => {
+                let encrypted = temp_dir.path().join("index.tar.gz.age");
+                encryption::encrypt(&encryption.recipients, &tarball, &encrypted)?;
+                encrypted
+            }
+            None => tarball,
+        };
+        info!("uploading {name}..");
+        self.upload_file(&upload, &Self::backup_name(&name))?;
+        info!(
+            "backed up to {}",
+            self.remote_path(&Self::backup_name(&name))
+        );
+        Ok(name)
+    }
+
+    /// Replaces the index with the backup `name`, or the latest one. An index that's already
+    /// there is moved aside rather than deleted.
+    pub fn restore(&self, name: Option<&str>, yes: bool) -> Result<()> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => match self.backups()?.pop() {
+                Some(latest) => latest,
+                None => bail!("no backups in {} - `kartka backup` makes one", self.remote),
+            },
+        };
+        if name.contains('/') || !name.starts_with(PREFIX) {
+            bail!("{name:?} isn't a backup - `kartka restore --list` lists them");
+        }
+
+        let index = self.index();
+        let in_use = index.exists() && index.read_dir()?.next().is_some();
+        let mut aside = index.as_os_str().to_owned();
+        aside.push(".before-restore");
+        if self.dry_run {
+            if in_use {
+                println!("would move {index:?} to {aside:?}");
+            }
+            println!("would restore {name} to {index:?}");
+            return Ok(());
+        }
+        if in_use {
+            if Path::new(&aside).exists() {
+                bail!("{aside:?} is in the way - move or delete it first");
+            }
+            let question =
+                format!("Replace the index in {index:?} with {name}? It'll be kept as {aside:?}");
+            if !yes && !self.confirm(&question, false)? {
+                return Ok(());
+            }
+        }
+
+        info!("downloading {name}..");
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("index.tar.gz");
+        self.download(&Self::backup_name(&name), &tarball)?;
+        self.decrypt_in_place(&tarball)?;
+
+        if in_use {
+            fs::rename(index, &aside).context(format!("moving {index:?} to {aside:?}"))?;
+        }
+        fs::create_dir_all(index).context(format!("creating {index:?}"))?;
+        run(
+            Command::new("tar")
+                .arg("-xzf")
+                .arg(&tarball)
+                .arg("-C")
+                .arg(index),
+            "unpacking the backup",
+        )?;
+        info!("restored {name}");
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Expected recurring documents, and working out which of them are missing for a period.
+
+use std::{ops::RangeInclusive, str::FromStr};
+
+use eyre::{bail, Result};
+use jiff::civil::Date;
+use serde::Deserialize;
+
+use crate::{
+    index::document_date,
+    metadata::{field_matches, Metadata},
+    Kartka,
+};
+
+/// A document that should turn up regularly, configured as `[[checklist]]` in kartka.toml.
+#[derive(Debug, Deserialize)]
+pub struct Expected {
+    pub name: String,
+    pub every: Every,
+    #[serde(default)]
+    pub doc_type: Option<String>,
+    #[serde(default)]
+    pub correspondent: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Every {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Expected {
+    /// Whether a document with `metadata` satisfies this entry. Every configured field has to
+    /// match; an entry with no fields matches nothing.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        (self.doc_type.is_some() || self.correspondent.is_some())
+            && field_matches(&self.doc_type, &metadata.doc_type)
+            && field_matches(&self.correspondent, &metadata.correspondent)
+    }
+}
+
+/// The span of time a checklist is run for: a whole year (`2024`) or a single month (`2024-03`).
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    year: i16,
+    month: Option<i8>,
+}
+
+impl Span {
+    pub fn year(year: i16) -> Self {
+        Span { year, month: None }
+    }
+}
+
+impl FromStr for Span {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (year, month) = match s.split_once('-') {
+            Some((year, month)) => (year, Some(month)),
+            None => (s, None),
+        };
+
+        let year: i16 = year.parse()?;
+        let month: Option<i8> = month.map(str::parse).transpose()?;
+        if let Some(month) = month {
+            if !(1..=12).contains(&month) {
+                bail!("invalid month in period {s:?}");
+            }
+        }
+
+        Ok(Span { year, month })
+    }
+}
+
+#[derive(Debug)]
+pub struct Period {
+    pub label: String,
+    year: i16,
+    months: RangeInclusive<i8>,
+}
+
+impl Period {
+    pub fn contains(&self, date: Date) -> bool {
+        date.year() == self.year && self.months.contains(&date.month())
+    }
+
+    fn has_started(&self, today: Date) -> bool {
+        (self.year, *self.months.start()) <= (today.year(), today.month())
+    }
+}
+
+/// The periods within `span` that a document due `every` so often should appear in, ignoring
+/// any that haven't started yet as of `today`.
+pub fn periods(every: Every, span: Span, today: Date) -> Vec<Period> {
+    let year = span.year;
+    let periods = match (every, span.month) {
+        (Every::Month, Some(month)) => vec![Period {
+            label: format!("{year}-{month:02}"),
+            year,
+            months: month..=month,
+        }],
+        (Every::Month, None) => (1..=12)
+            .map(|month| Period {
+                label: format!("{year}-{month:02}"),
+                year,
+                months: month..=month,
+            })
+            .collect(),
+        (Every::Quarter, month) => {
+            let quarters = match month {
+                Some(month) => vec![(month - 1) / 3],
+                None => (0..4).collect(),
+            };
+            quarters
+                .into_iter()
+                .map(|q| Period {
+                    label: format!("{year}-Q{}", q + 1),
+                    year,
+                    months: q * 3 + 1..=q * 3 + 3,
+                })
+                .collect()
+        }
+        (Every::Year, _) => vec![Period {
+            label: format!("{year}"),
+            year,
+            months: 1..=12,
+        }],
+    };
+
+    periods
+        .into_iter()
+        .filter(|it| it.has_started(today))
+        .collect()
+}
+
+impl Kartka {
+    /// Prints which configured checklist documents turned up in each period of `span`
+    /// (the current year by default), failing if none are configured.
+    pub fn checklist(&self, span: Option<Span>) -> Result<()> {
+        if self.checklist.is_empty() {
+            bail!("no [[checklist]] entries configured");
+        }
+
+        let today = jiff::Zoned::now().date();
+        let span = span.unwrap_or(Span::year(today.year()));
+
+        let documents: Vec<_> = self
+            .documents()?
+            .into_iter()
+            .filter_map(|id| document_date(&id).map(|date| (id, date)))
+            .map(|(id, date)| Ok((date, self.metadata(&id)?)))
+            .collect::<Result<_>>()?;
+
+        let mut missing = 0;
+        for expected in &self.checklist {
+            for period in periods(expected.every, span, today) {
+                let found = documents
+                    .iter()
+                    .any(|(date, meta)| period.contains(*date) && expected.matches(meta));
+                if !found {
+                    missing += 1;
+                }
+                println!(
+                    "{:<8} {:<24} {}",
+                    period.label,
+                    expected.name,
+                    if found { "ok" } else { "MISSING" }
+                );
+            }
+        }
+
+        println!("{missing} missing");
+        Ok(())
+    }
+}
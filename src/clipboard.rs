@@ -0,0 +1,14 @@
+//! Putting links on the system clipboard, for `--copy`, so they needn't be picked out of the
+//! terminal by hand.
+
+use eyre::{Context, Result};
+
+/// Puts `text` on the clipboard. On Linux it only stays there after kartka exits if a
+/// clipboard manager takes it over, as the ones most desktops come with do.
+pub fn copy(text: &str) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new().context("opening the clipboard")?;
+    clipboard
+        .set_text(text)
+        .context("putting it on the clipboard")?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! OCR by cloud services, which cope with handwriting and poor scans far better than tesseract.
+//! Pages are only sent off when `ocr_engine` asks for one of these, and tesseract takes over for
+//! any page the service can't be reached for.
+
+use std::{fs, path::Path, process::Command};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{bail, eyre, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::{
+    config,
+    ocr::{self, OcrEngine, Page, Tesseract},
+};
+
+const GOOGLE_VISION_URL: &str = "https://vision.googleapis.com/v1/images:annotate";
+
+/// Credentials for the cloud OCR services, configured as `[cloud_ocr]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CloudOcr {
+    /// A shell command that prints a Google Cloud API key with the Vision API enabled.
+    #[serde(default)]
+    pub google_api_key_command: Option<String>,
+    /// The AWS CLI profile to call Textract with; the CLI's default if not set.
+    #[serde(default)]
+    pub aws_profile: Option<String>,
+    /// The AWS region to call Textract in; the CLI's default if not set.
+    #[serde(default)]
+    pub aws_region: Option<String>,
+}
+
+/// Google Cloud Vision's document text detection.
+pub struct GoogleVision {
+    api_key: String,
+}
+
+impl GoogleVision {
+    pub fn new(config: &CloudOcr) -> Result<Self> {
+        let Some(command) = &config.google_api_key_command else {
+            bail!("ocr_engine = \"google-vision\" needs `google_api_key_command` in [cloud_ocr]");
+        };
+        Ok(GoogleVision {
+            api_key: config::secret_from_command(command, "google_api_key_command")?,
+        })
+    }
+}
+
+impl OcrEngine for GoogleVision {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        let image = fs::read(path).context(format!("reading {path:?}"))?;
+        let request = json!({
+            "requests": [{
+                "image": { "content": STANDARD.encode(image) },
+                "features": [{ "type": "DOCUMENT_TEXT_DETECTION" }],
+            }],
+        });
+        let response: Value = ureq::post(GOOGLE_VISION_URL)
+            .query("key", &self.api_key)
+            .send_json(&request)
+            .context("calling Google Vision")?
+            .body_mut()
+            .read_json()
+            .context("reading Google Vision's response")?;
+
+        let result = &response["responses"][0];
+        if let Some(message) = result["error"]["message"].as_str() {
+            bail!("Google Vision couldn't read {path:?}: {message}");
+        }
+        // a page with no text on it has no annotation at all
+        Ok(result["fullTextAnnotation"]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string())
+    }
+}
+
+/// AWS Textract, through the `aws` CLI so it can look after credentials.
+pub struct Textract {
+    profile: Option<String>,
+    region: Option<String>,
+}
+
+impl Textract {
+    pub fn new(config: &CloudOcr) -> Self {
+        Textract {
+            profile: config.aws_profile.clone(),
+            region: config.aws_region.clone(),
+        }
+    }
+}
+
+impl OcrEngine for Textract {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        Ok(self.image_to_text_with_confidence(path)?.0)
+    }
+
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        let mut command = Command::new("aws");
+        command
+            .arg("textract")
+            .arg("detect-document-text")
+            .arg("--document")
+            .arg(format!("Bytes=fileb://{}", path.display()))
+            .arg("--output")
+            .arg("json");
+        if let Some(profile) = &self.profile {
+            command.arg("--profile").arg(profile);
+        }
+        if let Some(region) = &self.region {
+            command.arg("--region").arg(region);
+        }
+        let output = command.output().context("running aws")?;
+        if !output.status.success() {
+            bail!(
+                "Textract couldn't read {path:?}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let response: Value =
+            serde_json::from_slice(&output.stdout).context("reading Textract's response")?;
+        let blocks = response["Blocks"]
+            .as_array()
+            .ok_or_else(|| eyre!("Textract's response has no blocks"))?;
+        let lines: Vec<_> = blocks
+            .iter()
+            .filter(|it| it["BlockType"] == "LINE")
+            .collect();
+        let text: Vec<_> = lines.iter().filter_map(|it| it["Text"].as_str()).collect();
+        let confidences: Vec<_> = lines
+            .iter()
+            .filter_map(|it| it["Confidence"].as_f64())
+            .map(|it| it as f32)
+            .collect();
+        Ok((text.join("\n"), ocr::mean(&confidences)))
+    }
+}
+
+/// Tries a cloud engine first, and tesseract if that fails - most likely because there's no
+/// network.
+pub struct WithFallback<E> {
+    pub engine: E,
+    pub fallback: Tesseract,
+}
+
+impl<E: OcrEngine> OcrEngine for WithFallback<E> {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        self.engine.image_to_text(path).or_else(|e| {
+            warn!("falling back to tesseract: {e:#}");
+            self.fallback.image_to_text(path)
+        })
+    }
+
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        self.engine
+            .image_to_text_with_confidence(path)
+            .or_else(|e| {
+                warn!("falling back to tesseract: {e:#}");
+                self.fallback.image_to_text_with_confidence(path)
+            })
+    }
+
+    fn image_to_page(&self, path: &Path) -> Result<Page> {
+        self.engine.image_to_page(path).or_else(|e| {
+            warn!("falling back to tesseract: {e:#}");
+            self.fallback.image_to_page(path)
+        })
+    }
+}
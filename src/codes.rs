@@ -0,0 +1,161 @@
+//! QR codes and barcodes on documents' pages, like the payment codes on invoices or tracking
+//! barcodes on parcels, read with `zbarimg` when `read_codes = true` and kept in metadata.
+
+use std::{path::Path, process::Command};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{pages::SEPARATOR_CODE, pdf, search, thumbnail, tools::ToolRunner, Kartka};
+
+/// A code found on a page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Code {
+    /// What sort of code it is, as zbar calls it, e.g. `QR-Code` or `EAN-13`.
+    pub kind: String,
+    /// What it says.
+    pub data: String,
+    /// The page it's on, counting from 1.
+    pub page: usize,
+}
+
+/// Every code zbar can find on the page image at `page`.
+pub fn read(tools: &dyn ToolRunner, page: &Path) -> Result<Vec<(String, String)>> {
+    // zbarimg exits with 4 when there's no code to be found, which isn't a failure here
+    let output = tools
+        .output(Command::new("zbarimg").args(["--quiet", "--xml"]).arg(page))
+        .context("running zbarimg")?;
+    if !matches!(output.status.code(), Some(0 | 4)) {
+        eyre::bail!(
+            "reading codes on {page:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_xml(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The kind and data of each `<symbol>` in zbarimg's XML. Data that isn't text comes base64
+/// encoded, and is decoded as well as it can be.
+fn parse_xml(xml: &str) -> Vec<(String, String)> {
+    let mut codes = vec![];
+    for symbol in xml.split("<symbol ").skip(1) {
+        let Some(kind) = attribute(symbol, "type") else {
+            continue;
+        };
+        let Some(data) = symbol.split_once("<data").map(|(_, it)| it) else {
+            continue;
+        };
+        let base64 = data
+            .split_once('>')
+            .is_some_and(|(tag, _)| tag.contains("base64"));
+        let Some(data) = data
+            .split_once("<![CDATA[")
+            .and_then(|(_, it)| it.split_once("]]>"))
+            .map(|(it, _)| it)
+        else {
+            continue;
+        };
+        let data = match base64 {
+            true => match STANDARD.decode(data.trim()) {
+                Ok(it) => String::from_utf8_lossy(&it).into_owned(),
+                Err(_) => continue,
+            },
+            false => data.to_string(),
+        };
+        codes.push((kind.to_string(), data));
+    }
+    codes
+}
+
+/// The value of `name='...'` in `tag`.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &tag[..tag.find('>')?];
+    let (_, value) = tag.split_once(&format!("{name}="))?;
+    let quote = value.chars().next()?;
+    value[1..].split(quote).next()
+}
+
+impl Kartka {
+    /// Reads the codes on the page images in `pages` into `id`'s metadata, if `read_codes` is
+    /// set. Like thumbnails, they're no reason to fail a scan, so problems are only warned
+    /// about.
+    pub(crate) fn save_codes(&self, id: &str, pages: &Path) {
+        if !self.read_codes {
+            return;
+        }
+        let codes = codes_in(self.tools.as_ref(), pages)
+            .and_then(|codes| self.update_metadata(id, |meta| meta.codes = codes));
+        if let Err(e) = codes {
+            warn!("couldn't read the codes on {id}: {e:#}");
+        }
+    }
+
+    /// Like [`Kartka::save_codes`] for the PDF at `pdf`, rendering its pages into `pages` if
+    /// they haven't been already.
+    pub(crate) fn save_pdf_codes(&self, id: &str, pdf: &Path, pages: &Path) {
+        if !self.read_codes {
+            return;
+        }
+        if matches!(thumbnail::first_image(pages), Ok(Some(_))) {
+            return self.save_codes(id, pages);
+        }
+        let rendered = pdf::render_pages(self.tools.as_ref(), pdf, pages, "page");
+        match rendered {
+            Ok(()) => self.save_codes(id, pages),
+            Err(e) => warn!("couldn't read the codes on {id}: {e:#}"),
+        }
+    }
+
+    /// The documents with a code saying something that matches `query`, newest first, and the
+    /// codes that matched. Case and accents are ignored unless `exact_search` is set.
+    pub fn search_codes(&self, query: &str) -> Result<Vec<(String, Vec<Code>)>> {
+        let query = search::comparable(query, self.exact_search);
+        let mut hits = vec![];
+        for id in self.documents()?.into_iter().rev() {
+            let codes: Vec<_> = self
+                .metadata(&id)?
+                .codes
+                .into_iter()
+                .filter(|it| search::comparable(&it.data, self.exact_search).contains(&query))
+                .collect();
+            if !codes.is_empty() {
+                hits.push((id, codes));
+            }
+        }
+        Ok(hits)
+    }
+}
+
+/// The codes on every page image in `dir`, in page order.
+fn codes_in(tools: &dyn ToolRunner, dir: &Path) -> Result<Vec<Code>> {
+    let mut pages: Vec<_> = dir
+        .read_dir()
+        .context(format!("reading dir: {dir:?}"))?
+        .map(|it| it.map(|it| it.path()))
+        .collect::<Result<_, _>>()?;
+    pages.retain(|it| {
+        it.file_name()
+            .and_then(|it| it.to_str())
+            .is_some_and(|it| !it.starts_with('.'))
+            && !pdf::is_pdf(it)
+    });
+    pages.sort();
+
+    let mut codes = vec![];
+    for (i, page) in pages.iter().enumerate() {
+        for (kind, data) in read(tools, page)? {
+            if data.trim() == SEPARATOR_CODE {
+                continue;
+            }
+            debug!("found a {kind} on {page:?}");
+            codes.push(Code {
+                kind,
+                data,
+                page: i + 1,
+            });
+        }
+    }
+    Ok(codes)
+}
@@ -0,0 +1,91 @@
+//! Collections, like "Taxes 2024" or "House purchase", for grouping documents that belong
+//! together without moving them anywhere. A document can be in any number of them, and they're
+//! kept in its metadata alongside its tags, so a collection is just the documents naming it.
+
+use std::collections::BTreeMap;
+
+use eyre::{bail, Result};
+
+use crate::Kartka;
+
+impl Kartka {
+    /// Puts `ids` in the collection `name`, making it if it's new. Returns how many weren't in
+    /// it already.
+    pub fn add_to_collection(&self, name: &str, ids: &[String]) -> Result<usize> {
+        if name.trim().is_empty() {
+            bail!("a collection needs a name");
+        }
+        for id in ids {
+            if !self.contains(id)? {
+                bail!("{id} not found in index");
+            }
+        }
+
+        let mut added = 0;
+        for id in ids {
+            self.update_metadata(id, |meta| {
+                if !meta.collections.iter().any(|it| it == name) {
+                    meta.collections.push(name.to_string());
+                    added += 1;
+                }
+            })?;
+        }
+        if added > 0 {
+            self.commit_index(&format!("Add {added} documents to the collection {name:?}"));
+        }
+        Ok(added)
+    }
+
+    /// Takes `ids` out of the collection `name`. Returns how many were in it.
+    pub fn remove_from_collection(&self, name: &str, ids: &[String]) -> Result<usize> {
+        let mut removed = 0;
+        for id in ids {
+            if !self.contains(id)? {
+                bail!("{id} not found in index");
+            }
+            self.update_metadata(id, |meta| {
+                let before = meta.collections.len();
+                meta.collections.retain(|it| it != name);
+                removed += before - meta.collections.len();
+            })?;
+        }
+        if removed > 0 {
+            self.commit_index(&format!(
+                "Remove {removed} documents from the collection {name:?}"
+            ));
+        }
+        Ok(removed)
+    }
+
+    /// Every collection, with how many documents are in it.
+    pub fn collections(&self) -> Result<BTreeMap<String, usize>> {
+        let mut collections = BTreeMap::new();
+        for id in self.documents()? {
+            for name in self.metadata(&id)?.collections {
+                *collections.entry(name).or_default() += 1;
+            }
+        }
+        Ok(collections)
+    }
+
+    /// The documents in the collection `name`, oldest first.
+    pub fn collection(&self, name: &str) -> Result<Vec<String>> {
+        let mut ids = vec![];
+        for id in self.documents()? {
+            if self.in_collection(&id, name)? {
+                ids.push(id);
+            }
+        }
+        if ids.is_empty() {
+            bail!(
+                "there's no collection called {name:?} - `kartka collection list` shows them all"
+            );
+        }
+        Ok(ids)
+    }
+
+    /// Whether `id` is in the collection `name`.
+    pub fn in_collection(&self, id: &str, name: &str) -> Result<bool> {
+        Ok(self.metadata(id)?.collections.iter().any(|it| it == name))
+    }
+}
@@ -0,0 +1,83 @@
+//! Shell completions. The scripts `kartka completions` prints call back into kartka as you
+//! type, so document ids, tags and collections complete from the index as well as subcommands and options.
+
+use std::{collections::BTreeSet, env, io, path::PathBuf};
+
+use clap_complete::{env::Shells, CompletionCandidate, Shell};
+use eyre::{eyre, Result};
+use kartka::{config, Kartka};
+
+/// The environment variable the scripts set to ask kartka for completions.
+pub const VAR: &str = "COMPLETE";
+
+/// Prints the completion script for `shell`.
+pub fn print(shell: Shell) -> Result<()> {
+    let name = shell.to_string();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&name)
+        .ok_or_else(|| eyre!("no completions for {name}"))?;
+    completer.write_registration(VAR, "kartka", "kartka", "kartka", &mut io::stdout())?;
+    Ok(())
+}
+
+/// The index, if there's a config that loads. Completing is no time to complain about one that
+/// doesn't, so then there's just nothing to offer.
+fn load() -> Option<Kartka> {
+    let explicit = env::var_os("KARTKA_CONFIG").map(PathBuf::from);
+    let profile = env::var("KARTKA_PROFILE").ok();
+    config::load(&config::path(explicit)?, profile.as_deref()).ok()
+}
+
+/// Every document id in the index.
+pub fn document_ids() -> Vec<CompletionCandidate> {
+    let Some(kartka) = load() else {
+        return vec![];
+    };
+    kartka
+        .documents()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Every tag used in the index.
+pub fn tags() -> Vec<CompletionCandidate> {
+    let Some(kartka) = load() else {
+        return vec![];
+    };
+    let mut tags = BTreeSet::new();
+    for id in kartka.documents().unwrap_or_default() {
+        if let Ok(meta) = kartka.metadata(&id) {
+            tags.extend(meta.tags);
+        }
+    }
+    tags.into_iter().map(CompletionCandidate::new).collect()
+}
+
+/// Every collection in the index.
+pub fn collections() -> Vec<CompletionCandidate> {
+    let Some(kartka) = load() else {
+        return vec![];
+    };
+    kartka
+        .collections()
+        .unwrap_or_default()
+        .into_keys()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// The name of every saved search.
+pub fn saved_searches() -> Vec<CompletionCandidate> {
+    let Some(kartka) = load() else {
+        return vec![];
+    };
+    kartka
+        .saved_searches()
+        .unwrap_or_default()
+        .into_keys()
+        .map(CompletionCandidate::new)
+        .collect()
+}
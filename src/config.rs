@@ -0,0 +1,177 @@
+//! Finding and loading the config file.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use toml::Table;
+
+use crate::{
+    validate::{self, Invalid},
+    KartkaError,
+};
+
+const CONFIG_FILE: &str = "kartka.toml";
+
+/// Environment variables starting with this override config values, e.g. `KARTKA_SCAN_DIR`.
+const ENV_PREFIX: &str = "KARTKA_";
+
+/// Variables with the prefix that pick which config to load rather than setting values in it.
+const ENV_SELECTORS: &[&str] = &["KARTKA_CONFIG", "KARTKA_PROFILE"];
+
+/// Where the config lives: `explicit` if given, otherwise `kartka.toml` in the platform config
+/// directory (`$XDG_CONFIG_HOME`, `~/.config`, `~/Library/Application Support`, `%APPDATA%`).
+///
+/// `~/.config/kartka.toml` is still picked up on platforms where that isn't the config
+/// directory, since that's where kartka used to look everywhere.
+pub fn path(explicit: Option<PathBuf>) -> Option<PathBuf> {
+    if explicit.is_some() {
+        return explicit;
+    }
+
+    let path = dirs::config_dir().map(|it| it.join(CONFIG_FILE));
+    if path.as_ref().is_some_and(|it| it.exists()) {
+        return path;
+    }
+
+    let legacy_path = dirs::home_dir().map(|it| it.join(".config").join(CONFIG_FILE));
+    if legacy_path.as_ref().is_some_and(|it| it.exists()) {
+        return legacy_path;
+    }
+
+    path.or(legacy_path)
+}
+
+/// Loads the config at `path`. Settings in the `[profiles.<name>]` table for `profile` (or
+/// `default_profile` if none is given) override the top-level ones, so shared settings only need
+/// writing once, and `KARTKA_<SETTING>` environment variables override both.
+///
+/// Failures are reported as [`KartkaError::Config`]. Settings that are the wrong type or don't
+/// exist are all reported together, as an [`Invalid`].
+pub fn load<T: DeserializeOwned>(path: &Path, profile: Option<&str>) -> Result<T> {
+    load_with_env(path, profile, env::vars())
+}
+
+/// [`load`], with `vars` standing in for the environment.
+pub fn load_with_env<T: DeserializeOwned>(
+    path: &Path,
+    profile: Option<&str>,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<T> {
+    load_layers(path, profile, vars).map_err(|e| {
+        KartkaError::Config {
+            path: path.to_path_buf(),
+            source: e.into(),
+        }
+        .into()
+    })
+}
+
+fn load_layers<T: DeserializeOwned>(
+    path: &Path,
+    profile: Option<&str>,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<T> {
+    // everything can come from the environment, so a missing file is just an empty one
+    let mut config: Table = if path.exists() {
+        let contents = fs::read_to_string(path).context(format!("reading config {path:?}"))?;
+        toml::from_str(&contents).context(format!("parsing {path:?}"))?
+    } else {
+        Table::new()
+    };
+
+    let profiles = match config.remove("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => bail!("`profiles` in {path:?} should be a table"),
+        None => Table::new(),
+    };
+    let default_profile = match config.remove("default_profile") {
+        Some(toml::Value::String(name)) => Some(name),
+        Some(_) => bail!("`default_profile` in {path:?} should be a string"),
+        None => None,
+    };
+
+    if let Some(name) = profile.or(default_profile.as_deref()) {
+        let Some(toml::Value::Table(overrides)) = profiles.get(name) else {
+            let known: Vec<_> = profiles.keys().map(String::as_str).collect();
+            bail!(
+                "no profile `{name}` in {path:?} (known profiles: {})",
+                known.join(", ")
+            );
+        };
+        config.extend(overrides.clone());
+    }
+    // the environment is full of things that aren't meant for kartka, so only the file is checked
+    let mut problems = validate::unknown_settings::<T>(&config);
+
+    // what was read as something other than a string, in case a string's what it's for
+    let mut retyped = HashMap::new();
+    for (key, value) in vars {
+        if ENV_SELECTORS.contains(&key.as_str()) {
+            continue;
+        }
+        if let Some(setting) = key.strip_prefix(ENV_PREFIX) {
+            let setting = setting.to_lowercase();
+            let typed = env_value(&value);
+            if !typed.is_str() {
+                retyped.insert(setting.clone(), value);
+            }
+            config.insert(setting, typed);
+        }
+    }
+
+    loop {
+        match serde_path_to_error::deserialize(toml::Value::Table(config.clone())) {
+            Ok(config) if problems.is_empty() => return Ok(config),
+            Ok(_) => return Err(Invalid(problems).into()),
+            Err(e) => {
+                // so `KARTKA_REMOTE=2024` is the remote called 2024 rather than a wrong type
+                let path = e.path().to_string();
+                let setting = path.split('.').next().unwrap_or_default();
+                if let Some(value) = retyped.remove(setting) {
+                    config.insert(setting.to_string(), toml::Value::String(value));
+                    continue;
+                }
+                problems.push(validate::load_problem(e));
+                return Err(Invalid(problems).into());
+            }
+        }
+    }
+}
+
+/// What a `KARTKA_*` variable is set to, read as TOML so `true` and `2` can set settings that
+/// aren't strings. Anything that isn't valid TOML, like most paths, is taken as a string.
+fn env_value(value: &str) -> toml::Value {
+    match toml::from_str::<Table>(&format!("value = {value}")) {
+        Ok(mut parsed) => parsed
+            .remove("value")
+            .unwrap_or_else(|| toml::Value::String(value.to_string())),
+        Err(_) => toml::Value::String(value.to_string()),
+    }
+}
+
+/// Runs `command` (the value of the `setting` setting) with the shell and returns what it
+/// prints, so secrets can come from a password manager rather than sitting in the config.
+pub fn secret_from_command(command: &str, setting: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).output()
+    } else {
+        Command::new("sh").arg("-c").arg(command).output()
+    }
+    .context(format!("running {setting}"))?;
+    if !output.status.success() {
+        bail!(
+            "{setting} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)
+        .context(format!("{setting} printed something that isn't UTF-8"))?
+        .trim_end_matches(['\r', '\n'])
+        .to_string())
+}
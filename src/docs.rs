@@ -0,0 +1,48 @@
+//! `kartka docs generate`: man pages built from the clap definitions, for packagers to ship.
+
+use std::{fs, path::Path};
+
+use clap::Command;
+use eyre::{Context, Result};
+
+/// Extra detail for `kartka --help` and the `kartka(1)` man page.
+pub const AFTER_HELP: &str = "\
+Exit codes:
+  0   Success
+  1   Something else went wrong
+  3   A batch (e.g. `hydrate`) finished, but some documents failed
+  65  OCR failed
+  69  Talking to the remote failed
+  78  The config is missing or invalid
+
+Environment:
+  KARTKA_CONFIG      Path to the config file, like --config
+  KARTKA_PROFILE     Config profile to use, like --profile
+  KARTKA_LOG_FILE    File to append a detailed log to, like --log-file
+  KARTKA_PREVIEW     How `show` draws previews: kitty, iterm2, sixel, or none
+  KARTKA_<SETTING>   Overrides that setting from the config, e.g. KARTKA_INDEX_DIR";
+
+/// Writes a man page for `command` and every subcommand under it into `out`, returning how many
+/// it wrote.
+pub fn generate(mut command: Command, out: &Path) -> Result<usize> {
+    fs::create_dir_all(out).context(format!("creating {out:?}"))?;
+    // propagates global options down so every page lists them
+    command.build();
+    write_pages(&command, command.get_name(), out)
+}
+
+fn write_pages(command: &Command, name: &str, out: &Path) -> Result<usize> {
+    let path = out.join(format!("{name}.1"));
+    let mut page = vec![];
+    clap_mangen::Man::new(command.clone().name(name.to_string())).render(&mut page)?;
+    fs::write(&path, page).context(format!("writing {path:?}"))?;
+
+    let mut written = 1;
+    for sub in command.get_subcommands() {
+        if sub.is_hide_set() || sub.get_name() == "help" {
+            continue;
+        }
+        written += write_pages(sub, &format!("{name}-{}", sub.get_name()), out)?;
+    }
+    Ok(written)
+}
@@ -0,0 +1,182 @@
+//! `kartka doctor`: checks everything kartka relies on outside itself, and says how to fix what
+//! isn't right. A missing tool otherwise tends to show up as empty text or nothing happening.
+
+use std::{path::Path, process::Command};
+
+use eyre::{bail, Result};
+use kartka::{config, index::Backend, ocr::Engine, ocrmypdf::Pipeline, pages::Separator, Kartka};
+
+/// Runs every check against the config at `config_path`, printing how each went. Fails if any
+/// of them did.
+pub fn run(config_path: &Path, profile: Option<&str>) -> Result<()> {
+    let mut doctor = Doctor::default();
+
+    let kartka: Option<Kartka> = match config::load(config_path, profile) {
+        Ok(kartka) => {
+            doctor.ok(&format!("config {config_path:?}"));
+            Some(kartka)
+        }
+        Err(_) if !config_path.exists() => {
+            doctor.fail(
+                &format!("no config at {config_path:?}"),
+                "run `kartka init` to write one",
+            );
+            None
+        }
+        Err(e) => {
+            doctor.fail(
+                &format!("config {config_path:?}: {e:#}"),
+                "fix the setting it mentions",
+            );
+            None
+        }
+    };
+
+    doctor.tool(
+        "tesseract",
+        "install tesseract, e.g. `brew install tesseract`",
+    );
+    doctor.tool("rclone", "install rclone from https://rclone.org/install/");
+    doctor.tool(
+        "magick",
+        "install ImageMagick 7, e.g. `brew install imagemagick`",
+    );
+    doctor.tool("gs", "install ghostscript, e.g. `brew install ghostscript`");
+    let Some(kartka) = kartka else {
+        return doctor.finish();
+    };
+
+    if kartka.index_backend == Backend::Files {
+        doctor.tool("rg", "install ripgrep, e.g. `brew install ripgrep`");
+    }
+    if kartka.encryption.is_some() {
+        doctor.tool("age", "install age from https://age-encryption.org");
+    }
+    if kartka.signing_key.is_some() || kartka.verify_key.is_some() {
+        doctor.tool("minisign", "install minisign, e.g. `brew install minisign`");
+    }
+    if kartka.pdf.password_command.is_some() {
+        doctor.tool("qpdf", "install qpdf, e.g. `brew install qpdf`");
+    }
+    if kartka.separator == Some(Separator::Qr) || kartka.read_codes {
+        doctor.tool("zbarimg", "install zbar, e.g. `brew install zbar`");
+    }
+    if kartka.pipeline == Pipeline::Ocrmypdf {
+        doctor.tool("ocrmypdf", "install OCRmyPDF, e.g. `brew install ocrmypdf`");
+    }
+    if kartka.ocr_engine == Engine::Textract {
+        doctor.tool(
+            "aws",
+            "install the AWS CLI and run `aws configure`, e.g. `brew install awscli`",
+        );
+    }
+
+    if doctor.has_tool("tesseract") {
+        let mut languages: Vec<_> = kartka.ocr_language.split('+').map(str::to_string).collect();
+        languages.extend(kartka.detect_languages.iter().cloned());
+        for language in languages {
+            if language_installed(&language) {
+                doctor.ok(&format!("tesseract language {language}"));
+            } else {
+                doctor.fail(
+                    &format!("tesseract has no data for {language}"),
+                    &format!("install it, e.g. `apt install tesseract-ocr-{language}`"),
+                );
+            }
+        }
+    }
+
+    if doctor.has_tool("rclone") {
+        match kartka.check_remotes() {
+            Ok(()) => doctor.ok(&format!("rclone remote {}", kartka.remote)),
+            Err(e) => doctor.fail(&format!("{e:#}"), "run `rclone config` to set it up"),
+        }
+    }
+
+    // the remotes were checked above, even if rclone has none set up yet
+    let problems: Vec<_> = kartka
+        .problems()
+        .into_iter()
+        .filter(|it| !it.setting.ends_with("remote"))
+        .collect();
+    for problem in &problems {
+        doctor.fail(
+            &format!("`{}` {}", problem.setting, problem.message),
+            problem
+                .fix
+                .as_deref()
+                .unwrap_or("fix the setting it mentions"),
+        );
+    }
+    if problems.is_empty() {
+        doctor.ok("settings");
+    }
+    doctor.finish()
+}
+
+#[derive(Default)]
+struct Doctor {
+    problems: usize,
+    found_tools: Vec<&'static str>,
+}
+
+impl Doctor {
+    fn ok(&mut self, what: &str) {
+        println!("ok    {what}");
+    }
+
+    fn fail(&mut self, what: &str, fix: &str) {
+        println!("FAIL  {what}");
+        println!("      fix: {fix}");
+        self.problems += 1;
+    }
+
+    fn tool(&mut self, tool: &'static str, fix: &str) {
+        match tool_version(tool) {
+            Some(version) => {
+                self.ok(&format!("{tool} ({version})"));
+                self.found_tools.push(tool);
+            }
+            None => self.fail(&format!("{tool} isn't on PATH or doesn't run"), fix),
+        }
+    }
+
+    fn has_tool(&self, tool: &str) -> bool {
+        self.found_tools.contains(&tool)
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.problems {
+            0 => {
+                println!("\neverything looks good");
+                Ok(())
+            }
+            1 => bail!("found a problem"),
+            n => bail!("found {n} problems"),
+        }
+    }
+}
+
+/// The first line `tool --version` prints, if it runs at all.
+fn tool_version(tool: &str) -> Option<String> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // some tools print their version to stderr
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let version = stdout.lines().chain(stderr.lines()).next()?;
+    Some(version.trim().to_string())
+}
+
+/// Whether tesseract has data for `languages`, which can be several joined with `+`.
+pub fn language_installed(languages: &str) -> bool {
+    let Ok(output) = Command::new("tesseract").arg("--list-langs").output() else {
+        return false;
+    };
+    let installed = String::from_utf8_lossy(&output.stdout);
+    languages
+        .split('+')
+        .all(|lang| installed.lines().any(|it| it.trim() == lang))
+}
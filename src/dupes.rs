@@ -0,0 +1,65 @@
+//! Spotting a letter that's already in the index, for when the same one gets scanned twice. OCR
+//! never reads two scans of a page quite the same, so rather than comparing hashes this compares
+//! the overlapping runs of words ("shingles") in each document's text.
+
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use eyre::Result;
+
+use crate::Kartka;
+
+/// How alike two documents' text must be to count as the same letter.
+const THRESHOLD: f64 = 0.8;
+/// How many words make up a shingle.
+const SHINGLE_WORDS: usize = 3;
+/// Documents with fewer shingles than this (blank pages, photos) are too short to judge.
+const MIN_SHINGLES: usize = 10;
+
+fn shingles(text: &str) -> HashSet<u64> {
+    let words: Vec<String> = text
+        .split(|it: char| !it.is_alphanumeric())
+        .filter(|it| it.chars().count() > 1)
+        .map(str::to_lowercase)
+        .collect();
+
+    words
+        .windows(SHINGLE_WORDS)
+        .map(|it| {
+            let mut hasher = DefaultHasher::new();
+            it.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// The share of shingles two documents have in common, from 0 to 1.
+fn similarity(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+impl Kartka {
+    /// The indexed document most like `text` and how alike they are, if it's alike enough to
+    /// probably be the same letter.
+    pub fn find_duplicate(&self, text: &str) -> Result<Option<(String, f64)>> {
+        let new = shingles(text);
+        if new.len() < MIN_SHINGLES {
+            return Ok(None);
+        }
+
+        let mut best: Option<(String, f64)> = None;
+        for id in self.documents()? {
+            let score = similarity(&new, &shingles(&self.text(&id)?));
+            if score >= THRESHOLD && best.as_ref().is_none_or(|(_, it)| score > *it) {
+                best = Some((id, score));
+            }
+        }
+        Ok(best)
+    }
+}
@@ -0,0 +1,149 @@
+//! Optional client-side encryption with [age](https://age-encryption.org), so PDFs are
+//! encrypted before they leave the machine. Documents keep their names on the remote, and
+//! whether one is encrypted is told from its header, so documents uploaded before encryption
+//! was turned on still work.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{bail, Context, Result};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{audit::Operation, remote_manifest::Change, Kartka};
+
+/// How documents are encrypted, configured as `[encryption]` in kartka.toml.
+#[derive(Debug, Deserialize)]
+pub struct Encryption {
+    /// age recipients (`age1...` public keys) to encrypt documents to.
+    pub recipients: Vec<String>,
+    /// age identity file used to decrypt documents. Only needed on machines that read them back.
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+}
+
+/// The ways an age file can start, binary or armored.
+const HEADERS: &[&[u8]] = &[
+    b"age-encryption.org/",
+    b"-----BEGIN AGE ENCRYPTED FILE-----",
+];
+
+/// Whether the file at `path` is age-encrypted.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)
+        .context(format!("opening {path:?}"))?
+        .take(64)
+        .read_to_end(&mut start)?;
+    Ok(HEADERS.iter().any(|it| start.starts_with(it)))
+}
+
+/// Encrypts `input` to `recipients`, writing the result to `output`.
+pub fn encrypt(recipients: &[String], input: &Path, output: &Path) -> Result<()> {
+    if recipients.is_empty() {
+        bail!("no encryption recipients configured");
+    }
+
+    let mut command = Command::new("age");
+    command.arg("--encrypt");
+    for recipient in recipients {
+        command.arg("--recipient").arg(recipient);
+    }
+    run(
+        command.arg("--output").arg(output).arg(input),
+        &format!("encrypting {input:?}"),
+    )
+}
+
+/// Decrypts `input` with the key in `identity`, writing the result to `output`.
+pub fn decrypt(identity: &Path, input: &Path, output: &Path) -> Result<()> {
+    run(
+        Command::new("age")
+            .arg("--decrypt")
+            .arg("--identity")
+            .arg(identity)
+            .arg("--output")
+            .arg(output)
+            .arg(input),
+        &format!("decrypting {input:?}"),
+    )
+}
+
+fn run(command: &mut Command, what: &str) -> Result<()> {
+    let output = command.output().context(format!("{what}: running age"))?;
+    if !output.status.success() {
+        bail!(
+            "{what} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+impl Kartka {
+    /// Uploads the PDF at `path` to the remote as `id`, encrypting it first if encryption is set
+    /// up, and records the hash of what was uploaded.
+    pub(crate) fn upload_document(&self, path: &Path, id: &str) -> Result<()> {
+        info!("Copying to {}..", self.remote);
+        let Some(encryption) = &self.encryption else {
+            let uploaded = self.upload_file(path, id);
+            self.audit(Operation::Upload, id, None, uploaded.as_ref().err());
+            uploaded?;
+            self.note_remote_change(Change::Added(id.to_string()));
+            self.record_hash(id, path)?;
+            self.keep_local_copy(path, id);
+            return Ok(());
+        };
+
+        let temp_dir = tempfile::tempdir()?;
+        let encrypted = temp_dir.path().join(id);
+        encrypt(&encryption.recipients, path, &encrypted)?;
+        let uploaded = self.upload_file(&encrypted, id);
+        self.audit(
+            Operation::Upload,
+            id,
+            Some("encrypted".to_string()),
+            uploaded.as_ref().err(),
+        );
+        uploaded?;
+        self.note_remote_change(Change::Added(id.to_string()));
+        self.record_hash(id, &encrypted)?;
+        self.keep_local_copy(path, id);
+        Ok(())
+    }
+
+    /// Decrypts the downloaded document at `path` in place, if it's encrypted.
+    pub(crate) fn decrypt_in_place(&self, path: &Path) -> Result<()> {
+        if !is_encrypted(path)? {
+            return Ok(());
+        }
+        let Some(identity) = self.encryption.as_ref().and_then(|it| it.identity.as_ref()) else {
+            bail!("{path:?} is encrypted, but there's no `identity` in the `[encryption]` config");
+        };
+
+        let mut encrypted = path.as_os_str().to_owned();
+        encrypted.push(".age");
+        let encrypted = PathBuf::from(encrypted);
+        fs::rename(path, &encrypted).context(format!("moving {path:?}"))?;
+        decrypt(identity, &encrypted, path)?;
+        fs::remove_file(&encrypted)?;
+        Ok(())
+    }
+
+    /// Downloads `id` from the remote to `dest`, decrypting it if need be. With a
+    /// `local_archive_dir` it's copied from there instead, and kept there if it wasn't yet.
+    pub fn fetch(&self, id: &str, dest: &Path) -> Result<()> {
+        if let Some(local) = self.local_copy(id) {
+            fs::copy(&local, dest).context(format!("copying {local:?}"))?;
+            return Ok(());
+        }
+        self.download(id, dest)?;
+        self.decrypt_in_place(dest)?;
+        self.keep_local_copy(dest, id);
+        Ok(())
+    }
+}
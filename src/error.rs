@@ -0,0 +1,61 @@
+//! The kinds of failure callers might want to tell apart.
+//!
+//! Most of kartka passes errors around as [`eyre::Report`]s with context attached. Where a
+//! failure falls into one of the categories below it's raised as a [`KartkaError`], which can be
+//! found again anywhere in a report's chain with [`category`].
+
+use std::{error::Error, path::PathBuf};
+
+use thiserror::Error;
+
+type Source = Box<dyn Error + Send + Sync + 'static>;
+
+#[derive(Debug, Error)]
+pub enum KartkaError {
+    /// The config file is missing, unreadable, or doesn't make sense.
+    #[error("problem with config {path:?}")]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: Source,
+    },
+    /// Tesseract couldn't read a page.
+    #[error("could not OCR {path:?}")]
+    Ocr {
+        path: PathBuf,
+        #[source]
+        source: Source,
+    },
+    /// Talking to the remote failed.
+    #[error("{what} failed: {message}")]
+    Storage { what: String, message: String },
+    /// A batch ran to the end, but some of its documents failed along the way.
+    #[error("{failed} of {total} documents failed")]
+    Partial { failed: usize, total: usize },
+}
+
+impl KartkaError {
+    /// The process exit code for this kind of failure, following `sysexits.h` where it has a
+    /// fitting code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            KartkaError::Config { .. } => 78,
+            KartkaError::Ocr { .. } => 65,
+            KartkaError::Storage { .. } => 69,
+            KartkaError::Partial { .. } => 3,
+        }
+    }
+}
+
+/// The first [`KartkaError`] in `report`'s chain, if there is one.
+pub fn category(report: &eyre::Report) -> Option<&KartkaError> {
+    report
+        .chain()
+        .find_map(|it| it.downcast_ref::<KartkaError>())
+}
+
+/// The exit code for a run that failed with `report`: the category's code, or 1 for anything
+/// uncategorised.
+pub fn exit_code(report: &eyre::Report) -> i32 {
+    category(report).map_or(1, KartkaError::exit_code)
+}
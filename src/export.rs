@@ -0,0 +1,111 @@
+//! `kartka export`: everything in the archive as plain files in one folder, readable without
+//! kartka, for handing to an accountant or moving off the remote altogether.
+
+use std::{fs, path::Path};
+
+use eyre::{bail, Context, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    index::document_date,
+    integrity::{self, Manifest},
+    metadata::Metadata,
+    progress, store, Kartka, KartkaError,
+};
+
+const PDF_DIR: &str = "pdfs";
+const TEXT_DIR: &str = "text";
+const MANIFEST: &str = "manifest.json";
+const CHECKSUMS: &str = "SHA256SUMS";
+
+/// A document's entry in `manifest.json`.
+#[derive(Serialize)]
+struct Entry {
+    id: String,
+    /// When the document was scanned, as `YYYY-MM-DD`.
+    date: Option<String>,
+    /// The PDF, relative to the export; missing if it couldn't be downloaded.
+    pdf: Option<String>,
+    text: String,
+    #[serde(flatten)]
+    metadata: Metadata,
+}
+
+impl Kartka {
+    /// Downloads every document's PDF into `dir`, decrypted, with its text and a
+    /// `manifest.json` of its metadata. PDFs already in `dir` aren't downloaded again, so an
+    /// export that was interrupted or had failures can be finished by running it again.
+    pub fn export(&self, dir: &Path) -> Result<()> {
+        let ids = self.documents()?;
+        if ids.is_empty() {
+            bail!("nothing to export");
+        }
+        if self.dry_run {
+            println!("would export {} documents to {dir:?}", ids.len());
+            return Ok(());
+        }
+
+        fs::create_dir_all(dir.join(PDF_DIR)).context(format!("creating {dir:?}"))?;
+        fs::create_dir_all(dir.join(TEXT_DIR))?;
+
+        let mut entries = vec![];
+        let mut checksums = Manifest::default();
+        let mut failed = 0;
+        let bar = progress::bar(ids.len(), "documents");
+        bar.set_message("exporting");
+        for id in ids.iter() {
+            let text = format!("{TEXT_DIR}/{id}.txt");
+            fs::write(dir.join(&text), self.text(id)?).context(format!("writing {text}"))?;
+
+            let pdf = format!("{PDF_DIR}/{id}");
+            let exported = self.export_pdf(id, &dir.join(&pdf));
+            if let Err(e) = &exported {
+                bar.suspend(|| warn!("couldn't export {id}: {e:#}"));
+                failed += 1;
+            } else {
+                checksums.insert(&pdf, integrity::sha256(&dir.join(&pdf))?);
+            }
+
+            let mut metadata = self.metadata(id)?;
+            // the hash of what was uploaded, which isn't what's exported if it was encrypted
+            metadata.sha256 = None;
+            entries.push(Entry {
+                id: id.clone(),
+                date: document_date(id).map(|it| it.to_string()),
+                pdf: exported.ok().map(|()| pdf),
+                text,
+                metadata,
+            });
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        store::write_atomic(&dir.join(MANIFEST), serde_json::to_string_pretty(&entries)?)?;
+        checksums.save(&dir.join(CHECKSUMS))?;
+
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: ids.len(),
+            }
+            .into());
+        }
+        info!("exported {} documents to {dir:?}", ids.len());
+        Ok(())
+    }
+
+    pub(crate) fn export_pdf(&self, id: &str, dest: &Path) -> Result<()> {
+        if dest.exists() {
+            return Ok(());
+        }
+        if self.is_archived(id)? && self.local_copy(id).is_none() {
+            bail!("it's in cold storage - `kartka open {id}` brings it back");
+        }
+
+        // downloaded alongside, so an interrupted download isn't mistaken for a finished one
+        let partial = dest.with_extension("part");
+        self.fetch(id, &partial)?;
+        fs::rename(&partial, dest).context(format!("moving {partial:?} to {dest:?}"))
+    }
+}
@@ -0,0 +1,422 @@
+//! Picking the figures out of receipts and invoices - the total, its currency, the invoice
+//! number and when it's due - so the archive can be exported as an expense ledger. The rules in
+//! `[extraction]` are tried first, then kartka's own for the usual English wording, and an LLM,
+//! if one's set up, fills in whatever those miss.
+
+use std::io::Write;
+
+use eyre::{bail, Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{index::document_date, openai::Endpoint, Kartka};
+
+/// What was picked out of a document. Amounts are kept as written, with a `.` before the
+/// pence, rather than as floats that would round them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<String>,
+    /// The ISO 4217 code of the total's currency, like `GBP`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<jiff::civil::Date>,
+}
+
+impl Fields {
+    pub fn is_empty(&self) -> bool {
+        *self == Fields::default()
+    }
+
+    fn has(&self, field: Field) -> bool {
+        match field {
+            Field::Total => self.total.is_some(),
+            Field::Currency => self.currency.is_some(),
+            Field::InvoiceNumber => self.invoice_number.is_some(),
+            Field::DueDate => self.due_date.is_some(),
+        }
+    }
+
+    /// Fills in the fields that haven't been found yet from `other`.
+    fn fill(&mut self, other: Fields) {
+        self.total = self.total.take().or(other.total);
+        self.currency = self.currency.take().or(other.currency);
+        self.invoice_number = self.invoice_number.take().or(other.invoice_number);
+        self.due_date = self.due_date.or(other.due_date);
+    }
+
+    /// Sets `field` from `value` as found in the text, returning whether it made sense.
+    fn set(&mut self, field: Field, value: &str) -> bool {
+        match field {
+            Field::Total => {
+                self.total = parse_amount(value);
+                if self.currency.is_none() {
+                    self.currency = parse_currency(value);
+                }
+                self.total.is_some()
+            }
+            Field::Currency => {
+                self.currency = parse_currency(value);
+                self.currency.is_some()
+            }
+            Field::InvoiceNumber => {
+                let value = value.trim();
+                self.invoice_number = (!value.is_empty()).then(|| value.to_string());
+                self.invoice_number.is_some()
+            }
+            Field::DueDate => {
+                self.due_date = parse_date(value);
+                self.due_date.is_some()
+            }
+        }
+    }
+
+    /// The fields that haven't been found yet.
+    fn missing(&self) -> Vec<Field> {
+        Field::ALL.into_iter().filter(|it| !self.has(*it)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Total,
+    Currency,
+    InvoiceNumber,
+    DueDate,
+}
+
+impl Field {
+    const ALL: [Field; 4] = [
+        Field::Total,
+        Field::Currency,
+        Field::InvoiceNumber,
+        Field::DueDate,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Total => "total",
+            Field::Currency => "currency",
+            Field::InvoiceNumber => "invoice_number",
+            Field::DueDate => "due_date",
+        }
+    }
+}
+
+/// A rule for finding a field, from `[[extraction.rules]]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub field: Field,
+    /// A regex, matched ignoring case. The field's value is its first group, or the whole
+    /// match if it hasn't got one.
+    pub pattern: String,
+}
+
+/// How fields are picked out of documents, configured as `[extraction]`. Documents are only
+/// looked at as they're indexed if this is set, even if it's empty.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Extraction {
+    /// Tried before kartka's own rules, in order.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// An LLM to ask for anything the rules don't find.
+    #[serde(default)]
+    pub llm: Option<Endpoint>,
+}
+
+/// The currencies recognised by their codes.
+const CODES: [&str; 12] = [
+    "GBP", "EUR", "USD", "PLN", "CHF", "CAD", "AUD", "JPY", "SEK", "NOK", "DKK", "CZK",
+];
+const SYMBOLS: &str = r"[£€$]|zł";
+const AMOUNT: &str = r"\d(?:[\d.,]*\d)?";
+const DATE: &str = r"\d{4}-\d{2}-\d{2}|\d{1,2}[/.-]\d{1,2}[/.-]\d{2,4}|\d{1,2}(?:st|nd|rd|th)?\s+[a-z]{3,9}\.?,?\s+\d{4}|[a-z]{3,9}\.?\s+\d{1,2}(?:st|nd|rd|th)?,?\s+\d{4}";
+
+/// kartka's own rules, for the usual English wording, best first.
+fn builtin_rules() -> Vec<Rule> {
+    let rule = |field, pattern: String| Rule { field, pattern };
+    let currency = format!(r"{SYMBOLS}|\b(?:{})\b", CODES.join("|"));
+    // the currency can go either side, and is kept so it can be picked out of the total
+    let amount = format!(r"(?:{currency})?\s?{AMOUNT}(?:\s?(?:{currency}))?");
+    vec![
+        rule(
+            Field::Total,
+            format!(r"\b(?:amount due|balance due|total due|amount payable|total to pay|grand total)\b\s*:?\s*({amount})"),
+        ),
+        rule(
+            Field::Total,
+            format!(r"\btotal\b(?: amount)?(?: \(?inc[a-z.]* vat\)?)?\s*:?\s*({amount})"),
+        ),
+        rule(Field::Currency, currency.clone()),
+        rule(
+            Field::InvoiceNumber,
+            r"\binvoice\s*(?:no\.?|number|num\.?|#|ref(?:erence)?)\s*:?\s*([a-z0-9][a-z0-9/_-]*\d[a-z0-9/_-]*)"
+                .to_string(),
+        ),
+        rule(
+            Field::DueDate,
+            format!(r"\b(?:due date|payment due(?: date)?|due by|pay by|due on|due)\b\s*:?\s*({DATE})"),
+        ),
+    ]
+}
+
+pub(crate) fn compile(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .context(format!("bad extraction rule {pattern:?}"))
+}
+
+/// Picks out what `rules` can find in `text`: for each field, the first rule with a match that
+/// makes sense wins.
+fn apply_rules(rules: &[Rule], text: &str) -> Result<Fields> {
+    let mut fields = Fields::default();
+    for rule in rules {
+        if fields.has(rule.field) {
+            continue;
+        }
+        let regex = compile(&rule.pattern)?;
+        for captures in regex.captures_iter(text) {
+            let value = captures.get(1).or_else(|| captures.get(0));
+            if value.is_some_and(|it| fields.set(rule.field, it.as_str())) {
+                break;
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// How much of a document's text is sent to the LLM; totals and due dates are rarely further in.
+const MAX_CHARS: usize = 12_000;
+
+const PROMPT: &str = "Pull these fields out of this scanned receipt or invoice: {fields}. \
+The total is the final amount to pay, the currency its ISO 4217 code, and dates are YYYY-MM-DD. \
+Reply with just a JSON object with those keys, using null for anything the document doesn't say.";
+
+/// Asks `llm` for `missing` fields in `text`.
+fn ask(llm: &Endpoint, text: &str, missing: &[Field]) -> Result<Fields> {
+    let text: String = text.chars().take(MAX_CHARS).collect();
+    let names: Vec<_> = missing.iter().map(|it| it.name()).collect();
+    let request = json!({
+        "model": llm.model,
+        "messages": [
+            { "role": "system", "content": PROMPT.replace("{fields}", &names.join(", ")) },
+            { "role": "user", "content": text },
+        ],
+    });
+    let response = llm.post("chat/completions", &request)?;
+    let Some(reply) = response["choices"][0]["message"]["content"].as_str() else {
+        bail!("{} didn't send any fields back", llm.endpoint);
+    };
+
+    // models like to wrap JSON in a code block
+    let json = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => bail!("{} didn't send JSON back: {reply}", llm.endpoint),
+    };
+    let found: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).context(format!("parsing {json}"))?;
+    let mut fields = Fields::default();
+    for field in missing {
+        let value = match &found.get(field.name()) {
+            Some(serde_json::Value::String(it)) => it.clone(),
+            Some(serde_json::Value::Number(it)) => it.to_string(),
+            _ => continue,
+        };
+        fields.set(*field, &value);
+    }
+    Ok(fields)
+}
+
+/// An amount like `£1,234.56` or `1.234,56 €` as `1234.56`.
+fn parse_amount(value: &str) -> Option<String> {
+    let digits: String = value
+        .chars()
+        .filter(|it| it.is_ascii_digit() || matches!(it, '.' | ','))
+        .collect();
+    let digits = digits.trim_matches(|it| it == '.' || it == ',');
+    // whichever of . and , comes last is the decimal point, if it's followed by pence
+    let (whole, fraction) = match digits.rfind(['.', ',']) {
+        Some(at) if digits.len() - at - 1 == 2 => (&digits[..at], &digits[at + 1..]),
+        _ => (digits, ""),
+    };
+    let whole: String = whole.chars().filter(char::is_ascii_digit).collect();
+    if whole.is_empty() {
+        return None;
+    }
+    let whole = whole.trim_start_matches('0');
+    let whole = if whole.is_empty() { "0" } else { whole };
+    Some(match fraction {
+        "" => whole.to_string(),
+        fraction => format!("{whole}.{fraction}"),
+    })
+}
+
+/// The ISO 4217 code for a currency symbol or code in `value`.
+fn parse_currency(value: &str) -> Option<String> {
+    if value.contains('£') {
+        return Some("GBP".to_string());
+    }
+    if value.contains('€') {
+        return Some("EUR".to_string());
+    }
+    if value.contains('$') {
+        return Some("USD".to_string());
+    }
+    if value.to_lowercase().contains("zł") {
+        return Some("PLN".to_string());
+    }
+    value
+        .split(|it: char| !it.is_ascii_alphabetic())
+        .map(|it| it.to_uppercase())
+        .find(|it| CODES.contains(&it.as_str()))
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// A date like `2024-03-31`, `31/03/2024`, `31.03.24`, `31 March 2024` or `March 31, 2024`.
+/// Dates written with numbers are read day first, as they are in Britain and Europe.
+fn parse_date(value: &str) -> Option<jiff::civil::Date> {
+    let value = value.trim();
+    if let Ok(date) = jiff::civil::Date::strptime("%Y-%m-%d", value) {
+        return Some(date);
+    }
+    let numbers: Vec<i16> = value
+        .split(|it: char| !it.is_ascii_digit())
+        .filter_map(|it| it.parse().ok())
+        .collect();
+    let month = value
+        .split(|it: char| !it.is_alphabetic())
+        .filter(|it| it.len() >= 3)
+        .find_map(|word| {
+            let word = word.to_lowercase();
+            MONTHS.iter().position(|it| word.starts_with(it))
+        });
+    let (day, month, year) = match (month, numbers.as_slice()) {
+        (Some(month), [day, year]) => (*day, month as i16 + 1, *year),
+        (None, [day, month, year]) => (*day, *month, *year),
+        _ => return None,
+    };
+    let year = if year < 100 { 2000 + year } else { year };
+    jiff::civil::Date::new(year, month.try_into().ok()?, day.try_into().ok()?).ok()
+}
+
+impl Kartka {
+    /// Picks the fields out of `text`, with the rules and then the LLM if one's set up.
+    fn extract(&self, extraction: &Extraction, text: &str) -> Result<Fields> {
+        let mut rules = extraction.rules.clone();
+        rules.extend(builtin_rules());
+        let mut fields = apply_rules(&rules, text)?;
+
+        let missing = fields.missing();
+        if let (Some(llm), false) = (&extraction.llm, missing.is_empty()) {
+            fields.fill(ask(llm, text, &missing)?);
+        }
+        Ok(fields)
+    }
+
+    /// Writes the fields found in `text` into `id`'s metadata, if `[extraction]` is set up. Not
+    /// finding them is no reason to fail a scan, so problems are only warned about.
+    pub(crate) fn save_fields(&self, id: &str, text: &str) {
+        let Some(extraction) = &self.extraction else {
+            return;
+        };
+        let saved = self
+            .extract(extraction, text)
+            .and_then(|fields| self.update_metadata(id, |meta| meta.fields = fields));
+        if let Err(e) = saved {
+            warn!("couldn't pick the fields out of {id}: {e:#}");
+        }
+    }
+
+    /// Picks the fields out of `ids`, or with none given every document that hasn't any yet.
+    /// Works without `[extraction]` too, with just kartka's own rules.
+    pub fn extract_fields(&self, ids: &[String]) -> Result<()> {
+        let extraction = self.extraction.clone().unwrap_or_default();
+        let ids = if ids.is_empty() {
+            let mut missing = vec![];
+            for id in self.documents()? {
+                if self.metadata(&id)?.fields.is_empty() {
+                    missing.push(id);
+                }
+            }
+            missing
+        } else {
+            ids.to_vec()
+        };
+
+        let mut found = 0;
+        for (i, id) in ids.iter().enumerate() {
+            if self.dry_run {
+                println!("would pick the fields out of {id}");
+                continue;
+            }
+            info!("({} / {}) reading the fields of {id}..", i + 1, ids.len());
+            let fields = self
+                .extract(&extraction, &self.text(id)?)
+                .context(format!("picking the fields out of {id}"))?;
+            if !fields.is_empty() {
+                found += 1;
+            }
+            self.update_metadata(id, |meta| meta.fields = fields)?;
+        }
+        if !self.dry_run {
+            self.commit_index(&format!("Extract fields from {} documents", ids.len()));
+            info!("found fields in {found} of {} documents", ids.len());
+        }
+        Ok(())
+    }
+
+    /// Writes a CSV of every document with any fields to `out`, oldest first, for opening in a
+    /// spreadsheet as an expense ledger.
+    pub fn export_csv(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(
+            out,
+            "id,date,type,correspondent,tags,total,currency,invoice_number,due_date"
+        )?;
+        for id in self.documents()? {
+            if self.is_old_version(&id)? {
+                continue;
+            }
+            let meta = self.metadata(&id)?;
+            if meta.fields.is_empty() {
+                continue;
+            }
+            let fields = &meta.fields;
+            let row = [
+                Some(id.clone()),
+                document_date(&id).map(|it| it.to_string()),
+                meta.doc_type.clone(),
+                meta.correspondent.clone(),
+                Some(meta.tags.join(";")),
+                fields.total.clone(),
+                fields.currency.clone(),
+                fields.invoice_number.clone(),
+                fields.due_date.map(|it| it.to_string()),
+            ];
+            let row: Vec<_> = row
+                .iter()
+                .map(|it| csv_field(it.as_deref().unwrap_or_default()))
+                .collect();
+            writeln!(out, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// `value` quoted for CSV if it needs to be.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
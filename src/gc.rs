@@ -0,0 +1,146 @@
+//! `kartka gc`: tidying up after documents were deleted from, or put on, the remote without
+//! going through kartka. Otherwise a document deleted in Dropbox keeps turning up in searches
+//! with nothing to open, and one dropped into Dropbox by hand is never found at all.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use eyre::Result;
+use tracing::warn;
+
+use crate::{audit::Operation, progress, remote_manifest::Change, Kartka};
+
+/// How `kartka gc` went.
+#[derive(Debug, Default)]
+pub struct Collected {
+    /// Documents only the index or only the remote had.
+    pub found: usize,
+    /// How many of those were put right.
+    pub fixed: usize,
+}
+
+/// How to put a document that's only in the index right.
+enum Fix {
+    /// Upload its copy from `local_archive_dir` again.
+    Reupload(PathBuf),
+    /// There's nothing left of it to open, so take it out of the index.
+    Remove,
+}
+
+impl Kartka {
+    /// Finds documents in the index that aren't on the remote, and ones on the remote that
+    /// aren't in the index, and offers to put each right. One only in the index is uploaded
+    /// again from its local copy if it has one, and otherwise removed from the index; one only
+    /// on the remote is downloaded and indexed. With `yes` they're all put right without
+    /// asking. Documents in cold storage are where they should be, so aren't looked at.
+    pub fn gc(&self, yes: bool) -> Result<Collected> {
+        let remote: HashSet<_> = self.remote_files()?.into_iter().collect();
+        let indexed = self.documents()?;
+        let mut index_only = vec![];
+        for id in &indexed {
+            if !remote.contains(id) && !self.is_archived(id)? {
+                index_only.push(id.clone());
+            }
+        }
+        let indexed: HashSet<_> = indexed.into_iter().collect();
+        let mut remote_only: Vec<_> = remote.difference(&indexed).cloned().collect();
+        remote_only.sort();
+        if !remote_only.is_empty() {
+            // one protected with a password can't be read to index it, so is where it should be
+            let known = self.remote_manifest()?.unwrap_or_default().documents;
+            remote_only.retain(|id| !known.get(id).is_some_and(|meta| meta.protected));
+        }
+
+        let mut collected = Collected {
+            found: index_only.len() + remote_only.len(),
+            fixed: 0,
+        };
+        for id in &index_only {
+            println!("INDEX ONLY   {id}");
+            let fix = match self.local_copy(id) {
+                Some(local) => Fix::Reupload(local),
+                None => Fix::Remove,
+            };
+            let question = match &fix {
+                Fix::Reupload(_) => {
+                    format!("Upload {id} to {} again from its local copy?", self.remote)
+                }
+                Fix::Remove => {
+                    format!("Remove {id} from the index, as there's nothing left of it to open?")
+                }
+            };
+            if self.dry_run {
+                println!("would ask: {question}");
+                continue;
+            }
+            if !yes && !self.confirm(&question, false)? {
+                continue;
+            }
+            let fixed = match &fix {
+                Fix::Reupload(local) => self.upload_document(local, id),
+                Fix::Remove => self.remove_stale(id),
+            };
+            match fixed {
+                Ok(()) => collected.fixed += 1,
+                Err(e) => warn!("couldn't put {id} right: {e:#}"),
+            }
+        }
+
+        for id in &remote_only {
+            println!("REMOTE ONLY  {id}");
+            let question = format!("Add {id} to the index?");
+            if self.dry_run {
+                println!("would ask: {question}");
+                continue;
+            }
+            if !yes && !self.confirm(&question, false)? {
+                continue;
+            }
+            match self.index_from_remote(id) {
+                Ok(()) => collected.fixed += 1,
+                Err(e) => warn!("couldn't add {id} to the index: {e:#}"),
+            }
+        }
+
+        if collected.fixed > 0 {
+            self.publish_manifest()?;
+            self.commit_index(&format!(
+                "Put right {} documents out of step with the remote",
+                collected.fixed
+            ));
+        }
+        Ok(collected)
+    }
+
+    /// Takes `id` out of the index, its PDF having gone from the remote.
+    fn remove_stale(&self, id: &str) -> Result<()> {
+        let removed = self.remove_from_index(id).map(|_| ());
+        self.audit(
+            Operation::Delete,
+            id,
+            Some("gone from the remote".to_string()),
+            removed.as_ref().err(),
+        );
+        removed?;
+        self.note_remote_change(Change::Removed(id.to_string()));
+        Ok(())
+    }
+
+    /// Downloads `id` from the remote and adds it to the index, as `hydrate` would.
+    fn index_from_remote(&self, id: &str) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pdf = dir.path().join(id);
+        let status = progress::spinner(&format!("{id}: downloading"));
+        let hydrated = self
+            .download(id, &pdf)
+            .and_then(|()| self.hydrate_one(id, &pdf, None, &status));
+        status.finish_and_clear();
+        self.audit(Operation::Hydrate, id, None, hydrated.as_ref().err());
+        if hydrated.is_err() {
+            self.roll_back(id);
+            return hydrated;
+        }
+        // its manifest was made without it
+        self.note_remote_change(Change::Added(id.to_string()));
+        Ok(())
+    }
+}
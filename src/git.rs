@@ -0,0 +1,111 @@
+//! Keeping the index in git, configured as `[git]` in kartka.toml. Kartka commits after every
+//! change it makes, with a message saying what it did, so there's a history to look back
+//! through and diff, and with `push` a copy kept up to date on another machine.
+
+use std::{ffi::OsStr, fs, process::Command};
+
+use eyre::Result;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{index::Backend, pdf, store, Kartka};
+
+/// What's left out of the repository: locks, caches and half-finished work that only make
+/// sense on this machine, thumbnails, which are made again when they're missing, and SQLite's
+/// write-ahead log, which is checkpointed into the database before every commit.
+const GITIGNORE: &str = "\
+kartka.db-*
+.kartka/lock
+.kartka/*.tmp
+.kartka/hydrate.state
+.kartka/ocr-cache/
+.kartka/thumbnails/
+";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Git {
+    /// Push to the branch's upstream after every commit.
+    #[serde(default)]
+    pub push: bool,
+}
+
+impl Kartka {
+    /// Commits everything that's changed in the index with `message`, if it's kept in git.
+    /// Failing to is only warned about, since the change itself has already been made.
+    pub(crate) fn commit_index(&self, message: &str) {
+        let Some(git) = &self.git else {
+            return;
+        };
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = self.try_commit_index(git, message) {
+            warn!("couldn't commit the index to git: {e:#}");
+        }
+    }
+
+    fn try_commit_index(&self, git: &Git, message: &str) -> Result<()> {
+        if !self.index().join(".git").exists() {
+            info!("making the index a git repository..");
+            self.git(["init", "-q"])?;
+        }
+        let gitignore = self.index().join(".gitignore");
+        let ignored = fs::read_to_string(&gitignore).unwrap_or_default();
+        // repositories made by older versions are missing newer lines
+        let missing: String = GITIGNORE
+            .lines()
+            .filter(|line| !ignored.lines().any(|it| it == *line))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        if !missing.is_empty() {
+            let separator = if ignored.is_empty() || ignored.ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            fs::write(&gitignore, format!("{ignored}{separator}{missing}"))?;
+        }
+
+        {
+            // so nothing changes between staging and committing
+            let _transaction = store::transaction(self.index())?;
+            if self.index_backend == Backend::Sqlite {
+                self.db()?.checkpoint()?;
+            }
+            self.git(["add", "-A"])?;
+            let unchanged = Command::new("git")
+                .arg("-C")
+                .arg(self.index())
+                .args(["diff", "--cached", "--quiet"])
+                .status()?
+                .success();
+            if unchanged {
+                return Ok(());
+            }
+            self.git(["commit", "-q", "-m", message])?;
+        }
+
+        if git.push {
+            self.git(["push", "-q"])?;
+        }
+        Ok(())
+    }
+
+    fn git<I, S>(&self, args: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<_> = args.into_iter().collect();
+        let what = format!(
+            "git {}",
+            args.first()
+                .map_or("".into(), |it| it.as_ref().to_string_lossy())
+        );
+        pdf::run(
+            Command::new("git").arg("-C").arg(self.index()).args(&args),
+            &what,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Keeping documents in Google Drive. Once signed in, rclone uploads, lists and downloads them
+//! as it does for any other remote; this sets up the rclone remote, signing in with Google's
+//! device flow so it works on a machine without a browser, like a server kartka runs on.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, eyre, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{config, storage::remote_name, Kartka};
+
+const DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const FILES_URL: &str = "https://www.googleapis.com/drive/v3/files";
+
+/// Only the files kartka makes itself, which is the widest access Google gives devices.
+const SCOPE: &str = "https://www.googleapis.com/auth/drive.file";
+
+/// Where in Google Drive documents go, and the OAuth client to sign in with, configured as
+/// `[google_drive]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoogleDrive {
+    /// The id of the folder documents go in, from the end of its URL. It has to be one kartka
+    /// made, since it can't see any others; `kartka google-drive` makes one if it's not set.
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    /// An OAuth client of the "TVs and Limited Input devices" kind, from the Google Cloud
+    /// console.
+    pub client_id: String,
+    /// A shell command that prints the client's secret.
+    pub client_secret_command: String,
+}
+
+/// What Google says to show to whoever's signing in.
+#[derive(Debug, Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_url: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+impl Kartka {
+    /// Signs in to Google Drive, then sets `remote` up in rclone as a Drive remote with the
+    /// token, so documents go in `folder_id` - or in a new folder, if that's not set, whose id
+    /// is returned to go in the config.
+    pub fn connect_google_drive(&self) -> Result<Option<String>> {
+        let Some(drive) = &self.google_drive else {
+            bail!("no [google_drive] configured");
+        };
+        let secret =
+            config::secret_from_command(&drive.client_secret_command, "client_secret_command")?;
+        let token = sign_in(drive, &secret)?;
+
+        let (folder_id, made) = match &drive.folder_id {
+            Some(id) => (id.clone(), None),
+            None => {
+                let id = make_folder(&token)?;
+                info!("made a folder called kartka in Google Drive");
+                (id.clone(), Some(id))
+            }
+        };
+
+        // written straight into rclone's config rather than passed to `rclone config create`,
+        // which would show the secret and token to anyone listing processes
+        let name = remote_name(&self.remote);
+        let section = format!(
+            "[{name}]\ntype = drive\nscope = drive.file\nclient_id = {}\nclient_secret = {secret}\n\
+             root_folder_id = {folder_id}\ntoken = {token}\n",
+            drive.client_id
+        );
+        let path = self.rclone_config_file()?;
+        let config = match fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(format!("reading {path:?}")),
+        };
+        if config.starts_with("# Encrypted rclone configuration File") {
+            bail!(
+                "rclone's config is encrypted, so kartka can't set `{name}` up in it - add it with \
+                 `rclone config` instead"
+            );
+        }
+        write_private(&path, &with_section(&config, name, &section))?;
+        info!("set up the rclone remote `{name}`");
+        Ok(made)
+    }
+
+    /// Where rclone keeps its config.
+    fn rclone_config_file(&self) -> Result<PathBuf> {
+        let output = self.tools.run(
+            Command::new("rclone").args(["config", "file"]),
+            "finding rclone's config",
+        )?;
+        // it says `Configuration file is stored at:`, then the path
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .rfind(|it| !it.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| eyre!("rclone didn't say where its config is"))
+    }
+}
+
+/// `config` with the remote `name`'s section swapped for `section`, or `section` added on the
+/// end if it has none.
+fn with_section(config: &str, name: &str, section: &str) -> String {
+    let header = format!("[{name}]");
+    let mut kept = String::new();
+    let mut in_section = false;
+    for line in config.lines() {
+        if line.trim_start().starts_with('[') {
+            in_section = line.trim() == header;
+        }
+        if !in_section {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    let kept = kept.trim_end();
+    match kept.is_empty() {
+        true => section.to_string(),
+        false => format!("{kept}\n\n{section}"),
+    }
+}
+
+/// Replaces `path` with `contents` only its owner can read, as rclone keeps its config.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("creating {dir:?}"))?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&tmp_path)
+        .and_then(|mut it| it.write_all(contents.as_bytes()))
+        .context(format!("writing {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path).context(format!("writing {path:?}"))?;
+    Ok(())
+}
+
+/// Signs in with the device flow, returning the token the way rclone keeps them.
+fn sign_in(drive: &GoogleDrive, secret: &str) -> Result<Value> {
+    let code: DeviceCode = ureq::post(DEVICE_CODE_URL)
+        .send_form([("client_id", drive.client_id.as_str()), ("scope", SCOPE)])
+        .context("asking Google for a sign-in code")?
+        .body_mut()
+        .read_json()
+        .context("reading Google's sign-in code")?;
+    println!(
+        "To let kartka use Google Drive, go to {} and enter {}",
+        code.verification_url, code.user_code
+    );
+
+    // Google says to wait until it's been done, and how often to check
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let deadline = Instant::now() + Duration::from_secs(code.expires_in);
+    let mut interval = Duration::from_secs(code.interval.max(1));
+    while Instant::now() < deadline {
+        thread::sleep(interval);
+        let response: Value = agent
+            .post(TOKEN_URL)
+            .send_form([
+                ("client_id", drive.client_id.as_str()),
+                ("client_secret", secret),
+                ("device_code", code.device_code.as_str()),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ])
+            .context("asking Google whether it's been done")?
+            .body_mut()
+            .read_json()
+            .context("reading Google's answer")?;
+        match response["error"].as_str() {
+            None => return rclone_token(&response),
+            Some("authorization_pending") => continue,
+            Some("slow_down") => interval += Duration::from_secs(5),
+            Some("access_denied") => bail!("Google Drive access wasn't allowed"),
+            Some(error) => bail!("Google wouldn't sign in: {error}"),
+        }
+    }
+    bail!("the sign-in code ran out before it was entered - run it again")
+}
+
+/// Google's `token` response as rclone keeps tokens, with when it runs out rather than how long
+/// it lasts.
+fn rclone_token(response: &Value) -> Result<Value> {
+    let field = |name: &str| {
+        response[name]
+            .as_str()
+            .ok_or_else(|| eyre!("Google's token has no {name}"))
+    };
+    let lasts = response["expires_in"].as_i64().unwrap_or(3600);
+    let expiry = jiff::Timestamp::now() + jiff::SignedDuration::from_secs(lasts);
+    Ok(json!({
+        "access_token": field("access_token")?,
+        "token_type": field("token_type")?,
+        "refresh_token": field("refresh_token")?,
+        "expiry": expiry.to_string(),
+    }))
+}
+
+/// Makes a folder called kartka at the top of the Drive `token` is for, returning its id.
+fn make_folder(token: &Value) -> Result<String> {
+    let access_token = token["access_token"].as_str().unwrap_or_default();
+    let folder: Value = ureq::post(FILES_URL)
+        .header("Authorization", format!("Bearer {access_token}"))
+        .send_json(json!({
+            "name": "kartka",
+            "mimeType": "application/vnd.google-apps.folder",
+        }))
+        .context("making a folder in Google Drive")?
+        .body_mut()
+        .read_json()
+        .context("reading the folder Google Drive made")?;
+    folder["id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("Google Drive didn't say what the folder's id is"))
+}
@@ -0,0 +1,96 @@
+//! Pointing at where a search matched on the page itself, using the word boxes kept as hOCR, so
+//! a match can be checked at a glance rather than by reading the whole page.
+
+use std::{path::Path, process::Command};
+
+use eyre::{bail, Result};
+
+use crate::{hocr, search, Kartka};
+
+/// The page that was drawn, and how many words on it matched.
+#[derive(Debug)]
+pub struct Highlighted {
+    /// Counting from 1.
+    pub page: usize,
+    pub hits: usize,
+}
+
+impl Kartka {
+    /// Finds the page of `id` where the words in `query` turn up most, and renders it to the
+    /// PNG `out`, at most `width` pixels wide, with a box round each match. Returns `None` if
+    /// no page matches. Words are matched as `search` matches them, and only pages that were
+    /// OCR'd with `hocr` set can be searched.
+    pub fn highlight(
+        &self,
+        id: &str,
+        query: &str,
+        width: u32,
+        out: &Path,
+    ) -> Result<Option<Highlighted>> {
+        let pages = self.hocr_pages(id)?;
+        if pages.is_empty() {
+            bail!(
+                "{id} has no word positions kept - set `hocr = true` under [ocr] and read it again"
+            );
+        }
+        let terms = search::terms(query, self.exact_search);
+        if terms.is_empty() {
+            bail!("nothing to look for in {query:?}");
+        }
+
+        let best = pages
+            .iter()
+            .map(|(page, hocr)| {
+                let (size, words) = hocr::parse(hocr);
+                let hits: Vec<_> = words
+                    .into_iter()
+                    .filter(|word| {
+                        let word = search::comparable(&word.text, self.exact_search);
+                        terms.iter().any(|term| word.contains(term.as_str()))
+                    })
+                    .collect();
+                (*page, size, hits)
+            })
+            // the first of the pages with the most hits
+            .max_by(|(a_page, _, a), (b_page, _, b)| {
+                a.len().cmp(&b.len()).then(b_page.cmp(a_page))
+            });
+        let Some((page, Some((page_width, page_height)), hits)) = best else {
+            bail!("{id}'s word positions don't say how big the page is");
+        };
+        if hits.is_empty() {
+            return Ok(None);
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let pdf_path = temp_dir.path().join(id);
+        self.fetch(id, &pdf_path)?;
+        let mut magick = Command::new("magick");
+        magick
+            .arg("-density")
+            .arg("150")
+            .arg(format!("{}[{}]", pdf_path.display(), page - 1))
+            .arg("-background")
+            .arg("white")
+            .arg("-flatten")
+            // the page at the size it was OCR'd at, so the boxes land where they should
+            .arg("-resize")
+            .arg(format!("{page_width}x{page_height}!"))
+            .args(["-fill", "#ffeb3b60", "-stroke", "#e53935"])
+            .arg("-strokewidth")
+            .arg((page_width / 400).max(2).to_string());
+        for word in &hits {
+            let [left, top, right, bottom] = word.bbox;
+            magick
+                .arg("-draw")
+                .arg(format!("rectangle {left},{top} {right},{bottom}"));
+        }
+        magick.arg("-resize").arg(format!("{width}x>")).arg(out);
+        self.tools.run(&mut magick, "drawing the matches")?;
+
+        Ok(Some(Highlighted {
+            page,
+            hits: hits.len(),
+        }))
+    }
+}
@@ -0,0 +1,265 @@
+//! Where OCR found each word on each page, kept as hOCR in the index's `.kartka/hocr` when
+//! `hocr = true` is set under `[ocr]`. hOCR is HTML with the bounding box of every block,
+//! paragraph, line and word in its `title`s, which is what's needed to point at a match on the
+//! page, or to lay text over a scan in the right place.
+
+use std::{fs, io, path::PathBuf};
+
+use eyre::{Context, Result};
+use tracing::warn;
+
+use crate::Kartka;
+
+const HOCR_DIR: &str = ".kartka/hocr";
+
+/// Lays tesseract's TSV output out as an hOCR page.
+pub fn from_data(data: &[rusty_tesseract::Data]) -> String {
+    let mut hocr = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \
+         \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\">\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\">\n<head>\n<title></title>\n\
+         <meta http-equiv=\"Content-Type\" content=\"text/html;charset=utf-8\"/>\n\
+         <meta name=\"ocr-system\" content=\"tesseract\"/>\n\
+         <meta name=\"ocr-capabilities\" content=\"ocr_page ocr_carea ocr_par ocr_line ocrx_word\"/>\n\
+         </head>\n<body>\n",
+    );
+    // the closing tags of the page, block, paragraph and line being written, outermost first
+    let mut open: Vec<(i32, &str)> = vec![];
+    for row in data {
+        let (left, top) = (row.left, row.top);
+        let bbox = format!(
+            "bbox {left} {top} {} {}",
+            left + row.width,
+            top + row.height
+        );
+        while open.last().is_some_and(|(level, _)| *level >= row.level) {
+            let (level, close) = open.pop().expect("just checked");
+            push_line(&mut hocr, level, close);
+        }
+        let (tag, class, id) = match row.level {
+            1 => ("div", "ocr_page", format!("page_{}", row.page_num)),
+            2 => ("div", "ocr_carea", format!("block_{}", row.block_num)),
+            3 => (
+                "p",
+                "ocr_par",
+                format!("par_{}_{}", row.block_num, row.par_num),
+            ),
+            4 => (
+                "span",
+                "ocr_line",
+                format!("line_{}_{}_{}", row.block_num, row.par_num, row.line_num),
+            ),
+            _ => {
+                if row.text.trim().is_empty() {
+                    continue;
+                }
+                let indent = "  ".repeat(open.len());
+                hocr.push_str(&format!(
+                    "{indent}<span class='ocrx_word' title='{bbox}; x_wconf {}'>{}</span>\n",
+                    row.conf.max(0.0).round(),
+                    escape(&row.text)
+                ));
+                continue;
+            }
+        };
+        let indent = "  ".repeat(open.len());
+        hocr.push_str(&format!(
+            "{indent}<{tag} class='{class}' id='{id}' title='{bbox}'>\n"
+        ));
+        open.push((
+            row.level,
+            match tag {
+                "div" => "</div>",
+                "p" => "</p>",
+                _ => "</span>",
+            },
+        ));
+    }
+    while let Some((level, close)) = open.pop() {
+        push_line(&mut hocr, level, close);
+    }
+    hocr.push_str("</body>\n</html>\n");
+    hocr
+}
+
+/// A word on an hOCR page, and the box around it in the page image's pixels.
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    /// Left, top, right and bottom.
+    pub bbox: [u32; 4],
+}
+
+/// The size of an hOCR page in pixels, and the words on it. Anything that isn't a word, or
+/// can't be made sense of, is skipped.
+pub fn parse(hocr: &str) -> (Option<(u32, u32)>, Vec<Word>) {
+    let size = find_class(hocr, "ocr_page")
+        .and_then(|it| element_at(&hocr[it..]))
+        .and_then(|(bbox, _)| Some((bbox?[2], bbox?[3])));
+    let mut words = vec![];
+    let mut rest = hocr;
+    while let Some(start) = find_class(rest, "ocrx_word") {
+        rest = &rest[start + 1..];
+        let Some((Some(bbox), inner)) = element_at(rest) else {
+            continue;
+        };
+        let text = unescape(&strip_tags(inner));
+        if !text.trim().is_empty() {
+            words.push(Word { text, bbox });
+        }
+    }
+    (size, words)
+}
+
+/// Where the first element with `class` starts its `class` attribute in `hocr`.
+fn find_class(hocr: &str, class: &str) -> Option<usize> {
+    // tesseract quotes attributes with ' but other tools use "
+    let single = hocr.find(&format!("class='{class}'"));
+    let double = hocr.find(&format!("class=\"{class}\""));
+    single.into_iter().chain(double).min()
+}
+
+/// The bbox in the `title` of the element whose opening tag `tag` is part way through, and
+/// everything from the end of that tag to the next `</span>`.
+fn element_at(tag: &str) -> Option<(Option<[u32; 4]>, &str)> {
+    let end = tag.find('>')?;
+    let bbox = tag[..end].split_once("bbox ").and_then(|(_, it)| {
+        let numbers: Vec<u32> = it
+            .split(|c: char| !c.is_ascii_digit())
+            .take(4)
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        numbers.try_into().ok()
+    });
+    let inner = &tag[end + 1..];
+    Some((bbox, &inner[..inner.find("</span>").unwrap_or(inner.len())]))
+}
+
+/// `html` without any `<tags>` in it, like the `<strong>` tesseract puts round bold words.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn push_line(hocr: &mut String, level: i32, line: &str) {
+    hocr.push_str(&"  ".repeat(level as usize - 1));
+    hocr.push_str(line);
+    hocr.push('\n');
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Kartka {
+    /// Where `id`'s hOCR is kept, a file a page.
+    fn hocr_dir(&self, id: &str) -> PathBuf {
+        self.index().join(HOCR_DIR).join(id)
+    }
+
+    fn hocr_path(&self, id: &str, page: usize) -> PathBuf {
+        self.hocr_dir(id).join(format!("page-{page:04}.hocr"))
+    }
+
+    /// Keeps `pages`' hOCR for `id`, replacing any it had. Like thumbnails, it's no reason to
+    /// fail a scan, so problems are only warned about.
+    pub(crate) fn save_hocr(&self, id: &str, pages: &[Option<String>]) {
+        let saved = self.remove_hocr(id).and_then(|()| {
+            if pages.iter().all(Option::is_none) {
+                return Ok(());
+            }
+            fs::create_dir_all(self.hocr_dir(id))?;
+            for (i, hocr) in pages.iter().enumerate() {
+                if let Some(hocr) = hocr {
+                    fs::write(self.hocr_path(id, i + 1), hocr)?;
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = saved {
+            warn!("couldn't keep the hOCR of {id}: {e:#}");
+        }
+    }
+
+    /// The hOCR of page `page` (counting from 1) of `id`, if it was kept.
+    pub fn hocr(&self, id: &str, page: usize) -> Result<Option<String>> {
+        let path = self.hocr_path(id, page);
+        match fs::read_to_string(&path) {
+            Ok(it) => Ok(Some(it)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("reading {path:?}")),
+        }
+    }
+
+    /// Every page of `id` that has hOCR kept, by page number.
+    pub fn hocr_pages(&self, id: &str) -> Result<Vec<(usize, String)>> {
+        let dir = self.hocr_dir(id);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut pages = vec![];
+        for entry in dir.read_dir().context(format!("reading {dir:?}"))? {
+            let path = entry?.path();
+            let page = path
+                .file_stem()
+                .and_then(|it| it.to_str())
+                .and_then(|it| it.strip_prefix("page-"))
+                .and_then(|it| it.parse().ok());
+            if let Some(page) = page {
+                pages.push((
+                    page,
+                    fs::read_to_string(&path).context(format!("reading {path:?}"))?,
+                ));
+            }
+        }
+        pages.sort_by_key(|(page, _)| *page);
+        Ok(pages)
+    }
+
+    pub(crate) fn remove_hocr(&self, id: &str) -> Result<()> {
+        let dir = self.hocr_dir(id);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).context(format!("removing {dir:?}"))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rename_hocr(&self, id: &str, new_id: &str) -> Result<()> {
+        let dir = self.hocr_dir(id);
+        if dir.exists() {
+            fs::rename(&dir, self.hocr_dir(new_id)).context(format!("renaming {dir:?}"))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! Bringing in documents from somewhere other than the scan dir: old scans lying around on disk,
+//! or PDFs from phone scanning apps. Each app has its own naming conventions, which are used to
+//! work out when the document was actually captured so it gets the right id and date.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use eyre::{bail, Context, Result};
+use jiff::{civil::DateTime, tz::TimeZone, Timestamp};
+use tracing::{info, warn};
+
+use crate::{
+    index::{unique_name, Ids},
+    integrity, pages, pdf,
+    tools::ToolRunner,
+    Kartka, KartkaError,
+};
+
+/// Where the documents being imported came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Any PDFs and images, which keep their file names in their ids.
+    Files,
+    /// The Google Drive app's scanner, which saves `Scanned_20240131-0915.pdf`.
+    GoogleDrive,
+    /// Scans shared from iOS Notes or the Files app, named `Scanned Document.pdf` and so on,
+    /// with the capture time in the PDF itself.
+    Ios,
+}
+
+impl FromStr for Source {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "files" => Ok(Source::Files),
+            "google-drive" | "gdrive" => Ok(Source::GoogleDrive),
+            "ios" => Ok(Source::Ios),
+            _ => bail!("unknown import source {s:?}, expected `files`, `google-drive` or `ios`"),
+        }
+    }
+}
+
+impl Source {
+    /// When the document at `path` was captured. Falls back to when the PDF says it was
+    /// created, or when the photo says it was taken, then to when the file was last modified.
+    pub fn captured(self, tools: &dyn ToolRunner, path: &Path) -> Result<Timestamp> {
+        if self == Source::GoogleDrive {
+            match google_drive_time(path) {
+                Some(time) => return Ok(time),
+                None => warn!("{path:?} isn't named like a Google Drive scan, guessing its date"),
+            }
+        }
+
+        if pdf::is_pdf(path) {
+            if let Some(time) = pdf::creation_date(tools, path)? {
+                return Ok(time);
+            }
+        } else if let Some(time) = pages::taken(path) {
+            return Ok(time);
+        }
+        let modified = fs::metadata(path)
+            .and_then(|it| it.modified())
+            .context(format!("reading modification time of {path:?}"))?;
+        Ok(Timestamp::try_from(modified)?)
+    }
+}
+
+/// The capture time from a Google Drive scan's name, like `Scanned_20240131-0915.pdf` or
+/// `Scanned_20240131-091502 (1).pdf`, which is in local time.
+fn google_drive_time(path: &Path) -> Option<Timestamp> {
+    let stamp = path.file_stem()?.to_str()?.strip_prefix("Scanned_")?;
+    let digits: String = stamp
+        .chars()
+        .take_while(|it| it.is_ascii_digit() || *it == '-')
+        .collect();
+    let time = match digits.len() {
+        13 => DateTime::strptime("%Y%m%d-%H%M", &digits),
+        15 => DateTime::strptime("%Y%m%d-%H%M%S", &digits),
+        _ => return None,
+    }
+    .ok()?;
+    Some(time.to_zoned(TimeZone::system()).ok()?.timestamp())
+}
+
+/// Whether `path` is a page image that can be imported.
+pub(crate) fn is_image(path: &Path) -> bool {
+    let extension = path.extension().and_then(|it| it.to_str());
+    extension.is_some_and(|it| {
+        ["png", "jpg", "jpeg", "tif", "tiff"].contains(&it.to_ascii_lowercase().as_str())
+    })
+}
+
+/// The documents to import: files as given, and the PDFs and images anywhere inside any
+/// directories (apart from hidden ones), in order.
+fn documents_in(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut documents = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            documents.push(path.clone());
+            continue;
+        }
+
+        let mut entries: Vec<_> = path
+            .read_dir()
+            .context(format!("reading {path:?}"))?
+            .map(|it| it.map(|it| it.path()))
+            .collect::<Result<_, _>>()?;
+        entries.sort();
+        for entry in entries {
+            let hidden = entry
+                .file_name()
+                .and_then(|it| it.to_str())
+                .is_none_or(|it| it.starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if entry.is_dir() {
+                documents.extend(documents_in(&[entry])?);
+            } else if pdf::is_pdf(&entry) || is_image(&entry) {
+                documents.push(entry);
+            }
+        }
+    }
+    Ok(documents)
+}
+
+/// The id for `path` when it was captured at `time`: `source` decides whether its file name is
+/// kept on the end, like `2019_03_04_10_00_00_boiler-warranty.pdf`.
+fn import_id(
+    ids: &Ids,
+    source: Source,
+    path: &Path,
+    time: Timestamp,
+    taken: &HashSet<String>,
+) -> String {
+    let name = path
+        .file_stem()
+        .map(|it| it.to_string_lossy())
+        .unwrap_or_default();
+    if source != Source::Files {
+        return ids.unique(taken, time);
+    }
+    named_id(ids, &name, time, taken)
+}
+
+/// The id for a document called `name` captured at `time`, with the name on the end of the
+/// timestamp, made safe for a file name. Just the timestamp if `name` is empty.
+pub(crate) fn named_id(ids: &Ids, name: &str, time: Timestamp, taken: &HashSet<String>) -> String {
+    let id = ids.id(time);
+    if name.is_empty() {
+        return unique_name(taken, id);
+    }
+
+    let name: String = name
+        .chars()
+        .map(|it| {
+            if it.is_alphanumeric() || it == '-' || it == '_' {
+                it
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let stem = id.trim_end_matches(".pdf");
+    unique_name(taken, format!("{stem}_{name}.pdf"))
+}
+
+/// The PDFs directly inside any directories in `paths`, and the files as given, in order.
+pub(crate) fn pdfs_in(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut pdfs = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            pdfs.push(path.clone());
+            continue;
+        }
+
+        let mut found = vec![];
+        for entry in path.read_dir().context(format!("reading {path:?}"))? {
+            let entry = entry?.path();
+            if pdf::is_pdf(&entry) && entry.is_file() {
+                found.push(entry);
+            }
+        }
+        found.sort();
+        pdfs.extend(found);
+    }
+    Ok(pdfs)
+}
+
+impl Kartka {
+    /// The hashes of the documents already in the index, as uploaded.
+    fn known_hashes(&self) -> Result<HashSet<String>> {
+        let mut hashes = HashSet::new();
+        for id in self.documents()? {
+            hashes.extend(self.metadata(&id)?.sha256);
+        }
+        Ok(hashes)
+    }
+
+    /// Indexes (OCRing if need be) and uploads the PDFs and images in `paths`, dating each by
+    /// when `source` says it was captured. Files that are byte-for-byte the same as a document
+    /// already in the index, or as one earlier in `paths`, are skipped; with `no_dupes`, so are
+    /// ones whose text looks like one already indexed.
+    pub fn import(&self, source: Source, paths: &[PathBuf], no_dupes: bool) -> Result<()> {
+        let documents = documents_in(paths)?;
+        if documents.is_empty() {
+            bail!("no PDFs or images to import");
+        }
+
+        let mut taken: HashSet<_> = self.documents()?.into_iter().collect();
+        let mut hashes = self.known_hashes()?;
+        let mut imported = 0;
+        let mut skipped = 0;
+        let mut failed = 0;
+        for (i, path) in documents.iter().enumerate() {
+            // encrypted documents are hashed as uploaded, so they won't match here, but the
+            // text check still catches them
+            let hash = integrity::sha256(path)?;
+            if hashes.contains(&hash) {
+                info!("skipping {path:?}, it's already been imported");
+                skipped += 1;
+                continue;
+            }
+
+            let id = match source.captured(self.tools.as_ref(), path) {
+                Ok(time) => import_id(&self.ids, source, path, time, &taken),
+                Err(e) => {
+                    warn!("couldn't date {path:?}: {e:#}");
+                    failed += 1;
+                    continue;
+                }
+            };
+            if self.dry_run {
+                println!("would import {path:?} as {id}");
+                taken.insert(id);
+                hashes.insert(hash);
+                continue;
+            }
+
+            info!(
+                "({} / {}) importing {path:?} as {id}..",
+                i + 1,
+                documents.len()
+            );
+            match self.import_one(path, &id, no_dupes) {
+                Ok(()) => {
+                    taken.insert(id);
+                    hashes.insert(hash);
+                    imported += 1;
+                }
+                Err(e) => {
+                    warn!("failed to import {path:?}: {e:#}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if imported > 0 {
+            self.publish_manifest()?;
+            self.commit_index(&format!("Import {imported} documents"));
+        }
+        if skipped > 0 {
+            info!("skipped {skipped} already imported");
+        }
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: documents.len(),
+            }
+            .into());
+        }
+        info!("done!");
+        Ok(())
+    }
+
+    /// Imports the PDF or image at `path` as `id`.
+    pub(crate) fn import_one(&self, path: &Path, id: &str, no_dupes: bool) -> Result<()> {
+        if pdf::is_pdf(path) {
+            return self.add_pdf_as(path, id, no_dupes);
+        }
+
+        // a lone image is a one-page document
+        let temp_dir = tempfile::tempdir()?;
+        let Some(name) = path.file_name() else {
+            bail!("{path:?} isn't a file");
+        };
+        fs::copy(path, temp_dir.path().join(name)).context(format!("copying {path:?}"))?;
+        self.add_pages_as(temp_dir.path(), id, no_dupes)
+    }
+}
@@ -0,0 +1,532 @@
+//! The index: the OCR output of every document, by id. By default that's one text file per
+//! document, named after its id, but it can be kept in SQLite instead (see [`Backend`]).
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use eyre::{bail, Context, Result};
+use jiff::{civil::Time, fmt::strtime, tz::TimeZone};
+use tracing::info;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Operation,
+    metadata::{self, Classification, Metadata},
+    ocr::Recognised,
+    remote_manifest::Change,
+    sqlite, Kartka,
+};
+
+/// How the index stores documents' text and metadata.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A text file per document, searched with ripgrep, with metadata in JSON sidecars.
+    #[default]
+    Files,
+    /// A single SQLite database, searched with FTS5.
+    Sqlite,
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Files => write!(f, "files"),
+            Backend::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UploadContent {
+    name: String,
+    content: String,
+}
+
+impl Kartka {
+    pub fn index(&self) -> &Path {
+        &self.index_dir
+    }
+
+    pub(crate) fn db(&self) -> Result<sqlite::Db> {
+        sqlite::open(self.index())
+    }
+
+    /// The ids of every document in the index.
+    pub fn documents(&self) -> Result<Vec<String>> {
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.documents();
+        }
+
+        let mut ids = vec![];
+        for entry in self.index().read_dir()? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if !name.starts_with('.') {
+                    ids.push(name.to_string());
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// The `count` documents most recently added to the index, scanned or hydrated, with when
+    /// they were added, newest first.
+    pub fn recent(&self, count: usize) -> Result<Vec<(String, jiff::Timestamp)>> {
+        let mut added = self.added()?;
+        added.sort_by_key(|(_, at)| std::cmp::Reverse(*at));
+        added.truncate(count);
+        Ok(added)
+    }
+
+    /// When each document was added to the index.
+    pub(crate) fn added(&self) -> Result<Vec<(String, jiff::Timestamp)>> {
+        let mut added = vec![];
+        if self.index_backend == Backend::Sqlite {
+            for (id, at) in self.db()?.added()? {
+                let at = at.parse().context(format!("reading when {id} was added"))?;
+                added.push((id, at));
+            }
+        } else {
+            // the text is written once when a document's added, and never touched after
+            for id in self.documents()? {
+                let path = self.index().join(&id);
+                let modified = fs::metadata(&path)
+                    .and_then(|it| it.modified())
+                    .context(format!("reading when {path:?} was written"))?;
+                added.push((id, jiff::Timestamp::try_from(modified)?));
+            }
+        }
+        Ok(added)
+    }
+
+    /// Changes when `id` was added to the index, for putting it back after its text has been
+    /// replaced.
+    pub(crate) fn set_added_at(&self, id: &str, added_at: jiff::Timestamp) -> Result<()> {
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.set_added_at(id, added_at);
+        }
+        let path = self.index().join(id);
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(added_at.into())
+            .context(format!("setting when {path:?} was written"))
+    }
+
+    /// Whether `id` is in the index.
+    pub fn contains(&self, id: &str) -> Result<bool> {
+        validate_id(id)?;
+        match self.index_backend {
+            Backend::Files => Ok(self.index().join(id).exists()),
+            Backend::Sqlite => self.db()?.contains(id),
+        }
+    }
+
+    fn upload(&self, content: &UploadContent) -> Result<()> {
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.add_text(&content.name, &content.content);
+        }
+
+        let content_path = self.index().join(&content.name);
+
+        if let Ok(mut out) = File::create_new(&content_path) {
+            out.write_all(&content.content.clone().into_bytes())?;
+        } else {
+            bail!("could not create file at {content_path:?}");
+        }
+
+        Ok(())
+    }
+
+    /// The OCR'd text of a document.
+    pub fn text(&self, id: &str) -> Result<String> {
+        validate_id(id)?;
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.text(id);
+        }
+
+        let path = self.index().join(id);
+        fs::read_to_string(&path).context(format!("reading index entry {path:?}"))
+    }
+
+    /// OCRs the page images in `dir` and adds them to the index as `output_name`.
+    pub fn read_and_index(&self, dir: &Path, output_name: &str) -> Result<()> {
+        let recognised = self.recognise(dir)?;
+        self.add_recognised(output_name, recognised)
+    }
+
+    /// Adds what was read from a document to the index as `id`: its text, and alongside it the
+    /// language it was detected as being in, how confident the engine was, and its hOCR, where
+    /// those are known.
+    pub(crate) fn add_recognised(&self, id: &str, recognised: Recognised) -> Result<()> {
+        self.add_text(id, recognised.text)?;
+        self.save_hocr(id, &recognised.hocr);
+        let Recognised {
+            language,
+            confidence,
+            ..
+        } = recognised;
+        if language.is_some() || confidence.is_some() {
+            self.update_metadata(id, |meta| {
+                meta.language = language.or(meta.language.take());
+                meta.ocr_confidence = confidence.or(meta.ocr_confidence);
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Adds already OCR'd text to the index as `id`, summarising and embedding it if those are
+    /// set up.
+    pub fn add_text(&self, id: &str, content: String) -> Result<()> {
+        let content = UploadContent {
+            name: id.to_string(),
+            content,
+        };
+        self.upload(&content).context("uploading content")?;
+        self.save_summary(id, &content.content);
+        self.save_fields(id, &content.content);
+        self.save_embedding(id, &content.content);
+
+        Ok(())
+    }
+
+    /// Removes `id`'s text from the index, returning whether it was there.
+    pub(crate) fn remove_text(&self, id: &str) -> Result<bool> {
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.remove(id);
+        }
+
+        let path = self.index().join(id);
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(&path).context(format!("removing index entry {path:?}"))?;
+        Ok(true)
+    }
+
+    fn rename_text(&self, id: &str, new_id: &str) -> Result<()> {
+        if self.index_backend == Backend::Sqlite {
+            return self.db()?.rename(id, new_id);
+        }
+
+        let path = self.index().join(id);
+        fs::rename(&path, self.index().join(new_id))
+            .context(format!("renaming index entry {path:?}"))
+    }
+
+    /// A document's metadata, or the default if none has been recorded.
+    pub fn metadata(&self, id: &str) -> Result<Metadata> {
+        match self.index_backend {
+            Backend::Files => metadata::read(self.index(), id),
+            Backend::Sqlite => self.db()?.metadata(id),
+        }
+    }
+
+    /// Applies `change` to a document's metadata in a single transaction, returning the result.
+    pub fn update_metadata(
+        &self,
+        id: &str,
+        change: impl FnOnce(&mut Metadata),
+    ) -> Result<Metadata> {
+        match self.index_backend {
+            Backend::Files => metadata::update(self.index(), id, change),
+            Backend::Sqlite => self.db()?.update_metadata(id, change),
+        }
+    }
+
+    fn remove_metadata(&self, id: &str) -> Result<()> {
+        match self.index_backend {
+            Backend::Files => metadata::remove(self.index(), id),
+            Backend::Sqlite => self.db()?.remove_metadata(id),
+        }
+    }
+
+    fn rename_metadata(&self, id: &str, new_id: &str) -> Result<()> {
+        match self.index_backend {
+            Backend::Files => metadata::rename(self.index(), id, new_id),
+            Backend::Sqlite => self.db()?.rename_metadata(id, new_id),
+        }
+    }
+
+    /// Removes a document from the index and the remote.
+    pub fn delete(&self, id: &str, yes: bool) -> Result<()> {
+        validate_id(id)?;
+
+        if self.dry_run {
+            if self.contains(id)? {
+                println!("would remove {id} from the index");
+            } else {
+                println!("{id} not found in index, would skip");
+            }
+            println!("would remove {id}'s metadata, thumbnail, hOCR, embedding, and local copy");
+            println!("would remove {}", self.remote_path(id));
+            return Ok(());
+        }
+
+        let question = format!("Delete {id} from the index and {}?", self.remote);
+        if !yes && !self.confirm(&question, false)? {
+            info!("aborted (pass --yes to delete without asking)");
+            return Ok(());
+        }
+
+        if !self.remove_from_index(id)? {
+            info!("{id} not found in index, skipping");
+        }
+        if let Some(local) = self.local_copy(id) {
+            fs::remove_file(&local).context(format!("removing {local:?}"))?;
+        }
+
+        info!("removing {id} from {}..", self.remote);
+        let deleted = self.delete_remote(id);
+        self.audit(Operation::Delete, id, None, deleted.as_ref().err());
+        deleted?;
+        self.note_remote_change(Change::Removed(id.to_string()));
+        self.publish_manifest()?;
+        self.commit_index(&format!("Delete {id}"));
+
+        info!("done!");
+        Ok(())
+    }
+
+    /// Removes everything the index has on `id`, leaving the remote and any local copy alone.
+    /// Returns whether it had its text.
+    pub(crate) fn remove_from_index(&self, id: &str) -> Result<bool> {
+        let removed = self.remove_text(id)?;
+        self.remove_metadata(id)?;
+        self.remove_thumbnail(id)?;
+        self.remove_hocr(id)?;
+        self.remove_embedding(id)?;
+        self.update_manifest(|manifest| manifest.remove(id))?;
+        Ok(removed)
+    }
+
+    /// Renames a document in the index and on the remote, undoing any half-done work if one
+    /// side fails.
+    pub fn rename(&self, id: &str, new_id: &str) -> Result<()> {
+        let renamed = self.rename_document(id, new_id);
+        self.audit(
+            Operation::Rename,
+            id,
+            Some(format!("to {new_id}")),
+            renamed.as_ref().err(),
+        );
+        renamed
+    }
+
+    fn rename_document(&self, id: &str, new_id: &str) -> Result<()> {
+        validate_id(id)?;
+        validate_id(new_id)?;
+
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+        if self.contains(new_id)? {
+            bail!("{new_id} already exists in index");
+        }
+
+        // move the remote first - if that fails nothing has changed locally
+        info!("moving {id} to {new_id} in {}..", self.remote);
+        self.rclone_moveto(id, new_id)?;
+
+        if let Err(e) = self.rename_text(id, new_id) {
+            // put the remote back so the two sides stay in sync
+            self.rclone_moveto(new_id, id)
+                .context("restoring remote after failed rename")?;
+            return Err(e);
+        }
+        if let Err(e) = self.rename_metadata(id, new_id) {
+            self.rename_text(new_id, id)
+                .context("restoring index entry after failed rename")?;
+            self.rclone_moveto(new_id, id)
+                .context("restoring remote after failed rename")?;
+            return Err(e);
+        }
+        self.rename_thumbnail(id, new_id)?;
+        self.rename_hocr(id, new_id)?;
+        self.rename_embedding(id, new_id)?;
+        if let Some(local) = self.local_copy(id) {
+            let renamed = local.with_file_name(new_id);
+            fs::rename(&local, &renamed).context(format!("moving {local:?} to {renamed:?}"))?;
+        }
+        self.update_manifest(|manifest| manifest.rename(id, new_id))?;
+        self.note_remote_change(Change::Renamed(id.to_string(), new_id.to_string()));
+        self.publish_manifest()?;
+        self.commit_index(&format!("Rename {id} to {new_id}"));
+
+        info!("done!");
+        Ok(())
+    }
+
+    /// Applies `classification` to a document's metadata, returning the result.
+    pub fn classify(&self, id: &str, classification: Classification) -> Result<Metadata> {
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+
+        if classification.is_empty() {
+            return self.metadata(id);
+        }
+        let message = format!("Classify {id}: {}", classification.describe());
+        let meta = self.update_metadata(id, |meta| classification.apply(meta))?;
+        self.commit_index(&message);
+        Ok(meta)
+    }
+}
+
+/// Separates one page's text from the next in the index, so search hits can say which page
+/// they're on. It's the form feed tesseract itself ends pages with.
+pub const PAGE_BREAK: char = '\x0c';
+
+/// Joins the text of each page of a document into what's stored in the index.
+pub fn join_pages(pages: impl IntoIterator<Item = String>) -> String {
+    let pages: Vec<_> = pages
+        .into_iter()
+        .map(|it| format!("{}\n", it.trim_end_matches([PAGE_BREAK, '\n'])))
+        .collect();
+    pages.join(&format!("{PAGE_BREAK}\n"))
+}
+
+/// The text of each page of a document, from what's stored in the index. Documents indexed
+/// before pages were kept apart come back as a single page.
+pub fn pages(text: &str) -> impl Iterator<Item = &str> {
+    text.split(PAGE_BREAK)
+}
+
+/// The page (from 1) that byte `offset` of a document's text is on.
+pub fn page_at(text: &str, offset: usize) -> usize {
+    let before = text.get(..offset).unwrap_or(text);
+    before.matches(PAGE_BREAK).count() + 1
+}
+
+/// How the timestamps document ids start with are written, configured as `[ids]` in
+/// kartka.toml. Setting `timezone` keeps ids from different machines in step.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Ids {
+    /// A strftime pattern, which has to start with the date, year first, so ids sort by when
+    /// documents were scanned.
+    pub format: String,
+    /// `UTC`, `local` for whatever the machine's set to, or a name like `Europe/London`.
+    pub timezone: String,
+}
+
+/// How ids' timestamps are written unless `[ids]` says otherwise.
+const ID_FORMAT: &str = "%Y_%m_%d_%H_%M_%S";
+
+impl Default for Ids {
+    fn default() -> Self {
+        Ids {
+            format: ID_FORMAT.to_string(),
+            timezone: "UTC".to_string(),
+        }
+    }
+}
+
+impl Ids {
+    /// The time zone ids are written in.
+    pub fn time_zone(&self) -> Result<TimeZone> {
+        match self.timezone.as_str() {
+            it if it.eq_ignore_ascii_case("utc") => Ok(TimeZone::UTC),
+            "local" => Ok(TimeZone::system()),
+            name => TimeZone::get(name).context(format!("unknown time zone {name:?}")),
+        }
+    }
+
+    /// The id for a document scanned at `time`.
+    pub fn id(&self, time: jiff::Timestamp) -> String {
+        // both are checked when the config's loaded
+        let time = time.to_zoned(self.time_zone().unwrap_or(TimeZone::UTC));
+        let stamp = strtime::format(&self.format, &time)
+            .unwrap_or_else(|_| time.strftime(ID_FORMAT).to_string());
+        format!("{stamp}.pdf")
+    }
+
+    /// An id for a document captured at `time` that isn't `taken` yet, adding a counter if
+    /// another document was captured in the same second.
+    pub fn unique(&self, taken: &HashSet<String>, time: jiff::Timestamp) -> String {
+        unique_name(taken, self.id(time))
+    }
+
+    /// How long the timestamp at the start of each id is.
+    pub fn stamp_len(&self) -> usize {
+        self.id(jiff::Timestamp::UNIX_EPOCH).len() - ".pdf".len()
+    }
+
+    /// What `id` is called: the name after its timestamp, if it was given one, or else just
+    /// the id without `.pdf`.
+    pub fn title(&self, id: &str) -> String {
+        let stem = id.trim_end_matches(".pdf");
+        match stem
+            .get(self.stamp_len()..)
+            .and_then(|it| it.strip_prefix('_'))
+        {
+            Some(name) if self.scanned(id).is_some() && !name.is_empty() => name.replace('-', " "),
+            _ => stem.to_string(),
+        }
+    }
+
+    /// When the document `id` was scanned, from the timestamp at its start.
+    pub fn scanned(&self, id: &str) -> Option<jiff::Timestamp> {
+        let stamp = id.get(..self.stamp_len())?;
+        let parsed = strtime::parse(&self.format, stamp).ok()?;
+        let time = parsed
+            .to_datetime()
+            .or_else(|_| parsed.to_date().map(|it| it.to_datetime(Time::midnight())))
+            .ok()?;
+        time.to_zoned(self.time_zone().ok()?)
+            .ok()
+            .map(|it| it.timestamp())
+    }
+}
+
+/// `id`, or if that's in `taken`, `id` with the first free `_2`, `_3`.. on the end.
+pub fn unique_name(taken: &HashSet<String>, id: String) -> String {
+    if !taken.contains(&id) {
+        return id;
+    }
+    let stem = id.trim_end_matches(".pdf");
+    (2..)
+        .map(|n| format!("{stem}_{n}.pdf"))
+        .find(|it| !taken.contains(it))
+        .expect("ran out of ids")
+}
+
+/// The date a document was scanned, from the timestamp at the start of its id. The year, month
+/// and day can be separated by anything, or nothing, as `[ids]` allows.
+pub fn document_date(id: &str) -> Option<jiff::civil::Date> {
+    let mut rest = id;
+    let mut number = |digits: usize| {
+        let start = rest.find(|c: char| c.is_ascii_digit())?;
+        // a separator is at most one character
+        if start > 1 {
+            return None;
+        }
+        let end = start + digits;
+        let number = rest.get(start..end)?.parse().ok()?;
+        rest = &rest[end..];
+        Some(number)
+    };
+    let year = number(4)?;
+    let month = number(2)?;
+    let day = number(2)?;
+    jiff::civil::Date::new(year, month as i8, day as i8).ok()
+}
+
+/// Checks `id` is a plain file name, so it can't reach outside the index.
+pub fn validate_id(id: &str) -> Result<()> {
+    if id.is_empty() || Path::new(id).file_name() != Some(OsStr::new(id)) {
+        bail!("invalid document id: {id:?}");
+    }
+    Ok(())
+}
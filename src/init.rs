@@ -0,0 +1,113 @@
+//! The `kartka init` wizard for writing a first config file.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{bail, Context, Result};
+use inquire::{Confirm, Text};
+use kartka::{storage, synced};
+
+use crate::doctor;
+use serde::Serialize;
+
+/// External tools kartka shells out to.
+const TOOLS: &[&str] = &["tesseract", "rclone", "magick", "rg"];
+
+#[derive(Debug, Serialize)]
+struct Config {
+    scan_dir: String,
+    index_dir: String,
+    remote: String,
+    ocr_language: String,
+}
+
+pub fn run(config_path: &Path, plain: bool) -> Result<()> {
+    if plain {
+        bail!("`kartka init` is interactive and can't run with --plain");
+    }
+
+    if config_path.exists()
+        && !Confirm::new(&format!("{config_path:?} already exists, overwrite it?"))
+            .with_default(false)
+            .prompt()?
+    {
+        println!("aborted");
+        return Ok(());
+    }
+
+    let missing_tools: Vec<_> = TOOLS.iter().filter(|tool| !tool_exists(tool)).collect();
+    for tool in &missing_tools {
+        println!("warning: `{tool}` not found on PATH, kartka needs it to run");
+    }
+
+    let default_dir = |base: Option<PathBuf>| {
+        base.map(|it| it.join("kartka").to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let scan_dir = Text::new("Where do scanned letters get saved?")
+        .with_default(&default_dir(dirs::download_dir()))
+        .prompt()?;
+    let index_dir = Text::new("Where should the index live?")
+        .with_default(&default_dir(dirs::document_dir()))
+        .prompt()?;
+    if let Some(provider) = synced::provider(Path::new(&index_dir)) {
+        println!(
+            "warning: {index_dir} is synced by {provider}, which would upload the text of every letter - {}",
+            provider.advice()
+        );
+    }
+    let remote = Text::new("Which rclone remote should PDFs be stored in?")
+        .with_help_message("e.g. `dropbox`, or `dropbox:documents/kartka` for a folder on it")
+        .with_default("dropbox")
+        .prompt()?;
+    let ocr_language = Text::new("Which language(s) should tesseract OCR with?")
+        .with_help_message("e.g. `eng`, or `eng+deu` for several")
+        .with_default("eng")
+        .prompt()?;
+
+    if !missing_tools.contains(&&"rclone") && !remote_exists(&remote)? {
+        println!("warning: no rclone remote called `{remote}` - set one up with `rclone config`");
+    }
+    if !missing_tools.contains(&&"tesseract") && !doctor::language_installed(&ocr_language) {
+        println!("warning: tesseract doesn't have data for `{ocr_language}` installed");
+    }
+
+    for dir in [&scan_dir, &index_dir] {
+        if !Path::new(dir).exists()
+            && Confirm::new(&format!("{dir} doesn't exist, create it?"))
+                .with_default(true)
+                .prompt()?
+        {
+            fs::create_dir_all(dir).context(format!("creating {dir}"))?;
+        }
+    }
+
+    let config = Config {
+        scan_dir,
+        index_dir,
+        remote,
+        ocr_language,
+    };
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(config_path, toml::to_string(&config)?)
+        .context(format!("writing {config_path:?}"))?;
+
+    println!("wrote {config_path:?}");
+    Ok(())
+}
+
+fn tool_exists(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
+
+fn remote_exists(remote: &str) -> Result<bool> {
+    let output = Command::new("rclone").arg("listremotes").output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|it| it.trim_end_matches(':') == storage::remote_name(remote)))
+}
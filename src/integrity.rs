@@ -0,0 +1,356 @@
+//! A signed list of every document's SHA-256, kept in the index and mirrored to the remote, so
+//! tampering or bit-rot in the archive can be spotted years later.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{remote_manifest::REMOTE_MANIFEST, store, Kartka};
+
+/// The manifest's name, both on the remote and in the index's `.kartka` directory.
+pub const MANIFEST: &str = "kartka-manifest.sha256";
+/// The minisign signature of the manifest, alongside it.
+pub const SIGNATURE: &str = "kartka-manifest.sha256.minisig";
+
+/// Whether a remote file is kartka's own bookkeeping rather than a document.
+pub fn is_manifest(name: &str) -> bool {
+    name == MANIFEST || name == SIGNATURE || name == REMOTE_MANIFEST
+}
+
+pub fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path).context(format!("opening {path:?}"))?;
+    io::copy(&mut file, &mut hasher).context(format!("hashing {path:?}"))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Document ids and their hashes, stored in `sha256sum` format so it can be checked by hand.
+#[derive(Debug, Default)]
+pub struct Manifest(BTreeMap<String, String>);
+
+impl Manifest {
+    /// Loads the manifest at `path`, or an empty one if there isn't one yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let contents = fs::read_to_string(path).context(format!("reading {path:?}"))?;
+        Ok(Manifest(parse_hashes(&contents)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents: String = self
+            .0
+            .iter()
+            .map(|(id, hash)| format!("{hash}  {id}\n"))
+            .collect();
+        store::write_atomic(path, contents)
+    }
+
+    pub fn insert(&mut self, id: &str, hash: String) {
+        self.0.insert(id.to_string(), hash);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.0.remove(id);
+    }
+
+    pub fn rename(&mut self, id: &str, new_id: &str) {
+        if let Some(hash) = self.0.remove(id) {
+            self.0.insert(new_id.to_string(), hash);
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.contains_key(id)
+    }
+
+    pub fn hashes(&self) -> &BTreeMap<String, String> {
+        &self.0
+    }
+}
+
+/// Parses `sha256sum`-style `<hash>  <name>` lines, as written by both us and `rclone hashsum`.
+pub fn parse_hashes(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, id)| (id.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// Signs `path` with minisign, writing the signature to `<path>.minisig`.
+pub fn sign(path: &Path, secret_key: &Path) -> Result<()> {
+    let status = Command::new("minisign")
+        .arg("-S")
+        .arg("-s")
+        .arg(secret_key)
+        .arg("-m")
+        .arg(path)
+        .status()
+        .context("running minisign")?;
+    if !status.success() {
+        bail!("could not sign {path:?}");
+    }
+    Ok(())
+}
+
+/// Checks `<path>.minisig` is a valid signature of `path` by the holder of `public_key`.
+pub fn verify_signature(path: &Path, public_key: &Path) -> Result<()> {
+    let output = Command::new("minisign")
+        .arg("-V")
+        .arg("-p")
+        .arg(public_key)
+        .arg("-m")
+        .arg(path)
+        .output()
+        .context("running minisign")?;
+    if !output.status.success() {
+        bail!(
+            "manifest signature is not valid: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+impl Kartka {
+    fn manifest_path(&self) -> PathBuf {
+        self.index().join(".kartka").join(MANIFEST)
+    }
+
+    /// Applies `change` to the local manifest, if signing is set up.
+    pub(crate) fn update_manifest(&self, change: impl FnOnce(&mut Manifest)) -> Result<()> {
+        if self.signing_key.is_none() {
+            return Ok(());
+        }
+
+        let _transaction = store::transaction(self.index())?;
+        let path = self.manifest_path();
+        let mut manifest = Manifest::load(&path)?;
+        change(&mut manifest);
+        manifest.save(&path)
+    }
+
+    /// Records the hash and size of `pdf` in `id`'s metadata, and its hash in the local manifest
+    /// if signing is set up.
+    pub(crate) fn record_hash(&self, id: &str, pdf: &Path) -> Result<()> {
+        let hash = sha256(pdf)?;
+        let size = fs::metadata(pdf)?.len();
+        self.update_metadata(id, |meta| {
+            meta.sha256 = Some(hash.clone());
+            meta.size = Some(size);
+        })?;
+        self.update_manifest(|manifest| manifest.insert(id, hash))
+    }
+
+    /// Uploads the remote manifest, then signs the local hash manifest and uploads it and its
+    /// signature if signing is set up.
+    pub(crate) fn publish_manifest(&self) -> Result<()> {
+        self.publish_remote_manifest()?;
+        let Some(signing_key) = &self.signing_key else {
+            return Ok(());
+        };
+
+        info!("signing manifest..");
+        // held until both are uploaded, so the signature always matches the manifest next to it
+        let _transaction = store::transaction(self.index())?;
+        let path = self.manifest_path();
+        if !path.exists() {
+            Manifest::default().save(&path)?;
+        }
+        sign(&path, signing_key)?;
+        self.upload_file(&path, MANIFEST)?;
+        self.upload_file(&path.with_file_name(SIGNATURE), SIGNATURE)?;
+        Ok(())
+    }
+
+    /// Adds any documents on the remote that are missing from the manifest, trusting them as
+    /// they are now, then re-signs and uploads it.
+    pub fn sign(&self) -> Result<()> {
+        if self.signing_key.is_none() {
+            bail!("no signing_key configured");
+        }
+
+        info!("hashing everything in {}..", self.remote);
+        let remote_hashes = self.remote_hashes()?;
+        let mut added = 0;
+        self.update_manifest(|manifest| {
+            for (id, hash) in remote_hashes {
+                if !manifest.contains(&id) {
+                    manifest.insert(&id, hash);
+                    added += 1;
+                }
+            }
+        })?;
+        info!("added {added} documents to the manifest");
+
+        self.publish_manifest()?;
+        info!("done!");
+        Ok(())
+    }
+
+    /// Checks the remote against the hashes recorded in the index, or with `integrity` against
+    /// its signed manifest. With `record`, documents indexed before their hashes were kept get
+    /// the remote's current hash recorded, trusting it as it is now. With `quick`, only the
+    /// remote's manifest is downloaded and compared with the index.
+    pub fn verify(&self, integrity: bool, record: bool, quick: bool) -> Result<()> {
+        if integrity {
+            self.verify_integrity()
+        } else if quick {
+            self.verify_manifest()
+        } else {
+            self.verify_hashes(record)
+        }
+    }
+
+    /// Compares the remote's manifest with the index. This trusts the manifest to describe
+    /// what's really on the remote, so catches documents going astray but not bit-rot.
+    fn verify_manifest(&self) -> Result<()> {
+        let Some(manifest) = self.remote_manifest()? else {
+            bail!(
+                "there's no manifest on {} yet - run `kartka verify` without --quick",
+                self.remote
+            );
+        };
+        let documents = self.documents()?;
+
+        let mut problems = 0;
+        for id in &documents {
+            let expected = self.metadata(id)?.sha256;
+            match manifest.documents.get(id) {
+                Some(known) if known.sha256.is_none() || expected.is_none() => {}
+                Some(known) if known.sha256 == expected => {}
+                Some(_) => {
+                    println!("CORRUPTED    {id}");
+                    problems += 1;
+                }
+                None => {
+                    println!("NOT REMOTE   {id}");
+                    problems += 1;
+                }
+            }
+        }
+        for id in manifest.documents.keys() {
+            if documents.binary_search(id).is_err() {
+                println!("NOT INDEXED  {id}");
+                problems += 1;
+            }
+        }
+
+        if problems > 0 {
+            bail!("{problems} problems found");
+        }
+        println!("all {} documents ok", documents.len());
+        Ok(())
+    }
+
+    /// Compares the remote with the index, failing if a document has been corrupted or is only
+    /// on one side.
+    fn verify_hashes(&self, record: bool) -> Result<()> {
+        info!("hashing everything in {}..", self.remote);
+        let remote_hashes = self.remote_hashes()?;
+        let documents = self.documents()?;
+
+        let mut problems = 0;
+        let mut unrecorded = 0;
+        for id in &documents {
+            let meta = self.metadata(id)?;
+            // cold storage isn't hashed, it's too slow and expensive to read back
+            if self.archive.is_some() && meta.archived {
+                continue;
+            }
+            match (remote_hashes.get(id), &meta.sha256) {
+                (Some(actual), Some(expected)) if actual == expected => {}
+                (Some(_), Some(_)) => {
+                    println!("CORRUPTED    {id}");
+                    problems += 1;
+                }
+                (Some(actual), None) if record => {
+                    self.update_metadata(id, |meta| meta.sha256 = Some(actual.clone()))?;
+                    println!("RECORDED     {id}");
+                }
+                (Some(_), None) => unrecorded += 1,
+                (None, _) => {
+                    println!("NOT REMOTE   {id}");
+                    problems += 1;
+                }
+            }
+        }
+        for id in remote_hashes.keys() {
+            if documents.binary_search(id).is_err() {
+                println!("NOT INDEXED  {id}");
+                problems += 1;
+            }
+        }
+
+        if unrecorded > 0 {
+            println!(
+                "{unrecorded} documents have no hash recorded - `kartka verify --record` trusts the remote's copies as they are now"
+            );
+        }
+        if problems > 0 {
+            bail!("{problems} problems found");
+        }
+        println!("all {} documents ok", documents.len() - unrecorded);
+        Ok(())
+    }
+
+    /// Checks the remote against its signed manifest, failing if anything has changed, gone
+    /// missing, or appeared without being signed.
+    fn verify_integrity(&self) -> Result<()> {
+        let Some(verify_key) = &self.verify_key else {
+            bail!("no verify_key configured");
+        };
+
+        // check the copy on the remote rather than ours, since that's what's being vouched for
+        let temp_dir = tempfile::tempdir()?;
+        let manifest_path = temp_dir.path().join(MANIFEST);
+        self.download(MANIFEST, &manifest_path)?;
+        self.download(SIGNATURE, &temp_dir.path().join(SIGNATURE))?;
+        verify_signature(&manifest_path, verify_key)?;
+        let manifest = Manifest::load(&manifest_path)?;
+        info!("manifest signature ok");
+
+        info!("hashing everything in {}..", self.remote);
+        let remote_hashes = self.remote_hashes()?;
+
+        let mut problems = 0;
+        for (id, expected) in manifest.hashes() {
+            match remote_hashes.get(id) {
+                Some(actual) if actual == expected => {}
+                Some(_) => {
+                    println!("CHANGED    {id}");
+                    problems += 1;
+                }
+                // cold storage isn't hashed, it's too slow and expensive to read back
+                None if self.is_archived(id)? => {}
+                None => {
+                    println!("MISSING    {id}");
+                    problems += 1;
+                }
+            }
+        }
+        for id in remote_hashes.keys() {
+            if !manifest.contains(id) {
+                println!("UNSIGNED   {id}");
+                problems += 1;
+            }
+        }
+
+        if problems > 0 {
+            bail!("{problems} problems found");
+        }
+        println!("all {} documents ok", manifest.hashes().len());
+        Ok(())
+    }
+}
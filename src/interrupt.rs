@@ -0,0 +1,63 @@
+//! Stopping `scan` and `hydrate` cleanly on Ctrl-C. The first Ctrl-C asks them to stop taking
+//! on new documents, and to finish or roll back the ones they're part way through, so nothing is
+//! left half in the index or half on the remote. A second Ctrl-C stops straight away.
+
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts listening for Ctrl-C, if it isn't being listened for already. Until this is called
+/// Ctrl-C stops kartka straight away, as usual.
+pub fn watch() {
+    static WATCHING: Once = Once::new();
+    WATCHING.call_once(|| {
+        let listening = thread::Builder::new()
+            .name("ctrl-c".to_string())
+            .spawn(|| {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(it) => it,
+                    Err(e) => {
+                        warn!("couldn't listen for Ctrl-C: {e}");
+                        return;
+                    }
+                };
+                runtime.block_on(async {
+                    while tokio::signal::ctrl_c().await.is_ok() {
+                        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                            eprintln!("stopping now - the index may need a `kartka hydrate` to tidy up after this");
+                            process::exit(130);
+                        }
+                        warn!("interrupted - finishing up the documents in progress, Ctrl-C again to stop now");
+                    }
+                });
+            });
+        if let Err(e) = listening {
+            warn!("couldn't listen for Ctrl-C: {e}");
+        }
+    });
+}
+
+/// Whether Ctrl-C has been pressed since [`watch`] was called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Waits until Ctrl-C is pressed, for racing against async work.
+pub async fn cancelled() {
+    while !interrupted() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -0,0 +1,102 @@
+//! A journal of `hydrate`'s progress, kept at `.kartka/hydrate.state` so an interrupted run can
+//! pick up where it left off. Each document gets a `start` line before any work on it and a
+//! `done` or `failed` line after, so a document that was started but never finished is one
+//! whose index entry may be half written.
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+
+const JOURNAL: &str = ".kartka/hydrate.state";
+
+/// What an earlier, interrupted hydrate got through.
+#[derive(Debug, Default)]
+pub struct Interrupted {
+    /// Documents that were hydrated before the interruption.
+    pub completed: BTreeSet<String>,
+    /// Documents that were started but never finished, whose index entries can't be trusted.
+    pub unfinished: BTreeSet<String>,
+}
+
+impl Interrupted {
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty() && self.unfinished.is_empty()
+    }
+}
+
+/// Reads the journal left by an interrupted hydrate, or nothing if the last one finished.
+pub fn read(index: &Path) -> Result<Interrupted> {
+    let path = index.join(JOURNAL);
+    if !path.exists() {
+        return Ok(Interrupted::default());
+    }
+
+    let contents = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+    let mut interrupted = Interrupted::default();
+    for line in contents.lines() {
+        // a torn last line just means that document's status wasn't recorded
+        let Some((status, id)) = line.split_once(' ') else {
+            continue;
+        };
+        match status {
+            "start" => {
+                interrupted.unfinished.insert(id.to_string());
+            }
+            "done" => {
+                interrupted.unfinished.remove(id);
+                interrupted.completed.insert(id.to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(interrupted)
+}
+
+/// The journal for a hydrate in progress.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal in `index`, carrying on from any interrupted hydrate.
+    pub fn open(index: &Path) -> Result<Self> {
+        fs::create_dir_all(index.join(".kartka"))?;
+        let path = index.join(JOURNAL);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("opening {path:?}"))?;
+        Ok(Journal { path, file })
+    }
+
+    pub fn start(&mut self, id: &str) -> Result<()> {
+        self.record("start", id)
+    }
+
+    pub fn done(&mut self, id: &str) -> Result<()> {
+        self.record("done", id)
+    }
+
+    pub fn failed(&mut self, id: &str) -> Result<()> {
+        self.record("failed", id)
+    }
+
+    fn record(&mut self, status: &str, id: &str) -> Result<()> {
+        writeln!(self.file, "{status} {id}").context(format!("writing {:?}", self.path))?;
+        // on disk before the work it describes, or the journal can't be trusted after a crash
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Removes the journal once the hydrate has run to the end.
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path).context(format!("removing {:?}", self.path))
+    }
+}
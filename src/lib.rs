@@ -0,0 +1,253 @@
+//! Quick and dirty indexing of your mail and PDFs so you can go paperless.
+//!
+//! [`Kartka`] is both the configuration and the way in: load it from a `kartka.toml` with
+//! [`config::load`], then call [`Kartka::scan`], [`Kartka::rehydrate`], [`Kartka::search`] and
+//! friends. The pipeline is split up by stage:
+//!
+//! - [`ocr`] turns page images into text, with tesseract or another [`ocr::OcrEngine`],
+//!   [`ocr_cache`] remembers what it made of each page, and [`hocr`] keeps where on the page it
+//!   found each word.
+//! - [`index`] stores that text, one file per document, with [`metadata`] kept alongside.
+//!   Changes to metadata and the manifest go through [`store`] transactions, so several kartka
+//!   processes can share an index.
+//! - [`search`] finds documents in the index with ripgrep, and [`semantic`] by meaning.
+//! - [`thumbnail`] keeps a small picture of each document's first page, so it can be recognised
+//!   at a glance.
+//! - [`storage`] moves PDFs to and from the rclone remote.
+//! - [`scan`] ties these together into the scan and hydrate pipelines, with [`quarantine`]
+//!   keeping track of documents that keep failing to hydrate.
+
+use std::{num::NonZeroUsize, path::PathBuf};
+
+use eyre::Result;
+use serde::Deserialize;
+
+pub mod audit;
+pub mod backup;
+pub mod checklist;
+pub mod cloud_ocr;
+pub mod codes;
+pub mod collections;
+pub mod config;
+pub mod dupes;
+pub mod encryption;
+pub mod error;
+pub mod export;
+pub mod fields;
+pub mod gc;
+pub mod git;
+pub mod google_drive;
+pub mod highlight;
+pub mod hocr;
+pub mod import;
+pub mod index;
+pub mod integrity;
+pub mod interrupt;
+pub mod journal;
+pub mod mail;
+pub mod metadata;
+pub mod ocr;
+pub mod ocr_cache;
+pub mod ocrmypdf;
+pub mod openai;
+pub mod pages;
+pub mod paperless;
+pub mod pdf;
+pub mod progress;
+pub mod quarantine;
+pub mod query;
+pub mod rebuild;
+pub mod remote_manifest;
+pub mod saved_search;
+pub mod scan;
+pub mod scanner;
+pub mod schema;
+pub mod search;
+pub mod semantic;
+pub mod sqlite;
+pub mod stats;
+pub mod storage;
+pub mod store;
+pub mod summary;
+pub mod sync;
+pub mod synced;
+pub mod tables;
+pub mod taxpack;
+pub mod text;
+pub mod thumbnail;
+pub mod tools;
+pub mod validate;
+pub mod versions;
+
+pub use error::KartkaError;
+pub use storage::{ArchiveTier, Retry};
+
+/// Everything kartka needs to know about where documents live and how to process them.
+#[derive(Debug, Deserialize)]
+pub struct Kartka {
+    /// Where newly scanned page images are picked up from.
+    pub scan_dir: PathBuf,
+    /// Where the index lives.
+    pub index_dir: PathBuf,
+    /// How the index stores documents.
+    #[serde(default)]
+    pub index_backend: index::Backend,
+    /// How the timestamps document ids start with are written.
+    #[serde(default)]
+    pub ids: index::Ids,
+    /// Allow the index to live in a cloud-synced folder without warning about it.
+    #[serde(default)]
+    pub index_in_synced_folder: bool,
+    /// The rclone remote PDFs are stored in, optionally with a folder on it, e.g.
+    /// `dropbox:documents/kartka`.
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    /// Where search results and `open` link to, with `{id}` standing for the document's id.
+    /// Worked out from the type of `remote` if not set.
+    #[serde(default)]
+    pub preview_link: Option<String>,
+    /// A folder with copies of the remote's files in, like a synced Dropbox folder. Documents
+    /// found there are linked to as local files rather than through `preview_link`.
+    #[serde(default)]
+    pub local_dir: Option<PathBuf>,
+    /// Where to keep a copy of every PDF as well as the remote, so documents can be opened and
+    /// fetched without it.
+    #[serde(default)]
+    pub local_archive_dir: Option<PathBuf>,
+    /// Tesseract language(s) to OCR with, e.g. `eng` or `eng+deu`.
+    #[serde(default = "default_ocr_language")]
+    pub ocr_language: String,
+    /// Tesseract languages documents might be in. If set, each document is OCR'd in whichever
+    /// of them it's detected as being in, rather than `ocr_language`.
+    #[serde(default)]
+    pub detect_languages: Vec<String>,
+    /// Which pages split a stack of scans into separate documents, if any.
+    #[serde(default)]
+    pub separator: Option<pages::Separator>,
+    /// How scanned pages are compressed into PDFs.
+    #[serde(default)]
+    pub pdf: pdf::Quality,
+    /// Keep pages with next to nothing on them, rather than leaving them out of the text and
+    /// the PDF.
+    #[serde(default)]
+    pub keep_blank_pages: bool,
+    /// Look for QR codes and barcodes on every page, and keep what they say in metadata.
+    /// Needs `zbarimg`.
+    #[serde(default)]
+    pub read_codes: bool,
+    /// Make searches match case and accents exactly, rather than `uber` finding `Über`.
+    #[serde(default)]
+    pub exact_search: bool,
+    /// What does the OCR.
+    #[serde(default)]
+    pub ocr_engine: ocr::Engine,
+    /// Who OCRs scans and makes them into PDFs: kartka, or OCRmyPDF for searchable PDFs.
+    #[serde(default)]
+    pub pipeline: ocrmypdf::Pipeline,
+    /// How tesseract reads pages.
+    #[serde(default)]
+    pub ocr: ocr::Settings,
+    /// Credentials for the cloud OCR engines.
+    #[serde(default)]
+    pub cloud_ocr: Option<cloud_ocr::CloudOcr>,
+    /// minisign secret key used to sign the manifest of document hashes.
+    #[serde(default)]
+    pub signing_key: Option<PathBuf>,
+    /// minisign public key used by `verify --integrity`.
+    #[serde(default)]
+    pub verify_key: Option<PathBuf>,
+    /// How to encrypt documents before they're uploaded, if at all.
+    #[serde(default)]
+    pub encryption: Option<encryption::Encryption>,
+    /// The LLM that summarises documents as they're indexed, if any.
+    #[serde(default)]
+    pub summaries: Option<openai::Endpoint>,
+    /// How totals, invoice numbers and the like are picked out of documents as they're
+    /// indexed, if they are.
+    #[serde(default)]
+    pub extraction: Option<fields::Extraction>,
+    /// The model that embeds documents for semantic search, if any.
+    #[serde(default)]
+    pub embeddings: Option<openai::Endpoint>,
+    /// The mailbox `ingest-mail` pulls attachments from.
+    #[serde(default)]
+    pub mail: Option<mail::Mail>,
+    /// The Google Drive folder `remote` is, for `kartka google-drive` to sign in to.
+    #[serde(default)]
+    pub google_drive: Option<google_drive::GoogleDrive>,
+    /// A cheaper, slower remote that old documents get moved to.
+    #[serde(default)]
+    pub archive: Option<ArchiveTier>,
+    /// How many hydrates in a row a document can fail before it's quarantined and skipped.
+    #[serde(default = "default_quarantine_after")]
+    pub quarantine_after: u32,
+    /// How to retry failed remote operations.
+    #[serde(default)]
+    pub retry: Retry,
+    /// How many documents `hydrate` downloads from the remote at once.
+    #[serde(default = "default_transfers")]
+    pub transfers: NonZeroUsize,
+    #[serde(default)]
+    pub checklist: Vec<checklist::Expected>,
+    /// Keep the index in git, committing after every change.
+    #[serde(default)]
+    pub git: Option<git::Git>,
+    /// Send a desktop notification when `scan`, `hydrate` or `sync` finishes.
+    #[serde(default)]
+    pub notify: bool,
+    /// Answer prompts with their defaults rather than asking.
+    #[serde(skip)]
+    pub plain: bool,
+    /// Documents added to, removed from or renamed on the remote since its manifest was last
+    /// published.
+    #[serde(skip)]
+    remote_changes: std::sync::Mutex<Vec<remote_manifest::Change>>,
+    /// rclone's remotes and their types, looked up the first time they're needed.
+    #[serde(skip)]
+    rclone_remotes: std::sync::OnceLock<std::collections::BTreeMap<String, String>>,
+    /// Print what `scan`, `hydrate` and `delete` would do without changing anything.
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// How `scan` splits the pages it's given into documents, set with `--split-every` or
+    /// `--interactive`. Without it they're split at `separator` pages, if that's set.
+    #[serde(skip)]
+    pub split: Option<scan::Split>,
+    /// The pages `scan` puts together, in this order and turned as they say, rather than
+    /// every page it's given in order of name. Set with `--review`.
+    #[serde(skip)]
+    pub page_order: Option<Vec<pages::Page>>,
+    /// What runs rclone, ImageMagick and the other tools, which tests swap for a
+    /// [`tools::Recording`].
+    #[serde(skip, default = "tools::system")]
+    pub tools: std::sync::Arc<dyn tools::ToolRunner>,
+}
+
+fn default_remote() -> String {
+    "dropbox".to_string()
+}
+
+fn default_ocr_language() -> String {
+    "eng".to_string()
+}
+
+fn default_quarantine_after() -> u32 {
+    3
+}
+
+fn default_transfers() -> NonZeroUsize {
+    NonZeroUsize::new(4).expect("4 isn't zero")
+}
+
+impl Kartka {
+    /// Asks a yes/no question, or answers it with `default` in plain mode.
+    pub(crate) fn confirm(&self, message: &str, default: bool) -> Result<bool> {
+        if self.plain {
+            println!("{message} {}", if default { "yes" } else { "no" });
+            return Ok(default);
+        }
+
+        Ok(inquire::Confirm::new(message)
+            .with_default(default)
+            .prompt()?)
+    }
+}
@@ -0,0 +1,249 @@
+//! Pulling documents out of email: PDF and image attachments in an IMAP folder are indexed and
+//! uploaded like any other scan.
+
+use std::{collections::HashSet, fs, net::TcpStream};
+
+use eyre::{bail, eyre, Context, Result};
+use jiff::Timestamp;
+use mail_parser::{MessageParser, MimeHeaders};
+use native_tls::TlsStream;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::{config, remote_manifest::Change, Kartka, KartkaError};
+
+/// Which mailbox to pull attachments from, configured as `[mail]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Mail {
+    pub host: String,
+    /// The IMAPS port; plain IMAP isn't supported.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    /// A shell command that prints the password, e.g. `pass show mail/kartka`.
+    pub password_command: String,
+    /// The folder to look in. Unread messages in it are ingested.
+    #[serde(default = "default_folder")]
+    pub folder: String,
+    /// Where processed messages are moved to. They're only marked as read if this isn't set.
+    #[serde(default)]
+    pub processed_folder: Option<String>,
+}
+
+fn default_port() -> u16 {
+    993
+}
+
+fn default_folder() -> String {
+    "INBOX".to_string()
+}
+
+type Session = imap::Session<TlsStream<TcpStream>>;
+
+impl Mail {
+    fn connect(&self) -> Result<Session> {
+        let tls = native_tls::TlsConnector::new()?;
+        let client = imap::connect((self.host.as_str(), self.port), &self.host, &tls)
+            .context(format!("connecting to {}:{}", self.host, self.port))?;
+        client
+            .login(
+                &self.username,
+                config::secret_from_command(&self.password_command, "password_command")?,
+            )
+            .map_err(|(e, _)| eyre!(e))
+            .context(format!("logging in to {} as {}", self.host, self.username))
+    }
+}
+
+/// The file extension for an attachment, if it's a PDF or an image kartka can take. It's worked
+/// out here rather than taken from the attachment's name, since that's up to whoever sent it.
+fn attachment_extension(
+    content_type: Option<(&str, Option<&str>)>,
+    name: &str,
+) -> Option<&'static str> {
+    let extension = name.rsplit_once('.').map(|(_, it)| it.to_ascii_lowercase());
+    match content_type {
+        Some(("application", Some("pdf"))) => Some("pdf"),
+        Some(("image", Some("png"))) => Some("png"),
+        Some(("image", Some("jpeg" | "jpg"))) => Some("jpg"),
+        Some(("image", Some("tiff"))) => Some("tiff"),
+        // plenty of mailers don't bother saying what an attachment is
+        _ => match extension.as_deref() {
+            Some("pdf") => Some("pdf"),
+            Some("png") => Some("png"),
+            Some("jpg" | "jpeg") => Some("jpg"),
+            Some("tif" | "tiff") => Some("tiff"),
+            _ => None,
+        },
+    }
+}
+
+impl Kartka {
+    /// Ingests the PDF and image attachments of every unread message in the `[mail]` folder,
+    /// each as its own document dated by when the message was sent. Messages are marked as read
+    /// (or moved to `processed_folder`) once all their attachments are in, so they aren't
+    /// ingested twice; ones with a failed attachment are left to try again next time.
+    pub fn ingest_mail(&self) -> Result<()> {
+        let Some(mail) = &self.mail else {
+            bail!("no [mail] configured");
+        };
+
+        info!("checking {} on {}..", mail.folder, mail.host);
+        let mut session = mail.connect()?;
+        session
+            .select(&mail.folder)
+            .context(format!("opening {}", mail.folder))?;
+        let mut uids: Vec<_> = session
+            .uid_search("UNSEEN")
+            .context("searching for unread messages")?
+            .into_iter()
+            .collect();
+        uids.sort();
+        info!("{} unread messages", uids.len());
+
+        let mut taken: HashSet<_> = self.documents()?.into_iter().collect();
+        let mut ingested = 0;
+        let mut failed = 0;
+        for uid in uids {
+            match self.ingest_message(&mut session, uid, &mut taken) {
+                Ok(0) => {}
+                Ok(count) => {
+                    ingested += count;
+                    if self.dry_run {
+                        continue;
+                    }
+                    if let Err(e) = mark_processed(&mut session, uid, mail) {
+                        // not fatal, but it'll be ingested again next time
+                        warn!("couldn't mark message {uid} as processed: {e:#}");
+                    }
+                }
+                Err(e) => {
+                    warn!("failed to ingest message {uid}: {e:#}");
+                    failed += 1;
+                }
+            }
+        }
+        session.logout().ok();
+
+        if ingested > 0 && !self.dry_run {
+            self.publish_manifest()?;
+            self.commit_index(&format!("Ingest {ingested} documents from mail"));
+        }
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: ingested + failed,
+            }
+            .into());
+        }
+        info!("done!");
+        Ok(())
+    }
+
+    /// Ingests one message's attachments, returning how many there were.
+    fn ingest_message(
+        &self,
+        session: &mut Session,
+        uid: u32,
+        taken: &mut HashSet<String>,
+    ) -> Result<usize> {
+        // peeking leaves the message unread if anything goes wrong
+        let fetches = session
+            .uid_fetch(uid.to_string(), "BODY.PEEK[]")
+            .context("downloading message")?;
+        let Some(raw) = fetches.iter().next().and_then(|it| it.body()) else {
+            bail!("server sent an empty message");
+        };
+        let Some(message) = MessageParser::default().parse(raw) else {
+            bail!("couldn't parse message");
+        };
+
+        let subject = message.subject().unwrap_or("(no subject)");
+        let sent = match message.date() {
+            Some(date) => Timestamp::from_second(date.to_timestamp())?,
+            None => Timestamp::now(),
+        };
+
+        let mut count = 0;
+        let mut ingested = Vec::new();
+        for (i, attachment) in message.attachments().enumerate() {
+            let name = attachment
+                .attachment_name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("attachment-{i}"));
+            let content_type = attachment
+                .content_type()
+                .map(|it| (it.ctype(), it.subtype()));
+            let Some(extension) = attachment_extension(content_type, &name) else {
+                debug!("skipping {name} in {subject:?}, it isn't a PDF or image");
+                continue;
+            };
+
+            let id = self.ids.unique(taken, sent);
+            if self.dry_run {
+                println!("would ingest {name} from {subject:?} as {id}");
+                taken.insert(id);
+                count += 1;
+                continue;
+            }
+
+            info!("ingesting {name} from {subject:?} as {id}..");
+            let added = self
+                .ingest_attachment(attachment.contents(), extension, &id)
+                .context(format!("ingesting {name}"));
+            if let Err(e) = added {
+                // the message stays unread, so take back its other attachments too rather than
+                // ingesting them twice when it's tried again
+                self.undo_ingested(&ingested);
+                return Err(e);
+            }
+            taken.insert(id.clone());
+            ingested.push(id);
+        }
+
+        count += ingested.len();
+        if count == 0 {
+            debug!("nothing to ingest in {subject:?}");
+        }
+        Ok(count)
+    }
+
+    fn ingest_attachment(&self, contents: &[u8], extension: &str, id: &str) -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join(format!("page.{extension}"));
+        fs::write(&path, contents)?;
+        if extension == "pdf" {
+            self.add_pdf_as(&path, id, false)
+        } else {
+            self.add_pages_as(temp_dir.path(), id, false)
+        }
+    }
+
+    /// Removes documents ingested from a message that then failed, from the index and the
+    /// remote.
+    fn undo_ingested(&self, ids: &[String]) {
+        for id in ids {
+            info!("taking back {id}..");
+            let undone = self
+                .remove_from_index(id)
+                .and_then(|_| self.delete_remote(id));
+            match undone {
+                Ok(()) => self.note_remote_change(Change::Removed(id.clone())),
+                Err(e) => warn!("couldn't take back {id}, so it'll be ingested twice: {e:#}"),
+            }
+            if let Some(local) = self.local_copy(id) {
+                fs::remove_file(&local).ok();
+            }
+        }
+    }
+}
+
+fn mark_processed(session: &mut Session, uid: u32, mail: &Mail) -> Result<()> {
+    session.uid_store(uid.to_string(), "+FLAGS (\\Seen)")?;
+    if let Some(folder) = &mail.processed_folder {
+        session
+            .uid_mv(uid.to_string(), folder)
+            .context(format!("moving to {folder}"))?;
+    }
+    Ok(())
+}
@@ -1,278 +1,1732 @@
 use std::{
-    collections::HashSet,
-    env,
-    ffi::OsStr,
-    fmt::Debug,
-    fs::{self, File},
-    io::{Read, Write},
-    path::{Path, PathBuf},
-    process::Command,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
 };
 
-use clap::{command, Parser, Subcommand};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use clap_complete::{engine::ArgValueCandidates, CompleteEnv, Shell};
 use eyre::{bail, Context, Result};
-use jsonpath_rust::JsonPath;
-use rusty_tesseract::Image;
-use serde::Deserialize;
-use serde_json::Value;
+use indicatif::ProgressDrawTarget;
+use kartka::{
+    checklist::Span,
+    config, error, import, metadata, pages, pdf, progress,
+    quarantine::Quarantine,
+    saved_search::SavedSearch,
+    scan,
+    search::{self, Sort},
+    semantic, Kartka,
+};
+use tracing::{info, warn};
+use tracing_subscriber::{
+    filter::{filter_fn, LevelFilter},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+mod clipboard;
+mod completions;
+mod docs;
+mod doctor;
+mod init;
+mod notify;
+mod pick;
+mod preview;
+mod serve;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_long_help = docs::AFTER_HELP)]
 struct Args {
     #[command(subcommand)]
     mode: Mode,
+    /// Path to the config file, instead of looking in the usual places
+    #[arg(long, global = true, env = "KARTKA_CONFIG")]
+    config: Option<PathBuf>,
+    /// Which `[profiles.<name>]` section of the config to use
+    #[arg(long, global = true, env = "KARTKA_PROFILE")]
+    profile: Option<String>,
+    /// Plain line-oriented output: no progress indicators, colours, or interactive prompts
+    #[arg(long, global = true)]
+    plain: bool,
+    /// Print what `scan`, `hydrate` and `delete` would do without touching any files or the
+    /// remote
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Print more detail, including how long each stage takes; repeat for even more
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Only print warnings; repeat to only print errors
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    quiet: u8,
+    /// Also append a detailed, timestamped log to this file
+    #[arg(long, global = true, env = "KARTKA_LOG_FILE")]
+    log_file: Option<PathBuf>,
+    /// Send a desktop notification when `scan`, `hydrate` or `sync` finishes, for leaving them
+    /// running in the background
+    #[arg(long, global = true)]
+    notify: bool,
+    /// Tesseract page segmentation mode to read pages with this time (overrides `[ocr]`)
+    #[arg(long, global = true, value_name = "MODE", help_heading = "OCR")]
+    psm: Option<i32>,
+    /// Tesseract OCR engine mode to read pages with this time (overrides `[ocr]`)
+    #[arg(long, global = true, value_name = "MODE", help_heading = "OCR")]
+    oem: Option<i32>,
+    /// Resolution tesseract reads pages at this time (overrides `[ocr]`)
+    #[arg(long, global = true, value_name = "DPI", help_heading = "OCR")]
+    ocr_dpi: Option<i32>,
+    /// Set a tesseract parameter this time, on top of `[ocr]`'s `config_variables`; can be
+    /// repeated
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME=VALUE",
+        value_parser = parse_config_variable,
+        help_heading = "OCR"
+    )]
+    ocr_config: Vec<(String, String)>,
 }
 
 #[derive(Debug, Subcommand)]
 enum Mode {
-    Scan,
-    Search { query: String },
-    Hydrate,
+    /// Interactively create the kartka config
+    #[command(after_long_help = "Examples:\n  kartka init\n  kartka --config ./kartka.toml init")]
+    Init,
+    /// OCR the scanned pages waiting in the scan dir (or the files given), then upload them as
+    /// one PDF
+    #[command(
+        after_long_help = "Examples:\n  kartka scan\n  kartka --dry-run scan\n  kartka scan --no-dupes\n  kartka scan --device 'airscan:e0:Brother MFC-L2710DW' --adf\n  kartka scan ~/Downloads/invoice.pdf\n  kartka scan --psm 4"
+    )]
+    Scan {
+        /// Images or PDFs to scan instead of the scan dir; they're left where they are
+        #[arg(conflicts_with = "device")]
+        files: Vec<PathBuf>,
+        /// Scan the pages with this SANE device first (see `scanimage -L`)
+        #[arg(long)]
+        device: Option<String>,
+        /// Take every page in the scanner's document feeder, rather than one at a time from
+        /// the flatbed
+        #[arg(long, requires = "device")]
+        adf: bool,
+        /// Refuse to scan a letter that looks like one already in the index, rather than just
+        /// warning
+        #[arg(long)]
+        no_dupes: bool,
+        /// Keep blank pages, rather than leaving them out of the text and the PDF
+        #[arg(long)]
+        keep_blank: bool,
+        /// Compress pages as JPEGs of this quality, from 1 to 100 (overrides `[pdf]`)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        jpeg_quality: Option<u8>,
+        /// Scale pages to this many dots per inch (overrides `[pdf]`)
+        #[arg(long)]
+        dpi: Option<u32>,
+        /// Store pages in shades of grey rather than colour
+        #[arg(long, alias = "grayscale")]
+        greyscale: bool,
+        /// Make a PDF/A, the kind made to be archived (overrides `[pdf]`)
+        #[arg(long)]
+        pdfa: bool,
+        /// Protect the PDFs with a password: the one `[pdf]`'s `password_command` prints, or
+        /// else one that's asked for
+        #[arg(long, conflicts_with = "pdfa")]
+        protect: bool,
+        /// Offer to delete the scan dir's files for good afterwards, rather than moving them to
+        /// the trash
+        #[arg(long, conflicts_with = "files")]
+        purge: bool,
+        /// Add every this many pages as a document of their own, for a stack of letters that
+        /// are all the same length
+        #[arg(long, value_name = "N")]
+        split_every: Option<NonZeroUsize>,
+        /// List the pages, then ask which of them make up each document and what to call it,
+        /// for a stack of several letters
+        #[arg(long, conflicts_with = "split_every")]
+        interactive: bool,
+        /// List the pages, then move, turn or drop them before they're put together, e.g. to
+        /// fix the order of a stack fed through twice to scan both sides
+        #[arg(long)]
+        review: bool,
+    },
+    /// Add what's in the scan dir as a new version of a document, keeping the old version
+    #[command(
+        after_long_help = "Examples:\n  kartka rescan 2024_01_31_09_15_00.pdf\n  kartka --dry-run rescan 2024_01_31_09_15_00.pdf"
+    )]
+    Rescan {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Offer to delete the scan dir's files for good afterwards, rather than moving them to
+        /// the trash
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Find documents whose text matches a regex, ignoring case and accents, narrowed down by
+    /// any fields like `tag:invoice`, `date:2024-03` or `amount:>100`
+    #[command(
+        after_long_help = "Examples:\n  kartka search 'council tax'\n  kartka search 'invoice.*2024'\n  kartka search --semantic 'car repair bill'\n  kartka search 'tag:invoice date:2024-03 amount:>100'\n  kartka search --in 2024_01_31_09_15_00.pdf 'notice period'\n  kartka search --codes 'https://example.com/pay'\n  kartka search --collection 'Taxes 2024' p60\n  kartka search --save taxes 'HMRC|tax return'\n  kartka search --saved taxes\n  kartka search --sort relevance --limit 5 'boiler service'\n  kartka search --copy --sort relevance 'council tax'"
+    )]
+    Search {
+        #[arg(required_unless_present = "saved")]
+        query: Option<String>,
+        /// Find the documents closest in meaning to the query, using `[embeddings]`
+        #[arg(long)]
+        semantic: bool,
+        /// Search just this document, printing the lines that match
+        #[arg(
+            long = "in",
+            value_name = "ID",
+            conflicts_with_all = ["semantic", "sort", "limit", "offset"],
+            add = ArgValueCandidates::new(completions::document_ids)
+        )]
+        within: Option<String>,
+        /// Search what the QR codes and barcodes on the pages say, rather than the text
+        #[arg(long, conflicts_with_all = ["semantic", "within"])]
+        codes: bool,
+        /// Only search the documents in this collection
+        #[arg(long, conflicts_with = "within", add = ArgValueCandidates::new(completions::collections))]
+        collection: Option<String>,
+        /// Save the search under this name as well as running it, to run again with --saved
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["within", "codes"])]
+        save: Option<String>,
+        /// Run the search saved under this name
+        #[arg(
+            long,
+            value_name = "NAME",
+            conflicts_with_all = ["query", "semantic", "within", "codes", "save"],
+            add = ArgValueCandidates::new(completions::saved_searches)
+        )]
+        saved: Option<String>,
+        #[command(flatten)]
+        order: Order,
+        /// Put the first document's link, or its path if there's a copy on this computer, on
+        /// the clipboard
+        #[arg(long, conflicts_with = "within")]
+        copy: bool,
+    },
+    /// List the searches saved with `search --save`
+    #[command(after_long_help = "Examples:\n  kartka searches\n  kartka searches --forget taxes")]
+    Searches {
+        /// Forget the search saved under this name
+        #[arg(long, value_name = "NAME", add = ArgValueCandidates::new(completions::saved_searches))]
+        forget: Option<String>,
+    },
+    /// List every document in the index, newest first
+    #[command(
+        after_long_help = "Examples:\n  kartka list\n  kartka list --thumbnails\n  kartka list --collection 'House purchase'\n  kartka list --sort name --limit 20 --offset 20"
+    )]
+    List {
+        /// Draw each document's thumbnail too, if the terminal can draw images
+        #[arg(long)]
+        thumbnails: bool,
+        /// Only list the documents in this collection
+        #[arg(long, add = ArgValueCandidates::new(completions::collections))]
+        collection: Option<String>,
+        #[command(flatten)]
+        order: Order,
+    },
+    /// List the documents added to the index most recently, to check a scanning session
+    /// all arrived
+    #[command(
+        after_long_help = "Examples:\n  kartka recent\n  kartka recent 30\n  kartka recent --thumbnails"
+    )]
+    Recent {
+        /// How many to list
+        #[arg(default_value_t = 10)]
+        count: usize,
+        /// Draw each document's first page too, if the terminal can draw images
+        #[arg(long)]
+        thumbnails: bool,
+    },
+    /// Bring in existing PDFs and images, ones from a phone scanning app, or a paperless-ngx export
+    #[command(
+        after_long_help = "Examples:\n  kartka import ~/Documents/old-scans\n  kartka import --from google-drive ~/Downloads/drive-scans\n  kartka import --from ios 'Scanned Document.pdf' 'Scanned Document 2.pdf'\n  kartka import --paperless ~/paperless-export"
+    )]
+    Import {
+        /// Which app made them: `google-drive` or `ios`, or `files` for anything else
+        #[arg(long, default_value = "files")]
+        from: import::Source,
+        /// Skip documents that look like one already indexed, not just identical files
+        #[arg(long)]
+        no_dupes: bool,
+        /// Import the export folder paperless-ngx's `document_exporter` wrote, keeping its
+        /// tags, correspondents and document types
+        #[arg(long, conflicts_with = "from")]
+        paperless: bool,
+        /// PDFs and images, or directories of them
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Index and upload the PDF and image attachments of unread mail in the `[mail]` folder
+    #[command(
+        name = "ingest-mail",
+        after_long_help = "Examples:\n  kartka ingest-mail\n  kartka --dry-run ingest-mail"
+    )]
+    IngestMail,
+    /// Summarise documents with the `[summaries]` LLM
+    #[command(
+        after_long_help = "Examples:\n  kartka summarise\n  kartka summarise 2024_01_31_09_15_00.pdf"
+    )]
+    Summarise {
+        /// Documents to (re)summarise; defaults to every document without a summary
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        ids: Vec<String>,
+    },
+    /// Pick totals, currencies, invoice numbers and due dates out of documents
+    #[command(
+        after_long_help = "Examples:\n  kartka extract\n  kartka extract 2024_01_31_09_15_00.pdf"
+    )]
+    Extract {
+        /// Documents to (re)read; defaults to every document without any fields yet
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        ids: Vec<String>,
+    },
+    /// Pull the tables out of a document, like the transactions on a statement, as CSV
+    #[command(
+        after_long_help = "Examples:\n  kartka extract-table 2024_01_31_09_15_00.pdf\n  kartka extract-table 2024_01_31_09_15_00.pdf --page 2 --out statement.csv"
+    )]
+    ExtractTable {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Only look on this page, counting from 1
+        #[arg(long)]
+        page: Option<usize>,
+        /// Write the CSV to this file rather than stdout
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    /// Embed documents with the `[embeddings]` model, for `search --semantic`
+    #[command(
+        after_long_help = "Examples:\n  kartka embed\n  kartka embed 2024_01_31_09_15_00.pdf"
+    )]
+    Embed {
+        /// Documents to (re)embed; defaults to every document that isn't embedded yet
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        ids: Vec<String>,
+    },
+    /// Download and index every PDF on the remote that isn't in the index yet
+    #[command(
+        after_long_help = "Examples:\n  kartka hydrate\n  kartka --log-file hydrate.log -v hydrate\n  kartka hydrate --retry-quarantined\n  kartka hydrate --jobs 4\n  kartka hydrate --watch 10m"
+    )]
+    Hydrate {
+        /// Try quarantined documents again too
+        #[arg(long)]
+        retry_quarantined: bool,
+        /// List the remote to find what's missing, rather than trusting its manifest
+        #[arg(long)]
+        list: bool,
+        /// How many documents to download, convert and OCR at once
+        #[arg(short, long, default_value = "1")]
+        jobs: NonZeroUsize,
+        /// Keep checking the remote for new documents this often (like 10m or 1h), until Ctrl-C
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        watch: Option<Duration>,
+    },
+    /// Scan any leftover pages, hydrate anything missing from the index, and report documents
+    /// missing from the remote
+    #[command(
+        after_long_help = "Examples:\n  kartka sync\n  kartka --dry-run sync\n  kartka sync --jobs 4"
+    )]
+    Sync {
+        /// How many documents to download, convert and OCR at once
+        #[arg(short, long, default_value = "1")]
+        jobs: NonZeroUsize,
+    },
+    /// Show what's been done to documents, from the audit log
+    #[command(
+        after_long_help = "Examples:\n  kartka log\n  kartka log 2024_01_31_09_15_00.pdf\n  kartka log --failed --since 2024-01-01"
+    )]
+    Log {
+        /// Only show what's been done to this document
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: Option<String>,
+        /// Only show operations that failed
+        #[arg(long)]
+        failed: bool,
+        /// Only show operations on or after this date, e.g. 2024-01-31
+        #[arg(long)]
+        since: Option<jiff::civil::Date>,
+    },
+    /// Remove a document from the index and the remote
+    #[command(
+        after_long_help = "Examples:\n  kartka delete 2024_01_31_09_15_00.pdf\n  kartka --dry-run delete 2024_01_31_09_15_00.pdf"
+    )]
+    Delete {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Don't ask for confirmation before deleting
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Find documents only in the index or only on the remote, such as ones deleted or added in
+    /// Dropbox by hand, and offer to put each right
+    #[command(
+        after_long_help = "Examples:\n  kartka gc\n  kartka --dry-run gc\n  kartka gc --yes"
+    )]
+    Gc {
+        /// Put everything right without asking
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Sign in to Google Drive and set `remote` up in rclone as the folder in `[google_drive]`
+    #[command(after_long_help = "Examples:\n  kartka google-drive")]
+    GoogleDrive,
+    /// Rename a document in the index and on the remote
+    #[command(
+        after_long_help = "Examples:\n  kartka rename 2024_01_31_09_15_00.pdf 2024_01_31_council_tax.pdf"
+    )]
+    Rename {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        new_id: String,
+    },
+    /// Record what kind of document this is and who sent it
+    #[command(
+        after_long_help = "Examples:\n  kartka classify 2024_01_31_09_15_00.pdf --type payslip --correspondent ACME\n  kartka classify 2024_01_31_09_15_00.pdf --tag tax --untag todo\n  kartka classify 2024_01_31_09_15_00.pdf"
+    )]
+    Classify {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        #[command(flatten)]
+        classification: ClassifyArgs,
+    },
+    /// Group documents that belong together, like "Taxes 2024", without moving them
+    Collection {
+        #[command(subcommand)]
+        action: CollectionAction,
+    },
+    /// Report expected recurring documents that are missing
+    #[command(after_long_help = "Examples:\n  kartka checklist\n  kartka checklist 2024-03")]
+    Checklist {
+        /// A year (`2024`) or month (`2024-03`); defaults to the current year
+        period: Option<Span>,
+    },
+    /// Bundle a year's tax documents into a single bookmarked PDF
+    #[command(
+        after_long_help = "Examples:\n  kartka taxpack 2024 --rules taxes.toml\n  kartka taxpack 2024 --rules taxes.toml --out ~/Desktop/taxes.pdf"
+    )]
+    Taxpack {
+        year: i16,
+        /// Which documents to include, and in what order
+        #[arg(long)]
+        rules: PathBuf,
+        /// Where to write the PDF; defaults to `taxpack-<year>.pdf`
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Upload a snapshot of the index to the remote
+    #[command(after_long_help = "Examples:\n  kartka backup")]
+    Backup,
+    /// Replace the index with a backup from the remote
+    #[command(
+        after_long_help = "Examples:\n  kartka restore\n  kartka restore --list\n  kartka restore index-2024_01_31_09_15_00.tar.gz"
+    )]
+    Restore {
+        /// The backup to restore; defaults to the latest
+        name: Option<String>,
+        /// List the backups instead
+        #[arg(long, conflicts_with = "name")]
+        list: bool,
+        /// Don't ask before replacing an existing index
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Copy every document, its text, and its metadata into a folder that doesn't need kartka
+    /// (or paperless-ngx), or with `--csv` write a spreadsheet of receipts' and invoices' totals
+    #[command(
+        after_long_help = "Examples:\n  kartka export ~/kartka-export\n  kartka export --csv expenses.csv\n  kartka export --csv - | grep 2024\n  kartka export --paperless ~/paperless-export"
+    )]
+    Export {
+        /// Where to put the export; made if it doesn't exist
+        #[arg(required_unless_present = "csv")]
+        dir: Option<PathBuf>,
+        /// Write the totals, currencies, invoice numbers and due dates picked out of documents
+        /// to this CSV file instead (`-` for stdout)
+        #[arg(long, value_name = "FILE", conflicts_with = "dir")]
+        csv: Option<PathBuf>,
+        /// Write it as a paperless-ngx export instead, for its `document_importer` to read
+        #[arg(long, conflicts_with = "csv")]
+        paperless: bool,
+    },
+    /// Check the remote archive for damage or tampering
+    #[command(after_long_help = "Examples:\n  kartka verify\n  kartka verify --integrity")]
+    Verify {
+        /// Check every document against the signed manifest
+        #[arg(long)]
+        integrity: bool,
+        /// Record the remote's current hash for documents indexed before hashes were kept
+        #[arg(long, conflicts_with = "integrity")]
+        record: bool,
+        /// Compare the index with the remote's manifest rather than reading back every document
+        #[arg(long, conflicts_with_all = ["integrity", "record"])]
+        quick: bool,
+    },
+    /// Add any documents missing from the signed manifest, then re-sign and upload it
+    Sign,
+    /// Move old documents to the cold storage remote
+    #[command(after_long_help = "Examples:\n  kartka archive\n  kartka archive --yes")]
+    Archive {
+        /// Don't ask for confirmation before moving
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Open a document's preview, restoring it from cold storage first if need be
+    #[command(
+        after_long_help = "Examples:\n  kartka open 2024_01_31_09_15_00.pdf\n  kartka open --copy 2024_01_31_09_15_00.pdf"
+    )]
+    Open {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Put its link, or its path if there's a copy on this computer, on the clipboard
+        /// rather than opening it
+        #[arg(long)]
+        copy: bool,
+    },
+    /// Download a document, decrypted, and print where it went
+    #[command(
+        after_long_help = "Examples:\n  kartka get 2024_01_31_09_15_00.pdf\n  kartka get 2024_01_31_09_15_00.pdf ~/Desktop"
+    )]
+    Get {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Where to put it, or a directory to put it in; a temporary directory if not given
+        dest: Option<PathBuf>,
+    },
+    /// Choose a document by fuzzy-finding it, previewing the start of each, and open it
+    Pick,
+    /// Show a document's details, the start of its text, and its first page if the terminal
+    /// can draw images; or with a query, the page it matches best with the matches boxed
+    #[command(
+        after_long_help = "Examples:\n  kartka show 2024_01_31_09_15_00.pdf\n  kartka show 2024_01_31_09_15_00.pdf net pay\n  kartka show 2024_01_31_09_15_00.pdf 'net pay' --save page.png\n  kartka show --codes 2024_01_31_09_15_00.pdf"
+    )]
+    Show {
+        #[arg(add = ArgValueCandidates::new(completions::document_ids))]
+        id: String,
+        /// Words to find on the page; needs `hocr = true` under `[ocr]` when it was read
+        query: Vec<String>,
+        /// Save the page with the matches boxed to this PNG, rather than drawing it in the
+        /// terminal
+        #[arg(long, value_name = "PNG", requires = "query")]
+        save: Option<PathBuf>,
+        /// Print what the QR codes and barcodes on its pages say, read with `read_codes = true`
+        #[arg(long, conflicts_with_all = ["query", "save"])]
+        codes: bool,
+    },
+    /// Serve a web page for searching the archive from a browser
+    #[command(after_long_help = "Examples:\n  kartka serve\n  kartka serve --listen 0.0.0.0:8080")]
+    Serve {
+        /// Address to listen on; use `0.0.0.0:<port>` to let other devices on the network in
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
+    /// Print a shell completion script, which completes document ids and tags too
+    #[command(
+        after_long_help = "Examples:\n  source <(kartka completions bash)\n  kartka completions zsh > ~/.zfunc/_kartka\n  kartka completions fish > ~/.config/fish/completions/kartka.fish"
+    )]
+    Completions { shell: Shell },
+    /// Check that the tools, remote, and directories kartka needs are all working
+    Doctor,
+    /// Look after the index itself
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+    /// Upgrade the index to the layout this kartka uses, and move it into `index_backend` if
+    /// that's been changed
+    #[command(after_long_help = "Examples:\n  kartka migrate\n  kartka --dry-run migrate")]
+    Migrate,
+    /// Summarise the index, including documents quarantined after failing to hydrate
+    Status,
+    /// Show how many documents there are, how fast they're coming in, and how well they OCR'd
+    Stats,
+    /// Generate documentation, such as man pages
+    Docs {
+        #[command(subcommand)]
+        action: DocsAction,
+    },
 }
 
-const CONFIG_PATH: &str = ".config/kartka.toml";
+#[derive(Debug, Subcommand)]
+enum DocsAction {
+    /// Write man pages for kartka and each of its subcommands
+    #[command(after_long_help = "Examples:\n  kartka docs generate --out share/man/man1")]
+    Generate {
+        /// The directory to write the pages to
+        #[arg(long, default_value = "man")]
+        out: PathBuf,
+    },
+}
 
-#[derive(Debug, Deserialize)]
-struct Kartka {
-    scan_dir: PathBuf,
-    index_dir: PathBuf,
+#[derive(Debug, Subcommand)]
+enum CollectionAction {
+    /// Put documents in a collection, making it if it's new
+    #[command(
+        after_long_help = "Examples:\n  kartka collection add 'Taxes 2024' 2024_04_20_10_02_11.pdf 2024_05_31_09_15_00.pdf"
+    )]
+    Add {
+        #[arg(add = ArgValueCandidates::new(completions::collections))]
+        name: String,
+        #[arg(required = true, add = ArgValueCandidates::new(completions::document_ids))]
+        ids: Vec<String>,
+    },
+    /// Take documents out of a collection, leaving them in the index
+    #[command(
+        after_long_help = "Examples:\n  kartka collection remove 'Taxes 2024' 2024_05_31_09_15_00.pdf"
+    )]
+    Remove {
+        #[arg(add = ArgValueCandidates::new(completions::collections))]
+        name: String,
+        #[arg(required = true, add = ArgValueCandidates::new(completions::document_ids))]
+        ids: Vec<String>,
+    },
+    /// List every collection, with how many documents are in each
+    List,
+    /// List the documents in a collection
+    #[command(after_long_help = "Examples:\n  kartka collection show 'House purchase'")]
+    Show {
+        #[arg(add = ArgValueCandidates::new(completions::collections))]
+        name: String,
+    },
 }
 
-#[derive(Debug)]
-struct UploadContent {
-    name: String,
-    content: String,
+#[derive(Debug, Subcommand)]
+enum IndexAction {
+    /// Throw away the index's text and read every document again, keeping their tags and
+    /// types, after changing OCR settings or if the index is damaged
+    #[command(
+        after_long_help = "Examples:\n  kartka index rebuild\n  kartka index rebuild --remote --jobs 4\n  kartka --dry-run index rebuild"
+    )]
+    Rebuild {
+        /// Download every document from the remote, rather than reading the copies in
+        /// `local_archive_dir`
+        #[arg(long)]
+        remote: bool,
+        /// How many documents to download, convert and OCR at once, with --remote
+        #[arg(short, long, default_value = "1", requires = "remote")]
+        jobs: NonZeroUsize,
+        /// Don't ask for confirmation first
+        #[arg(long)]
+        yes: bool,
+    },
 }
 
-impl Kartka {
-    fn index(&self) -> &Path {
-        &self.index_dir
-    }
-
-    fn scans(&self) -> &Path {
-        &self.scan_dir
-    }
-
-    fn search(&self, search_str: &str) -> Result<()> {
-        let output = Command::new("rg")
-            .arg("--json")
-            .arg("-i")
-            .arg(search_str)
-            .current_dir(self.index())
-            .output()
-            .context("running ripgrep")?;
-        let stdout_str = String::from_utf8_lossy(&output.stdout);
-        let match_type_path = JsonPath::try_from("$.type")?;
-        let match_file_path = JsonPath::try_from("$.data.path.text")?;
-        let ids: HashSet<String> = stdout_str
-            .lines()
-            .map(|it| serde_json::from_str(it).unwrap())
-            .filter(|it| &extract_path(it, &match_type_path) == "match")
-            .map(|it| extract_path(&it, &match_file_path))
-            .flat_map(|it| {
-                Path::new(&it)
-                    .file_name()
-                    .and_then(OsStr::to_str)
-                    .map(str::to_string)
-            })
-            .collect();
+#[derive(Debug, clap::Args)]
+struct Order {
+    /// What order to print them in: `relevance` (best match first, for searches), `date`
+    /// (newest first), or `name` (the name after the date in each id)
+    #[arg(long)]
+    sort: Option<search::Sort>,
+    /// Only print this many
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Skip this many first, to page through them with --limit
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+}
 
-        let mut links: Vec<_> = ids
+impl Order {
+    /// The page of `items` asked for.
+    fn page<T>(&self, items: Vec<T>) -> Vec<T> {
+        items
             .into_iter()
-            .map(|it| format!("https://www.dropbox.com/home/Apps/kartka?preview={it}"))
-            .collect();
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
 
-        links.sort();
-        links.reverse();
-        for link in links {
-            println!("{link}");
+#[derive(Debug, clap::Args)]
+struct ClassifyArgs {
+    #[arg(long = "type")]
+    doc_type: Option<String>,
+    #[arg(long)]
+    correspondent: Option<String>,
+    /// Add a tag; can be repeated
+    #[arg(long, add = ArgValueCandidates::new(completions::tags))]
+    tag: Vec<String>,
+    /// Remove a tag; can be repeated
+    #[arg(long, add = ArgValueCandidates::new(completions::tags))]
+    untag: Vec<String>,
+}
+
+impl From<ClassifyArgs> for metadata::Classification {
+    fn from(args: ClassifyArgs) -> Self {
+        metadata::Classification {
+            doc_type: args.doc_type,
+            correspondent: args.correspondent,
+            add_tags: args.tag,
+            remove_tags: args.untag,
         }
-        Ok(())
     }
+}
 
-    fn upload(&self, content: &UploadContent) -> Result<()> {
-        let content_path = self.index().join(&content.name);
+/// How many lines of text `show` prints.
+const SHOW_LINES: usize = 12;
+/// How wide previews are drawn, in pixels.
+const PREVIEW_WIDTH: u32 = 600;
 
-        if let Ok(mut out) = File::create_new(&content_path) {
-            out.write_all(&content.content.clone().into_bytes())?;
-        } else {
-            bail!("could not create file at {content_path:?}");
+fn show(kartka: &Kartka, id: &str) -> Result<()> {
+    let text = kartka.text(id)?;
+    let meta = kartka.metadata(id)?;
+    let archived = kartka.is_archived(id)?;
+
+    println!("{id}");
+    println!("{}", kartka.preview_link(id));
+    if let Some(doc_type) = &meta.doc_type {
+        println!("type: {doc_type}");
+    }
+    if let Some(correspondent) = &meta.correspondent {
+        println!("from: {correspondent}");
+    }
+    if !meta.tags.is_empty() {
+        println!("tags: {}", meta.tags.join(", "));
+    }
+    if !meta.collections.is_empty() {
+        println!("collections: {}", meta.collections.join(", "));
+    }
+    if let Some(language) = &meta.language {
+        println!("language: {language}");
+    }
+    if let Some(confidence) = meta.ocr_confidence {
+        println!("OCR confidence: {confidence:.0}%");
+    }
+    if let Some(summary) = &meta.summary {
+        println!("summary: {summary}");
+    }
+    let fields = &meta.fields;
+    if let Some(total) = &fields.total {
+        println!(
+            "total: {total}{}",
+            fields
+                .currency
+                .as_ref()
+                .map(|it| format!(" {it}"))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(invoice_number) = &fields.invoice_number {
+        println!("invoice number: {invoice_number}");
+    }
+    if let Some(due_date) = fields.due_date {
+        println!("due: {due_date}");
+    }
+    if archived {
+        println!("in cold storage");
+    }
+    println!();
+    for line in text
+        .lines()
+        .filter(|it| !it.trim().is_empty())
+        .take(SHOW_LINES)
+    {
+        println!("    {line}");
+    }
+
+    // previews need the PDF, which cold storage can't hand over quickly
+    if let (false, false, Some(protocol)) = (kartka.plain, archived, preview::detect()) {
+        let temp_dir = tempfile::tempdir()?;
+        let png_path = temp_dir.path().join("preview.png");
+        kartka.first_page(id, PREVIEW_WIDTH, &png_path)?;
+        println!();
+        preview::show(protocol, &png_path)?;
+    }
+    Ok(())
+}
+
+/// How wide a page saved by `show --save` is, which is big enough to read.
+const SAVE_WIDTH: u32 = 1600;
+
+/// Draws the page of `id` that `query` matches best with the matches boxed, or saves it to
+/// `save`.
+fn show_matches(kartka: &Kartka, id: &str, query: &str, save: Option<PathBuf>) -> Result<()> {
+    let protocol = match (&save, kartka.plain, preview::detect()) {
+        (Some(_), _, _) => None,
+        (None, false, Some(protocol)) => Some(protocol),
+        (None, _, _) => {
+            bail!("this terminal can't draw images - pass --save to write the page to a file")
         }
+    };
+    let temp_dir = tempfile::tempdir()?;
+    let (out, width) = match &save {
+        Some(save) => (save.clone(), SAVE_WIDTH),
+        None => (temp_dir.path().join("matches.png"), PREVIEW_WIDTH),
+    };
+    let Some(highlighted) = kartka.highlight(id, query, width, &out)? else {
+        bail!("{query:?} isn't on any page of {id}");
+    };
+    let matches = match highlighted.hits {
+        1 => "1 match".to_string(),
+        n => format!("{n} matches"),
+    };
+    println!("{id}: {matches} on page {}", highlighted.page);
+    match protocol {
+        Some(protocol) => preview::show(protocol, &out)?,
+        None => println!("saved to {out:?}"),
+    }
+    Ok(())
+}
 
-        Ok(())
+/// Writes the tables on `id` to `out`, or stdout, as CSV with a blank line between tables.
+fn extract_table(
+    kartka: &Kartka,
+    id: &str,
+    page: Option<usize>,
+    out: Option<PathBuf>,
+) -> Result<()> {
+    let tables = kartka.extract_tables(id, page)?;
+    if tables.is_empty() {
+        bail!("couldn't find any tables in {id}");
     }
+    let mut csv: Box<dyn Write> = match &out {
+        Some(out) => Box::new(io::BufWriter::new(
+            fs::File::create(out).context(format!("creating {out:?}"))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    for (i, table) in tables.iter().enumerate() {
+        if i > 0 {
+            writeln!(csv)?;
+        }
+        info!("table {} is on page {}", i + 1, table.page);
+        table.write_csv(&mut csv)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
 
-    fn read_and_index(&self, dir: &Path, output_name: &str) -> Result<()> {
-        let mut content = String::new();
+/// Prints what the codes on `id`'s pages say.
+fn show_codes(kartka: &Kartka, id: &str) -> Result<()> {
+    let codes = kartka.metadata(id)?.codes;
+    if codes.is_empty() {
+        println!("no codes found on {id}");
+    }
+    for code in codes {
+        println!("page {}  {}: {}", code.page, code.kind, code.data);
+    }
+    Ok(())
+}
 
-        let mut entries: Vec<_> = dir
-            .read_dir()
-            .context(format!("reading dir: {:?}", dir))?
-            .collect::<Result<_, _>>()?;
+/// Lists `pages`, then asks for changes to their order until there are none left to make.
+fn review_pages(pages: &[PathBuf]) -> Result<Vec<pages::Page>> {
+    let mut order: Vec<_> = pages
+        .iter()
+        .map(|it| pages::Page {
+            path: it.clone(),
+            turn: 0,
+        })
+        .collect();
+    loop {
+        for (i, page) in order.iter().enumerate() {
+            let name = page.path.file_name().unwrap_or_default().to_string_lossy();
+            match page.turn {
+                0 => println!("{:>4}  {name}", i + 1),
+                turn => println!("{:>4}  {name} (turned {turn}°)", i + 1),
+            }
+        }
+        let answer = inquire::Text::new("Change:")
+            .with_help_message(
+                "`move 5 2`, `swap 1 2`, `turn 3` or `turn 3 180`, `drop 4`, `duplex` to put fronts and backs together - or nothing when they're right",
+            )
+            .prompt()?;
+        if answer.trim().is_empty() {
+            return Ok(order);
+        }
+        let changed = answer
+            .parse::<pages::Change>()
+            .and_then(|it| it.apply(&mut order));
+        if let Err(e) = changed {
+            println!("{e}");
+        }
+    }
+}
 
-        entries.sort_by_key(|it| it.file_name());
+/// Lists `pages`, then asks which of them make up each document and what it's called, until
+/// every page is in one.
+fn ask_parts(pages: &[PathBuf]) -> Result<Vec<scan::Part>> {
+    if pages.is_empty() {
+        return Ok(vec![]);
+    }
+    for (i, page) in pages.iter().enumerate() {
+        let name = page.file_name().unwrap_or_default().to_string_lossy();
+        println!("{:>4}  {name}", i + 1);
+    }
 
-        for dir_entry in entries.iter() {
-            // skip if can't read name or is hidden
-            if dir_entry
-                .file_name()
-                .to_str()
-                .map(|it| it.starts_with("."))
-                .unwrap_or(true)
-            {
+    let mut parts: Vec<scan::Part> = vec![];
+    let mut next = 1;
+    while next <= pages.len() {
+        let default = match next == pages.len() {
+            true => next.to_string(),
+            false => format!("{next}-{}", pages.len()),
+        };
+        let answer = inquire::Text::new(&format!("Pages of document {}:", parts.len() + 1))
+            .with_help_message("e.g. `3`, `3-5`, or `3-` for the rest")
+            .with_default(&default)
+            .prompt()?;
+        let range = match scan::page_range(&answer, pages.len()) {
+            Ok(range) if *range.start() != next => {
+                println!("the documents go in order, so this one has to start at page {next}");
                 continue;
             }
+            Ok(range) => range,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+        let title = inquire::Text::new("Title:")
+            .with_help_message("put on the end of its id - leave it empty for none")
+            .prompt()?;
+        next = range.end() + 1;
+        parts.push(scan::Part {
+            pages: range,
+            title: Some(title),
+        });
+    }
+    Ok(parts)
+}
 
-            let contents = Image::from_path(dir_entry.path()).context("open file for OCR")?;
-            let tsrt_args = rusty_tesseract::Args::default();
-            let output =
-                rusty_tesseract::image_to_string(&contents, &tsrt_args).context("running OCR")?;
-
-            content.push_str(&output);
-            content.push('\n');
+/// Runs `search`, printing a link to each document it finds, in `order`. Returns the ids of
+/// the documents printed.
+fn print_search(kartka: &Kartka, search: &SavedSearch, order: &Order) -> Result<Vec<String>> {
+    let hits = if search.semantic {
+        // they come best match first, so only as many as are wanted need finding
+        let wanted = order
+            .limit
+            .map_or(semantic::RESULTS, |it| it + order.offset);
+        let mut hits: Vec<_> = kartka
+            .semantic_search(&search.query, wanted)?
+            .into_iter()
+            .map(|(id, _)| (id, vec![]))
+            .collect();
+        order.sort.unwrap_or(Sort::Relevance).apply(&mut hits);
+        hits
+    } else {
+        kartka.search_sorted(&search.query, order.sort.unwrap_or_default())?
+    };
+    let hits = order.page(in_collection(kartka, hits, search.collection.as_deref())?);
+    for (id, pages) in &hits {
+        let mut link = kartka.preview_link(id);
+        if !pages.is_empty() {
+            let pages: Vec<_> = pages.iter().map(usize::to_string).collect();
+            link.push_str(&format!(" (page {})", pages.join(", ")));
+        }
+        if kartka.is_archived(id)? {
+            println!("{link} (in cold storage - `kartka open {id}` to restore)");
+        } else {
+            println!("{link}");
         }
+        if let Some(summary) = kartka.metadata(id)?.summary {
+            println!("    {summary}");
+        }
+    }
+    Ok(hits.into_iter().map(|(id, _)| id).collect())
+}
 
-        self.upload(&UploadContent {
-            name: output_name.to_string(),
-            content,
-        })
-        .context("uploading content")?;
+/// Puts the link to the first of `ids` on the clipboard, for `--copy`.
+fn copy_first(kartka: &Kartka, ids: &[String]) -> Result<()> {
+    let Some(id) = ids.first() else {
+        warn!("nothing was found, so there's nothing to copy");
+        return Ok(());
+    };
+    if kartka.is_archived(id)? {
+        warn!(
+            "{id} is in cold storage, so its link won't work until `kartka open {id}` restores it"
+        );
+    }
+    let link = kartka.preview_link(id);
+    clipboard::copy(&link)?;
+    info!("copied {link}");
+    Ok(())
+}
 
-        Ok(())
+/// The `hits` for documents in `collection`, or all of them without one.
+fn in_collection<T>(
+    kartka: &Kartka,
+    hits: Vec<(String, T)>,
+    collection: Option<&str>,
+) -> Result<Vec<(String, T)>> {
+    let Some(name) = collection else {
+        return Ok(hits);
+    };
+    // so a misspelt collection isn't mistaken for one with nothing matching in it
+    kartka.collection(name)?;
+    let mut kept = vec![];
+    for (id, hit) in hits {
+        if kartka.in_collection(&id, name)? {
+            kept.push((id, hit));
+        }
     }
+    Ok(kept)
+}
 
-    fn scan(&self) -> Result<()> {
-        let timestamp = jiff::Zoned::now().timestamp().strftime("%Y_%m_%d_%H_%M_%S");
-        let pdf_name = format!("{timestamp}.pdf");
-        self.read_and_index(self.scans(), &pdf_name)?;
+/// `id` with its type, correspondent and tags, as listed.
+fn describe(meta: &metadata::Metadata, id: &str) -> String {
+    let mut line = id.to_string();
+    if let Some(doc_type) = &meta.doc_type {
+        line.push_str(&format!("  {doc_type}"));
+    }
+    if let Some(correspondent) = &meta.correspondent {
+        line.push_str(&format!("  from {correspondent}"));
+    }
+    if !meta.tags.is_empty() {
+        line.push_str(&format!("  [{}]", meta.tags.join(", ")));
+    }
+    line
+}
 
-        println!("converting to PDF..");
-        let temp_dir = tempfile::tempdir()?;
-        Command::new("magick")
-            .arg(self.scans().join("*.png"))
-            .arg(temp_dir.path().join(&pdf_name))
-            .output()?;
+/// How to draw thumbnails, if they're wanted and the terminal can.
+fn thumbnail_protocol(kartka: &Kartka, thumbnails: bool) -> Option<preview::Protocol> {
+    let protocol = if thumbnails && !kartka.plain {
+        preview::detect()
+    } else {
+        None
+    };
+    if thumbnails && protocol.is_none() {
+        warn!("this terminal can't draw thumbnails, listing without them");
+    }
+    protocol
+}
 
-        upload_to_dropbox(temp_dir.path(), &pdf_name)?;
+fn show_thumbnail(kartka: &Kartka, protocol: preview::Protocol, id: &str) -> Result<()> {
+    // making a thumbnail for something in cold storage would mean restoring it
+    let thumbnail = if kartka.thumbnail_path(id).exists() || !kartka.is_archived(id)? {
+        kartka
+            .thumbnail(id)
+            .inspect_err(|e| warn!("no thumbnail for {id}: {e:#}"))
+            .ok()
+    } else {
+        None
+    };
+    if let Some(thumbnail) = thumbnail {
+        preview::show(protocol, &thumbnail)?;
+    }
+    Ok(())
+}
 
-        if inquire::Confirm::new("Delete files in scan dir?")
-            .with_default(false)
-            .prompt()?
-        {
-            for entry in self.scans().read_dir()? {
-                fs::remove_file(entry?.path())?;
-            }
-        }
+fn list(kartka: &Kartka, thumbnails: bool, collection: Option<&str>, order: &Order) -> Result<()> {
+    let protocol = thumbnail_protocol(kartka, thumbnails);
 
-        println!("done!");
-        Ok(())
+    let sort = match order.sort.unwrap_or_default() {
+        Sort::Relevance => {
+            bail!("there's nothing to sort a list by relevance to - try `date` or `name`")
+        }
+        sort => sort,
+    };
+    let ids = match collection {
+        Some(name) => kartka.collection(name)?,
+        None => kartka.documents()?,
+    };
+    let mut listed = vec![];
+    for id in ids {
+        if !kartka.is_old_version(&id)? {
+            listed.push(id);
+        }
     }
+    listed.sort_by(|a, b| sort.compare(a, b));
+    for id in &order.page(listed) {
+        let meta = kartka.metadata(id)?;
+        let mut line = describe(&meta, id);
+        if let Some(summary) = &meta.summary {
+            line.push_str(&format!("\n    {summary}"));
+        }
+        for (n, old) in meta.versions.iter().enumerate().rev() {
+            line.push_str(&format!("\n    version {}: {old}", n + 1));
+        }
 
-    fn rehydrate(&self) -> Result<()> {
-        // want to download all files that I don't have in my index
-        let remote_files: HashSet<_> = String::from_utf8(
-            Command::new("rclone")
-                .arg("lsf")
-                .arg("dropbox:")
-                .output()?
-                .stdout,
-        )?
-        .lines()
-        .map(|it| it.to_string())
-        .collect();
+        let Some(protocol) = protocol else {
+            println!("{line}");
+            continue;
+        };
+        show_thumbnail(kartka, protocol, id)?;
+        println!("{line}");
+        println!();
+    }
+    Ok(())
+}
 
-        let local_files: HashSet<_> = self
-            .index()
-            .read_dir()?
-            .into_iter()
-            .map(|res| {
-                res.map_err(|e| eyre::eyre!("{e:?}")).and_then(|it| {
-                    it.file_name()
-                        .into_string()
-                        .map_err(|e| eyre::eyre!("{e:?}"))
-                })
-            })
-            .collect::<Result<_>>()?;
-
-        let missing_files = remote_files.difference(&local_files);
-        let num_missing = missing_files.clone().count();
-        for (i, missing) in missing_files.enumerate() {
-            let temp_dir = tempfile::tempdir()?;
-            let dest = temp_dir.path().join(missing);
+fn status(kartka: &Kartka) -> Result<()> {
+    println!("index: {}", kartka.index().display());
+    println!("documents: {}", kartka.documents()?.len());
 
+    let quarantine = Quarantine::load(kartka.index())?;
+    let quarantined: Vec<_> = quarantine.quarantined(kartka.quarantine_after).collect();
+    if !quarantined.is_empty() {
+        println!();
+        println!("quarantined (`kartka hydrate --retry-quarantined` to try again):");
+        for (id, failure) in quarantined {
             println!(
-                "({} / {}) pulling, converting, and processing: {missing}..",
-                i + 1,
-                num_missing
+                "    {id} ({} attempts): {}",
+                failure.attempts, failure.error
             );
-            Command::new("rclone")
-                .arg("copyto")
-                .arg(format!("dropbox:{missing}"))
-                .arg(&dest)
-                .output()?;
+        }
+    }
+    Ok(())
+}
 
-            Command::new("magick")
-                .arg(&dest)
-                .arg(temp_dir.path().join(format!("{missing}-%d.png")))
-                .output()?;
+fn stats(kartka: &Kartka) -> Result<()> {
+    let stats = kartka.stats()?;
+    println!("documents: {}", stats.documents);
+    println!("pages: {}", stats.pages);
+    println!(
+        "index size: {:.1} MB",
+        stats.index_bytes as f64 / 1_000_000.0
+    );
+    if let Some(confidence) = stats.ocr_confidence {
+        println!("average OCR confidence: {confidence:.0}%");
+    }
 
-            fs::remove_file(dest)?;
+    println!();
+    println!("documents per month:");
+    // bars scaled to fit a terminal, however many there were in the busiest month
+    let busiest = stats.documents_per_month.iter().map(|(_, it)| *it).max();
+    let scale = busiest.unwrap_or_default().div_ceil(40).max(1);
+    for (month, count) in &stats.documents_per_month {
+        let line = format!(
+            "    {month}  {count:>4}  {}",
+            "#".repeat(count.div_ceil(scale))
+        );
+        println!("{}", line.trim_end());
+    }
 
-            self.read_and_index(temp_dir.path(), missing)?;
+    if !stats.top_tags.is_empty() {
+        println!();
+        println!("top tags:");
+        for (tag, count) in &stats.top_tags {
+            println!("    {count:>4}  {tag}");
         }
+    }
+    Ok(())
+}
+
+/// Writes to stderr above any progress bars, rather than through the middle of them.
+struct AboveBars;
 
-        println!("done!");
-        Ok(())
+impl io::Write for AboveBars {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress::bars().suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
     }
 }
 
-fn extract_path(value: &Value, path: &JsonPath) -> String {
-    let value: Value = path.find_slice(value)[0].clone().to_data();
-    value.as_str().unwrap().to_string()
+/// Parses a `--ocr-config` tesseract parameter, like "tessedit_char_blacklist=|".
+fn parse_config_variable(variable: &str) -> Result<(String, String)> {
+    let Some((name, value)) = variable.split_once('=') else {
+        bail!("expected NAME=VALUE");
+    };
+    if name.is_empty() {
+        bail!("the parameter needs a name");
+    }
+    Ok((name.to_string(), value.to_string()))
 }
 
-fn upload_to_dropbox(dir: &Path, target: &str) -> Result<()> {
-    println!("Copying to Dropbox..");
-    Command::new("rclone")
-        .arg("copy")
-        .arg("--exclude")
-        .arg(".DS_Store")
-        .arg("--include")
-        .arg(target)
-        .arg(dir)
-        .arg("dropbox:")
-        .output()?;
+/// Parses how often `hydrate --watch` checks the remote, like "10m", "1h 30m" or "PT5M".
+fn parse_interval(interval: &str) -> Result<Duration> {
+    let span: jiff::Span = interval.parse()?;
+    let interval =
+        jiff::SignedDuration::try_from(span).context("use days, hours, minutes or seconds")?;
+    if !interval.is_positive() {
+        bail!("the interval has to be more than zero");
+    }
+    Ok(Duration::try_from(interval)?)
+}
+
+/// Where the error that stopped kartka is logged, so it ends up in the log file too.
+const EXIT_TARGET: &str = "kartka::exit";
+
+/// Sends log messages to stderr at the level picked with `-v`/`-q`, and to the log file (if
+/// any) with timestamps and at least debug level, so slow runs can be looked into afterwards.
+fn init_logging(args: &Args) -> Result<()> {
+    let level = match (args.verbose, args.quiet) {
+        (0, 0) => LevelFilter::INFO,
+        (1, _) => LevelFilter::DEBUG,
+        (_, 0) => LevelFilter::TRACE,
+        (_, 1) => LevelFilter::WARN,
+        _ => LevelFilter::ERROR,
+    };
+    if args.plain || args.quiet > 0 {
+        progress::bars().set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    let stderr = fmt::layer()
+        .with_writer(|| AboveBars)
+        .with_ansi(!args.plain)
+        .with_target(false)
+        .without_time()
+        .with_level(args.verbose > 0)
+        .with_filter(level)
+        // the final error is printed to stderr by `main` in full
+        .with_filter(filter_fn(|it| it.target() != EXIT_TARGET));
 
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("opening log file {path:?}"))?;
+            Some(
+                fmt::layer()
+                    .with_writer(Mutex::new(file))
+                    .with_ansi(false)
+                    .with_filter(level.max(LevelFilter::DEBUG)),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .init();
     Ok(())
 }
 
 fn main() {
+    // the completion scripts run kartka with `COMPLETE` set to ask what could come next
+    CompleteEnv::with_factory(Args::command)
+        .var(completions::VAR)
+        .complete();
+
     let args = Args::parse();
+    if let Err(e) = init_logging(&args) {
+        eprintln!("Error: {e:?}");
+        std::process::exit(1);
+    }
 
-    let config_path =
-        Path::new(&env::var("HOME").expect("no home env variable set")).join(CONFIG_PATH);
+    if let Err(e) = run(args) {
+        tracing::error!(target: EXIT_TARGET, "{e:#}");
+        eprintln!("Error: {e:?}");
+        std::process::exit(error::exit_code(&e));
+    }
+}
 
-    if !config_path.exists() {
-        panic!("no kartka config found at {config_path:?}");
+fn run(args: Args) -> Result<()> {
+    let Some(config_path) = config::path(args.config) else {
+        bail!("couldn't work out where the config lives - pass it with --config");
+    };
+
+    if let Mode::Init = args.mode {
+        return init::run(&config_path, args.plain);
+    }
+    if let Mode::Completions { shell } = args.mode {
+        return completions::print(shell);
+    }
+    // a config that won't load is one of the things the doctor checks
+    if let Mode::Doctor = args.mode {
+        return doctor::run(&config_path, args.profile.as_deref());
+    }
+    // docs don't need a config, so packagers can generate them at build time
+    if let Mode::Docs {
+        action: DocsAction::Generate { out },
+    } = &args.mode
+    {
+        let written = docs::generate(Args::command(), out)?;
+        println!("wrote {written} man pages to {out:?}");
+        return Ok(());
     }
 
-    let mut contents = String::new();
-    File::open(config_path)
-        .expect("open config")
-        .read_to_string(&mut contents)
-        .expect("could not read string contents");
+    let mut kartka: Kartka = match config::load(&config_path, args.profile.as_deref()) {
+        Ok(kartka) => kartka,
+        Err(e) if !config_path.exists() => {
+            return Err(e.wrap_err(format!(
+                "no kartka config found at {config_path:?} - run `kartka init` to create one"
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+    kartka.plain = args.plain;
+    kartka.dry_run = args.dry_run;
+    kartka.notify |= args.notify;
+    kartka.ocr.psm = args.psm.or(kartka.ocr.psm);
+    kartka.ocr.oem = args.oem.or(kartka.ocr.oem);
+    kartka.ocr.dpi = args.ocr_dpi.or(kartka.ocr.dpi);
+    kartka.ocr.config_variables.extend(args.ocr_config.clone());
+    kartka.validate(&config_path)?;
+    // migrating or restoring is how an index in the wrong layout gets put right
+    if !matches!(args.mode, Mode::Migrate | Mode::Restore { .. }) {
+        kartka.check_schema()?;
+    }
 
-    let kartka: Kartka = toml::from_str(&contents).expect("could not parse config");
+    if let Some(provider) = kartka.index_synced_by() {
+        eprintln!(
+            "WARNING: the index at {:?} is in a {provider} folder, so the text of every letter is being uploaded to {provider}",
+            kartka.index()
+        );
+        if provider.can_ignore()
+            && !kartka.plain
+            && inquire::Confirm::new(&format!("Tell {provider} to stop syncing the index?"))
+                .with_default(true)
+                .prompt()?
+        {
+            kartka.ignore_sync(provider)?;
+            eprintln!("{provider} will now leave the index alone");
+        } else {
+            eprintln!(
+                "{} - or set `index_in_synced_folder = true` if that's intended",
+                provider.advice()
+            );
+        }
+    }
 
     match args.mode {
-        Mode::Scan => {
-            kartka.scan().unwrap();
+        Mode::Init | Mode::Completions { .. } | Mode::Doctor | Mode::Docs { .. } => {
+            unreachable!("handled before loading config")
         }
-        Mode::Search { query } => {
-            kartka.search(&query).unwrap();
+        Mode::Scan {
+            files,
+            device,
+            adf,
+            no_dupes,
+            keep_blank,
+            purge,
+            jpeg_quality,
+            dpi,
+            greyscale,
+            pdfa,
+            protect,
+            split_every,
+            interactive,
+            review,
+        } => {
+            kartka.keep_blank_pages |= keep_blank;
+            kartka.pdf.jpeg_quality = jpeg_quality.or(kartka.pdf.jpeg_quality);
+            kartka.pdf.dpi = dpi.or(kartka.pdf.dpi);
+            kartka.pdf.greyscale |= greyscale;
+            kartka.pdf.pdfa |= pdfa;
+            if protect {
+                if kartka.pdf.pdfa {
+                    bail!("a PDF/A can't have a password, so --protect can't be used with `pdfa`");
+                }
+                kartka.pdf.protect_with = match &kartka.pdf.password_command {
+                    Some(command) => {
+                        Some(config::secret_from_command(command, "password_command")?)
+                    }
+                    None if kartka.plain => {
+                        bail!("set `password_command` under `[pdf]` to use --protect with --plain")
+                    }
+                    None => Some(
+                        inquire::Password::new("Password for the PDFs:")
+                            .with_display_mode(inquire::PasswordDisplayMode::Masked)
+                            .prompt()?,
+                    ),
+                };
+            }
+            kartka.split = split_every.map(scan::Split::Every);
+            if (review || interactive) && kartka.plain {
+                bail!("--review and --interactive ask questions, so can't be used with --plain");
+            }
+            if let Some(device) = device {
+                kartka.acquire(&device, adf)?;
+            }
+            let mut pages = match files.is_empty() {
+                true => scan::pages_in(kartka.scans())?,
+                false => files
+                    .iter()
+                    .filter(|it| !pdf::is_pdf(it))
+                    .cloned()
+                    .collect(),
+            };
+            if review {
+                let order = review_pages(&pages)?;
+                pages = order.iter().map(|it| it.path.clone()).collect();
+                kartka.page_order = Some(order);
+            }
+            if interactive {
+                kartka.split = Some(scan::Split::Parts(ask_parts(&pages)?));
+            }
+
+            let scanned = match files.is_empty() {
+                true => kartka.scan(no_dupes, purge),
+                false => kartka.scan_files(&files, no_dupes),
+            };
+            notify::finished(&kartka, "scan", &scanned, |it| {
+                format!("added {}", notify::documents(it.len()))
+            });
+            scanned?;
         }
-        Mode::Hydrate => {
-            kartka.rehydrate().unwrap();
+        Mode::Rescan { id, purge } => {
+            kartka.rescan(&id, purge)?;
         }
-    };
+        Mode::Search {
+            saved: Some(name),
+            collection,
+            order,
+            copy,
+            ..
+        } => {
+            let mut search = kartka.saved_search(&name)?;
+            search.collection = collection.or(search.collection);
+            let found = print_search(&kartka, &search, &order)?;
+            if copy {
+                copy_first(&kartka, &found)?;
+            }
+        }
+        Mode::Search {
+            query: Some(query),
+            codes: true,
+            collection,
+            order,
+            copy,
+            ..
+        } => {
+            let mut hits = kartka.search_codes(&query)?;
+            if let Some(sort) = order.sort {
+                sort.apply(&mut hits);
+            }
+            let hits = order.page(in_collection(&kartka, hits, collection.as_deref())?);
+            for (id, codes) in &hits {
+                println!("{}", kartka.preview_link(id));
+                for code in codes {
+                    println!("    page {}  {}: {}", code.page, code.kind, code.data);
+                }
+            }
+            if copy {
+                let found: Vec<_> = hits.into_iter().map(|(id, _)| id).collect();
+                copy_first(&kartka, &found)?;
+            }
+        }
+        Mode::Search {
+            query: Some(query),
+            within: Some(id),
+            ..
+        } => {
+            for (page, line) in kartka.search_in(&id, &query)? {
+                println!("page {page}: {line}");
+            }
+        }
+        Mode::Search {
+            query: Some(query),
+            semantic,
+            within: None,
+            collection,
+            save,
+            order,
+            copy,
+            ..
+        } => {
+            let search = SavedSearch {
+                query,
+                semantic,
+                collection,
+            };
+            if let Some(name) = save {
+                kartka.save_search(&name, &search)?;
+            }
+            let found = print_search(&kartka, &search, &order)?;
+            if copy {
+                copy_first(&kartka, &found)?;
+            }
+        }
+        Mode::Search { query: None, .. } => {
+            unreachable!("clap insists on a query unless --saved is given")
+        }
+        Mode::Searches { forget: Some(name) } => {
+            kartka.forget_search(&name)?;
+        }
+        Mode::Searches { forget: None } => {
+            for (name, search) in kartka.saved_searches()? {
+                println!("{name}  {}", search.describe());
+            }
+        }
+        Mode::List {
+            thumbnails,
+            collection,
+            order,
+        } => {
+            list(&kartka, thumbnails, collection.as_deref(), &order)?;
+        }
+        Mode::Recent { count, thumbnails } => {
+            let protocol = thumbnail_protocol(&kartka, thumbnails);
+            for (id, added) in kartka.recent(count)? {
+                if let Some(protocol) = protocol {
+                    show_thumbnail(&kartka, protocol, &id)?;
+                }
+                println!("{}", describe(&kartka.metadata(&id)?, &id));
+                let added = added.to_zoned(jiff::tz::TimeZone::system());
+                println!("    added {}", added.strftime("%Y-%m-%d %H:%M"));
+                println!("    {}", kartka.preview_link(&id));
+                if protocol.is_some() {
+                    println!();
+                }
+            }
+        }
+        Mode::Hydrate {
+            retry_quarantined,
+            list,
+            jobs,
+            watch: Some(interval),
+        } => {
+            if list {
+                warn!("--watch always lists the remote, so --list makes no difference");
+            }
+            kartka.watch_remote(interval, retry_quarantined, jobs, |round| {
+                // only worth interrupting someone for when something's changed
+                if !matches!(round, Ok(0)) {
+                    notify::finished(&kartka, "hydrate", round, |it| {
+                        format!("hydrated {}", notify::documents(*it))
+                    });
+                }
+            })?;
+        }
+        Mode::Hydrate {
+            retry_quarantined,
+            list,
+            jobs,
+            watch: None,
+        } => {
+            let hydrated = kartka.rehydrate(retry_quarantined, list, jobs);
+            notify::finished(&kartka, "hydrate", &hydrated, |it| {
+                format!("hydrated {}", notify::documents(*it))
+            });
+            hydrated?;
+        }
+        Mode::IngestMail => {
+            kartka.ingest_mail()?;
+        }
+        Mode::Import {
+            no_dupes,
+            paperless: true,
+            paths,
+            ..
+        } => {
+            let [dir] = paths.as_slice() else {
+                bail!("--paperless takes the one folder paperless exported to");
+            };
+            kartka.import_paperless(dir, no_dupes)?;
+        }
+        Mode::Import {
+            from,
+            no_dupes,
+            paths,
+            ..
+        } => {
+            kartka.import(from, &paths, no_dupes)?;
+        }
+        Mode::Embed { ids } => {
+            kartka.embed(&ids)?;
+        }
+        Mode::Summarise { ids } => {
+            kartka.summarise(&ids)?;
+        }
+        Mode::Extract { ids } => {
+            kartka.extract_fields(&ids)?;
+        }
+        Mode::ExtractTable { id, page, out } => {
+            extract_table(&kartka, &id, page, out)?;
+        }
+        Mode::Sync { jobs } => {
+            let synced = kartka.sync(jobs);
+            notify::finished(&kartka, "sync", &synced, |()| "in sync".to_string());
+            synced?;
+        }
+        Mode::Log { id, failed, since } => {
+            let tz = jiff::tz::TimeZone::system();
+            for entry in kartka.audit_log()? {
+                let time = entry.time.to_zoned(tz.clone());
+                // a rename is logged under the old id, but it's the same document after
+                let about = |it: &str| {
+                    entry.id == it || entry.detail.as_deref() == Some(&format!("to {it}"))
+                };
+                if id.as_deref().is_some_and(|it| !about(it))
+                    || (failed && entry.error.is_none())
+                    || since.is_some_and(|it| time.date() < it)
+                {
+                    continue;
+                }
+                let mut line = format!(
+                    "{}  {:<7}  {}",
+                    time.strftime("%Y-%m-%d %H:%M:%S"),
+                    entry.operation,
+                    entry.id
+                );
+                if let Some(detail) = &entry.detail {
+                    line.push_str(&format!("  {detail}"));
+                }
+                match &entry.error {
+                    Some(error) => line.push_str(&format!("  FAILED: {error}")),
+                    None => line.push_str("  ok"),
+                }
+                println!("{line}");
+            }
+        }
+        Mode::Delete { id, yes } => {
+            kartka.delete(&id, yes)?;
+        }
+        Mode::GoogleDrive => {
+            if let Some(folder_id) = kartka.connect_google_drive()? {
+                println!(
+                    "documents will go in a new folder called kartka - add `folder_id = \"{folder_id}\"` under [google_drive] to keep using it"
+                );
+            }
+            println!("{} is ready to use", kartka.remote);
+        }
+        Mode::Gc { yes } => {
+            let collected = kartka.gc(yes)?;
+            match collected.found {
+                0 => println!("the index and {} agree", kartka.remote),
+                found if !kartka.dry_run => {
+                    println!("put right {} of {found} documents", collected.fixed)
+                }
+                _ => {}
+            }
+        }
+        Mode::Rename { id, new_id } => {
+            kartka.rename(&id, &new_id)?;
+        }
+        Mode::Classify { id, classification } => {
+            let classification = metadata::Classification::from(classification);
+            let show = classification.is_empty();
+            let meta = kartka.classify(&id, classification)?;
+            if show {
+                println!("{}", describe(&meta, &id));
+            }
+        }
+        Mode::Collection {
+            action: CollectionAction::Add { name, ids },
+        } => {
+            let added = kartka.add_to_collection(&name, &ids)?;
+            println!("added {} to {name:?}", notify::documents(added));
+        }
+        Mode::Collection {
+            action: CollectionAction::Remove { name, ids },
+        } => {
+            let removed = kartka.remove_from_collection(&name, &ids)?;
+            println!("removed {} from {name:?}", notify::documents(removed));
+        }
+        Mode::Collection {
+            action: CollectionAction::List,
+        } => {
+            for (name, count) in kartka.collections()? {
+                println!("{name}  ({})", notify::documents(count));
+            }
+        }
+        Mode::Collection {
+            action: CollectionAction::Show { name },
+        } => {
+            for id in kartka.collection(&name)?.iter().rev() {
+                println!("{}", describe(&kartka.metadata(id)?, id));
+                println!("    {}", kartka.preview_link(id));
+            }
+        }
+        Mode::Checklist { period } => {
+            kartka.checklist(period)?;
+        }
+        Mode::Taxpack { year, rules, out } => {
+            let out = out.unwrap_or_else(|| PathBuf::from(format!("taxpack-{year}.pdf")));
+            kartka.taxpack(year, &rules, &out)?;
+        }
+        Mode::Backup => {
+            kartka.backup()?;
+        }
+        Mode::Restore { name, list, yes } => {
+            if list {
+                for backup in kartka.backups()? {
+                    println!("{backup}");
+                }
+            } else {
+                kartka.restore(name.as_deref(), yes)?;
+            }
+        }
+        Mode::Export { csv: Some(csv), .. } if csv.as_os_str() == "-" => {
+            kartka.export_csv(&mut io::stdout().lock())?;
+        }
+        Mode::Export { csv: Some(csv), .. } => {
+            let mut out =
+                io::BufWriter::new(fs::File::create(&csv).context(format!("creating {csv:?}"))?);
+            kartka.export_csv(&mut out)?;
+            out.flush()?;
+        }
+        Mode::Export {
+            dir: Some(dir),
+            paperless: true,
+            ..
+        } => {
+            kartka.export_paperless(&dir)?;
+        }
+        Mode::Export { dir, .. } => {
+            kartka.export(&dir.expect("clap requires a dir without --csv"))?;
+        }
+        Mode::Verify {
+            integrity,
+            record,
+            quick,
+        } => {
+            kartka.verify(integrity, record, quick)?;
+        }
+        Mode::Sign => {
+            kartka.sign()?;
+        }
+        Mode::Archive { yes } => {
+            kartka.archive(yes)?;
+        }
+        Mode::Open { id, copy: false } => {
+            kartka.open(&id)?;
+        }
+        Mode::Open { id, copy: true } => {
+            if !kartka.contains(&id)? {
+                bail!("{id} not found in index");
+            }
+            copy_first(&kartka, &[id])?;
+        }
+        Mode::Get { id, dest } => {
+            let path = kartka.get(&id, dest.as_deref())?;
+            if !kartka.dry_run {
+                println!("{}", path.display());
+            }
+        }
+        Mode::Pick => {
+            if let Some(id) = pick::pick(&kartka)? {
+                kartka.open(&id)?;
+            }
+        }
+        Mode::Show {
+            id, codes: true, ..
+        } => {
+            show_codes(&kartka, &id)?;
+        }
+        Mode::Show {
+            id, query, save, ..
+        } if !query.is_empty() => {
+            show_matches(&kartka, &id, &query.join(" "), save)?;
+        }
+        Mode::Show { id, .. } => {
+            show(&kartka, &id)?;
+        }
+        Mode::Serve { listen } => {
+            serve::serve(kartka, listen)?;
+        }
+        Mode::Index {
+            action: IndexAction::Rebuild { remote, jobs, yes },
+        } => {
+            let rebuilt = kartka.rebuild_index(remote, jobs, yes);
+            notify::finished(&kartka, "index rebuild", &rebuilt, |it| {
+                format!("read {} again", notify::documents(*it))
+            });
+            rebuilt?;
+        }
+        Mode::Migrate => {
+            let done = kartka.migrate()?;
+            if done.is_empty() {
+                println!("the index is already up to date");
+            }
+            for it in done {
+                println!("{it}");
+            }
+        }
+        Mode::Status => {
+            status(&kartka)?;
+        }
+        Mode::Stats => {
+            stats(&kartka)?;
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,166 @@
+//! Per-document metadata, kept as JSON sidecars in the index's hidden `.kartka` directory so
+//! ripgrep doesn't pick it up when searching.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{codes::Code, fields::Fields, store};
+
+const META_DIR: &str = ".kartka/meta";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    /// What kind of document this is, e.g. "payslip" or "bank statement".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc_type: Option<String>,
+    /// Who sent the document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correspondent: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The collections it's been put in, like "Taxes 2024".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<String>,
+    /// The tesseract code of the language the document was detected as being in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// How sure the OCR engine was of the document's text on average, from 0 to 100.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_confidence: Option<f32>,
+    /// A few sentences about the document, written by an LLM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// The total, invoice number and so on, for receipts and invoices.
+    #[serde(default, skip_serializing_if = "Fields::is_empty")]
+    pub fields: Fields,
+    /// The QR codes and barcodes on the document's pages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codes: Vec<Code>,
+    /// The SHA-256 of the PDF as uploaded, to check the remote copy against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The size of the PDF as uploaded, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Whether the PDF has been moved to the cold storage remote.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    /// Whether the PDF is protected with a password, so kartka can't read it again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// Earlier versions of the document, oldest first, each kept under an id of its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+    /// The document this is an earlier version of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<String>,
+}
+
+/// Changes to make to a document's metadata with [`Kartka::classify`](crate::Kartka::classify).
+#[derive(Debug, Default)]
+pub struct Classification {
+    pub doc_type: Option<String>,
+    pub correspondent: Option<String>,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+}
+
+impl Classification {
+    pub fn is_empty(&self) -> bool {
+        self.doc_type.is_none()
+            && self.correspondent.is_none()
+            && self.add_tags.is_empty()
+            && self.remove_tags.is_empty()
+    }
+
+    /// What the classification changes, e.g. `type payslip, +tax, -todo`.
+    pub fn describe(&self) -> String {
+        let mut changes = vec![];
+        if let Some(doc_type) = &self.doc_type {
+            changes.push(format!("type {doc_type}"));
+        }
+        if let Some(correspondent) = &self.correspondent {
+            changes.push(format!("from {correspondent}"));
+        }
+        changes.extend(self.add_tags.iter().map(|it| format!("+{it}")));
+        changes.extend(self.remove_tags.iter().map(|it| format!("-{it}")));
+        changes.join(", ")
+    }
+
+    pub fn apply(self, meta: &mut Metadata) {
+        if self.doc_type.is_some() {
+            meta.doc_type = self.doc_type;
+        }
+        if self.correspondent.is_some() {
+            meta.correspondent = self.correspondent;
+        }
+        for tag in self.add_tags {
+            if !meta.tags.contains(&tag) {
+                meta.tags.push(tag);
+            }
+        }
+        meta.tags.retain(|it| !self.remove_tags.contains(it));
+    }
+}
+
+/// Whether an `actual` metadata field satisfies an `expected` one from a rule, ignoring case.
+/// Rules that don't care about a field leave it as `None`.
+pub fn field_matches(expected: &Option<String>, actual: &Option<String>) -> bool {
+    match (expected, actual) {
+        (None, _) => true,
+        (Some(expected), Some(actual)) => expected.eq_ignore_ascii_case(actual),
+        (Some(_), None) => false,
+    }
+}
+
+fn path(index: &Path, id: &str) -> PathBuf {
+    index.join(META_DIR).join(format!("{id}.json"))
+}
+
+/// Reads the metadata for `id`, or the default if none has been recorded.
+pub fn read(index: &Path, id: &str) -> Result<Metadata> {
+    let path = path(index, id);
+    if !path.exists() {
+        return Ok(Metadata::default());
+    }
+
+    let contents = fs::read_to_string(&path).context(format!("reading metadata {path:?}"))?;
+    serde_json::from_str(&contents).context(format!("parsing metadata {path:?}"))
+}
+
+/// Applies `change` to the metadata for `id` in a single [`store::transaction`], returning the
+/// result.
+pub fn update(index: &Path, id: &str, change: impl FnOnce(&mut Metadata)) -> Result<Metadata> {
+    let _transaction = store::transaction(index)?;
+    let mut metadata = read(index, id)?;
+    change(&mut metadata);
+
+    fs::create_dir_all(index.join(META_DIR))?;
+    store::write_atomic(&path(index, id), serde_json::to_string_pretty(&metadata)?)
+        .context(format!("writing metadata for {id}"))?;
+    Ok(metadata)
+}
+
+pub fn remove(index: &Path, id: &str) -> Result<()> {
+    let _transaction = store::transaction(index)?;
+    let path = path(index, id);
+    if path.exists() {
+        fs::remove_file(&path).context(format!("removing metadata {path:?}"))?;
+    }
+    Ok(())
+}
+
+pub fn rename(index: &Path, id: &str, new_id: &str) -> Result<()> {
+    let _transaction = store::transaction(index)?;
+    let path = path(index, id);
+    if path.exists() {
+        fs::rename(&path, self::path(index, new_id))
+            .context(format!("renaming metadata {path:?}"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Desktop notifications for when a long `scan`, `hydrate` or `sync` finishes, so it can be
+//! left running in the background. Turned on with `--notify` or `notify = true`.
+
+use eyre::Result;
+use kartka::Kartka;
+use tracing::warn;
+
+/// Tells the desktop `what` has finished, with `summary` of what it did if it worked or the
+/// error if it didn't. Does nothing unless notifications are turned on, or on a dry run.
+pub fn finished<T>(
+    kartka: &Kartka,
+    what: &str,
+    outcome: &Result<T>,
+    summary: impl FnOnce(&T) -> String,
+) {
+    if !kartka.notify || kartka.dry_run {
+        return;
+    }
+    let (title, body) = match outcome {
+        Ok(it) => (format!("kartka {what} finished"), summary(it)),
+        Err(e) => (format!("kartka {what} failed"), format!("{e:#}")),
+    };
+    let shown = notify_rust::Notification::new()
+        .appname("kartka")
+        .summary(&title)
+        .body(&body)
+        .show();
+    if let Err(e) = shown {
+        warn!("couldn't show a notification: {e}");
+    }
+}
+
+/// "1 document" or "2 documents".
+pub fn documents(count: usize) -> String {
+    match count {
+        1 => "1 document".to_string(),
+        n => format!("{n} documents"),
+    }
+}
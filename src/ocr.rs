@@ -0,0 +1,441 @@
+//! Turning page images into text. Tesseract does the work by default, but anything implementing
+//! [`OcrEngine`] can stand in for it.
+
+use std::{collections::BTreeMap, path::Path, time::Instant};
+
+use eyre::{Context, Result};
+use rusty_tesseract::Image;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{
+    cloud_ocr::{GoogleVision, Textract, WithFallback},
+    hocr, index,
+    ocr_cache::{self, Cached},
+    pdf, progress, text, Kartka, KartkaError,
+};
+
+/// Something that can read the text on a page image.
+pub trait OcrEngine: Send + Sync {
+    /// OCRs a single page image. Failures should be [`KartkaError::Ocr`]s, so a bad page can be
+    /// told apart from kartka itself going wrong.
+    fn image_to_text(&self, path: &Path) -> Result<String>;
+
+    /// Like [`OcrEngine::image_to_text`], along with how sure the engine is of the text, from 0
+    /// to 100, for engines that say.
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        Ok((self.image_to_text(path)?, None))
+    }
+
+    /// Like [`OcrEngine::image_to_text_with_confidence`], along with the page as hOCR - every
+    /// word with where it is on the page - for engines that can give it.
+    fn image_to_page(&self, path: &Path) -> Result<Page> {
+        let (text, confidence) = self.image_to_text_with_confidence(path)?;
+        Ok(Page {
+            text,
+            confidence,
+            hocr: None,
+        })
+    }
+}
+
+/// Everything an [`OcrEngine`] made of a page.
+#[derive(Debug)]
+pub struct Page {
+    pub text: String,
+    /// How sure the engine was of the text, from 0 to 100, if it said.
+    pub confidence: Option<f32>,
+    /// The page as hOCR, if it was asked for and the engine can give it.
+    pub hocr: Option<String>,
+}
+
+/// Which [`OcrEngine`] to use, set with `ocr_engine`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Engine {
+    /// The `tesseract` command.
+    #[default]
+    Tesseract,
+    /// Tesseract's library, called in-process. Needs kartka built with the `leptess` feature.
+    Leptess,
+    /// Google Cloud Vision, falling back to tesseract.
+    GoogleVision,
+    /// AWS Textract, falling back to tesseract.
+    Textract,
+}
+
+/// How tesseract reads pages, configured as `[ocr]` in kartka.toml. Anything not set is left to
+/// tesseract.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Page segmentation mode, e.g. 4 for receipts and other single columns of text, or 6 for
+    /// one uniform block. See `tesseract --help-psm`.
+    pub psm: Option<i32>,
+    /// OCR engine mode. See `tesseract --help-oem`.
+    pub oem: Option<i32>,
+    /// The resolution to read pages at, for scans that don't say or say wrongly.
+    pub dpi: Option<i32>,
+    /// Any other tesseract parameters, like `tessedit_char_blacklist`. See
+    /// `tesseract --print-parameters`.
+    pub config_variables: BTreeMap<String, String>,
+    /// Keep where tesseract found each word on each page, as hOCR in the index.
+    pub hocr: bool,
+}
+
+impl Settings {
+    /// A short name for these settings, so pages read with them are cached apart from ones
+    /// read without, or `None` if nothing's set.
+    fn cache_key(&self) -> Option<String> {
+        if *self == Settings::default() {
+            return None;
+        }
+        let hash = Sha256::digest(format!("{self:?}"));
+        Some(format!("{hash:x}")[..12].to_string())
+    }
+}
+
+/// Runs the `tesseract` command for each page.
+#[derive(Debug, Clone)]
+pub struct Tesseract {
+    /// Tesseract language(s), e.g. `eng` or `eng+deu`.
+    pub language: String,
+    pub settings: Settings,
+}
+
+impl Tesseract {
+    fn args(&self) -> rusty_tesseract::Args {
+        let defaults = rusty_tesseract::Args::default();
+        rusty_tesseract::Args {
+            lang: self.language.clone(),
+            config_variables: self.settings.config_variables.clone().into_iter().collect(),
+            dpi: self.settings.dpi.or(defaults.dpi),
+            psm: self.settings.psm.or(defaults.psm),
+            oem: self.settings.oem.or(defaults.oem),
+        }
+    }
+}
+
+impl OcrEngine for Tesseract {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        let ocr_error = |e: rusty_tesseract::TessError| KartkaError::Ocr {
+            path: path.to_path_buf(),
+            source: e.into(),
+        };
+
+        let contents = Image::from_path(path).map_err(ocr_error)?;
+        Ok(rusty_tesseract::image_to_string(&contents, &self.args()).map_err(ocr_error)?)
+    }
+
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        let page = self.image_to_page(path)?;
+        Ok((page.text, page.confidence))
+    }
+
+    fn image_to_page(&self, path: &Path) -> Result<Page> {
+        let ocr_error = |e: rusty_tesseract::TessError| KartkaError::Ocr {
+            path: path.to_path_buf(),
+            source: e.into(),
+        };
+
+        let contents = Image::from_path(path).map_err(ocr_error)?;
+        // one run gives everything, where asking for the text and then the data would OCR it
+        // twice
+        let data = rusty_tesseract::image_to_data(&contents, &self.args()).map_err(ocr_error)?;
+        let (text, confidence) = words_to_text(&data.data);
+        Ok(Page {
+            text,
+            confidence,
+            hocr: self.settings.hocr.then(|| hocr::from_data(&data.data)),
+        })
+    }
+}
+
+/// Lays tesseract's words back out as text, a line at a time with a blank line between
+/// paragraphs, along with their average confidence.
+fn words_to_text(words: &[rusty_tesseract::Data]) -> (String, Option<f32>) {
+    let mut text = String::new();
+    let mut confidences = vec![];
+    let mut last = None;
+    for word in words.iter().filter(|it| !it.text.trim().is_empty()) {
+        let paragraph = (word.page_num, word.block_num, word.par_num);
+        match last {
+            Some((last_paragraph, _)) if last_paragraph != paragraph => text.push_str("\n\n"),
+            Some((_, line)) if line != word.line_num => text.push('\n'),
+            Some(_) => text.push(' '),
+            None => {}
+        }
+        text.push_str(&word.text);
+        last = Some((paragraph, word.line_num));
+        if word.conf >= 0.0 {
+            confidences.push(word.conf);
+        }
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    (text, mean(&confidences))
+}
+
+pub(crate) fn mean(values: &[f32]) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f32>() / values.len() as f32)
+}
+
+/// Calls tesseract's library directly, which saves starting a process for every page.
+#[cfg(feature = "leptess")]
+#[derive(Debug, Clone)]
+pub struct Leptess {
+    /// Tesseract language(s), e.g. `eng` or `eng+deu`.
+    pub language: String,
+    /// Only `psm`, `dpi` and `hocr` are used, since the rest have to be set as the library's
+    /// loaded.
+    pub settings: Settings,
+}
+
+#[cfg(feature = "leptess")]
+impl OcrEngine for Leptess {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        Ok(self.image_to_text_with_confidence(path)?.0)
+    }
+
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        let page = self.image_to_page(path)?;
+        Ok((page.text, page.confidence))
+    }
+
+    fn image_to_page(&self, path: &Path) -> Result<Page> {
+        let ocr_error = |e: Box<dyn std::error::Error + Send + Sync>| KartkaError::Ocr {
+            path: path.to_path_buf(),
+            source: e,
+        };
+
+        // a LepTess can't be shared between threads, so each page gets its own
+        let mut tesseract =
+            leptess::LepTess::new(None, &self.language).map_err(|e| ocr_error(e.into()))?;
+        if let Some(psm) = self.settings.psm {
+            tesseract
+                .set_variable(leptess::Variable::TesseditPagesegMode, &psm.to_string())
+                .map_err(|e| ocr_error(e.into()))?;
+        }
+        tesseract.set_image(path).map_err(|e| ocr_error(e.into()))?;
+        if let Some(dpi) = self.settings.dpi {
+            tesseract.set_source_resolution(dpi);
+        }
+        let text = tesseract.get_utf8_text().map_err(|e| ocr_error(e.into()))?;
+        let hocr = match self.settings.hocr {
+            true => Some(
+                tesseract
+                    .get_hocr_text(0)
+                    .map_err(|e| ocr_error(e.into()))?,
+            ),
+            false => None,
+        };
+        Ok(Page {
+            text,
+            confidence: Some(tesseract.mean_text_conf() as f32),
+            hocr,
+        })
+    }
+}
+
+/// How many letters and digits a PDF's own text needs per page to be used rather than OCRing
+/// it. Scanned PDFs sometimes have a few stray characters in, which aren't worth keeping.
+const MIN_CHARS_PER_PAGE: usize = 20;
+
+/// A document's text, and the language it was read as if that was worked out.
+#[derive(Debug)]
+pub struct Recognised {
+    pub text: String,
+    pub language: Option<String>,
+    /// How sure the OCR engine was of the text on average, from 0 to 100, if it said.
+    pub confidence: Option<f32>,
+    /// Each page as hOCR, where the engine gave it, or nothing if the text didn't come from OCR.
+    pub hocr: Vec<Option<String>>,
+}
+
+/// Which of the tesseract `languages` `text` is most likely written in. Tesseract's codes are
+/// mostly the ISO 639-3 ones, so a language only counts if tesseract calls it the same thing.
+pub fn detect_language(text: &str, languages: &[String]) -> Option<String> {
+    let allowed: Vec<_> = whatlang::Lang::all()
+        .iter()
+        .filter(|it| languages.iter().any(|code| code == it.code()))
+        .copied()
+        .collect();
+    let info = whatlang::Detector::with_allowlist(allowed).detect(text)?;
+    Some(info.lang().code().to_string())
+}
+
+impl Kartka {
+    /// The OCR engine picked in the config, reading `language`, with what it's read before
+    /// cached in the index.
+    pub fn ocr_engine(&self, language: &str) -> Result<Box<dyn OcrEngine>> {
+        let mut engine = format!("{:?}-{language}", self.ocr_engine).to_lowercase();
+        if let Some(settings) = self.ocr.cache_key() {
+            engine = format!("{engine}-{settings}");
+        }
+        Ok(Box::new(Cached {
+            engine: self.uncached_ocr_engine(language)?,
+            dir: self.index().join(ocr_cache::CACHE_DIR).join(engine),
+        }))
+    }
+
+    fn uncached_ocr_engine(&self, language: &str) -> Result<Box<dyn OcrEngine>> {
+        let tesseract = Tesseract {
+            language: language.to_string(),
+            settings: self.ocr.clone(),
+        };
+        let cloud_ocr = self.cloud_ocr.clone().unwrap_or_default();
+        match self.ocr_engine {
+            Engine::Tesseract => Ok(Box::new(tesseract)),
+            #[cfg(feature = "leptess")]
+            Engine::Leptess => {
+                if self.ocr.oem.is_some() || !self.ocr.config_variables.is_empty() {
+                    eyre::bail!(
+                        "ocr_engine = \"leptess\" can only use `psm` and `dpi` from [ocr], not `oem` or `config_variables`"
+                    );
+                }
+                Ok(Box::new(Leptess {
+                    language: language.to_string(),
+                    settings: self.ocr.clone(),
+                }))
+            }
+            #[cfg(not(feature = "leptess"))]
+            Engine::Leptess => {
+                eyre::bail!("ocr_engine = \"leptess\" needs kartka built with `--features leptess`")
+            }
+            Engine::GoogleVision => Ok(Box::new(WithFallback {
+                engine: GoogleVision::new(&cloud_ocr)?,
+                fallback: tesseract,
+            })),
+            Engine::Textract => Ok(Box::new(WithFallback {
+                engine: Textract::new(&cloud_ocr),
+                fallback: tesseract,
+            })),
+        }
+    }
+
+    /// OCRs the page images in `dir`. With `detect_languages` set, a first pass reading all of
+    /// them works out which one the document is in, then it's read again in just that one,
+    /// which is far more accurate than reading everything as a mix.
+    pub fn recognise(&self, dir: &Path) -> Result<Recognised> {
+        if self.detect_languages.is_empty() {
+            return dir_to_text(dir, self.ocr_engine(&self.ocr_language)?.as_ref());
+        }
+
+        let all = self.detect_languages.join("+");
+        let recognised = dir_to_text(dir, self.ocr_engine(&all)?.as_ref())?;
+        let Some(language) = detect_language(&recognised.text, &self.detect_languages) else {
+            debug!("couldn't tell what language {dir:?} is in");
+            return Ok(recognised);
+        };
+        debug!("{dir:?} looks like {language}");
+        if self.detect_languages.len() == 1 {
+            return Ok(Recognised {
+                language: Some(language),
+                ..recognised
+            });
+        }
+        let recognised = dir_to_text(dir, self.ocr_engine(&language)?.as_ref())?;
+        Ok(Recognised {
+            language: Some(language),
+            ..recognised
+        })
+    }
+
+    /// Reads the text of the PDF at `path`: the text it already has if there's enough of it,
+    /// otherwise by rendering its pages into `work_dir` and OCRing them.
+    pub fn recognise_pdf(&self, path: &Path, work_dir: &Path) -> Result<Recognised> {
+        if let Some(recognised) = self.own_text(path)? {
+            return Ok(recognised);
+        }
+        pdf::render_pages(self.tools.as_ref(), path, work_dir, "page")?;
+        self.recognise(work_dir)
+    }
+
+    /// The text the PDF at `path` already has, if there's enough of it to not need OCRing.
+    pub fn own_text(&self, path: &Path) -> Result<Option<Recognised>> {
+        let pages: Vec<_> = pdf::extract_text(self.tools.as_ref(), path)?
+            .iter()
+            .map(|it| text::clean(it))
+            .collect();
+        let chars: usize = pages
+            .iter()
+            .map(|it| it.chars().filter(|it| it.is_alphanumeric()).count())
+            .sum();
+        if chars >= MIN_CHARS_PER_PAGE * pages.len() {
+            debug!("using the text already in {path:?}");
+            let text = index::join_pages(pages);
+            let language = detect_language(&text, &self.detect_languages);
+            return Ok(Some(Recognised {
+                text,
+                language,
+                confidence: None,
+                hocr: vec![],
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// OCRs every page image in `dir`, in filename order, one page after another. Pages that can't
+/// be read are skipped with a warning, so one bad page doesn't lose the rest of the document,
+/// but it's an error if none of them can be. The language is left for the caller to work out.
+pub fn dir_to_text(dir: &Path, engine: &dyn OcrEngine) -> Result<Recognised> {
+    let started = Instant::now();
+    let mut last_error = None;
+
+    let mut entries: Vec<_> = dir
+        .read_dir()
+        .context(format!("reading dir: {:?}", dir))?
+        .collect::<Result<_, _>>()?;
+
+    // skip if can't read name or is hidden, and PDFs, which aren't page images
+    entries.retain(|it| {
+        it.file_name()
+            .to_str()
+            .is_some_and(|it| !it.starts_with("."))
+            && !pdf::is_pdf(&it.path())
+    });
+    entries.sort_by_key(|it| it.file_name());
+
+    let bar = progress::bar(entries.len(), "pages");
+    bar.set_message("OCR");
+    let mut pages = vec![];
+    let mut confidences = vec![];
+    let mut hocr = vec![];
+    let mut read_any = false;
+    for dir_entry in entries.iter() {
+        match engine.image_to_page(&dir_entry.path()) {
+            Ok(page) => {
+                pages.push(text::clean(&page.text));
+                confidences.extend(page.confidence);
+                hocr.push(page.hocr);
+                read_any = true;
+            }
+            Err(e) => {
+                warn!("skipping page: {e:#}");
+                // kept as a blank page so the ones after it keep their page numbers
+                pages.push(String::new());
+                hocr.push(None);
+                last_error = Some(e);
+            }
+        }
+        bar.inc(1);
+    }
+
+    bar.finish_and_clear();
+    debug!("OCR'd {} pages in {:?}", entries.len(), started.elapsed());
+    match last_error {
+        Some(e) if !read_any => Err(e),
+        _ => Ok(Recognised {
+            text: index::join_pages(pages),
+            language: None,
+            confidence: mean(&confidences),
+            hocr,
+        }),
+    }
+}
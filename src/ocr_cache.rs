@@ -0,0 +1,94 @@
+//! A cache of what OCR made of each page, kept in the index's `.kartka` directory and keyed by
+//! a hash of the page image, so hydrating again or retrying a failed scan only OCRs pages it
+//! hasn't seen before.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    integrity,
+    ocr::{OcrEngine, Page},
+};
+
+pub const CACHE_DIR: &str = ".kartka/ocr-cache";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    text: String,
+    confidence: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hocr: Option<String>,
+}
+
+/// Wraps `engine`, answering from the cache in `dir` where it can. Different engines and
+/// languages read the same page differently, so each gets a `dir` of its own.
+pub struct Cached {
+    pub engine: Box<dyn OcrEngine>,
+    pub dir: PathBuf,
+}
+
+impl Cached {
+    fn lookup(&self, path: &Path) -> Option<(PathBuf, Option<Entry>)> {
+        // a page that can't be hashed just doesn't get cached
+        let hash = integrity::sha256(path).ok()?;
+        let entry_path = self.dir.join(format!("{hash}.json"));
+        let entry = fs::read(&entry_path)
+            .ok()
+            .and_then(|it| serde_json::from_slice(&it).ok());
+        Some((entry_path, entry))
+    }
+
+    fn store(&self, entry_path: &Path, entry: &Entry) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temp = entry_path.with_extension("tmp");
+        fs::write(&temp, serde_json::to_vec(entry)?)?;
+        fs::rename(&temp, entry_path)?;
+        Ok(())
+    }
+}
+
+impl OcrEngine for Cached {
+    fn image_to_text(&self, path: &Path) -> Result<String> {
+        Ok(self.image_to_text_with_confidence(path)?.0)
+    }
+
+    fn image_to_text_with_confidence(&self, path: &Path) -> Result<(String, Option<f32>)> {
+        let page = self.image_to_page(path)?;
+        Ok((page.text, page.confidence))
+    }
+
+    fn image_to_page(&self, path: &Path) -> Result<Page> {
+        let Some((entry_path, entry)) = self.lookup(path) else {
+            return self.engine.image_to_page(path);
+        };
+        if let Some(entry) = entry {
+            debug!("already OCR'd {path:?}");
+            return Ok(Page {
+                text: entry.text,
+                confidence: entry.confidence,
+                hocr: entry.hocr,
+            });
+        }
+
+        let page = self.engine.image_to_page(path)?;
+        let entry = Entry {
+            text: page.text,
+            confidence: page.confidence,
+            hocr: page.hocr,
+        };
+        if let Err(e) = self.store(&entry_path, &entry) {
+            warn!("couldn't cache the text of {path:?}: {e:#}");
+        }
+        Ok(Page {
+            text: entry.text,
+            confidence: entry.confidence,
+            hocr: entry.hocr,
+        })
+    }
+}
@@ -0,0 +1,74 @@
+//! Handing OCR over to [OCRmyPDF](https://ocrmypdf.readthedocs.io) with `pipeline = "ocrmypdf"`.
+//! It straightens skewed pages and turns sideways ones the right way up before reading them with
+//! tesseract, then lays the text over the pages, so what's uploaded is a PDF that can be searched
+//! and copied from in any viewer. Kartka still names, indexes and uploads it, taking its text
+//! from the sidecar file OCRmyPDF writes alongside.
+
+use std::{fs, path::Path, process::Command};
+
+use eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    index,
+    ocr::{detect_language, Recognised},
+    text, Kartka,
+};
+
+/// Who turns scanned pages into text and PDFs, set with `pipeline`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pipeline {
+    /// Kartka OCRs the pages itself with `ocr_engine`, and uploads a PDF of just the images.
+    #[default]
+    Kartka,
+    /// OCRmyPDF OCRs them and makes the PDF, with the text laid over the pages.
+    Ocrmypdf,
+}
+
+/// The page break in OCRmyPDF's sidecar file.
+const FORM_FEED: char = '\u{c}';
+
+impl Kartka {
+    /// Runs OCRmyPDF over the PDF at `input`, writing the searchable PDF to `output`, and
+    /// returns its text. `psm` and `oem` from `[ocr]` are passed on to tesseract. With
+    /// `detect_languages` set the pages are read in all of them at once, rather than read
+    /// again in the one they turn out to be in.
+    pub fn ocrmypdf(&self, input: &Path, output: &Path) -> Result<Recognised> {
+        let temp_dir = tempfile::tempdir()?;
+        let sidecar = temp_dir.path().join("text.txt");
+        let languages = match self.detect_languages.is_empty() {
+            true => self.ocr_language.clone(),
+            false => self.detect_languages.join("+"),
+        };
+
+        let mut command = Command::new("ocrmypdf");
+        command
+            .args(["--quiet", "--deskew", "--rotate-pages"])
+            // what's passed in is either page images or a PDF without enough text to use, so
+            // any text it has is stray characters not worth keeping
+            .arg("--force-ocr")
+            .arg("--language")
+            .arg(&languages)
+            .arg("--sidecar")
+            .arg(&sidecar);
+        if let Some(psm) = self.ocr.psm {
+            command.arg("--tesseract-pagesegmode").arg(psm.to_string());
+        }
+        if let Some(oem) = self.ocr.oem {
+            command.arg("--tesseract-oem").arg(oem.to_string());
+        }
+        self.tools
+            .run(command.arg(input).arg(output), "running ocrmypdf")?;
+
+        let text = fs::read_to_string(&sidecar).context(format!("reading {sidecar:?}"))?;
+        let text = index::join_pages(text.split(FORM_FEED).map(text::clean));
+        let language = detect_language(&text, &self.detect_languages);
+        Ok(Recognised {
+            text,
+            language,
+            confidence: None,
+            hocr: vec![],
+        })
+    }
+}
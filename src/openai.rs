@@ -0,0 +1,36 @@
+//! Calling OpenAI-compatible APIs, which is what most hosted LLMs and local ones like Ollama
+//! speak.
+
+use eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config;
+
+/// An OpenAI-compatible API and the model to use on it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Endpoint {
+    /// The API's base URL, e.g. `https://api.openai.com/v1` or `http://localhost:11434/v1`.
+    pub endpoint: String,
+    pub model: String,
+    /// A shell command that prints the API key, if the API needs one.
+    #[serde(default)]
+    pub api_key_command: Option<String>,
+}
+
+impl Endpoint {
+    /// POSTs `request` to `path` under the endpoint, returning the JSON that comes back.
+    pub fn post(&self, path: &str, request: &Value) -> Result<Value> {
+        let url = format!("{}/{path}", self.endpoint.trim_end_matches('/'));
+        let mut call = ureq::post(&url);
+        if let Some(command) = &self.api_key_command {
+            let key = config::secret_from_command(command, "api_key_command")?;
+            call = call.header("Authorization", format!("Bearer {key}"));
+        }
+        call.send_json(request)
+            .context(format!("calling {url}"))?
+            .body_mut()
+            .read_json()
+            .context(format!("reading the response from {url}"))
+    }
+}
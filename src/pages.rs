@@ -0,0 +1,279 @@
+//! Looking at page images before they're OCR'd: finding the blank ones, the separator
+//! sheets that split a stack of scans into several documents, and putting them in order.
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use exif::{In, Tag, Value};
+use eyre::{bail, eyre, Context, Result};
+use jiff::{
+    civil::DateTime,
+    tz::{Offset, TimeZone},
+    Timestamp,
+};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::tools::ToolRunner;
+
+/// What a QR code on a separator sheet says. Print one with e.g.
+/// `qrencode -o separator.png KARTKA-SEPARATOR`.
+pub const SEPARATOR_CODE: &str = "KARTKA-SEPARATOR";
+
+/// The share of a page that can be ink for it to still count as blank. Scanners leave specks
+/// of dust and the odd shadow behind, so this can't be zero.
+const BLANK_INK: f64 = 0.002;
+
+/// Which pages separate one document from the next, set with `separator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Separator {
+    /// Blank pages. Don't use this when scanning double-sided.
+    Blank,
+    /// Pages with a QR code saying [`SEPARATOR_CODE`]. Needs `zbarimg`.
+    Qr,
+}
+
+/// How much of the page at `page` is ink, from 0 to 1. The edges are left out, since that's
+/// where scanners leave shadows.
+pub fn ink_ratio(tools: &dyn ToolRunner, page: &Path) -> Result<f64> {
+    let output = tools.run(
+        Command::new("magick")
+            .arg(page)
+            .args(["-gravity", "center", "-crop", "90%x90%+0+0", "+repage"])
+            .args(["-colorspace", "Gray", "-threshold", "60%"])
+            .args(["-format", "%[fx:1-mean]", "info:"]),
+        &format!("measuring ink on {page:?}"),
+    )?;
+    let ratio = String::from_utf8_lossy(&output.stdout);
+    ratio
+        .trim()
+        .parse()
+        .context(format!("reading ink on {page:?} from {ratio:?}"))
+}
+
+/// When the photo at `page` was taken, from its EXIF `DateTimeOriginal`, in the time zone it
+/// says it was taken in or else the local one. `None` for anything without one, which
+/// includes most scans: only cameras write it.
+pub fn taken(page: &Path) -> Option<Timestamp> {
+    let file = File::open(page).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let ascii = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().cloned(),
+        _ => None,
+    };
+
+    let mut time = exif::DateTime::from_ascii(&ascii(Tag::DateTimeOriginal)?).ok()?;
+    if let Some(offset) = ascii(Tag::OffsetTimeOriginal) {
+        // a photo without a sensible offset is no worse off than one without any
+        let _ = time.parse_offset(&offset);
+    }
+    let civil = DateTime::new(
+        i16::try_from(time.year).ok()?,
+        time.month as i8,
+        time.day as i8,
+        time.hour as i8,
+        time.minute as i8,
+        time.second as i8,
+        0,
+    )
+    .ok()?;
+    match time.offset {
+        Some(minutes) => {
+            let offset = Offset::from_seconds(i32::from(minutes) * 60).ok()?;
+            offset.to_timestamp(civil).ok()
+        }
+        None => Some(civil.to_zoned(TimeZone::system()).ok()?.timestamp()),
+    }
+}
+
+/// Whether the page at `page` has next to nothing on it.
+pub fn is_blank(tools: &dyn ToolRunner, page: &Path) -> Result<bool> {
+    Ok(ink_ratio(tools, page)? < BLANK_INK)
+}
+
+/// `pages` without the blank ones. A page that can't be looked at is kept, since losing a page
+/// is worse than keeping an empty one.
+pub fn without_blanks(tools: &dyn ToolRunner, pages: &[PathBuf]) -> Vec<PathBuf> {
+    pages
+        .iter()
+        .filter(|page| match is_blank(tools, page) {
+            Ok(blank) => {
+                if blank {
+                    debug!("{page:?} is blank");
+                }
+                !blank
+            }
+            Err(e) => {
+                warn!("couldn't check whether {page:?} is blank: {e:#}");
+                true
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/// Whether the page at `page` is a separator sheet of kind `separator`.
+pub fn is_separator(tools: &dyn ToolRunner, page: &Path, separator: Separator) -> Result<bool> {
+    match separator {
+        Separator::Blank => is_blank(tools, page),
+        Separator::Qr => {
+            // zbarimg exits with 4 when there's no code to be found, which isn't a failure here
+            let output = tools
+                .output(
+                    Command::new("zbarimg")
+                        .args(["--quiet", "--raw", "-Sdisable", "-Sqrcode.enable"])
+                        .arg(page),
+                )
+                .context("running zbarimg")?;
+            let codes = String::from_utf8_lossy(&output.stdout);
+            Ok(codes.lines().any(|it| it.trim() == SEPARATOR_CODE))
+        }
+    }
+}
+
+/// Splits `pages` into documents at each separator page, leaving the separators themselves
+/// out. A page that can't be looked at is kept with the document it's in rather than risking a
+/// split in the wrong place.
+pub fn split(tools: &dyn ToolRunner, pages: &[PathBuf], separator: Separator) -> Vec<Vec<PathBuf>> {
+    let mut documents = vec![vec![]];
+    for page in pages {
+        let is_separator = is_separator(tools, page, separator).unwrap_or_else(|e| {
+            warn!("couldn't check whether {page:?} is a separator: {e:#}");
+            false
+        });
+        if is_separator {
+            debug!("{page:?} is a separator");
+            documents.push(vec![]);
+        } else if let Some(document) = documents.last_mut() {
+            document.push(page.clone());
+        }
+    }
+    // several separators in a row, or one at either end, don't make empty documents
+    documents.retain(|it| !it.is_empty());
+    documents
+}
+
+/// A page image, turned clockwise by `turn` degrees (0, 90, 180 or 270) before it's OCR'd.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub path: PathBuf,
+    pub turn: u16,
+}
+
+/// A change to the order of the pages in a scan, as typed when reviewing them, with pages
+/// counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// `move 5 2`: moves page 5 to be page 2.
+    Move(usize, usize),
+    /// `swap 1 2`
+    Swap(usize, usize),
+    /// `turn 3`, or `turn 3 180`: turns a page clockwise, by 90 degrees unless it says.
+    Turn(usize, u16),
+    /// `drop 4`: leaves a page out.
+    Drop(usize),
+    /// `duplex`: puts the pages from feeding a stack through twice, fronts and then backs,
+    /// back together. The backs come out last page first, so they're reversed.
+    Duplex,
+}
+
+impl FromStr for Change {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        let number = |at: usize| -> Result<usize> {
+            let word = words
+                .get(at)
+                .ok_or_else(|| eyre!("{s:?} needs a page number"))?;
+            word.parse()
+                .map_err(|_| eyre!("{word:?} isn't a page number"))
+        };
+        Ok(match words.first().copied() {
+            Some("move") => Change::Move(number(1)?, number(2)?),
+            Some("swap") => Change::Swap(number(1)?, number(2)?),
+            Some("turn") => {
+                let degrees = match words.get(2) {
+                    Some(degrees) => degrees.parse().ok(),
+                    None => Some(90),
+                };
+                let Some(degrees) = degrees.filter(|it| [90, 180, 270].contains(it)) else {
+                    bail!("pages can only be turned by 90, 180 or 270 degrees");
+                };
+                Change::Turn(number(1)?, degrees)
+            }
+            Some("drop") => Change::Drop(number(1)?),
+            Some("duplex") => Change::Duplex,
+            _ => bail!("unknown change {s:?}, expected `move`, `swap`, `turn`, `drop` or `duplex`"),
+        })
+    }
+}
+
+impl Change {
+    /// Makes the change to `pages`.
+    pub fn apply(self, pages: &mut Vec<Page>) -> Result<()> {
+        let count = pages.len();
+        let index = |page: usize| match page {
+            1.. if page <= count => Ok(page - 1),
+            _ => Err(eyre!("there's no page {page}, only 1 to {count}")),
+        };
+        match self {
+            Change::Move(from, to) => {
+                let (from, to) = (index(from)?, index(to)?);
+                let page = pages.remove(from);
+                pages.insert(to, page);
+            }
+            Change::Swap(a, b) => pages.swap(index(a)?, index(b)?),
+            Change::Turn(page, degrees) => {
+                let page = &mut pages[index(page)?];
+                page.turn = (page.turn + degrees) % 360;
+            }
+            Change::Drop(page) => {
+                pages.remove(index(page)?);
+            }
+            Change::Duplex => {
+                if !count.is_multiple_of(2) {
+                    bail!("there are {count} pages, so they can't be fronts and backs");
+                }
+                let backs = pages.split_off(count / 2);
+                let fronts = std::mem::take(pages);
+                for (front, back) in fronts.into_iter().zip(backs.into_iter().rev()) {
+                    pages.push(front);
+                    pages.push(back);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies `pages` into `dir` in order, turning the ones that need it, so they're numbered the
+/// way they're to be OCR'd. Returns where they went.
+pub fn arrange(tools: &dyn ToolRunner, pages: &[Page], dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut arranged = vec![];
+    for (i, page) in pages.iter().enumerate() {
+        let extension = page.path.extension().unwrap_or_default().to_string_lossy();
+        let dest = dir.join(format!("page-{:04}.{extension}", i + 1));
+        if page.turn == 0 {
+            fs::copy(&page.path, &dest).context(format!("copying {:?}", page.path))?;
+        } else {
+            tools.run(
+                Command::new("magick")
+                    .arg(&page.path)
+                    .args(["-rotate", &page.turn.to_string()])
+                    .arg(&dest),
+                &format!("turning {:?}", page.path),
+            )?;
+        }
+        arranged.push(dest);
+    }
+    Ok(arranged)
+}
@@ -0,0 +1,348 @@
+//! Moving between kartka and [paperless-ngx](https://docs.paperless-ngx.com), through the
+//! folder its `document_exporter` writes and `document_importer` reads: the files, and a
+//! `manifest.json` of Django records for the documents and their tags, correspondents and
+//! document types. Only what the two have in common makes the trip; paperless' notes, custom
+//! fields and users are left behind, as are kartka's summaries and versions.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, Context, Result};
+use jiff::{civil::Date, tz::TimeZone, Timestamp};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    import::{is_image, named_id},
+    index::document_date,
+    integrity, pdf, progress, store, Kartka, KartkaError,
+};
+
+const MANIFEST: &str = "manifest.json";
+
+const DOCUMENT: &str = "documents.document";
+const TAG: &str = "documents.tag";
+const CORRESPONDENT: &str = "documents.correspondent";
+const DOCUMENT_TYPE: &str = "documents.documenttype";
+
+/// A record in `manifest.json`, as Django serialises a model.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Record {
+    model: String,
+    pk: u64,
+    #[serde(default)]
+    fields: RecordFields,
+    /// The original file, relative to the export.
+    #[serde(
+        rename = "__exported_file_name__",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    file_name: Option<String>,
+    /// The PDF paperless made of the original, if it did.
+    #[serde(
+        rename = "__exported_archive_name__",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    archive_name: Option<String>,
+}
+
+/// The fields kartka knows about, of all the models it reads. Anything else is ignored when
+/// importing and left to paperless' defaults when exporting.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecordFields {
+    /// A tag's, correspondent's or document type's name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mime_type: Option<String>,
+    /// The MD5 of the original file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    /// The document's date: a date in newer versions of paperless, a date and time in older.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    added: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    storage_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    correspondent: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    document_type: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<u64>,
+}
+
+/// Names numbered in the order they're first seen, as the tags, correspondents and document
+/// types of an export.
+#[derive(Default)]
+struct Names(BTreeMap<String, u64>);
+
+impl Names {
+    fn pk(&mut self, name: &str) -> u64 {
+        let next = self.0.len() as u64 + 1;
+        *self.0.entry(name.to_string()).or_insert(next)
+    }
+
+    fn records(self, model: &str) -> impl Iterator<Item = Record> + '_ {
+        self.0.into_iter().map(move |(name, pk)| Record {
+            model: model.to_string(),
+            pk,
+            fields: RecordFields {
+                name: Some(name),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads paperless' `created`, which is either a date or a date and time.
+fn parse_created(created: &str) -> Option<Timestamp> {
+    if let Ok(time) = created.parse::<Timestamp>() {
+        return Some(time);
+    }
+    let date: Date = created.get(..10)?.parse().ok()?;
+    date.to_zoned(TimeZone::system())
+        .ok()
+        .map(|it| it.timestamp())
+}
+
+impl Kartka {
+    /// Writes every document to `dir` as paperless-ngx's `document_exporter` would, for
+    /// `document_importer` to read. Earlier versions of documents are left out, since paperless
+    /// has nowhere to put them. PDFs already in `dir` aren't downloaded again.
+    pub fn export_paperless(&self, dir: &Path) -> Result<()> {
+        let mut ids = vec![];
+        for id in self.documents()? {
+            if !self.is_old_version(&id)? {
+                ids.push(id);
+            }
+        }
+        if ids.is_empty() {
+            bail!("nothing to export");
+        }
+        if self.dry_run {
+            println!(
+                "would export {} documents to {dir:?} for paperless",
+                ids.len()
+            );
+            return Ok(());
+        }
+        fs::create_dir_all(dir).context(format!("creating {dir:?}"))?;
+
+        let added: HashMap<_, _> = self.recent(usize::MAX)?.into_iter().collect();
+        let mut tags = Names::default();
+        let mut correspondents = Names::default();
+        let mut document_types = Names::default();
+        let mut documents = vec![];
+        let mut failed = 0;
+        let bar = progress::bar(ids.len(), "documents");
+        bar.set_message("exporting");
+        for (pk, id) in ids.iter().enumerate() {
+            bar.inc(1);
+            let path = dir.join(id);
+            if let Err(e) = self.export_pdf(id, &path) {
+                bar.suspend(|| warn!("couldn't export {id}: {e:#}"));
+                failed += 1;
+                continue;
+            }
+            let meta = self.metadata(id)?;
+            let scanned = self.ids.scanned(id);
+            let added = added.get(id).copied().or(scanned).map(|it| it.to_string());
+            documents.push(Record {
+                model: DOCUMENT.to_string(),
+                pk: pk as u64 + 1,
+                fields: RecordFields {
+                    title: Some(self.ids.title(id)),
+                    content: Some(self.text(id)?),
+                    mime_type: Some("application/pdf".to_string()),
+                    checksum: Some(md5(&fs::read(&path).context(format!("reading {path:?}"))?)),
+                    created: document_date(id).map(|it| it.to_string()),
+                    modified: added.clone(),
+                    added,
+                    storage_type: Some("unencrypted".to_string()),
+                    original_filename: Some(id.clone()),
+                    correspondent: meta
+                        .correspondent
+                        .as_deref()
+                        .map(|it| correspondents.pk(it)),
+                    document_type: meta.doc_type.as_deref().map(|it| document_types.pk(it)),
+                    tags: meta.tags.iter().map(|it| tags.pk(it)).collect(),
+                    ..Default::default()
+                },
+                file_name: Some(id.clone()),
+                ..Default::default()
+            });
+        }
+        bar.finish_and_clear();
+
+        let records: Vec<_> = tags
+            .records(TAG)
+            .chain(correspondents.records(CORRESPONDENT))
+            .chain(document_types.records(DOCUMENT_TYPE))
+            .chain(documents)
+            .collect();
+        store::write_atomic(&dir.join(MANIFEST), serde_json::to_string_pretty(&records)?)?;
+
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: ids.len(),
+            }
+            .into());
+        }
+        info!(
+            "exported {} documents to {dir:?} - import them with `document_importer`",
+            ids.len()
+        );
+        Ok(())
+    }
+
+    /// Imports the documents in the paperless-ngx export at `dir`, with their dates, tags,
+    /// correspondents and document types. Documents already in the index are skipped, as
+    /// [`Kartka::import`] skips them, and so are ones with a file kartka can't read, like
+    /// office documents paperless didn't make a PDF of.
+    pub fn import_paperless(&self, dir: &Path, no_dupes: bool) -> Result<()> {
+        let manifest = dir.join(MANIFEST);
+        let records: Vec<Record> = serde_json::from_str(
+            &fs::read_to_string(&manifest).context(format!("reading {manifest:?}"))?,
+        )
+        .context(format!("parsing {manifest:?}"))?;
+
+        let names = |model: &str| -> HashMap<u64, String> {
+            records
+                .iter()
+                .filter(|it| it.model == model)
+                .filter_map(|it| Some((it.pk, it.fields.name.clone()?)))
+                .collect()
+        };
+        let tags = names(TAG);
+        let correspondents = names(CORRESPONDENT);
+        let document_types = names(DOCUMENT_TYPE);
+        let documents: Vec<_> = records.iter().filter(|it| it.model == DOCUMENT).collect();
+        if documents.is_empty() {
+            bail!("no documents in {manifest:?}");
+        }
+
+        let mut taken: HashSet<_> = self.documents()?.into_iter().collect();
+        let mut hashes = HashSet::new();
+        for id in &taken {
+            hashes.extend(self.metadata(id)?.sha256);
+        }
+        let mut imported = 0;
+        let mut skipped = 0;
+        let mut failed = 0;
+        for (i, record) in documents.iter().enumerate() {
+            let fields = &record.fields;
+            let name = fields.title.clone().unwrap_or_default();
+            let Some(path) = importable(dir, record) else {
+                warn!("skipping {name:?}, kartka can't read its file");
+                failed += 1;
+                continue;
+            };
+            let hash = integrity::sha256(&path)?;
+            if hashes.contains(&hash) {
+                info!("skipping {name:?}, it's already been imported");
+                skipped += 1;
+                continue;
+            }
+            let Some(created) = fields.created.as_deref().and_then(parse_created) else {
+                warn!("couldn't date {name:?}");
+                failed += 1;
+                continue;
+            };
+
+            // paperless titles documents exported from kartka with their timestamps
+            let id_name =
+                match name.len() == self.ids.stamp_len() && self.ids.scanned(&name).is_some() {
+                    true => "",
+                    false => name.as_str(),
+                };
+            let id = named_id(&self.ids, id_name, created, &taken);
+            if self.dry_run {
+                println!("would import {name:?} as {id}");
+                taken.insert(id);
+                hashes.insert(hash);
+                continue;
+            }
+            info!(
+                "({} / {}) importing {name:?} as {id}..",
+                i + 1,
+                documents.len()
+            );
+            let result = self.import_one(&path, &id, no_dupes).and_then(|()| {
+                self.update_metadata(&id, |meta| {
+                    meta.doc_type = fields
+                        .document_type
+                        .and_then(|it| document_types.get(&it).cloned());
+                    meta.correspondent = fields
+                        .correspondent
+                        .and_then(|it| correspondents.get(&it).cloned());
+                    for tag in fields.tags.iter().filter_map(|it| tags.get(it)) {
+                        if !meta.tags.contains(tag) {
+                            meta.tags.push(tag.clone());
+                        }
+                    }
+                })
+            });
+            match result {
+                Ok(_) => {
+                    taken.insert(id);
+                    hashes.insert(hash);
+                    imported += 1;
+                }
+                Err(e) => {
+                    warn!("failed to import {name:?}: {e:#}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if imported > 0 {
+            self.publish_manifest()?;
+            self.commit_index(&format!("Import {imported} documents from paperless"));
+        }
+        if skipped > 0 {
+            info!("skipped {skipped} already imported");
+        }
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: documents.len(),
+            }
+            .into());
+        }
+        info!("done!");
+        Ok(())
+    }
+}
+
+/// The file of `record` kartka can import: the original if it's a PDF or image, otherwise
+/// the PDF paperless made of it.
+fn importable(dir: &Path, record: &Record) -> Option<PathBuf> {
+    [&record.file_name, &record.archive_name]
+        .into_iter()
+        .flatten()
+        .map(|it| dir.join(it))
+        .find(|it| it.is_file() && (pdf::is_pdf(it) || is_image(it)))
+}
+
+/// The MD5 of `data` in hex, which is what paperless checks its files against.
+fn md5(data: &[u8]) -> String {
+    format!("{:x}", Md5::digest(data))
+}
@@ -0,0 +1,483 @@
+//! Putting PDFs together with ghostscript and ImageMagick.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    thread,
+    time::Instant,
+};
+
+use eyre::{bail, eyre, Context, Result};
+use jiff::{
+    civil::DateTime,
+    tz::{Offset, TimeZone},
+    Timestamp,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::tools::{self, ToolRunner};
+
+/// A PDF to be merged into a bigger one, bookmarked under `title`.
+#[derive(Debug)]
+pub struct Part {
+    pub title: String,
+    pub path: PathBuf,
+    pub pages: usize,
+}
+
+impl Part {
+    pub fn new(tools: &dyn ToolRunner, title: String, path: PathBuf) -> Result<Self> {
+        let pages = page_count(tools, &path)?;
+        Ok(Part { title, path, pages })
+    }
+}
+
+/// Whether `path` is named like a PDF.
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("pdf"))
+}
+
+pub fn page_count(tools: &dyn ToolRunner, path: &Path) -> Result<usize> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| eyre!("non-UTF-8 path {path:?}"))?;
+    let output = tools.run(
+        Command::new("gs")
+            .arg("-q")
+            .arg("-dNODISPLAY")
+            .arg(format!("--permit-file-read={path_str}"))
+            .arg("-c")
+            .arg(format!(
+                "{} (r) file runpdfbegin pdfpagecount = quit",
+                ps_string(path_str)
+            )),
+        "counting PDF pages",
+    )?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .context(format!("reading page count of {path:?}"))
+}
+
+/// When the PDF at `path` says it was created, if it says at all.
+pub fn creation_date(tools: &dyn ToolRunner, path: &Path) -> Result<Option<Timestamp>> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| eyre!("non-UTF-8 path {path:?}"))?;
+    let output = tools.run(
+        Command::new("gs")
+            .arg("-q")
+            .arg("-dNODISPLAY")
+            .arg(format!("--permit-file-read={path_str}"))
+            .arg("-c")
+            .arg(format!(
+                "{} (r) file runpdfbegin Trailer /Info knownoget {{ /CreationDate knownoget {{ = }} if }} if quit",
+                ps_string(path_str)
+            )),
+        "reading PDF creation date",
+    )?;
+
+    Ok(parse_date(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Parses a PDF date like `D:20240131091500+01'00'`. Dates without a UTC offset are taken to be
+/// in the local time zone.
+fn parse_date(date: &str) -> Option<Timestamp> {
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let time = DateTime::strptime("%Y%m%d%H%M%S", date.get(..14)?).ok()?;
+    let sign = match date.get(14..15) {
+        Some("+") => 1,
+        Some("-") => -1,
+        Some("Z") => return Offset::UTC.to_timestamp(time).ok(),
+        _ => {
+            return time
+                .to_zoned(TimeZone::system())
+                .ok()
+                .map(|it| it.timestamp())
+        }
+    };
+    let hours: i32 = date.get(15..17)?.parse().ok()?;
+    let minutes: i32 = date.get(18..20).and_then(|it| it.parse().ok()).unwrap_or(0);
+    let offset = Offset::from_seconds(sign * (hours * 3600 + minutes * 60)).ok()?;
+    offset.to_timestamp(time).ok()
+}
+
+/// Concatenates `parts` into `out`. The outline gets a bookmark for each part, and under that
+/// (collapsed) one for each of its pages, and opens by default in viewers that support it.
+pub fn merge(tools: &dyn ToolRunner, parts: &[Part], out: &Path) -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let marks_path = temp_dir.path().join("bookmarks.ps");
+    fs::write(&marks_path, outline(parts)?)?;
+
+    tools.run(
+        Command::new("gs")
+            .arg("-q")
+            .arg("-dBATCH")
+            .arg("-dNOPAUSE")
+            .arg("-sDEVICE=pdfwrite")
+            .arg(format!("-sOutputFile={}", out.display()))
+            .args(parts.iter().map(|it| &it.path))
+            .arg(&marks_path),
+        "merging PDFs",
+    )?;
+
+    Ok(())
+}
+
+fn outline(parts: &[Part]) -> Result<String> {
+    let mut marks = String::from("[/PageMode /UseOutlines /DOCVIEW pdfmark\n");
+    let mut page = 1;
+    for part in parts {
+        // a page bookmark on a one page document is just noise
+        let children = if part.pages > 1 { part.pages } else { 0 };
+        let count = if children > 0 {
+            format!("/Count -{children} ")
+        } else {
+            String::new()
+        };
+        writeln!(
+            marks,
+            "[{count}/Title {} /Page {page} /OUT pdfmark",
+            pdf_text(&part.title)
+        )?;
+        for i in 0..children {
+            writeln!(
+                marks,
+                "[/Title {} /Page {} /OUT pdfmark",
+                pdf_text(&format!("Page {}", i + 1)),
+                page + i
+            )?;
+        }
+        page += part.pages;
+    }
+    Ok(marks)
+}
+
+/// How page images are compressed when they're made into a PDF, configured as `[pdf]` in
+/// kartka.toml. Anything not set leaves the pages as they were scanned.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Quality {
+    /// Compress pages as JPEGs of this quality, from 1 to 100.
+    pub jpeg_quality: Option<u8>,
+    /// Scale pages to this many dots per inch. Only scans that say what resolution they are can
+    /// be scaled.
+    pub dpi: Option<u32>,
+    /// Store pages in shades of grey rather than colour.
+    #[serde(alias = "grayscale")]
+    pub greyscale: bool,
+    /// Make PDF/A-2b files, the kind made to be archived, which carry their colour profile
+    /// and metadata with them so they can still be read the same way decades from now.
+    pub pdfa: bool,
+    /// The ICC colour profile PDF/A files are made with, rather than the sRGB one that comes
+    /// with ghostscript.
+    pub icc_profile: Option<PathBuf>,
+    /// A shell command that prints the password `scan --protect` protects PDFs with, rather
+    /// than asking for one, e.g. `pass show kartka/pdf`.
+    pub password_command: Option<String>,
+    /// The password to protect the PDFs being made with, if they're to be protected.
+    #[serde(skip)]
+    pub protect_with: Option<String>,
+}
+
+/// Puts the page images `pages` together into a PDF at `out`, compressed as `quality` says.
+pub fn images_to_pdf(
+    tools: &dyn ToolRunner,
+    pages: &[PathBuf],
+    out: &Path,
+    quality: &Quality,
+) -> Result<()> {
+    let mut command = Command::new("magick");
+    command.args(pages);
+    if let Some(dpi) = quality.dpi {
+        command
+            .arg("-units")
+            .arg("PixelsPerInch")
+            .arg("-resample")
+            .arg(dpi.to_string());
+    }
+    if quality.greyscale {
+        command.arg("-colorspace").arg("Gray");
+    }
+    if let Some(jpeg_quality) = quality.jpeg_quality {
+        if !(1..=100).contains(&jpeg_quality) {
+            bail!("jpeg_quality has to be from 1 to 100, not {jpeg_quality}");
+        }
+        command
+            .arg("-compress")
+            .arg("jpeg")
+            .arg("-quality")
+            .arg(jpeg_quality.to_string());
+    }
+    tools.run(command.arg(out), "converting pages to PDF")?;
+    Ok(())
+}
+
+/// What a PDF says about itself, so it can be told apart without kartka, e.g. when it's
+/// browsed straight on the remote.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Info {
+    pub id: String,
+    pub title: String,
+    /// When it was scanned.
+    pub created: Option<Timestamp>,
+    /// Who sent it.
+    pub author: Option<String>,
+    /// Its type and tags.
+    pub keywords: Vec<String>,
+}
+
+/// Writes the PDF at `input` to `out` with `info` in its metadata, both its Info dictionary
+/// and the XMP ghostscript writes from that. If `quality` says so it's made a PDF/A-2b, with
+/// the colour profile PDF/A needs embedded in it.
+pub fn rewrite(
+    tools: &dyn ToolRunner,
+    input: &Path,
+    out: &Path,
+    info: &Info,
+    quality: &Quality,
+) -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let def_path = temp_dir.path().join("info.ps");
+    let mut def = docinfo(info);
+    if quality.pdfa {
+        let profile = match &quality.icc_profile {
+            Some(path) => {
+                let path = path
+                    .to_str()
+                    .ok_or_else(|| eyre!("non-UTF-8 path {path:?}"))?;
+                format!("{} (r) file", ps_string(path))
+            }
+            // it's in ghostscript's iccprofiles directory, which is on its search path
+            None => "(srgb.icc) findlibfile { exch pop } { (no srgb.icc in ghostscript's search path) = quit } ifelse".to_string(),
+        };
+        def.push_str(&output_intent(&profile));
+    }
+    fs::write(&def_path, def)?;
+
+    let mut command = Command::new("gs");
+    command
+        .args(["-q", "-dBATCH", "-dNOPAUSE", "-sDEVICE=pdfwrite"])
+        .arg(format!("-sOutputFile={}", out.display()));
+    if quality.pdfa {
+        command
+            .arg("-dPDFA=2")
+            // anything that can't be made PDF/A is left out rather than failing the scan
+            .arg("-dPDFACompatibilityPolicy=1")
+            .arg("-sColorConversionStrategy=RGB");
+        if let Some(path) = &quality.icc_profile {
+            command.arg(format!("--permit-file-read={}", path.display()));
+        }
+    }
+    tools.run(
+        command.arg(&def_path).arg(input),
+        "writing the PDF's metadata",
+    )?;
+    Ok(())
+}
+
+/// Protects the PDF at `path` with `password`, encrypting it with AES-256 so it can't be opened
+/// without it.
+pub fn protect(tools: &dyn ToolRunner, path: &Path, password: &str) -> Result<()> {
+    if password.is_empty() {
+        bail!("a PDF can't be protected with an empty password");
+    }
+    // qpdf reads its arguments from a file, so the password doesn't show up in `ps`
+    let temp_dir = tempfile::tempdir()?;
+    let args_path = temp_dir.path().join("args");
+    let protected = temp_dir.path().join("protected.pdf");
+    fs::write(
+        &args_path,
+        format!("--encrypt\n{password}\n{password}\n256\n--\n"),
+    )?;
+    tools.run(
+        Command::new("qpdf")
+            .arg(format!("@{}", args_path.display()))
+            .arg(path)
+            .arg(&protected),
+        "protecting the PDF with a password",
+    )?;
+    fs::copy(&protected, path).context(format!("replacing {path:?}"))?;
+    Ok(())
+}
+
+/// The pdfmark that sets a PDF's Info dictionary to `info`.
+fn docinfo(info: &Info) -> String {
+    let mut fields = vec![
+        ("Title", info.title.clone()),
+        ("Subject", format!("kartka document {}", info.id)),
+        ("Creator", "kartka".to_string()),
+    ];
+    if let Some(author) = &info.author {
+        fields.push(("Author", author.clone()));
+    }
+    if !info.keywords.is_empty() {
+        fields.push(("Keywords", info.keywords.join(", ")));
+    }
+    let mut marks = String::from("%!\n[");
+    for (key, value) in fields {
+        let _ = write!(marks, "/{key} {} ", pdf_text(&value));
+    }
+    if let Some(created) = info.created {
+        let date = created.strftime("D:%Y%m%d%H%M%SZ").to_string();
+        let _ = write!(marks, "/CreationDate {} ", ps_string(&date));
+    }
+    marks.push_str("/DOCINFO pdfmark\n");
+    marks
+}
+
+/// The PostScript that gives a PDF/A its output intent, with the colour profile read by
+/// `profile` embedded in it.
+fn output_intent(profile: &str) -> String {
+    format!(
+        "[/_objdef {{icc_PDFA}} /type /stream /OBJ pdfmark\n\
+         [{{icc_PDFA}} <</N 3>> /PUT pdfmark\n\
+         [{{icc_PDFA}} {profile} /PUT pdfmark\n\
+         [/_objdef {{OutputIntent_PDFA}} /type /dict /OBJ pdfmark\n\
+         [{{OutputIntent_PDFA}} <</Type /OutputIntent /S /GTS_PDFA1 /DestOutputProfile {{icc_PDFA}} /OutputConditionIdentifier (sRGB)>> /PUT pdfmark\n\
+         [{{Catalog}} <</OutputIntents [{{OutputIntent_PDFA}}]>> /PUT pdfmark\n"
+    )
+}
+
+/// Renders plain `text` onto as many A4 pages as it needs.
+pub fn text_pages(tools: &dyn ToolRunner, text: &str, out: &Path) -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let text_path = temp_dir.path().join("text.txt");
+    fs::write(&text_path, text)?;
+
+    tools.run(
+        Command::new("magick")
+            .arg("-density")
+            .arg("150")
+            .arg("-page")
+            .arg("A4")
+            .arg("-pointsize")
+            .arg("10")
+            .arg(format!("text:{}", text_path.display()))
+            .arg(out),
+        "rendering text to PDF",
+    )?;
+
+    Ok(())
+}
+
+/// Renders every page of the PDF at `path` to `<out_dir>/<prefix>-NNNN.png`, numbered from 0.
+///
+/// ImageMagick only uses one core per PDF, so long documents are split into page ranges that
+/// are rendered side by side.
+pub fn render_pages(
+    tools: &dyn ToolRunner,
+    path: &Path,
+    out_dir: &Path,
+    prefix: &str,
+) -> Result<()> {
+    let started = Instant::now();
+    let out_pattern = out_dir.join(format!("{prefix}-%04d.png"));
+    let pages = page_count(tools, path)?;
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = pages.div_ceil(threads).max(1);
+
+    thread::scope(|scope| -> Result<()> {
+        let handles: Vec<_> = (0..pages)
+            .step_by(chunk_size)
+            .map(|start| {
+                let end = (start + chunk_size).min(pages) - 1;
+                let out_pattern = &out_pattern;
+                scope.spawn(move || {
+                    tools.run(
+                        Command::new("magick")
+                            .arg(format!("{}[{start}-{end}]", path.display()))
+                            .arg("-scene")
+                            .arg(start.to_string())
+                            .arg(out_pattern),
+                        "rendering PDF pages",
+                    )
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .try_for_each(|it| it.join().expect("render thread panicked").map(|_| ()))
+    })?;
+
+    debug!("rendered {pages} pages in {:?}", started.elapsed());
+    Ok(())
+}
+
+/// The text in the PDF at `path` itself, as opposed to in pictures of pages, a page at a time.
+/// Scans don't have any, but PDFs that were made on a computer usually do.
+pub fn extract_text(tools: &dyn ToolRunner, path: &Path) -> Result<Vec<String>> {
+    let mut pages = vec![];
+    for page in 1..=page_count(tools, path)? {
+        let output = tools.run(
+            Command::new("gs")
+                .arg("-q")
+                .arg("-dNOPAUSE")
+                .arg("-dBATCH")
+                .arg("-sDEVICE=txtwrite")
+                .arg(format!("-dFirstPage={page}"))
+                .arg(format!("-dLastPage={page}"))
+                .arg("-sOutputFile=-")
+                .arg(path),
+            "extracting PDF text",
+        )?;
+        pages.push(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    Ok(pages)
+}
+
+/// Renders page `page` (from 0) of the PDF at `path` as a PNG at most `width` pixels wide.
+pub fn render_page(
+    tools: &dyn ToolRunner,
+    path: &Path,
+    page: usize,
+    width: u32,
+    out: &Path,
+) -> Result<()> {
+    tools.run(
+        Command::new("magick")
+            .arg("-density")
+            .arg("100")
+            .arg(format!("{}[{page}]", path.display()))
+            .arg("-background")
+            .arg("white")
+            .arg("-flatten")
+            .arg("-resize")
+            .arg(format!("{width}x>"))
+            .arg(out),
+        "rendering PDF page",
+    )?;
+    Ok(())
+}
+
+/// Runs `command` directly rather than through a [`ToolRunner`], failing with what it printed
+/// to stderr if it doesn't succeed.
+pub(crate) fn run(command: &mut Command, what: &str) -> Result<Output> {
+    tools::checked(command.output(), what)
+}
+
+/// A PostScript string literal.
+fn ps_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('(', "\\(")
+        .replace(')', "\\)");
+    format!("({escaped})")
+}
+
+/// A PDF text string, as UTF-16 hex so any title survives.
+fn pdf_text(s: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in s.encode_utf16() {
+        let _ = write!(hex, "{unit:04X}");
+    }
+    hex.push('>');
+    hex
+}
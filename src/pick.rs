@@ -0,0 +1,89 @@
+//! `kartka pick`: choosing a document by fuzzy-finding it, for when it's quicker to recognise
+//! than to search for. Uses fzf if it's installed, for its preview pane, or else inquire's
+//! filtering list.
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use eyre::{bail, Context, Result};
+use kartka::Kartka;
+use tracing::warn;
+
+use crate::{describe, SHOW_LINES};
+
+/// Lets the user choose a document, returning its id, or `None` if they changed their mind.
+pub fn pick(kartka: &Kartka) -> Result<Option<String>> {
+    let mut choices = vec![];
+    for id in kartka.documents()?.iter().rev() {
+        choices.push((id.clone(), describe(&kartka.metadata(id)?, id)));
+    }
+    if choices.is_empty() {
+        bail!("there's nothing in the index to pick from");
+    }
+
+    match with_fzf(kartka, &choices) {
+        Ok(picked) => Ok(picked),
+        Err(e) => {
+            warn!("couldn't run fzf, so there's no preview: {e:#}");
+            let lines = choices.iter().map(|(_, line)| line.clone()).collect();
+            let Some(picked) = inquire::Select::new("Document:", lines).prompt_skippable()? else {
+                return Ok(None);
+            };
+            Ok(choices
+                .into_iter()
+                .find(|(_, line)| *line == picked)
+                .map(|(id, _)| id))
+        }
+    }
+}
+
+/// Picks one of `choices` with fzf, previewing the start of each document's text.
+fn with_fzf(kartka: &Kartka, choices: &[(String, String)]) -> Result<Option<String>> {
+    // fzf runs the preview command for every line it lands on, so the previews are written out
+    // up front rather than starting kartka again each time
+    let previews = tempfile::tempdir()?;
+    for (id, line) in choices {
+        let mut preview = format!("{line}\n");
+        if let Some(summary) = kartka.metadata(id)?.summary {
+            preview.push_str(&format!("\n{summary}\n"));
+        }
+        preview.push('\n');
+        let text = kartka.text(id)?;
+        for line in text
+            .lines()
+            .filter(|it| !it.trim().is_empty())
+            .take(SHOW_LINES)
+        {
+            preview.push_str(line);
+            preview.push('\n');
+        }
+        fs::write(previews.path().join(id), preview)?;
+    }
+
+    let preview_dir = previews.path().display().to_string().replace('\'', "'\\''");
+    let mut fzf = Command::new("fzf")
+        .arg("--no-sort")
+        .arg("--preview")
+        .arg(format!("cat '{preview_dir}'/{{1}}"))
+        .arg("--preview-window")
+        .arg("right,60%,wrap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("running fzf")?;
+    if let Some(mut stdin) = fzf.stdin.take() {
+        for (_, line) in choices {
+            writeln!(stdin, "{line}")?;
+        }
+    }
+    let output = fzf.wait_with_output()?;
+    // fzf exits with 1 when nothing matched and 130 when it's cancelled
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let picked = String::from_utf8_lossy(&output.stdout);
+    Ok(picked.split_whitespace().next().map(str::to_string))
+}
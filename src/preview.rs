@@ -0,0 +1,106 @@
+//! Drawing images inline in terminals that can, using whichever graphics protocol the terminal
+//! speaks.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process::Command,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{bail, Context, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+}
+
+/// Works out which graphics protocol the terminal supports, if any. Sixel support can't be
+/// spotted from the environment, so it has to be asked for with `KARTKA_PREVIEW=sixel`
+/// (`KARTKA_PREVIEW=none` turns previews off).
+pub fn detect() -> Option<Protocol> {
+    match env::var("KARTKA_PREVIEW").ok().as_deref() {
+        Some("kitty") => return Some(Protocol::Kitty),
+        Some("iterm2") => return Some(Protocol::Iterm2),
+        Some("sixel") => return Some(Protocol::Sixel),
+        Some("none") => return None,
+        _ => {}
+    }
+
+    let term = env::var("TERM").unwrap_or_default();
+    let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+    if env::var_os("KITTY_WINDOW_ID").is_some()
+        || term == "xterm-kitty"
+        || term_program == "ghostty"
+    {
+        Some(Protocol::Kitty)
+    } else if matches!(term_program.as_str(), "iTerm.app" | "WezTerm") {
+        Some(Protocol::Iterm2)
+    } else {
+        None
+    }
+}
+
+/// Draws the image at `path` at the cursor.
+pub fn show(protocol: Protocol, path: &Path) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    match protocol {
+        Protocol::Kitty => {
+            // kitty only takes PNGs as they are
+            let data = fs::read(path)?;
+            let png = if data.starts_with(b"\x89PNG") {
+                data
+            } else {
+                convert(path, "png:-")?
+            };
+            let encoded = STANDARD.encode(png);
+            // kitty wants the payload in chunks of at most 4096 bytes
+            let chunks: Vec<_> = encoded.as_bytes().chunks(4096).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = if i + 1 < chunks.len() { 1 } else { 0 };
+                let control = if i == 0 {
+                    format!("f=100,a=T,m={more}")
+                } else {
+                    format!("m={more}")
+                };
+                write!(stdout, "\x1b_G{control};")?;
+                stdout.write_all(chunk)?;
+                write!(stdout, "\x1b\\")?;
+            }
+        }
+        Protocol::Iterm2 => {
+            let data = fs::read(path)?;
+            write!(
+                stdout,
+                "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+                data.len(),
+                STANDARD.encode(&data)
+            )?;
+        }
+        Protocol::Sixel => {
+            stdout.write_all(&convert(path, "sixel:-")?)?;
+        }
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Converts the image at `path` with ImageMagick, returning what it writes to `format` (e.g.
+/// `png:-`).
+fn convert(path: &Path, format: &str) -> Result<Vec<u8>> {
+    let output = Command::new("magick")
+        .arg(path)
+        .arg(format)
+        .output()
+        .context("running magick")?;
+    if !output.status.success() {
+        bail!(
+            "converting preview to {format} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,29 @@
+//! Progress bars for the long-running pipelines. Every bar is drawn through one shared
+//! [`MultiProgress`], which the binary hides for `--plain` and `--quiet` and routes log lines
+//! through so they print above the bars rather than through them.
+
+use std::{sync::OnceLock, time::Duration};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// Where every progress bar is drawn.
+pub fn bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// A bar counting `len` `unit`s (e.g. "pages"), with an ETA and room for the current stage.
+pub(crate) fn bar(len: usize, unit: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template(&format!(
+        "{{bar:30}} {{pos}}/{{len}} {unit} (eta {{eta}}) {{msg}}"
+    ))
+    .expect("progress template is valid");
+    bars().add(ProgressBar::new(len as u64).with_style(style))
+}
+
+/// A spinner for a step of unknown length, showing `message`.
+pub(crate) fn spinner(message: &str) -> ProgressBar {
+    let spinner = bars().add(ProgressBar::new_spinner().with_message(message.to_string()));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
@@ -0,0 +1,80 @@
+//! Documents that keep failing to hydrate. After enough failed attempts a document is
+//! quarantined: `hydrate` skips it rather than trying (and failing) every run, and `status`
+//! lists it so it can be looked at by hand.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+const QUARANTINE: &str = ".kartka/quarantine.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    /// How many hydrates in a row this document has failed.
+    pub attempts: u32,
+    /// The most recent error.
+    pub error: String,
+}
+
+/// Failed hydrate attempts per document id.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Quarantine(BTreeMap<String, Failure>);
+
+impl Quarantine {
+    /// Reads the failures recorded in `index`, or none if nothing has failed yet.
+    pub fn load(index: &Path) -> Result<Self> {
+        let path = index.join(QUARANTINE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+        serde_json::from_str(&contents).context(format!("parsing {path:?}"))
+    }
+
+    pub fn save(&self, index: &Path) -> Result<()> {
+        let path = index.join(QUARANTINE);
+        fs::create_dir_all(index.join(".kartka"))?;
+
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .context(format!("writing {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &path).context(format!("writing {path:?}"))?;
+        Ok(())
+    }
+
+    /// Records a failed attempt at `id`, returning how many attempts have now failed.
+    pub fn record_failure(&mut self, id: &str, error: String) -> u32 {
+        let failure = self.0.entry(id.to_string()).or_insert(Failure {
+            attempts: 0,
+            error: String::new(),
+        });
+        failure.attempts += 1;
+        failure.error = error;
+        failure.attempts
+    }
+
+    /// Forgets any failures for `id`, e.g. once it hydrates successfully.
+    pub fn clear(&mut self, id: &str) {
+        self.0.remove(id);
+    }
+
+    /// Forgets every failure, giving quarantined documents another go.
+    pub fn clear_all(&mut self) {
+        self.0.clear();
+    }
+
+    /// Whether `id` has failed at least `after` times.
+    pub fn is_quarantined(&self, id: &str, after: u32) -> bool {
+        self.0.get(id).is_some_and(|it| it.attempts >= after)
+    }
+
+    /// Documents that have failed at least `after` times.
+    pub fn quarantined(&self, after: u32) -> impl Iterator<Item = (&str, &Failure)> {
+        self.0
+            .iter()
+            .filter(move |(_, failure)| failure.attempts >= after)
+            .map(|(id, failure)| (id.as_str(), failure))
+    }
+}
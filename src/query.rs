@@ -0,0 +1,206 @@
+//! Narrowing searches down by what's known about documents, not just their text. A search like
+//! `tag:invoice date:2024-03 title:electric amount:>100 overdue` is split into filters on each
+//! document's metadata and the text to look for (here `overdue`), which can be left out to
+//! filter every document.
+
+use std::{ops::RangeInclusive, sync::OnceLock};
+
+use eyre::{bail, eyre, Result};
+use jiff::civil::Date;
+use regex::Regex;
+
+use crate::{index::document_date, metadata::Metadata, search::comparable, Kartka};
+
+/// A `field:value` anywhere in a search, with the value optionally in quotes so it can have
+/// spaces in it.
+fn field() -> &'static Regex {
+    static FIELD: OnceLock<Regex> = OnceLock::new();
+    FIELD.get_or_init(|| {
+        Regex::new(
+            r#"(?:^|\s)(tag|type|from|correspondent|collection|date|title|amount|total):("[^"]*"|'[^']*'|\S+)"#,
+        )
+        .expect("field regex is valid")
+    })
+}
+
+/// A search split into its parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    /// What to look for in the text, which is empty if only the filters matter.
+    pub text: String,
+    pub filters: Vec<Filter>,
+}
+
+/// One `field:value` in a search.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Tag(String),
+    Type(String),
+    /// Who sent it.
+    From(String),
+    Collection(String),
+    /// When it was scanned.
+    Date(Compare, RangeInclusive<Date>),
+    /// Words in its id or its first line.
+    Title(String),
+    /// Its total, as picked out by `kartka extract`.
+    Amount(Compare, f64),
+}
+
+/// How a document's date or amount is compared with the one in a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Is,
+    Before,
+    AtMost,
+    After,
+    AtLeast,
+}
+
+impl Compare {
+    /// The comparison at the start of `value`, and what's left of it.
+    fn split(value: &str) -> (Compare, &str) {
+        let comparisons = [
+            (">=", Compare::AtLeast),
+            ("<=", Compare::AtMost),
+            (">", Compare::After),
+            ("<", Compare::Before),
+        ];
+        comparisons
+            .into_iter()
+            .find_map(|(prefix, compare)| Some((compare, value.strip_prefix(prefix)?)))
+            .unwrap_or((Compare::Is, value))
+    }
+}
+
+impl Query {
+    /// Splits `search` into the filters in it and the text to look for. Anything that only
+    /// looks like a filter, such as `Ref:` or `https://`, is left in the text.
+    pub fn parse(search: &str) -> Result<Query> {
+        let mut filters = vec![];
+        let mut text = String::new();
+        let mut rest = 0;
+        for found in field().captures_iter(search) {
+            let whole = found.get(0).expect("there's always a whole match");
+            text.push_str(&search[rest..whole.start()]);
+            text.push(' ');
+            rest = whole.end();
+
+            let value = found[2].trim_matches(['"', '\'']);
+            filters.push(Filter::parse(&found[1], value)?);
+        }
+        text.push_str(&search[rest..]);
+
+        let text = match filters.is_empty() {
+            // a regex may mean every space it has
+            true => search.trim().to_string(),
+            false => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        Ok(Query { text, filters })
+    }
+}
+
+impl Filter {
+    fn parse(field: &str, value: &str) -> Result<Filter> {
+        if value.is_empty() {
+            bail!("`{field}:` needs something after it");
+        }
+        Ok(match field {
+            "tag" => Filter::Tag(value.to_string()),
+            "type" => Filter::Type(value.to_string()),
+            "from" | "correspondent" => Filter::From(value.to_string()),
+            "collection" => Filter::Collection(value.to_string()),
+            "title" => Filter::Title(value.to_string()),
+            "date" => {
+                let (compare, date) = Compare::split(value);
+                Filter::Date(compare, dates(date)?)
+            }
+            "amount" | "total" => {
+                let (compare, amount) = Compare::split(value);
+                let amount = amount
+                    .trim_start_matches(|c: char| !c.is_ascii_digit())
+                    .replace(',', "")
+                    .parse()
+                    .map_err(|_| eyre!("`{field}:{value}` needs a number, like `{field}:>100`"))?;
+                Filter::Amount(compare, amount)
+            }
+            _ => unreachable!("the field regex only finds known fields"),
+        })
+    }
+}
+
+/// The days `value` covers, whether it's a year (`2024`), a month (`2024-03`) or a day
+/// (`2024-03-05`).
+fn dates(value: &str) -> Result<RangeInclusive<Date>> {
+    let parts: Vec<_> = value.split('-').collect();
+    let numbers: Option<Vec<i16>> = parts.iter().map(|it| it.parse().ok()).collect();
+    let range = match numbers.as_deref() {
+        Some(&[year]) => Date::new(year, 1, 1).map(|start| start..=start.last_of_year()),
+        Some(&[year, month]) => {
+            Date::new(year, month as i8, 1).map(|start| start..=start.last_of_month())
+        }
+        Some(&[year, month, day]) => Date::new(year, month as i8, day as i8).map(|day| day..=day),
+        _ => bail!("`date:{value}` needs a year, month or day, like 2024, 2024-03 or 2024-03-05"),
+    };
+    range.map_err(|e| eyre!("`date:{value}` isn't a date: {e}"))
+}
+
+/// Whether `value` falls `compare` of `range`: in it, or before or after it.
+fn compared<T: PartialOrd>(value: T, compare: Compare, range: &RangeInclusive<T>) -> bool {
+    match compare {
+        Compare::Is => range.contains(&value),
+        Compare::Before => value < *range.start(),
+        Compare::AtMost => value <= *range.end(),
+        Compare::After => value > *range.end(),
+        Compare::AtLeast => value >= *range.start(),
+    }
+}
+
+impl Kartka {
+    /// Whether `id` passes every one of `filters`.
+    pub fn passes(&self, id: &str, filters: &[Filter]) -> Result<bool> {
+        if filters.is_empty() {
+            return Ok(true);
+        }
+        let meta = self.metadata(id)?;
+        for filter in filters {
+            if !self.passes_one(id, &meta, filter)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn passes_one(&self, id: &str, meta: &Metadata, filter: &Filter) -> Result<bool> {
+        let is = |expected: &str, actual: &Option<String>| {
+            actual
+                .as_ref()
+                .is_some_and(|it| it.eq_ignore_ascii_case(expected))
+        };
+        Ok(match filter {
+            Filter::Tag(tag) => meta.tags.iter().any(|it| it.eq_ignore_ascii_case(tag)),
+            Filter::Type(doc_type) => is(doc_type, &meta.doc_type),
+            Filter::From(correspondent) => is(correspondent, &meta.correspondent),
+            Filter::Collection(name) => meta
+                .collections
+                .iter()
+                .any(|it| it.eq_ignore_ascii_case(name)),
+            Filter::Date(compare, range) => {
+                document_date(id).is_some_and(|it| compared(it, *compare, range))
+            }
+            Filter::Amount(compare, amount) => meta
+                .fields
+                .total
+                .as_ref()
+                .and_then(|it| it.parse::<f64>().ok())
+                .is_some_and(|it| compared(it, *compare, &(*amount..=*amount))),
+            Filter::Title(words) => {
+                let text = self.text(id)?;
+                let first_line = text.lines().find(|it| !it.trim().is_empty());
+                let title = format!("{} {}", id.replace('_', " "), first_line.unwrap_or(""));
+                comparable(&title, self.exact_search)
+                    .contains(&comparable(words, self.exact_search))
+            }
+        })
+    }
+}
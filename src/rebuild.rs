@@ -0,0 +1,188 @@
+//! `kartka index rebuild`: throwing away the index's text and reading every document again,
+//! after changing the OCR language or settings, or when the index has been damaged. Types, tags
+//! and the rest of each document's metadata are kept. The OCR cache goes too, as it's only
+//! keyed by language and wouldn't notice other settings changing.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    num::NonZeroUsize,
+};
+
+use eyre::{bail, Result};
+use tracing::{info, warn};
+
+use crate::{audit::Operation, interrupt, ocr_cache, progress, Kartka, KartkaError};
+
+impl Kartka {
+    /// Reads every document in the index again, from its copy in `local_archive_dir`, or with
+    /// `from_remote` by downloading them all afresh `jobs` at a time as `hydrate` does. Asks
+    /// first unless `yes` is set. Returns how many documents were read again.
+    ///
+    /// Local copies are read one at a time, each only replacing what's in the index once it's
+    /// been read, so a document that can't be keeps its old text. From the remote, the text is
+    /// all thrown away first and hydrated back, so anything that fails is left for the next
+    /// `hydrate` to pick up. Documents in cold storage can't be downloaded without thawing, so
+    /// they keep their text.
+    pub fn rebuild_index(&self, from_remote: bool, jobs: NonZeroUsize, yes: bool) -> Result<usize> {
+        let source = match (&self.local_archive_dir, from_remote) {
+            (_, true) => self.remote.clone(),
+            (Some(dir), false) => format!("{dir:?}"),
+            (None, false) => bail!(
+                "there's no `local_archive_dir` to rebuild from - pass --remote to download every \
+                 document instead"
+            ),
+        };
+        let mut ids = vec![];
+        let mut protected = 0;
+        for id in self.documents()? {
+            match self.metadata(&id)?.protected {
+                true => protected += 1,
+                false => ids.push(id),
+            }
+        }
+        if protected > 0 {
+            // their PDFs can't be read, so the text there is now is all there'll ever be
+            info!("leaving the {protected} documents protected with a password as they are");
+        }
+        if from_remote {
+            // what can't be downloaded again had better not be thrown away
+            let on_remote: HashSet<_> = self.remote_files()?.into_iter().collect();
+            let mut kept = 0;
+            for id in std::mem::take(&mut ids) {
+                match on_remote.contains(&id) && !self.is_archived(&id)? {
+                    true => ids.push(id),
+                    false => kept += 1,
+                }
+            }
+            if kept > 0 {
+                info!("leaving the {kept} documents in cold storage or missing from the remote as they are");
+            }
+        }
+
+        if self.dry_run {
+            println!("would read {} documents again from {source}", ids.len());
+            return Ok(0);
+        }
+        if ids.is_empty() {
+            info!("nothing in the index to rebuild");
+            return Ok(0);
+        }
+        if !yes
+            && !self.confirm(
+                &format!(
+                    "Throw away the text of {} documents and read them again from {source}?",
+                    ids.len()
+                ),
+                false,
+            )?
+        {
+            return Ok(0);
+        }
+
+        let cache = self.index().join(ocr_cache::CACHE_DIR);
+        if cache.exists() {
+            fs::remove_dir_all(&cache)?;
+        }
+
+        // `recent` goes by when documents were added, which reading them again shouldn't change
+        let added: HashMap<_, _> = self.added()?.into_iter().collect();
+        if from_remote {
+            info!("clearing the text of {} documents..", ids.len());
+            for id in &ids {
+                self.roll_back(id);
+            }
+            let rehydrated = self.rehydrate(true, true, jobs);
+            let mut restored = 0;
+            for id in &ids {
+                let Some(at) = added.get(id) else {
+                    continue;
+                };
+                if self.contains(id)? {
+                    self.set_added_at(id, *at)?;
+                    restored += 1;
+                }
+            }
+            if restored > 0 {
+                self.commit_index("Keep when rebuilt documents were added");
+            }
+            return rehydrated;
+        }
+        self.rebuild_from_local(&ids, &added)
+    }
+
+    /// Reads each of `ids` again from its copy in `local_archive_dir`.
+    fn rebuild_from_local(
+        &self,
+        ids: &[String],
+        added: &HashMap<String, jiff::Timestamp>,
+    ) -> Result<usize> {
+        interrupt::watch();
+        let bar = progress::bar(ids.len(), "documents");
+        let status = progress::spinner("");
+        let mut done = 0;
+        let mut failed = 0;
+        for id in ids {
+            if interrupt::interrupted() {
+                break;
+            }
+            status.set_message(format!("{id}: reading"));
+            let result = self.reread_local(id, added.get(id).copied());
+            self.audit(
+                Operation::Rebuild,
+                id,
+                Some("read again from its local copy".to_string()),
+                result.as_ref().err(),
+            );
+            match result {
+                Ok(()) => done += 1,
+                Err(e) => {
+                    warn!("couldn't read {id} again, so it keeps its old text: {e:#}");
+                    failed += 1;
+                }
+            }
+            bar.inc(1);
+        }
+        status.finish_and_clear();
+        bar.finish_and_clear();
+
+        if done > 0 {
+            self.commit_index(&format!("Rebuild the index from {done} local copies"));
+        }
+        if interrupt::interrupted() {
+            bail!(
+                "interrupted after reading {done} of {} documents again - the rest keep their \
+                 old text",
+                ids.len()
+            );
+        }
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: ids.len(),
+            }
+            .into());
+        }
+        Ok(done)
+    }
+
+    /// Reads `id` from its local copy, then swaps what's in the index for what was read, keeping
+    /// when it was `added`.
+    fn reread_local(&self, id: &str, added: Option<jiff::Timestamp>) -> Result<()> {
+        let Some(pdf) = self.local_copy(id) else {
+            bail!("there's no copy of it in local_archive_dir");
+        };
+        let pages = tempfile::tempdir()?;
+        let recognised = self.recognise_pdf(&pdf, pages.path())?;
+        self.remove_text(id)?;
+        self.remove_hocr(id)?;
+        self.add_recognised(id, recognised)?;
+        if let Some(added) = added {
+            self.set_added_at(id, added)?;
+        }
+        self.remove_thumbnail(id)?;
+        self.save_pdf_thumbnail(id, &pdf, pages.path());
+        self.save_pdf_codes(id, &pdf, pages.path());
+        Ok(())
+    }
+}
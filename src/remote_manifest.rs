@@ -0,0 +1,157 @@
+//! `kartka-manifest.json`, kept on the remote next to the documents: every document with its
+//! hash, size and metadata. `hydrate` and `verify --quick` find out what's on the remote from
+//! this one download rather than listing the remote or reading back every file, and `hydrate`
+//! gets tags and the like back from it too.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    process::Command,
+};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{encryption::encrypt, metadata::Metadata, Kartka};
+
+/// The manifest's name on the remote.
+pub const REMOTE_MANIFEST: &str = "kartka-manifest.json";
+
+/// Every document on the remote, by id.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RemoteManifest {
+    pub documents: BTreeMap<String, Metadata>,
+}
+
+impl RemoteManifest {
+    /// The documents on the main remote, as [`Kartka::remote_files`] would list them. Ones
+    /// moved to cold storage aren't there.
+    pub fn ids(&self) -> Vec<String> {
+        self.documents
+            .iter()
+            .filter(|(_, meta)| !meta.archived)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+/// A change to what's on the remote, waiting to go into the manifest when it's next published.
+#[derive(Debug)]
+pub(crate) enum Change {
+    Added(String),
+    Removed(String),
+    Renamed(String, String),
+}
+
+impl Kartka {
+    /// Remembers `change` for the next [`Kartka::publish_remote_manifest`].
+    pub(crate) fn note_remote_change(&self, change: Change) {
+        self.remote_changes
+            .lock()
+            .expect("remote changes poisoned")
+            .push(change);
+    }
+
+    /// Brings the remote's manifest up to date with the changes made since it was last
+    /// published, and with the metadata of every document in the index, then uploads it -
+    /// encrypted, if documents are. Documents this index doesn't have yet are left as they are,
+    /// so hydrating part way doesn't lose them.
+    pub(crate) fn publish_remote_manifest(&self) -> Result<()> {
+        let changes =
+            std::mem::take(&mut *self.remote_changes.lock().expect("remote changes poisoned"));
+        let documents: BTreeSet<_> = self.documents()?.into_iter().collect();
+        let mut manifest = match self.remote_manifest()? {
+            Some(it) => it,
+            // a remote from before there was a manifest, whose listing already has the changes
+            None => RemoteManifest {
+                documents: self
+                    .remote_files()?
+                    .into_iter()
+                    .map(|id| (id, Metadata::default()))
+                    .collect(),
+            },
+        };
+        for change in changes {
+            match change {
+                Change::Added(id) => {
+                    manifest.documents.insert(id, Metadata::default());
+                }
+                Change::Removed(id) => {
+                    manifest.documents.remove(&id);
+                }
+                Change::Renamed(id, new_id) => {
+                    let meta = manifest.documents.remove(&id).unwrap_or_default();
+                    manifest.documents.insert(new_id, meta);
+                }
+            }
+        }
+        for (id, meta) in &mut manifest.documents {
+            if documents.contains(id) {
+                *meta = self.metadata(id)?;
+            }
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join(REMOTE_MANIFEST);
+        fs::write(&path, serde_json::to_vec_pretty(&manifest)?)?;
+        match &self.encryption {
+            Some(encryption) => {
+                let encrypted = temp_dir.path().join("encrypted");
+                encrypt(&encryption.recipients, &path, &encrypted)?;
+                self.upload_file(&encrypted, REMOTE_MANIFEST)
+            }
+            None => self.upload_file(&path, REMOTE_MANIFEST),
+        }
+    }
+
+    /// The manifest on the remote, or `None` if there isn't one or it can't be read, in which
+    /// case the remote has to be listed instead.
+    pub fn remote_manifest(&self) -> Result<Option<RemoteManifest>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join(REMOTE_MANIFEST);
+        // not retried, since a remote from before there was a manifest won't have one
+        let remote_path = self.remote_path(REMOTE_MANIFEST);
+        let output = self
+            .tools
+            .output(
+                Command::new("rclone")
+                    .args(["copyto", &remote_path])
+                    .arg(&path),
+            )
+            .context("running rclone")?;
+        if !output.status.success() || !path.exists() {
+            debug!(
+                "couldn't download {remote_path}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            info!("there's no manifest on {} yet", self.remote);
+            return Ok(None);
+        }
+
+        self.decrypt_in_place(&path)?;
+        let manifest = fs::read(&path).context(format!("reading {remote_path}"))?;
+        Ok(Some(
+            serde_json::from_slice(&manifest).context(format!("parsing {remote_path}"))?,
+        ))
+    }
+}
+
+/// Fills in the fields of `meta` that come from people or an LLM rather than from the PDF, from
+/// what `known` says about the same document.
+pub fn restore(meta: &mut Metadata, known: &Metadata) {
+    meta.doc_type = meta.doc_type.take().or_else(|| known.doc_type.clone());
+    meta.correspondent = meta
+        .correspondent
+        .take()
+        .or_else(|| known.correspondent.clone());
+    for tag in &known.tags {
+        if !meta.tags.contains(tag) {
+            meta.tags.push(tag.clone());
+        }
+    }
+    meta.summary = meta.summary.take().or_else(|| known.summary.clone());
+    if meta.fields.is_empty() {
+        meta.fields = known.fields.clone();
+    }
+}
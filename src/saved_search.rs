@@ -0,0 +1,100 @@
+//! Searches saved under a name with `kartka search --save`, to run again with `--saved`. They're
+//! kept in the index rather than the config, so they go wherever the index does, backups
+//! included.
+
+use std::{collections::BTreeMap, fs};
+
+use eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{store, Kartka};
+
+const SAVED: &str = ".kartka/searches.json";
+
+/// A search, as saved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub query: String,
+    /// Whether it finds documents by meaning rather than by their text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub semantic: bool,
+    /// The collection it's limited to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+impl SavedSearch {
+    /// The search as it'd be typed, e.g. `--semantic 'car repair'`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if self.semantic {
+            parts.push("--semantic".to_string());
+        }
+        if let Some(collection) = &self.collection {
+            parts.push(format!("--collection {collection:?}"));
+        }
+        parts.push(format!("{:?}", self.query));
+        parts.join(" ")
+    }
+}
+
+impl Kartka {
+    /// Every saved search, by name.
+    pub fn saved_searches(&self) -> Result<BTreeMap<String, SavedSearch>> {
+        let path = self.index().join(SAVED);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+        serde_json::from_str(&contents).context(format!("parsing {path:?}"))
+    }
+
+    /// The search saved as `name`.
+    pub fn saved_search(&self, name: &str) -> Result<SavedSearch> {
+        match self.saved_searches()?.remove(name) {
+            Some(search) => Ok(search),
+            None => bail!("there's no search saved as {name:?} - `kartka searches` lists them"),
+        }
+    }
+
+    /// Saves `search` as `name`, replacing any search already saved as it.
+    pub fn save_search(&self, name: &str, search: &SavedSearch) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("a saved search needs a name");
+        }
+        self.change_saved_searches(|searches| {
+            if let Some(old) = searches.insert(name.to_string(), search.clone()) {
+                if &old != search {
+                    info!("replacing {name:?}, which was {}", old.describe());
+                }
+            }
+        })?;
+        self.commit_index(&format!("Save the search {name:?}"));
+        Ok(())
+    }
+
+    /// Forgets the search saved as `name`.
+    pub fn forget_search(&self, name: &str) -> Result<()> {
+        let mut found = false;
+        self.change_saved_searches(|searches| found = searches.remove(name).is_some())?;
+        if !found {
+            bail!("there's no search saved as {name:?} - `kartka searches` lists them");
+        }
+        self.commit_index(&format!("Forget the search {name:?}"));
+        Ok(())
+    }
+
+    fn change_saved_searches(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, SavedSearch>),
+    ) -> Result<()> {
+        let _transaction = store::transaction(self.index())?;
+        let mut searches = self.saved_searches()?;
+        change(&mut searches);
+        let path = self.index().join(SAVED);
+        fs::create_dir_all(path.parent().expect("saved searches are in a directory"))?;
+        store::write_atomic(&path, serde_json::to_string_pretty(&searches)?)
+            .context(format!("writing {path:?}"))
+    }
+}
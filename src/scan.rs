@@ -0,0 +1,1145 @@
+//! The two ways documents get into the index: scanning new letters, and rehydrating the index
+//! from PDFs already on the remote.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use indicatif::ProgressBar;
+use tracing::{debug, info, warn};
+
+use crate::{
+    audit::Operation,
+    import::{named_id, pdfs_in},
+    integrity, interrupt,
+    journal::{self, Journal},
+    metadata::Metadata,
+    ocr::Recognised,
+    ocrmypdf::Pipeline,
+    pages, pdf, progress,
+    quarantine::Quarantine,
+    remote_manifest, Kartka, KartkaError,
+};
+
+/// How to split the pages of a scan into documents, rather than at separator pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Split {
+    /// Every this many pages.
+    Every(NonZeroUsize),
+    /// Into these parts, which between them have to cover every page once.
+    Parts(Vec<Part>),
+}
+
+/// Some of the pages of a scan, to be added as a document of their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// Which pages, counting from 1 in the order they're scanned.
+    pub pages: RangeInclusive<usize>,
+    /// What to call it, put on the end of its id as `kartka import` does with file names.
+    pub title: Option<String>,
+}
+
+/// The pages `value` means out of `total`: one page (`3`), a range (`3-5`), or every page from
+/// one on (`3-`).
+pub fn page_range(value: &str, total: usize) -> Result<RangeInclusive<usize>> {
+    let number = |it: &str| {
+        it.trim()
+            .parse::<usize>()
+            .map_err(|_| eyre::eyre!("{value:?} isn't a page or pages, like 3 or 3-5"))
+    };
+    let range = match value.split_once('-') {
+        Some((start, end)) if end.trim().is_empty() => number(start)?..=total,
+        Some((start, end)) => number(start)?..=number(end)?,
+        None => number(value)?..=number(value)?,
+    };
+    if range.is_empty() || *range.start() == 0 || *range.end() > total {
+        bail!("there are only pages 1 to {total}, so {value:?} can't be scanned");
+    }
+    Ok(range)
+}
+
+impl Kartka {
+    pub fn scans(&self) -> &Path {
+        &self.scan_dir
+    }
+
+    /// OCRs the pages in the scan dir into the index, then converts them to a PDF named after
+    /// the current time and uploads it. PDFs in the scan dir are each added as a document of
+    /// their own, as they are. If a letter looks like one already in the index it's scanned
+    /// anyway with a warning, or with `no_dupes` not at all.
+    ///
+    /// Once everything's been checked to be on the remote, it offers to move the scan dir's
+    /// files to the trash, or with `purge` to delete them for good. Returns the ids the
+    /// documents were added as.
+    pub fn scan(&self, no_dupes: bool, purge: bool) -> Result<Vec<String>> {
+        let pages = pages_in(self.scans())?;
+        let pdfs = pdfs_in(&[self.scans().to_path_buf()])?;
+        if pages.is_empty() && pdfs.is_empty() {
+            bail!("nothing to scan in {:?}", self.scans());
+        }
+
+        let added = self.scan_documents(&pdfs, &pages, self.scans(), no_dupes)?;
+        if self.dry_run {
+            let done = if purge { "delete" } else { "move to the trash" };
+            println!(
+                "would offer to {done} {} files in {:?}",
+                pages.len() + pdfs.len(),
+                self.scans()
+            );
+            return Ok(added);
+        }
+        self.clear_scans(&added, purge)?;
+
+        info!("done!");
+        Ok(added)
+    }
+
+    /// Once every one of `added` has been checked to be on the remote, offers to move the scan
+    /// dir's files to the trash, or with `purge` to delete them for good.
+    pub(crate) fn clear_scans(&self, added: &[String], purge: bool) -> Result<()> {
+        // the scans are the only other copy, so they stay until the remote has them
+        let on_remote: HashSet<_> = self.remote_files()?.into_iter().collect();
+        if let Some(missing) = added.iter().find(|it| !on_remote.contains(*it)) {
+            bail!(
+                "{missing} isn't on {} after uploading it, so the scan dir has been left alone",
+                self.remote
+            );
+        }
+        let question = if purge {
+            "Delete files in scan dir for good?"
+        } else {
+            "Move files in scan dir to the trash?"
+        };
+        if self.confirm(question, false)? {
+            let files = self
+                .scans()
+                .read_dir()?
+                .map(|it| it.map(|it| it.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            if purge {
+                for file in files {
+                    fs::remove_file(&file).context(format!("deleting {file:?}"))?;
+                }
+            } else {
+                trash::delete_all(&files)
+                    .context("moving scans to the trash (--purge deletes them instead)")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans `files` as [`Kartka::scan`] would if they were in the scan dir - each PDF a
+    /// document of its own, and the images together as the pages of another, in the order
+    /// given - but leaves them where they are. Returns the ids they were added as.
+    pub fn scan_files(&self, files: &[PathBuf], no_dupes: bool) -> Result<Vec<String>> {
+        if let Some(missing) = files.iter().find(|it| !it.is_file()) {
+            bail!("{missing:?} isn't a file");
+        }
+        let (pdfs, pages): (Vec<_>, Vec<_>) = files.iter().cloned().partition(|it| pdf::is_pdf(it));
+
+        // the pages are numbered in a directory of their own, since that's what gets OCR'd
+        let temp_dir = tempfile::tempdir()?;
+        for (i, page) in pages.iter().enumerate() {
+            let extension = page.extension().unwrap_or_default().to_string_lossy();
+            let dest = temp_dir
+                .path()
+                .join(format!("page-{:04}.{extension}", i + 1));
+            fs::copy(page, &dest).context(format!("copying {page:?}"))?;
+        }
+
+        let added = self.scan_documents(&pdfs, &pages, temp_dir.path(), no_dupes)?;
+        if !self.dry_run {
+            info!("done!");
+        }
+        Ok(added)
+    }
+
+    /// Adds each of `pdfs`, and the images in `pages_dir` (which are `pages`, unless
+    /// `page_order` says otherwise) as one more document - or several, split at separator pages
+    /// or as `split` says - publishing the manifest afterwards.
+    /// Returns the ids they were added as.
+    fn scan_documents(
+        &self,
+        pdfs: &[PathBuf],
+        pages: &[PathBuf],
+        pages_dir: &Path,
+        no_dupes: bool,
+    ) -> Result<Vec<String>> {
+        // reviewed pages are put in their new order in a directory of their own
+        let arranged = tempfile::tempdir()?;
+        let (pages, pages_dir) = match &self.page_order {
+            Some(order) if self.dry_run => {
+                for page in order.iter().filter(|it| it.turn != 0) {
+                    println!("would turn {:?} by {} degrees", page.path, page.turn);
+                }
+                (order.iter().map(|it| it.path.clone()).collect(), pages_dir)
+            }
+            Some(order) => (
+                pages::arrange(self.tools.as_ref(), order, arranged.path())?,
+                arranged.path(),
+            ),
+            None => (pages.to_vec(), pages_dir),
+        };
+        let pages = pages.as_slice();
+
+        if self.dry_run {
+            let mut taken: HashSet<_> = self.documents()?.into_iter().collect();
+            for pdf in pdfs {
+                if interrupt::interrupted() {
+                    break;
+                }
+                let id = self.ids.unique(&taken, jiff::Timestamp::now());
+                println!("would read {pdf:?} and add it to the index as {id}");
+                println!("would upload {}", self.remote_path(&id));
+                taken.insert(id);
+            }
+            for (document, title) in self.split_pages(pages)? {
+                for page in &document {
+                    println!("would OCR {page:?}");
+                }
+                let pdf_name = self.document_id(title.as_deref(), &document, &taken);
+                println!("would add {pdf_name} to the index");
+                println!("would upload {}", self.remote_path(&pdf_name));
+                taken.insert(pdf_name);
+            }
+            return Ok(vec![]);
+        }
+
+        interrupt::watch();
+        let documents = self.split_pages(pages)?;
+        let total = pdfs.len() + documents.len();
+        let mut taken: HashSet<_> = self.documents()?.into_iter().collect();
+        // each document is uploaded while the next one's being read, which is when the CPU
+        // would otherwise sit idle
+        let (added, mut failures, rolled_back) = thread::scope(|scope| {
+            let (to_upload, uploads) = mpsc::sync_channel::<Prepared>(1);
+            let uploader = scope.spawn(move || {
+                let mut added = vec![];
+                let mut failures = vec![];
+                for prepared in uploads {
+                    let id = prepared.id.clone();
+                    let uploaded = self.upload_prepared(prepared);
+                    self.audit(Operation::Scan, &id, None, uploaded.as_ref().err());
+                    match uploaded {
+                        Ok(()) => added.push(id),
+                        Err(e) => {
+                            if total > 1 {
+                                warn!("failed to upload {id}: {e:#}");
+                            }
+                            failures.push(e);
+                        }
+                    }
+                }
+                (added, failures)
+            });
+
+            let mut failures = vec![];
+            let mut rolled_back = vec![];
+            let mut prepare = |id: &str, what: String, prepared: Result<Prepared>| match prepared {
+                // Ctrl-C reaches the tools it was reading with too, so what they made of it
+                // can't be trusted
+                Ok(prepared) if interrupt::interrupted() => {
+                    self.roll_back(&prepared.id);
+                    let e = eyre::eyre!("interrupted, so rolled back");
+                    self.audit(Operation::Scan, id, Some(what), Some(&e));
+                    rolled_back.push(prepared.id);
+                }
+                Ok(prepared) => {
+                    // this only fails if the uploader's gone, which joining it reports
+                    let _ = to_upload.send(prepared);
+                }
+                Err(e) => {
+                    self.audit(Operation::Scan, id, Some(what.clone()), Some(&e));
+                    if total > 1 && !interrupt::interrupted() {
+                        warn!("failed to scan {what}: {e:#}");
+                    }
+                    if !interrupt::interrupted() {
+                        failures.push(e);
+                    }
+                }
+            };
+            for pdf in pdfs {
+                if interrupt::interrupted() {
+                    break;
+                }
+                let id = self.ids.unique(&taken, jiff::Timestamp::now());
+                taken.insert(id.clone());
+                info!("adding {pdf:?} as {id}..");
+                prepare(
+                    &id,
+                    format!("{pdf:?}"),
+                    self.prepare_pdf_as(pdf, &id, no_dupes),
+                );
+            }
+            for (i, (document, title)) in documents.iter().enumerate() {
+                if interrupt::interrupted() {
+                    break;
+                }
+                let id = self.document_id(title.as_deref(), document, &taken);
+                taken.insert(id.clone());
+                if documents.len() > 1 {
+                    info!("adding document {} of {} as {id}..", i + 1, documents.len());
+                }
+                // with nothing split off, the pages can be added from where they are
+                let prepared = if document.len() == pages.len() {
+                    self.prepare_pages_as(pages_dir, &id, no_dupes)
+                } else {
+                    self.prepare_pages_from(document, &id, no_dupes)
+                };
+                prepare(&id, format!("document {}", i + 1), prepared);
+            }
+            drop(to_upload);
+
+            let (added, upload_failures) = uploader.join().expect("upload thread panicked");
+            failures.extend(upload_failures);
+            (added, failures, rolled_back)
+        });
+        self.publish_manifest()?;
+        if !added.is_empty() {
+            self.commit_index(&format!("Scan {}", added.join(", ")));
+        }
+
+        if interrupt::interrupted() {
+            let mut report = format!(
+                "interrupted after adding {} of {total} documents",
+                added.len()
+            );
+            if !added.is_empty() {
+                report.push_str(&format!(" ({})", added.join(", ")));
+            }
+            if !rolled_back.is_empty() {
+                report.push_str(&format!(
+                    ", rolling back {} which were part way through",
+                    rolled_back.join(", ")
+                ));
+            }
+            bail!("{report} - the rest are still where they were, ready to scan again");
+        }
+
+        // anything that failed is still only where it started
+        match failures.len() {
+            0 => Ok(added),
+            1 if total == 1 => Err(failures.remove(0)),
+            failed => Err(KartkaError::Partial { failed, total }.into()),
+        }
+    }
+
+    /// Splits `pages` into the documents they make up, each with its title if it has one: as
+    /// `split` says, or else at the `separator` pages if that's set.
+    fn split_pages(&self, pages: &[PathBuf]) -> Result<Vec<(Vec<PathBuf>, Option<String>)>> {
+        if pages.is_empty() {
+            return Ok(vec![]);
+        }
+        let untitled = |documents: Vec<Vec<PathBuf>>| {
+            documents
+                .into_iter()
+                .map(|it| (it, None))
+                .collect::<Vec<_>>()
+        };
+        Ok(match (&self.split, self.separator) {
+            (Some(Split::Every(every)), _) => {
+                untitled(pages.chunks(every.get()).map(<[_]>::to_vec).collect())
+            }
+            (Some(Split::Parts(parts)), _) => split_into(pages, parts)?,
+            (None, Some(separator)) => {
+                info!("looking for separator pages..");
+                let documents = pages::split(self.tools.as_ref(), pages, separator);
+                if documents.len() > 1 {
+                    info!("found {} documents", documents.len());
+                }
+                untitled(documents)
+            }
+            (None, None) => untitled(vec![pages.to_vec()]),
+        })
+    }
+
+    /// A new id for the document made of `pages`, with `title` on the end if it has one. It's
+    /// named after when the first page was taken, if it's a photo that says, or else now.
+    fn document_id(
+        &self,
+        title: Option<&str>,
+        pages: &[PathBuf],
+        taken: &HashSet<String>,
+    ) -> String {
+        let time = pages
+            .first()
+            .and_then(|it| pages::taken(it))
+            .unwrap_or_else(jiff::Timestamp::now);
+        match title {
+            Some(title) => named_id(&self.ids, title, time, taken),
+            None => self.ids.unique(taken, time),
+        }
+    }
+
+    /// Prepares `pages` as `id`, as [`Kartka::prepare_pages_as`] does for a whole directory.
+    fn prepare_pages_from(&self, pages: &[PathBuf], id: &str, no_dupes: bool) -> Result<Prepared> {
+        let temp_dir = tempfile::tempdir()?;
+        copy_pages(pages, temp_dir.path())?;
+        self.prepare_pages_as(temp_dir.path(), id, no_dupes)
+    }
+
+    /// Adds the page images in `dir` as a new document named after the current time, returning
+    /// its id: they're OCR'd into the index, then converted to a PDF and uploaded. `no_dupes`
+    /// works as for [`Kartka::scan`].
+    pub fn add_pages(&self, dir: &Path, no_dupes: bool) -> Result<String> {
+        let taken: HashSet<_> = self.documents()?.into_iter().collect();
+        let id = self.ids.unique(&taken, jiff::Timestamp::now());
+        self.add_pages_as(dir, &id, no_dupes)?;
+        self.publish_manifest()?;
+        Ok(id)
+    }
+
+    /// Warns that `text` looks like a document already in the index, or with `no_dupes` fails.
+    fn check_duplicate(&self, text: &str, no_dupes: bool) -> Result<()> {
+        if let Some((dupe, similarity)) = self.find_duplicate(text)? {
+            let percent = similarity * 100.0;
+            if no_dupes {
+                bail!("this looks like {dupe} ({percent:.0}% the same), not scanning it again");
+            }
+            warn!("this looks like {dupe} ({percent:.0}% the same), which is already indexed");
+        }
+        Ok(())
+    }
+
+    /// Adds the page images in `dir` as `id`, as for [`Kartka::add_pages`] but leaving the
+    /// manifest for the caller to publish. Blank pages are left out unless `keep_blank_pages`
+    /// is set.
+    pub(crate) fn add_pages_as(&self, dir: &Path, pdf_name: &str, no_dupes: bool) -> Result<()> {
+        let prepared = self.prepare_pages_as(dir, pdf_name, no_dupes)?;
+        self.upload_prepared(prepared)
+    }
+
+    /// Does everything [`Kartka::add_pages_as`] does short of uploading the PDF.
+    fn prepare_pages_as(&self, dir: &Path, pdf_name: &str, no_dupes: bool) -> Result<Prepared> {
+        let pages = pages_in(dir)?;
+        if pages.is_empty() {
+            bail!("no pages in {dir:?}");
+        }
+        if self.keep_blank_pages {
+            return self.prepare_page_images(dir, &pages, pdf_name, no_dupes);
+        }
+
+        let kept = pages::without_blanks(self.tools.as_ref(), &pages);
+        if kept.is_empty() {
+            bail!("every page in {dir:?} is blank");
+        }
+        if kept.len() == pages.len() {
+            return self.prepare_page_images(dir, &pages, pdf_name, no_dupes);
+        }
+        info!("leaving out {} blank pages", pages.len() - kept.len());
+        let temp_dir = tempfile::tempdir()?;
+        let kept = copy_pages(&kept, temp_dir.path())?;
+        self.prepare_page_images(temp_dir.path(), &kept, pdf_name, no_dupes)
+    }
+
+    /// Indexes `pages`, which are every page image in `dir`, as `pdf_name`, and makes them into
+    /// a PDF. The PDF's put together while the pages are being OCR'd, since neither needs the
+    /// other, unless OCRmyPDF is doing both.
+    fn prepare_page_images(
+        &self,
+        dir: &Path,
+        pages: &[PathBuf],
+        pdf_name: &str,
+        no_dupes: bool,
+    ) -> Result<Prepared> {
+        let work_dir = tempfile::tempdir()?;
+        let pdf_path = work_dir.path().join(pdf_name);
+        if self.pipeline == Pipeline::Ocrmypdf {
+            let images = work_dir.path().join("pages.pdf");
+            pdf::images_to_pdf(self.tools.as_ref(), pages, &images, &self.pdf)?;
+            let recognised = self.ocrmypdf(&images, &pdf_path)?;
+            fs::remove_file(&images)?;
+            return self.finish_preparing(dir, pdf_name, recognised, no_dupes, work_dir);
+        }
+        let (recognised, converted) = thread::scope(|scope| {
+            let converting = scope.spawn(|| {
+                let started = Instant::now();
+                let converted =
+                    pdf::images_to_pdf(self.tools.as_ref(), pages, &pdf_path, &self.pdf);
+                debug!("converted to PDF in {:?}", started.elapsed());
+                converted
+            });
+            let recognised = self.recognise(dir);
+            let converted = converting.join().expect("PDF conversion thread panicked");
+            (recognised, converted)
+        });
+        let recognised = recognised?;
+        converted?;
+        self.finish_preparing(dir, pdf_name, recognised, no_dupes, work_dir)
+    }
+
+    /// Writes what's known about `id` into the PDF kartka made of it at `path`, making it a
+    /// PDF/A or protecting it with a password if `[pdf]` says to. Only those failing is an
+    /// error, since without them the PDF's fine as it is.
+    fn describe_pdf(&self, id: &str, path: &Path) -> Result<()> {
+        let meta = self.metadata(id)?;
+        let title = match self.ids.title(id) {
+            title if title != id.trim_end_matches(".pdf") => title,
+            // without a name, the first line's the nearest thing it has to a title
+            stem => {
+                let text = self.text(id)?;
+                let first_line = text.lines().map(str::trim).find(|it| !it.is_empty());
+                first_line.map_or(stem, |it| it.chars().take(100).collect())
+            }
+        };
+        let info = pdf::Info {
+            id: id.to_string(),
+            title,
+            created: self.ids.scanned(id),
+            author: meta.correspondent,
+            keywords: meta.doc_type.into_iter().chain(meta.tags).collect(),
+        };
+
+        let described = path.with_extension("described.pdf");
+        let rewritten = pdf::rewrite(self.tools.as_ref(), path, &described, &info, &self.pdf)
+            .and_then(|()| Ok(fs::rename(&described, path)?));
+        match rewritten {
+            Err(e) if self.pdf.pdfa => return Err(e.wrap_err("making a PDF/A")),
+            Err(e) => warn!("couldn't write {id}'s metadata into its PDF: {e:#}"),
+            Ok(()) => {}
+        }
+
+        if let Some(password) = &self.pdf.protect_with {
+            self.protect_pdf(id, path, password)?;
+        }
+        Ok(())
+    }
+
+    /// Protects `id`'s PDF at `path` with `password`, noting that it has been, since the text
+    /// in the index is all there'll ever be of it.
+    fn protect_pdf(&self, id: &str, path: &Path, password: &str) -> Result<()> {
+        pdf::protect(self.tools.as_ref(), path, password)?;
+        self.update_metadata(id, |it| it.protected = true)?;
+        Ok(())
+    }
+
+    /// A copy of the PDF at `pdf`, protected with `password`, in `work_dir` if it's already
+    /// been made and a new one if not. The original's left as it is.
+    fn protected_copy(
+        &self,
+        id: &str,
+        pdf: &Path,
+        password: &str,
+        work_dir: Option<tempfile::TempDir>,
+    ) -> Result<(PathBuf, tempfile::TempDir)> {
+        let work_dir = match work_dir {
+            Some(it) => it,
+            None => tempfile::tempdir()?,
+        };
+        let protected = work_dir.path().join(id);
+        if pdf != protected {
+            fs::copy(pdf, &protected).context(format!("copying {pdf:?}"))?;
+        }
+        self.protect_pdf(id, &protected, password)?;
+        Ok((protected, work_dir))
+    }
+
+    /// Indexes the page images in `dir` as `pdf_name`, once they've been read and made into a
+    /// PDF of the same name in `work_dir`.
+    fn finish_preparing(
+        &self,
+        dir: &Path,
+        pdf_name: &str,
+        recognised: Recognised,
+        no_dupes: bool,
+        work_dir: tempfile::TempDir,
+    ) -> Result<Prepared> {
+        self.check_duplicate(&recognised.text, no_dupes)?;
+        self.add_recognised(pdf_name, recognised)?;
+        self.save_thumbnail(pdf_name, dir);
+        self.save_codes(pdf_name, dir);
+        if let Err(e) = self.describe_pdf(pdf_name, &work_dir.path().join(pdf_name)) {
+            self.roll_back(pdf_name);
+            return Err(e);
+        }
+        Ok(Prepared {
+            id: pdf_name.to_string(),
+            pdf: work_dir.path().join(pdf_name),
+            _work_dir: Some(work_dir),
+        })
+    }
+
+    /// Adds the PDF at `pdf` to the index as `id` and uploads it as it is, leaving the manifest
+    /// for the caller to publish. Its own text is used if it has any, so PDFs that were never on
+    /// paper don't need OCRing. `no_dupes` works as for [`Kartka::scan`].
+    pub(crate) fn add_pdf_as(&self, pdf: &Path, id: &str, no_dupes: bool) -> Result<()> {
+        let prepared = self.prepare_pdf_as(pdf, id, no_dupes)?;
+        self.upload_prepared(prepared)
+    }
+
+    /// Does everything [`Kartka::add_pdf_as`] does short of uploading the PDF. With OCRmyPDF
+    /// doing the OCR, a PDF without text of its own is swapped for the searchable one it makes,
+    /// and a scan being protected with a password has a protected copy uploaded instead.
+    fn prepare_pdf_as(&self, pdf: &Path, id: &str, no_dupes: bool) -> Result<Prepared> {
+        let temp_dir = tempfile::tempdir()?;
+        let (recognised, work_dir) = match self.pipeline {
+            Pipeline::Ocrmypdf => match self.own_text(pdf)? {
+                Some(recognised) => (recognised, None),
+                None => {
+                    let work_dir = tempfile::tempdir()?;
+                    let recognised = self.ocrmypdf(pdf, &work_dir.path().join(id))?;
+                    (recognised, Some(work_dir))
+                }
+            },
+            Pipeline::Kartka => (self.recognise_pdf(pdf, temp_dir.path())?, None),
+        };
+        let pdf = match &work_dir {
+            Some(work_dir) => work_dir.path().join(id),
+            None => pdf.to_path_buf(),
+        };
+        self.check_duplicate(&recognised.text, no_dupes)?;
+        self.add_recognised(id, recognised)?;
+        self.save_pdf_thumbnail(id, &pdf, temp_dir.path());
+        self.save_pdf_codes(id, &pdf, temp_dir.path());
+        let Some(password) = &self.pdf.protect_with else {
+            return Ok(Prepared {
+                id: id.to_string(),
+                pdf,
+                _work_dir: work_dir,
+            });
+        };
+        match self.protected_copy(id, &pdf, password, work_dir) {
+            Ok((pdf, work_dir)) => Ok(Prepared {
+                id: id.to_string(),
+                pdf,
+                _work_dir: Some(work_dir),
+            }),
+            Err(e) => {
+                self.roll_back(id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes `id` back out of the index, for a document that was interrupted part way through.
+    pub(crate) fn roll_back(&self, id: &str) {
+        let removed = self
+            .remove_text(id)
+            .and_then(|_| self.remove_thumbnail(id))
+            .and_then(|_| self.remove_hocr(id));
+        if let Err(e) = removed {
+            warn!("couldn't roll back {id}, so it's half in the index: {e:#}");
+        }
+    }
+
+    /// Uploads a prepared document's PDF.
+    fn upload_prepared(&self, prepared: Prepared) -> Result<()> {
+        let spinner = progress::spinner(&format!("uploading {}", prepared.id));
+        // an indexed document that never reached the remote can't be opened, so undo the index
+        if let Err(e) = self.upload_document(&prepared.pdf, &prepared.id) {
+            self.remove_text(&prepared.id)?;
+            self.remove_thumbnail(&prepared.id)?;
+            self.remove_hocr(&prepared.id)?;
+            return Err(e);
+        }
+        spinner.finish_and_clear();
+        Ok(())
+    }
+
+    /// Downloads and indexes every PDF on the remote that isn't in the index yet, `jobs` at a
+    /// time, skipping quarantined documents unless `retry_quarantined` is set. What's on the
+    /// remote comes from its manifest if it has one, unless `list` is set, and each document's
+    /// tags and the like are restored from there. Returns how many documents were hydrated.
+    pub fn rehydrate(
+        &self,
+        retry_quarantined: bool,
+        list: bool,
+        jobs: NonZeroUsize,
+    ) -> Result<usize> {
+        // want to download all files that I don't have in my index
+        let manifest = match list {
+            true => None,
+            false => self.remote_manifest()?,
+        };
+        let remote_files: HashSet<_> = match &manifest {
+            Some(manifest) => manifest.ids().into_iter().collect(),
+            None => self.remote_files()?.into_iter().collect(),
+        };
+        let known = manifest.map(|it| it.documents).unwrap_or_default();
+
+        // anything an interrupted run didn't finish may only be partly in the index, so it
+        // gets done again from scratch
+        let interrupted = journal::read(self.index())?;
+        if !interrupted.is_empty() {
+            info!(
+                "resuming interrupted hydrate: {} documents already done, {} to redo",
+                interrupted.completed.len(),
+                interrupted.unfinished.len()
+            );
+        }
+        for id in &interrupted.unfinished {
+            if self.dry_run {
+                println!("would remove partial index entry {id}");
+            } else {
+                self.remove_text(id)?;
+            }
+        }
+
+        let mut local_files: HashSet<_> = self.documents()?.into_iter().collect();
+        for id in &interrupted.unfinished {
+            local_files.remove(id);
+        }
+
+        let mut quarantine = Quarantine::load(self.index())?;
+        if retry_quarantined && !self.dry_run {
+            quarantine.clear_all();
+            quarantine.save(self.index())?;
+        }
+
+        let (skipped, missing_files): (Vec<_>, Vec<_>) =
+            remote_files.difference(&local_files).partition(|it| {
+                !retry_quarantined && quarantine.is_quarantined(it, self.quarantine_after)
+            });
+        if !skipped.is_empty() {
+            info!(
+                "skipping {} quarantined documents - see `kartka status`",
+                skipped.len()
+            );
+        }
+        // their text is only in the index they were scanned into. Listing the remote doesn't
+        // say which they are, so the manifest is still asked
+        let listed_known = match list && !missing_files.is_empty() {
+            true => self.remote_manifest()?.unwrap_or_default().documents,
+            false => BTreeMap::new(),
+        };
+        let (protected, missing_files): (Vec<_>, Vec<_>) =
+            missing_files.into_iter().partition(|it| {
+                known
+                    .get(*it)
+                    .or(listed_known.get(*it))
+                    .is_some_and(|meta| meta.protected)
+            });
+        if !protected.is_empty() {
+            info!(
+                "skipping {} documents protected with a password, which can't be read",
+                protected.len()
+            );
+        }
+
+        if self.dry_run {
+            for missing in &missing_files {
+                println!(
+                    "would download {} and add it to the index",
+                    self.remote_path(missing)
+                );
+            }
+            return Ok(0);
+        }
+
+        interrupt::watch();
+        let num_missing = missing_files.len();
+        let bar = progress::bar(num_missing, "documents");
+        let state = Mutex::new(HydrateState {
+            journal: Journal::open(self.index())?,
+            quarantine,
+            done: 0,
+            failed: 0,
+            rolled_back: vec![],
+        });
+        // downloads wait for a worker to be free, so only a few are ever on disk at once
+        let (send, receive) = tokio::sync::mpsc::channel(jobs.get());
+        // shared rather than borrowed, so that if every worker fails the downloader finds out
+        let receive = Arc::new(Mutex::new(receive));
+        thread::scope(|scope| {
+            let downloader = scope.spawn(|| self.download_missing(&missing_files, &known, send));
+            let workers: Vec<_> = (0..jobs.get().min(num_missing))
+                .map(|_| {
+                    let receive = Arc::clone(&receive);
+                    let (state, bar) = (&state, &bar);
+                    scope.spawn(move || self.hydrate_worker(&receive, num_missing, state, bar))
+                })
+                .collect();
+            drop(receive);
+            downloader.join().expect("hydrate downloader panicked")?;
+            workers
+                .into_iter()
+                .try_for_each(|it| it.join().expect("hydrate worker panicked"))
+        })?;
+        let HydrateState {
+            journal,
+            done,
+            failed,
+            rolled_back,
+            ..
+        } = state.into_inner().expect("hydrate state poisoned");
+        bar.finish_and_clear();
+
+        // documents finished before an interruption haven't made it into the published
+        // manifest yet either
+        if done > 0 || !interrupted.completed.is_empty() {
+            self.publish_manifest()?;
+            self.commit_index(&format!("Hydrate {done} documents"));
+        }
+        journal.finish()?;
+
+        if interrupt::interrupted() {
+            let mut report =
+                format!("interrupted after hydrating {done} of {num_missing} documents");
+            if !rolled_back.is_empty() {
+                report.push_str(&format!(
+                    ", rolling back {} which were part way through",
+                    rolled_back.join(", ")
+                ));
+            }
+            bail!("{report} - run `kartka hydrate` again to carry on");
+        }
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: num_missing,
+            }
+            .into());
+        }
+        info!("done!");
+        Ok(done)
+    }
+
+    /// Hydrates every `interval` until Ctrl-C, so documents put on the remote some other way,
+    /// like from the Dropbox app on a phone, turn up in the index without anyone running
+    /// `hydrate`. The remote is listed each time rather than trusting its manifest, since
+    /// those don't update it. A round that fails is warned about and tried again next time,
+    /// and `hydrated` is told how each round went.
+    pub fn watch_remote(
+        &self,
+        interval: Duration,
+        retry_quarantined: bool,
+        jobs: NonZeroUsize,
+        mut hydrated: impl FnMut(&Result<usize>),
+    ) -> Result<()> {
+        if self.dry_run {
+            return self.rehydrate(retry_quarantined, true, jobs).map(|_| ());
+        }
+        interrupt::watch();
+        info!(
+            "checking {} for new documents every {}, Ctrl-C to stop",
+            self.remote,
+            friendly(interval)
+        );
+        // only worth trying quarantined documents again the first time round
+        let mut retry_quarantined = retry_quarantined;
+        loop {
+            let round = self.rehydrate(retry_quarantined, true, jobs);
+            retry_quarantined = false;
+            if interrupt::interrupted() {
+                return round.map(|_| ());
+            }
+            hydrated(&round);
+            if let Err(e) = round {
+                warn!(
+                    "couldn't hydrate, trying again in {}: {e:#}",
+                    friendly(interval)
+                );
+            }
+
+            // in small steps, so Ctrl-C doesn't have to wait out the interval
+            let next = Instant::now() + interval;
+            while Instant::now() < next {
+                if interrupt::interrupted() {
+                    info!("stopped watching {}", self.remote);
+                    return Ok(());
+                }
+                thread::sleep(Duration::from_millis(250).min(next - Instant::now()));
+            }
+        }
+    }
+
+    /// Downloads `missing`, `transfers` at a time, sending each to the hydrate workers as it
+    /// arrives. Ctrl-C stops it early, abandoning any downloads still going.
+    fn download_missing(
+        &self,
+        missing: &[&String],
+        known: &BTreeMap<String, Metadata>,
+        to_read: tokio::sync::mpsc::Sender<Downloaded>,
+    ) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            let mut downloads = stream::iter(missing.iter().enumerate())
+                .map(|(i, id)| self.download_one(i + 1, id, known.get(*id)))
+                .buffer_unordered(self.transfers.get());
+            let cancelled = interrupt::cancelled();
+            tokio::pin!(cancelled);
+            loop {
+                let downloaded = tokio::select! {
+                    _ = &mut cancelled => return Ok(()),
+                    it = downloads.next() => it,
+                };
+                let Some(downloaded) = downloaded else {
+                    return Ok(());
+                };
+                tokio::select! {
+                    _ = &mut cancelled => return Ok(()),
+                    sent = to_read.send(downloaded) => {
+                        // the workers only stop early if one of them failed
+                        if sent.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Downloads `id`, the `number`th document to hydrate, into a temporary directory, checking
+    /// it's the copy the remote manifest says it should be if it's `known` there.
+    async fn download_one(&self, number: usize, id: &str, known: Option<&Metadata>) -> Downloaded {
+        let dir = match tempfile::tempdir() {
+            Ok(it) => it,
+            Err(e) => {
+                return Downloaded {
+                    number,
+                    id: id.to_string(),
+                    pdf: Err(e.into()),
+                    known: None,
+                    _dir: None,
+                }
+            }
+        };
+        let dest = dir.path().join(id);
+        let pdf = self
+            .download_async(id, &dest)
+            .await
+            .and_then(|()| check_download(&dest, known))
+            .map(|()| dest);
+        Downloaded {
+            number,
+            id: id.to_string(),
+            pdf,
+            known: known.cloned(),
+            _dir: Some(dir),
+        }
+    }
+
+    /// Takes downloaded documents from `to_read` and hydrates them until there are none left.
+    /// Several of these run at once with `--jobs`.
+    fn hydrate_worker(
+        &self,
+        to_read: &Mutex<tokio::sync::mpsc::Receiver<Downloaded>>,
+        total: usize,
+        state: &Mutex<HydrateState>,
+        bar: &ProgressBar,
+    ) -> Result<()> {
+        let status = progress::spinner("");
+        loop {
+            let next = to_read
+                .lock()
+                .expect("hydrate queue poisoned")
+                .blocking_recv();
+            let Some(Downloaded {
+                number,
+                ref id,
+                pdf,
+                known,
+                _dir,
+            }) = next
+            else {
+                break;
+            };
+            if interrupt::interrupted() {
+                break;
+            }
+            info!("({number} / {total}) converting and processing: {id}..");
+
+            state
+                .lock()
+                .expect("hydrate state poisoned")
+                .journal
+                .start(id)?;
+            // one broken document shouldn't stop the rest being hydrated
+            let result = pdf.and_then(|pdf| self.hydrate_one(id, &pdf, known.as_ref(), &status));
+
+            let mut state = state.lock().expect("hydrate state poisoned");
+            match result {
+                // Ctrl-C reaches the tools it was reading with too, so what they made of it
+                // can't be trusted. Left out of the index, it's picked up again next time.
+                _ if interrupt::interrupted() => {
+                    self.roll_back(id);
+                    let e = eyre::eyre!("interrupted, so rolled back");
+                    self.audit(Operation::Hydrate, id, None, Some(&e));
+                    state.rolled_back.push(id.clone());
+                }
+                Ok(()) => {
+                    self.audit(Operation::Hydrate, id, None, None);
+                    state.journal.done(id)?;
+                    state.done += 1;
+                    state.quarantine.clear(id);
+                }
+                Err(e) => {
+                    self.audit(Operation::Hydrate, id, None, Some(&e));
+                    state.journal.failed(id)?;
+                    // it wasn't in the index before, so anything there now is half written
+                    self.roll_back(id);
+                    warn!("failed to hydrate {id}: {e:#}");
+                    let attempts = state.quarantine.record_failure(id, format!("{e:#}"));
+                    if attempts >= self.quarantine_after {
+                        warn!("quarantined {id} after {attempts} failed attempts");
+                    }
+                    state.failed += 1;
+                }
+            }
+            // saved as we go so a crash part way through doesn't lose count
+            state.quarantine.save(self.index())?;
+            bar.inc(1);
+        }
+        status.finish_and_clear();
+        Ok(())
+    }
+
+    /// Hydrates a single document from its download at `pdf`, showing which stage it's at on
+    /// `bar`. Metadata that can't be worked out again from the PDF comes from `known`.
+    pub(crate) fn hydrate_one(
+        &self,
+        missing: &str,
+        pdf: &Path,
+        known: Option<&Metadata>,
+        bar: &ProgressBar,
+    ) -> Result<()> {
+        // hashed as stored, so it can be checked against the remote
+        self.record_hash(missing, pdf)?;
+        self.decrypt_in_place(pdf)?;
+        self.keep_local_copy(pdf, missing);
+
+        bar.set_message(format!("{missing}: reading"));
+        let pages = tempfile::tempdir()?;
+        let recognised = self.recognise_pdf(pdf, pages.path())?;
+        self.add_recognised(missing, recognised)?;
+        self.save_pdf_thumbnail(missing, pdf, pages.path());
+        self.save_pdf_codes(missing, pdf, pages.path());
+        if let Some(known) = known {
+            self.update_metadata(missing, |meta| remote_manifest::restore(meta, known))?;
+        }
+        Ok(())
+    }
+}
+
+/// A document downloaded for hydrating, or why it couldn't be.
+struct Downloaded {
+    /// Its place in the queue, for showing progress.
+    number: usize,
+    id: String,
+    pdf: Result<PathBuf>,
+    /// What the remote manifest says about it.
+    known: Option<Metadata>,
+    /// Where it was downloaded to, which has to stay around until it's been read.
+    _dir: Option<tempfile::TempDir>,
+}
+
+/// A document that's been indexed and made into a PDF, and only needs uploading.
+struct Prepared {
+    id: String,
+    pdf: PathBuf,
+    /// Where the PDF was made, if it was, which has to stay around until it's uploaded.
+    _work_dir: Option<tempfile::TempDir>,
+}
+
+/// What the hydrate workers share, behind a lock.
+struct HydrateState {
+    journal: Journal,
+    quarantine: Quarantine,
+    done: usize,
+    failed: usize,
+    /// Documents that were part way through when Ctrl-C was pressed.
+    rolled_back: Vec<String>,
+}
+
+/// Checks the download at `pdf` has the hash the remote manifest has for it in `known`.
+fn check_download(pdf: &Path, known: Option<&Metadata>) -> Result<()> {
+    let Some(expected) = known.and_then(|it| it.sha256.as_ref()) else {
+        return Ok(());
+    };
+    if integrity::sha256(pdf)? != *expected {
+        bail!("the download doesn't match the hash in the remote manifest");
+    }
+    Ok(())
+}
+
+/// `pages` split into `parts`, which have to cover every page exactly once so none are left
+/// behind in the scan dir, to be thrown away with it.
+fn split_into(pages: &[PathBuf], parts: &[Part]) -> Result<Vec<(Vec<PathBuf>, Option<String>)>> {
+    let mut covered = vec![0; pages.len()];
+    for part in parts {
+        if part.pages.is_empty() || *part.pages.start() == 0 || *part.pages.end() > pages.len() {
+            bail!(
+                "there are only pages 1 to {}, so pages {}-{} can't be scanned",
+                pages.len(),
+                part.pages.start(),
+                part.pages.end()
+            );
+        }
+        for page in part.pages.clone() {
+            covered[page - 1] += 1;
+        }
+    }
+    let pages_where = |count: fn(usize) -> bool| {
+        let numbers: Vec<_> = (1..=pages.len())
+            .filter(|it| count(covered[it - 1]))
+            .map(|it| it.to_string())
+            .collect();
+        match numbers.as_slice() {
+            [] => None,
+            [page] => Some(format!("page {page} is")),
+            _ => Some(format!("pages {} are", numbers.join(", "))),
+        }
+    };
+    if let Some(twice) = pages_where(|it| it > 1) {
+        bail!("{twice} in more than one document");
+    }
+    if let Some(left_out) = pages_where(|it| it == 0) {
+        bail!("{left_out} not in any document");
+    }
+
+    Ok(parts
+        .iter()
+        .map(|part| {
+            let document = pages[part.pages.start() - 1..*part.pages.end()].to_vec();
+            let title = part.title.clone().filter(|it| !it.trim().is_empty());
+            (document, title)
+        })
+        .collect())
+}
+
+/// Copies `pages` into `dir` under the same names, returning where they went.
+fn copy_pages(pages: &[PathBuf], dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut copies = vec![];
+    for page in pages {
+        let copy = dir.join(page.file_name().unwrap_or_default());
+        fs::copy(page, &copy).context(format!("copying {page:?}"))?;
+        copies.push(copy);
+    }
+    Ok(copies)
+}
+
+/// The page images in `dir`, in the order they're OCR'd and go into the PDF.
+pub fn pages_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut pages = vec![];
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let hidden = entry
+            .file_name()
+            .to_str()
+            .is_none_or(|it| it.starts_with('.'));
+        if !hidden && !pdf::is_pdf(&entry.path()) && entry.file_type()?.is_file() {
+            pages.push(entry.path());
+        }
+    }
+    pages.sort();
+    Ok(pages)
+}
+
+/// `interval` the way people write it, like "10m" or "1h 30m".
+fn friendly(interval: Duration) -> String {
+    match jiff::SignedDuration::try_from(interval) {
+        Ok(it) => format!("{it:#}"),
+        Err(_) => format!("{interval:?}"),
+    }
+}
@@ -0,0 +1,64 @@
+//! Driving a scanner directly with SANE's `scanimage`, which covers USB scanners and (through
+//! the airscan backend) network ones speaking eSCL.
+
+use std::process::Command;
+
+use eyre::{bail, Context, Result};
+use tracing::info;
+
+use crate::Kartka;
+
+/// Resolution pages are scanned at; tesseract does best at 300dpi.
+const RESOLUTION: u32 = 300;
+
+impl Kartka {
+    /// Scans pages from `device` into the scan dir, returning how many there were. With `adf`
+    /// it takes everything in the document feeder; otherwise it asks before each page on the
+    /// flatbed (or just scans one in plain mode).
+    pub fn acquire(&self, device: &str, adf: bool) -> Result<usize> {
+        if self.dry_run {
+            println!("would scan pages from {device} into {:?}", self.scans());
+            return Ok(0);
+        }
+
+        // pages already waiting would end up in the same PDF as the new ones
+        if self.scans().read_dir()?.next().is_some() {
+            bail!(
+                "{:?} already has files in it - scan or clear them first",
+                self.scans()
+            );
+        }
+
+        info!("scanning from {device}..");
+        let mut command = Command::new("scanimage");
+        command
+            .arg("--device-name")
+            .arg(device)
+            .arg("--format=png")
+            .arg(format!("--resolution={RESOLUTION}"))
+            .arg(format!(
+                "--batch={}",
+                self.scans().join("page-%04d.png").display()
+            ));
+        if adf {
+            command.arg("--source=ADF");
+        } else if self.plain {
+            command.arg("--batch-count=1");
+        } else {
+            command.arg("--batch-prompt");
+        }
+        let status = command.status().context("running scanimage")?;
+
+        let pages = self.scans().read_dir()?.count();
+        // scanimage exits with an error when the feeder runs out, so it's only a failure if
+        // nothing was scanned
+        if pages == 0 {
+            if !status.success() {
+                bail!("scanning from {device} failed");
+            }
+            bail!("{device} didn't scan any pages");
+        }
+        info!("scanned {pages} pages");
+        Ok(pages)
+    }
+}
@@ -0,0 +1,279 @@
+//! Which layout the index is in, and `kartka migrate` for moving it to another. The layout's
+//! version and the [`Backend`] holding the documents are recorded in `.kartka/schema.json`, so
+//! when a newer kartka lays the index out differently, or `index_backend` is changed, the index
+//! can be upgraded where it is rather than hydrated again from nothing. Indexes from before the
+//! marker was kept are version 1.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::SystemTime,
+};
+
+use eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    index::Backend,
+    metadata::{self, Metadata},
+    sqlite, store, Kartka,
+};
+
+/// The version of the layout this kartka reads and writes.
+pub const VERSION: u32 = 1;
+
+const MARKER: &str = ".kartka/schema.json";
+
+/// The layout an index is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    pub version: u32,
+    pub backend: Backend,
+}
+
+/// A change to the layout: the version it brings an index up to, what it does, and how, given
+/// the index and the backend its documents are in.
+type Migration = (u32, &'static str, fn(&Path, Backend) -> Result<()>);
+
+/// Every change to the layout since it started being versioned, oldest first.
+const MIGRATIONS: &[Migration] = &[];
+
+impl Kartka {
+    /// The layout the index is in, or `None` if there's nothing in it yet.
+    pub fn schema(&self) -> Result<Option<Schema>> {
+        let path = self.index().join(MARKER);
+        if path.exists() {
+            let contents = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+            return Ok(Some(
+                serde_json::from_str(&contents).context(format!("parsing {path:?}"))?,
+            ));
+        }
+
+        // from before the marker, so what it's in has to be worked out from what's there
+        let backend = if self.index().join(sqlite::DATABASE).exists() {
+            Backend::Sqlite
+        } else if !text_files(self.index())?.is_empty() {
+            Backend::Files
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Schema {
+            version: 1,
+            backend,
+        }))
+    }
+
+    /// Fails unless the index is in the layout this kartka uses, with the configured backend.
+    /// An index without a marker has one written, so later versions know where it started.
+    pub fn check_schema(&self) -> Result<()> {
+        let Some(schema) = self.schema()? else {
+            return Ok(());
+        };
+        if schema.version > VERSION {
+            bail!(
+                "the index at {:?} is laid out for a newer kartka (version {}, this one \
+                 understands up to {VERSION}) - upgrade kartka to use it",
+                self.index(),
+                schema.version
+            );
+        }
+        if schema.version < VERSION {
+            bail!(
+                "the index at {:?} is laid out for an older kartka (version {}) - run `kartka \
+                 migrate` to upgrade it",
+                self.index(),
+                schema.version
+            );
+        }
+        if schema.backend != self.index_backend {
+            bail!(
+                "the index at {:?} is kept as {}, but `index_backend` is {} - run `kartka \
+                 migrate` to move it over, or set `index_backend` back",
+                self.index(),
+                schema.backend,
+                self.index_backend
+            );
+        }
+        if !self.index().join(MARKER).exists() && !self.dry_run {
+            self.write_schema(schema)?;
+        }
+        Ok(())
+    }
+
+    /// Brings the index up to this kartka's layout, and moves its documents into the configured
+    /// backend if they're in the other one. Returns what was done, which is nothing if it was
+    /// already up to date.
+    pub fn migrate(&self) -> Result<Vec<String>> {
+        let current = Schema {
+            version: VERSION,
+            backend: self.index_backend,
+        };
+        let Some(mut schema) = self.schema()? else {
+            if !self.dry_run && self.index().exists() {
+                self.write_schema(current)?;
+            }
+            return Ok(vec![]);
+        };
+        if schema.version > VERSION {
+            bail!(
+                "the index at {:?} is version {}, which is newer than this kartka knows how to \
+                 migrate to ({VERSION})",
+                self.index(),
+                schema.version
+            );
+        }
+
+        let mut done = vec![];
+        let from = schema.version;
+        for (to, what, migrate) in MIGRATIONS.iter().filter(|(to, ..)| *to > from) {
+            if self.dry_run {
+                done.push(format!("would {what}"));
+                continue;
+            }
+            info!("migrating the index to version {to}: {what}..");
+            migrate(self.index(), schema.backend)
+                .context(format!("migrating the index to version {to}"))?;
+            // each step is recorded as it's done, so an interrupted migrate picks up after it
+            schema.version = *to;
+            self.write_schema(schema)?;
+            done.push(what.to_string());
+        }
+
+        if schema.backend != self.index_backend {
+            let what = format!(
+                "move the documents from {} to {}",
+                schema.backend, self.index_backend
+            );
+            if self.dry_run {
+                done.push(format!("would {what}"));
+            } else {
+                info!(
+                    "moving the documents from {} to {}..",
+                    schema.backend, self.index_backend
+                );
+                // so an interrupted move is picked up again, rather than the index being taken
+                // as being in whichever backend it had got furthest with
+                self.write_schema(schema)?;
+                let moved = match self.index_backend {
+                    Backend::Sqlite => files_to_sqlite(self.index())?,
+                    Backend::Files => sqlite_to_files(self.index())?,
+                };
+                done.push(format!(
+                    "moved {moved} documents from {} to {}",
+                    schema.backend, self.index_backend
+                ));
+            }
+        }
+
+        if !self.dry_run {
+            self.write_schema(current)?;
+            if !done.is_empty() {
+                self.commit_index(&format!("Migrate the index to version {VERSION}"));
+            }
+        }
+        Ok(done)
+    }
+
+    fn write_schema(&self, schema: Schema) -> Result<()> {
+        let _transaction = store::transaction(self.index())?;
+        store::write_atomic(
+            &self.index().join(MARKER),
+            serde_json::to_string_pretty(&schema)?,
+        )
+    }
+}
+
+/// The ids of the documents kept as text files in `index`.
+fn text_files(index: &Path) -> Result<Vec<String>> {
+    if !index.exists() {
+        return Ok(vec![]);
+    }
+    let mut ids = vec![];
+    for entry in index.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // the database's write-ahead log and the like sit next to it
+        if entry.file_type()?.is_file()
+            && !name.starts_with('.')
+            && !name.starts_with(sqlite::DATABASE)
+        {
+            ids.push(name);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Moves every document kept as a text file in `index` into its database, with its metadata.
+/// Documents already in the database are left be, so an interrupted move can be carried on.
+fn files_to_sqlite(index: &Path) -> Result<usize> {
+    let ids = text_files(index)?;
+    let mut db = sqlite::open(index)?;
+    for id in &ids {
+        let path = index.join(id);
+        if !db.contains(id)? {
+            let text = fs::read_to_string(&path).context(format!("reading {path:?}"))?;
+            // the text file was written when the document was added, and not touched since
+            let added_at = fs::metadata(&path)?.modified()?;
+            db.add_text_at(id, &text, added_at.try_into()?)?;
+        }
+        let meta = metadata::read(index, id)?;
+        if !is_default(&meta)? {
+            db.update_metadata(id, |it| *it = meta)?;
+        }
+    }
+    for id in &ids {
+        fs::remove_file(index.join(id))?;
+        metadata::remove(index, id)?;
+    }
+    Ok(ids.len())
+}
+
+/// Moves every document in `index`'s database out into text files, with their metadata in
+/// sidecars, then removes the database.
+fn sqlite_to_files(index: &Path) -> Result<usize> {
+    let db = sqlite::open(index)?;
+    let added = db.added()?;
+    for (id, added_at) in &added {
+        let path = index.join(id);
+        if !path.exists() {
+            store::write_atomic(&path, db.text(id)?)?;
+            // `recent` goes by when the text file was written
+            let added_at: jiff::Timestamp = added_at
+                .parse()
+                .context(format!("reading when {id} was added"))?;
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(SystemTime::from(added_at))?;
+        }
+        let meta = db.metadata(id)?;
+        if !is_default(&meta)? {
+            metadata::update(index, id, |it| *it = meta)?;
+        }
+    }
+    drop(db);
+    for name in database_files() {
+        let path = index.join(name);
+        if path.exists() {
+            fs::remove_file(&path).context(format!("removing {path:?}"))?;
+        }
+    }
+    Ok(added.len())
+}
+
+/// Whether `meta` is what a document with nothing recorded about it has, which needn't be
+/// written anywhere.
+fn is_default(meta: &Metadata) -> Result<bool> {
+    Ok(serde_json::to_value(meta)? == serde_json::to_value(Metadata::default())?)
+}
+
+/// The database and the files SQLite keeps alongside it.
+fn database_files() -> [String; 3] {
+    [
+        sqlite::DATABASE.to_string(),
+        format!("{}-wal", sqlite::DATABASE),
+        format!("{}-shm", sqlite::DATABASE),
+    ]
+}
@@ -0,0 +1,303 @@
+//! Free-text search over the index with ripgrep, or FTS5 for the SQLite backend.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs,
+    path::Path,
+    process::Command,
+    str::FromStr,
+};
+
+use eyre::{bail, Context, Result};
+use jsonpath_rust::JsonPath;
+use serde_json::Value;
+
+use crate::{
+    index::{self, Backend},
+    query::Query,
+    sqlite, text, Kartka,
+};
+
+impl Kartka {
+    /// The ids of documents whose text matches `search_str`, newest first. Case and accents are
+    /// ignored unless `exact_search` is set.
+    pub fn search(&self, search_str: &str) -> Result<Vec<String>> {
+        Ok(self
+            .search_pages(search_str)?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    /// Like [`Kartka::search`], along with the pages (from 1) of each document that matched.
+    /// Any filters in `search_str`, like `tag:invoice`, are applied to what's found; with only
+    /// filters, every document that passes them is found, with no pages.
+    pub fn search_pages(&self, search_str: &str) -> Result<Vec<(String, Vec<usize>)>> {
+        self.search_sorted(search_str, Sort::Date)
+    }
+
+    /// Like [`Kartka::search_pages`], in `sort` order.
+    pub fn search_sorted(&self, search_str: &str, sort: Sort) -> Result<Vec<(String, Vec<usize>)>> {
+        let query = Query::parse(search_str)?;
+        let mut hits = match query.text.is_empty() {
+            true => self
+                .documents()?
+                .into_iter()
+                .rev()
+                .map(|id| (id, vec![], 0.0))
+                .collect(),
+            false => self.search_text(&query.text)?,
+        };
+        // ties stay newest first
+        hits.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let mut passed = vec![];
+        for (id, pages, _) in hits {
+            if self.passes(&id, &query.filters)? {
+                passed.push((id, pages));
+            }
+        }
+        sort.apply(&mut passed);
+        Ok(passed)
+    }
+
+    /// The documents whose text matches `search_str`, newest first, with the pages it matched
+    /// on and how well it matched, the higher the better.
+    fn search_text(&self, search_str: &str) -> Result<Vec<(String, Vec<usize>, f64)>> {
+        if self.index_backend == Backend::Sqlite {
+            let mut hits = vec![];
+            for (id, rank) in sqlite::open(self.index())?.search(search_str)? {
+                let pages = matching_pages(&self.text(&id)?, search_str, self.exact_search);
+                // FTS5 always ignores case and accents, so exact searches are narrowed down here
+                if self.exact_search && pages.is_empty() {
+                    continue;
+                }
+                hits.push((id, pages, -rank));
+            }
+            return Ok(hits);
+        }
+
+        let output = self
+            .tools
+            .output(&mut self.ripgrep(search_str))
+            .context("running ripgrep")?;
+        let stdout_str = String::from_utf8_lossy(&output.stdout);
+        let match_type_path = JsonPath::try_from("$.type")?;
+        let match_file_path = JsonPath::try_from("$.data.path.text")?;
+        let match_offset_path = JsonPath::try_from("$.data.absolute_offset")?;
+        let mut offsets: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for line in stdout_str.lines() {
+            let message: Value = serde_json::from_str(line).context("parsing ripgrep output")?;
+            if extract_path(&message, &match_type_path).as_deref() != Some("match") {
+                continue;
+            }
+            let Some(path) = extract_path(&message, &match_file_path) else {
+                continue;
+            };
+            let offset = match_offset_path
+                .find_slice(&message)
+                .first()
+                .and_then(|it| it.clone().to_data().as_u64());
+            offsets
+                .entry(path)
+                .or_default()
+                .extend(offset.map(|it| it as usize));
+        }
+
+        let mut hits = vec![];
+        for (path, offsets) in offsets {
+            let Some(id) = Path::new(&path).file_name().and_then(OsStr::to_str) else {
+                continue;
+            };
+            let text = fs::read_to_string(self.index().join(&path))
+                .context(format!("reading index entry {path:?}"))?;
+            // each offset is a line that matched
+            let score = offsets.len() as f64;
+            let pages: BTreeSet<_> = offsets
+                .into_iter()
+                .map(|it| index::page_at(&text, it))
+                .collect();
+            hits.push((id.to_string(), pages.into_iter().collect(), score));
+        }
+        hits.reverse();
+        Ok(hits)
+    }
+
+    /// The lines of `id` that match `search_str`, each with the page (from 1) it's on.
+    pub fn search_in(&self, id: &str, search_str: &str) -> Result<Vec<(usize, String)>> {
+        let text = self.text(id)?;
+        if self.index_backend == Backend::Sqlite {
+            // FTS5 queries can't be run over a single document, so this just looks for the words
+            let terms = terms(search_str, self.exact_search);
+            let mut lines = vec![];
+            for (i, page) in index::pages(&text).enumerate() {
+                for line in page.lines().map(str::trim) {
+                    let comparable = comparable(line, self.exact_search);
+                    if terms.iter().any(|term| comparable.contains(term.as_str())) {
+                        lines.push((i + 1, line.to_string()));
+                    }
+                }
+            }
+            return Ok(lines);
+        }
+
+        let output = self
+            .tools
+            .output(self.ripgrep(search_str).arg(id))
+            .context("running ripgrep")?;
+        let stdout_str = String::from_utf8_lossy(&output.stdout);
+        let match_type_path = JsonPath::try_from("$.type")?;
+        let match_line_path = JsonPath::try_from("$.data.lines.text")?;
+        let match_offset_path = JsonPath::try_from("$.data.absolute_offset")?;
+        let mut lines = vec![];
+        for line in stdout_str.lines() {
+            let message: Value = serde_json::from_str(line).context("parsing ripgrep output")?;
+            if extract_path(&message, &match_type_path).as_deref() != Some("match") {
+                continue;
+            }
+            let Some(matched) = extract_path(&message, &match_line_path) else {
+                continue;
+            };
+            let page = match_offset_path
+                .find_slice(&message)
+                .first()
+                .and_then(|it| it.clone().to_data().as_u64())
+                .map_or(1, |it| index::page_at(&text, it as usize));
+            // the last line of each page ends with its page break
+            let matched =
+                matched.trim_matches(|c: char| c == index::PAGE_BREAK || c.is_whitespace());
+            lines.push((page, matched.to_string()));
+        }
+        Ok(lines)
+    }
+
+    /// ripgrep set up to search the index for `search_str`, with JSON output. Files to search
+    /// can be added on the end.
+    fn ripgrep(&self, search_str: &str) -> Command {
+        let mut command = Command::new("rg");
+        command.arg("--json").current_dir(self.index());
+        if self.exact_search {
+            command.arg("-e").arg(search_str);
+        } else {
+            command
+                .arg("-i")
+                .arg("-e")
+                .arg(text::accent_insensitive(search_str));
+        }
+        command
+    }
+}
+
+/// The order search results and lists come in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sort {
+    /// Best match first.
+    Relevance,
+    /// Newest first.
+    #[default]
+    Date,
+    /// By the name after the date in each id, like `council_tax.pdf`, then oldest first.
+    Name,
+}
+
+impl FromStr for Sort {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "relevance" => Ok(Sort::Relevance),
+            "date" => Ok(Sort::Date),
+            "name" => Ok(Sort::Name),
+            _ => bail!("unknown sort {s:?}, expected `relevance`, `date` or `name`"),
+        }
+    }
+}
+
+impl Sort {
+    /// Which of the documents `a` and `b` comes first. Results are already in order of
+    /// relevance, so for `Relevance` everything's equal.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Sort::Relevance => Ordering::Equal,
+            Sort::Date => (index::document_date(b), b).cmp(&(index::document_date(a), a)),
+            Sort::Name => (name(a), a).cmp(&(name(b), b)),
+        }
+    }
+
+    /// Puts `hits`, each starting with a document's id, in this order.
+    pub fn apply<T>(self, hits: &mut [(String, T)]) {
+        hits.sort_by(|a, b| self.compare(&a.0, &b.0));
+    }
+}
+
+/// The name after the date and time `id` starts with, which is empty if it hasn't got one.
+fn name(id: &str) -> &str {
+    let mut rest = id;
+    while let Some((part, after)) = rest.split_once('_') {
+        if part.is_empty() || !part.bytes().all(|it| it.is_ascii_digit()) {
+            break;
+        }
+        rest = after;
+    }
+    match rest.split('.').next() {
+        Some(stem) if stem.bytes().all(|it| it.is_ascii_digit()) => "",
+        _ => rest,
+    }
+}
+
+/// The pages (from 1) of `text` with a word from `query` on them.
+fn matching_pages(text: &str, query: &str, exact: bool) -> Vec<usize> {
+    let terms = terms(query, exact);
+    index::pages(text)
+        .enumerate()
+        .filter(|(_, page)| {
+            let page = comparable(page, exact);
+            terms.iter().any(|term| page.contains(term.as_str()))
+        })
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+pub(crate) fn terms(query: &str, exact: bool) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|it| !it.is_empty())
+        .map(|it| comparable(it, exact))
+        .collect()
+}
+
+/// `text` as it's compared with a search: as it is for exact searches, or else folded.
+pub(crate) fn comparable(text: &str, exact: bool) -> String {
+    if exact {
+        text.to_string()
+    } else {
+        text::fold(text)
+    }
+}
+
+fn extract_path(value: &Value, path: &JsonPath) -> Option<String> {
+    let value: Value = path.find_slice(value).first()?.clone().to_data();
+    value.as_str().map(str::to_string)
+}
+
+/// How long a snippet gets before it's cut short.
+const SNIPPET_CHARS: usize = 160;
+
+/// The first line of `text` with a word from `query` in it, for showing alongside a result.
+/// `exact` is as for `exact_search`.
+pub fn snippet(text: &str, query: &str, exact: bool) -> Option<String> {
+    let terms = terms(query, exact);
+    let line = text.lines().map(str::trim).find(|line| {
+        let line = comparable(line, exact);
+        terms.iter().any(|term| line.contains(term.as_str()))
+    })?;
+    if line.chars().count() <= SNIPPET_CHARS {
+        return Some(line.to_string());
+    }
+    Some(format!(
+        "{}…",
+        line.chars().take(SNIPPET_CHARS).collect::<String>()
+    ))
+}
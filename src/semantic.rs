@@ -0,0 +1,151 @@
+//! Semantic search: each document's text is turned into an embedding, so searches can find
+//! documents about the same thing even when they use different words.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, Context, Result};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{openai::Endpoint, store, Kartka};
+
+const EMBEDDINGS_DIR: &str = ".kartka/embeddings";
+
+/// How much of a document's text is embedded; embedding models only take so much.
+const MAX_CHARS: usize = 8_000;
+
+/// How many documents `search --semantic` returns.
+pub const RESULTS: usize = 10;
+
+/// Asks `api` for the embedding of `text`.
+fn embed(api: &Endpoint, text: &str) -> Result<Vec<f32>> {
+    let text: String = text.chars().take(MAX_CHARS).collect();
+    let response = api.post("embeddings", &json!({ "model": api.model, "input": text }))?;
+    let Some(embedding) = response["data"][0]["embedding"].as_array() else {
+        bail!("{} didn't send an embedding back", api.endpoint);
+    };
+    embedding
+        .iter()
+        .map(|it| it.as_f64().map(|it| it as f32))
+        .collect::<Option<_>>()
+        .ok_or_else(|| eyre::eyre!("{} sent back a malformed embedding", api.endpoint))
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|it| it * it).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+impl Kartka {
+    fn embedding_path(&self, id: &str) -> PathBuf {
+        self.index().join(EMBEDDINGS_DIR).join(format!("{id}.json"))
+    }
+
+    fn embeddings_api(&self) -> Result<&Endpoint> {
+        match &self.embeddings {
+            Some(api) => Ok(api),
+            None => bail!("no [embeddings] configured"),
+        }
+    }
+
+    fn write_embedding(&self, id: &str, embedding: &[f32]) -> Result<()> {
+        fs::create_dir_all(self.index().join(EMBEDDINGS_DIR))?;
+        store::write_atomic(&self.embedding_path(id), serde_json::to_string(embedding)?)
+    }
+
+    /// Embeds `text` as `id`'s, if `[embeddings]` is set up. A document without one still
+    /// turns up in ordinary searches, so problems are only warned about.
+    pub(crate) fn save_embedding(&self, id: &str, text: &str) {
+        let Some(api) = &self.embeddings else {
+            return;
+        };
+        let saved = embed(api, text).and_then(|it| self.write_embedding(id, &it));
+        if let Err(e) = saved {
+            warn!("couldn't embed {id}: {e:#}");
+        }
+    }
+
+    pub(crate) fn remove_embedding(&self, id: &str) -> Result<()> {
+        let path = self.embedding_path(id);
+        if path.exists() {
+            fs::remove_file(&path).context(format!("removing {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rename_embedding(&self, id: &str, new_id: &str) -> Result<()> {
+        let path = self.embedding_path(id);
+        if path.exists() {
+            fs::rename(&path, self.embedding_path(new_id)).context(format!("renaming {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Embeds `ids`, or with none given every document that isn't embedded yet.
+    pub fn embed(&self, ids: &[String]) -> Result<()> {
+        let api = self.embeddings_api()?;
+
+        let ids = if ids.is_empty() {
+            let mut missing = vec![];
+            for id in self.documents()? {
+                if !self.embedding_path(&id).exists() {
+                    missing.push(id);
+                }
+            }
+            missing
+        } else {
+            ids.to_vec()
+        };
+
+        for (i, id) in ids.iter().enumerate() {
+            if self.dry_run {
+                println!("would embed {id}");
+                continue;
+            }
+            info!("({} / {}) embedding {id}..", i + 1, ids.len());
+            let embedding = embed(api, &self.text(id)?).context(format!("embedding {id}"))?;
+            self.write_embedding(id, &embedding)?;
+        }
+        info!("done!");
+        Ok(())
+    }
+
+    /// The `limit` documents most like `query` in meaning, most similar first, with how
+    /// similar they are (1 being identical).
+    pub fn semantic_search(&self, query: &str, limit: usize) -> Result<Vec<(String, f32)>> {
+        let query = embed(self.embeddings_api()?, query)?;
+
+        let mut scores = vec![];
+        let mut unembedded = 0;
+        for id in self.documents()? {
+            let path = self.embedding_path(&id);
+            if !path.exists() {
+                unembedded += 1;
+                continue;
+            }
+            let embedding = read_embedding(&path)?;
+            scores.push((id, cosine_similarity(&query, &embedding)));
+        }
+        if unembedded > 0 {
+            warn!("{unembedded} documents aren't embedded yet - `kartka embed` adds them");
+        }
+
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores.truncate(limit);
+        Ok(scores)
+    }
+}
+
+fn read_embedding(path: &Path) -> Result<Vec<f32>> {
+    let contents = fs::read_to_string(path).context(format!("reading {path:?}"))?;
+    serde_json::from_str(&contents).context(format!("parsing {path:?}"))
+}
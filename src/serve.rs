@@ -0,0 +1,384 @@
+//! `kartka serve`: a small web page for searching the archive, for people who'd rather not use
+//! a terminal, and a JSON API for scripts to search it and add to it.
+
+use std::{
+    fmt::Write,
+    fs,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use eyre::Result;
+use kartka::{query, search, Kartka};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+type Shared = Arc<Kartka>;
+
+/// The largest page image `POST /documents` accepts.
+const MAX_UPLOAD_BYTES: usize = 50 * 1024 * 1024;
+
+/// Held while a document is being added, so two arriving in the same second don't get the same
+/// id.
+static ADDING: Mutex<()> = Mutex::new(());
+
+/// Serves the search page and API on `listen` until kartka is stopped.
+pub fn serve(kartka: Kartka, listen: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/search", get(api_search))
+        .route("/documents", post(api_add))
+        .route("/documents/{id}", get(document))
+        .route("/documents/{id}/thumbnail", get(thumbnail))
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES))
+        .layer(middleware::from_fn_with_state(listen, check_host))
+        .with_state(Arc::new(kartka));
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        info!("serving on http://{listen} - press Ctrl-C to stop");
+        axum::serve(listener, app).await?;
+        Ok(())
+    })
+}
+
+/// Turns away requests for any host but this one when it's only listening on this machine.
+/// Otherwise a web page could point a name of its own at 127.0.0.1 (DNS rebinding) and, as far
+/// as the browser's concerned, read the archive from its own site.
+async fn check_host(
+    State(listen): State<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    if listen.ip().is_loopback() {
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|it| it.to_str().ok())
+            .unwrap_or_default();
+        if !is_this_host(host, listen) {
+            return Err(Error(
+                StatusCode::MISDIRECTED_REQUEST,
+                format!(
+                    "this is only served as http://{listen} or http://localhost:{}",
+                    listen.port()
+                ),
+            ));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Whether the `Host` header `host` names what's listening on `listen`.
+fn is_this_host(host: &str, listen: SocketAddr) -> bool {
+    let port = listen.port();
+    let mut hosts = vec![listen.to_string(), format!("localhost:{port}")];
+    // browsers leave the default port out
+    if port == 80 {
+        let address = listen.to_string();
+        hosts.push(address.trim_end_matches(":80").to_string());
+        hosts.push("localhost".to_string());
+    }
+    hosts.iter().any(|it| it.eq_ignore_ascii_case(host))
+}
+
+/// A failed request, shown to the browser as its status and a line of text.
+struct Error(StatusCode, String);
+
+impl From<eyre::Report> for Error {
+    fn from(e: eyre::Report) -> Self {
+        warn!("{e:#}");
+        Error(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+/// Runs `f` on a thread that's allowed to block, since everything kartka does shells out.
+async fn blocking<T: Send + 'static>(
+    kartka: &Shared,
+    f: impl FnOnce(&Kartka) -> Result<T> + Send + 'static,
+) -> Result<T, Error> {
+    let kartka = kartka.clone();
+    tokio::task::spawn_blocking(move || f(&kartka))
+        .await
+        .map_err(|e| eyre::eyre!("request handler failed: {e}"))?
+        .map_err(Error::from)
+}
+
+async fn require_document(kartka: &Shared, id: &str) -> Result<(), Error> {
+    let id = id.to_string();
+    if !blocking(kartka, move |kartka| kartka.contains(&id)).await? {
+        return Err(Error(StatusCode::NOT_FOUND, "no such document".to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    #[serde(default)]
+    q: String,
+}
+
+/// One search result, as shown on the page and returned by the API.
+#[derive(Serialize)]
+struct Hit {
+    id: String,
+    doc_type: Option<String>,
+    correspondent: Option<String>,
+    tags: Vec<String>,
+    summary: Option<String>,
+    snippet: Option<String>,
+    /// The pages (from 1) the query matched on.
+    pages: Vec<usize>,
+    archived: bool,
+}
+
+/// Searches the index for `query`, filling in what's shown about each result.
+async fn find(kartka: &Shared, query: String) -> Result<Vec<Hit>, Error> {
+    blocking(kartka, move |kartka| {
+        let mut hits = vec![];
+        // the filters in it, like `tag:tax`, aren't in the text to be shown
+        let words = query::Query::parse(&query)?.text;
+        for (id, pages) in kartka.search_pages(&query)? {
+            let meta = kartka.metadata(&id)?;
+            let snippet = search::snippet(&kartka.text(&id)?, &words, kartka.exact_search);
+            hits.push(Hit {
+                archived: kartka.is_archived(&id)?,
+                id,
+                doc_type: meta.doc_type,
+                correspondent: meta.correspondent,
+                tags: meta.tags,
+                summary: meta.summary,
+                snippet,
+                pages,
+            });
+        }
+        Ok(hits)
+    })
+    .await
+}
+
+async fn index(
+    State(kartka): State<Shared>,
+    Query(params): Query<SearchParams>,
+) -> Result<Html<String>, Error> {
+    let query = params.q.trim().to_string();
+    let mut page = String::new();
+    write!(
+        page,
+        r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>kartka</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; padding: 0 1em; }}
+input[type=search] {{ width: 70%; font-size: 1.2em; }}
+ul {{ list-style: none; padding: 0; }}
+li {{ display: flex; gap: 1em; margin: 1em 0; }}
+li img {{ width: 100px; border: 1px solid #ccc; }}
+.meta {{ color: #666; }}
+</style>
+</head>
+<body>
+<form>
+<input type="search" name="q" value="{}" placeholder="Search your letters" autofocus>
+<button>Search</button>
+</form>
+"#,
+        escape(&query)
+    )
+    .expect("writing to a String");
+
+    if !query.is_empty() {
+        write_results(&mut page, find(&kartka, query).await);
+    }
+
+    page.push_str("</body>\n</html>\n");
+    Ok(Html(page))
+}
+
+fn write_results(page: &mut String, hits: Result<Vec<Hit>, Error>) {
+    let hits = match hits {
+        Ok(hits) => hits,
+        // most likely a query the search backend doesn't understand
+        Err(Error(_, message)) => {
+            writeln!(page, "<p>Search failed: {}</p>", escape(&message)).expect("writing");
+            return;
+        }
+    };
+    if hits.is_empty() {
+        page.push_str("<p>Nothing found.</p>\n");
+        return;
+    }
+
+    writeln!(page, "<p>{} found</p>\n<ul>", hits.len()).expect("writing");
+    for hit in hits {
+        let url = format!("/documents/{}", encode_path(&hit.id));
+        // browsers' PDF viewers open at the page given in the fragment
+        let link = match hit.pages.first() {
+            Some(page) => format!("{url}#page={page}"),
+            None => url.clone(),
+        };
+        let mut meta = vec![];
+        if let Some(doc_type) = &hit.doc_type {
+            meta.push(escape(doc_type));
+        }
+        if let Some(correspondent) = &hit.correspondent {
+            meta.push(format!("from {}", escape(correspondent)));
+        }
+        if !hit.pages.is_empty() {
+            let pages: Vec<_> = hit.pages.iter().map(usize::to_string).collect();
+            meta.push(format!("page {}", pages.join(", ")));
+        }
+        if hit.archived {
+            meta.push("in cold storage".to_string());
+        }
+        writeln!(
+            page,
+            r#"<li><img src="{url}/thumbnail" alt="" loading="lazy" onerror="this.remove()"><div><a href="{link}">{}</a><div class="meta">{}</div><div>{}</div><div>{}</div></div></li>"#,
+            escape(&hit.id),
+            meta.join(" · "),
+            hit.summary.as_deref().map(escape).unwrap_or_default(),
+            hit.snippet.as_deref().map(escape).unwrap_or_default(),
+        )
+        .expect("writing");
+    }
+    page.push_str("</ul>\n");
+}
+
+/// `GET /search?q=`: the same results as the search page, as JSON.
+async fn api_search(
+    State(kartka): State<Shared>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<Hit>>, Error> {
+    let query = params.q.trim().to_string();
+    if query.is_empty() {
+        return Err(Error(StatusCode::BAD_REQUEST, "missing q".to_string()));
+    }
+    Ok(Json(find(&kartka, query).await?))
+}
+
+/// `POST /documents`: adds a page image (PNG or JPEG) in the request body as a new document,
+/// just like `kartka scan`, and returns its id.
+async fn api_add(
+    State(kartka): State<Shared>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Value>), Error> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|it| it.to_str().ok())
+        .unwrap_or_default();
+    let extension = match content_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        _ => {
+            return Err(Error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "send a page as image/png or image/jpeg".to_string(),
+            ))
+        }
+    };
+    if body.is_empty() {
+        return Err(Error(StatusCode::BAD_REQUEST, "empty body".to_string()));
+    }
+
+    let id = blocking(&kartka, move |kartka| {
+        let temp_dir = tempfile::tempdir()?;
+        fs::write(temp_dir.path().join(format!("page.{extension}")), &body)?;
+        let _adding = ADDING.lock().expect("add lock poisoned");
+        kartka.add_pages(temp_dir.path(), false)
+    })
+    .await?;
+    info!("added {id}");
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))))
+}
+
+/// Downloads a document and hands it to the browser.
+async fn document(State(kartka): State<Shared>, Path(id): Path<String>) -> Result<Response, Error> {
+    require_document(&kartka, &id).await?;
+    let archived = {
+        let id = id.clone();
+        blocking(&kartka, move |kartka| kartka.is_archived(&id)).await?
+    };
+    if archived {
+        return Err(Error(
+            StatusCode::CONFLICT,
+            format!("{id} is in cold storage - run `kartka open {id}` to restore it first"),
+        ));
+    }
+
+    let pdf = blocking(&kartka, move |kartka| {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join(&id);
+        kartka.fetch(&id, &path)?;
+        Ok(fs::read(path)?)
+    })
+    .await?;
+    Ok(([(header::CONTENT_TYPE, "application/pdf")], pdf).into_response())
+}
+
+/// A document's thumbnail, made on the spot if it wasn't cached.
+async fn thumbnail(
+    State(kartka): State<Shared>,
+    Path(id): Path<String>,
+) -> Result<Response, Error> {
+    require_document(&kartka, &id).await?;
+    let jpeg = blocking(&kartka, move |kartka| {
+        // making one for something in cold storage would mean restoring it
+        if !kartka.thumbnail_path(&id).exists() && kartka.is_archived(&id)? {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(kartka.thumbnail(&id)?)?))
+    })
+    .await?;
+    match jpeg {
+        Some(jpeg) => Ok(([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response()),
+        None => Err(Error(StatusCode::NOT_FOUND, "no thumbnail".to_string())),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes `id` so it can go in a URL path.
+fn encode_path(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{byte:02X}").expect("writing to a String");
+        }
+    }
+    encoded
+}
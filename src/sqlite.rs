@@ -0,0 +1,209 @@
+//! The SQLite index backend (`index_backend = "sqlite"`): every document's text and metadata in
+//! one `kartka.db` in the index directory, searched with FTS5 instead of ripgrep.
+
+use std::path::Path;
+
+use eyre::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::metadata::Metadata;
+
+/// The database's name in the index directory.
+pub const DATABASE: &str = "kartka.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    id TEXT PRIMARY KEY,
+    text TEXT NOT NULL,
+    added_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS metadata (
+    id TEXT PRIMARY KEY,
+    json TEXT NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS documents_fts USING fts5(
+    text, content = 'documents', content_rowid = 'rowid'
+);
+CREATE TRIGGER IF NOT EXISTS documents_insert AFTER INSERT ON documents BEGIN
+    INSERT INTO documents_fts (rowid, text) VALUES (new.rowid, new.text);
+END;
+CREATE TRIGGER IF NOT EXISTS documents_delete AFTER DELETE ON documents BEGIN
+    INSERT INTO documents_fts (documents_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+END;
+";
+
+pub struct Db(Connection);
+
+/// Opens the index database in `index`, creating it if need be.
+pub fn open(index: &Path) -> Result<Db> {
+    let path = index.join(DATABASE);
+    let connection = Connection::open(&path).context(format!("opening {path:?}"))?;
+    // WAL lets searches carry on while another kartka is writing
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.busy_timeout(std::time::Duration::from_secs(30))?;
+    connection
+        .execute_batch(SCHEMA)
+        .context(format!("setting up {path:?}"))?;
+    Ok(Db(connection))
+}
+
+impl Db {
+    /// Writes a consistent copy of the database to `dest`, even while other kartkas are using it.
+    pub fn snapshot(&self, dest: &Path) -> Result<()> {
+        let dest = dest
+            .to_str()
+            .ok_or_else(|| eyre::eyre!("{dest:?} isn't UTF-8"))?;
+        self.0
+            .execute("VACUUM INTO ?1", [dest])
+            .context(format!("copying the database to {dest}"))?;
+        Ok(())
+    }
+
+    /// Moves everything in the write-ahead log into the database file and empties the log, so
+    /// the file alone is up to date.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.0
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .context("checkpointing the database")
+    }
+
+    pub fn documents(&self) -> Result<Vec<String>> {
+        let mut statement = self.0.prepare("SELECT id FROM documents ORDER BY id")?;
+        let ids = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(ids)
+    }
+
+    /// Every document's id and when it was added, as RFC 3339.
+    pub fn added(&self) -> Result<Vec<(String, String)>> {
+        let mut statement = self.0.prepare("SELECT id, added_at FROM documents")?;
+        let added = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(added)
+    }
+
+    pub fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .0
+            .query_row("SELECT 1 FROM documents WHERE id = ?1", [id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    pub fn text(&self, id: &str) -> Result<String> {
+        self.0
+            .query_row("SELECT text FROM documents WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("{id} not found in index"))
+    }
+
+    pub fn add_text(&self, id: &str, text: &str) -> Result<()> {
+        self.add_text_at(id, text, jiff::Timestamp::now())
+    }
+
+    /// [`Db::add_text`], for a document that was added to the index at `added_at`.
+    pub fn add_text_at(&self, id: &str, text: &str, added_at: jiff::Timestamp) -> Result<()> {
+        if self.contains(id)? {
+            bail!("{id} is already in the index");
+        }
+        self.0.execute(
+            "INSERT INTO documents (id, text, added_at) VALUES (?1, ?2, ?3)",
+            params![id, text, added_at.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Changes when `id` was added to the index.
+    pub fn set_added_at(&self, id: &str, added_at: jiff::Timestamp) -> Result<()> {
+        self.0.execute(
+            "UPDATE documents SET added_at = ?2 WHERE id = ?1",
+            params![id, added_at.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Removes `id`'s text, returning whether there was any.
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .0
+            .execute("DELETE FROM documents WHERE id = ?1", [id])?
+            > 0)
+    }
+
+    pub fn rename(&self, id: &str, new_id: &str) -> Result<()> {
+        // the rowid stays put, so the full-text index doesn't need touching
+        self.0.execute(
+            "UPDATE documents SET id = ?2 WHERE id = ?1",
+            params![id, new_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn metadata(&self, id: &str) -> Result<Metadata> {
+        read_metadata(&self.0, id)
+    }
+
+    /// Applies `change` to `id`'s metadata in a single transaction, returning the result.
+    pub fn update_metadata(
+        &mut self,
+        id: &str,
+        change: impl FnOnce(&mut Metadata),
+    ) -> Result<Metadata> {
+        let transaction = self
+            .0
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut metadata = read_metadata(&transaction, id)?;
+        change(&mut metadata);
+        transaction.execute(
+            "INSERT OR REPLACE INTO metadata (id, json) VALUES (?1, ?2)",
+            params![id, serde_json::to_string(&metadata)?],
+        )?;
+        transaction.commit()?;
+        Ok(metadata)
+    }
+
+    pub fn remove_metadata(&self, id: &str) -> Result<()> {
+        self.0.execute("DELETE FROM metadata WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    pub fn rename_metadata(&self, id: &str, new_id: &str) -> Result<()> {
+        self.0.execute(
+            "UPDATE metadata SET id = ?2 WHERE id = ?1",
+            params![id, new_id],
+        )?;
+        Ok(())
+    }
+
+    /// The ids of documents matching the FTS5 `query`, newest first, each with its rank: the
+    /// lower, the better it matched.
+    pub fn search(&self, query: &str) -> Result<Vec<(String, f64)>> {
+        let mut statement = self.0.prepare(
+            "SELECT documents.id, documents_fts.rank FROM documents_fts
+             JOIN documents ON documents.rowid = documents_fts.rowid
+             WHERE documents_fts MATCH ?1
+             ORDER BY documents.id DESC",
+        )?;
+        let ids = statement
+            .query_map([query], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()
+            .context(format!("searching for {query:?}"))?;
+        Ok(ids)
+    }
+}
+
+fn read_metadata(connection: &Connection, id: &str) -> Result<Metadata> {
+    let json: Option<String> = connection
+        .query_row("SELECT json FROM metadata WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    match json {
+        Some(json) => serde_json::from_str(&json).context(format!("parsing metadata for {id}")),
+        None => Ok(Metadata::default()),
+    }
+}
@@ -0,0 +1,90 @@
+//! `kartka stats`: numbers about the archive, mostly for checking that scanning is keeping up.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use eyre::{Context, Result};
+use jiff::ToSpan;
+
+use crate::{
+    index::{self, document_date},
+    ocr, Kartka,
+};
+
+/// How many months `documents_per_month` goes back.
+const MONTHS: i32 = 12;
+
+/// How many tags `top_tags` has at most.
+const TOP_TAGS: usize = 10;
+
+#[derive(Debug)]
+pub struct Stats {
+    pub documents: usize,
+    pub pages: usize,
+    /// How much space the index takes up on disk, in bytes.
+    pub index_bytes: u64,
+    /// Documents scanned in each of the last twelve months, as `YYYY-MM`, oldest first. Months
+    /// with none are included, since those are the ones worth noticing.
+    pub documents_per_month: Vec<(String, usize)>,
+    /// The most used tags and how many documents have them, most used first.
+    pub top_tags: Vec<(String, usize)>,
+    /// The average OCR confidence over the documents that have one, from 0 to 100.
+    pub ocr_confidence: Option<f32>,
+}
+
+impl Kartka {
+    pub fn stats(&self) -> Result<Stats> {
+        let this_month = jiff::Zoned::now().date().first_of_month();
+        let mut per_month = BTreeMap::new();
+        for months_ago in 0..MONTHS {
+            let month = this_month.checked_sub(months_ago.months())?;
+            per_month.insert(month.strftime("%Y-%m").to_string(), 0);
+        }
+
+        let ids = self.documents()?;
+        let mut pages = 0;
+        let mut tags = BTreeMap::new();
+        let mut confidences = vec![];
+        for id in ids.iter() {
+            pages += index::pages(&self.text(id)?).count();
+            if let Some(count) = document_date(id)
+                .and_then(|it| per_month.get_mut(it.strftime("%Y-%m").to_string().as_str()))
+            {
+                *count += 1;
+            }
+
+            let meta = self.metadata(id)?;
+            for tag in meta.tags {
+                *tags.entry(tag).or_insert(0) += 1;
+            }
+            confidences.extend(meta.ocr_confidence);
+        }
+
+        let mut top_tags: Vec<_> = tags.into_iter().collect();
+        // most used first, then alphabetically
+        top_tags.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        top_tags.truncate(TOP_TAGS);
+
+        Ok(Stats {
+            documents: ids.len(),
+            pages,
+            index_bytes: dir_size(self.index())?,
+            documents_per_month: per_month.into_iter().collect(),
+            top_tags,
+            ocr_confidence: ocr::mean(&confidences),
+        })
+    }
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir).context(format!("reading {dir:?}"))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
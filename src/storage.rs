@@ -0,0 +1,581 @@
+//! Moving PDFs to and from the rclone remote, including the cold storage tier.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, Context, Result};
+use jiff::ToSpan;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    index::{document_date, validate_id},
+    integrity, pdf, Kartka, KartkaError,
+};
+
+/// Dropbox's web viewer, for kartka's app folder.
+const DROPBOX_PREVIEW: &str = "https://www.dropbox.com/home/Apps/kartka?preview={id}";
+
+/// A cheaper, slower remote that old documents are moved to by [`Kartka::archive`].
+#[derive(Debug, Deserialize)]
+pub struct ArchiveTier {
+    /// The rclone remote, e.g. an S3 bucket with a Glacier storage class.
+    pub remote: String,
+    /// How old a document has to be before `kartka archive` moves it.
+    pub after_years: i16,
+}
+
+impl Kartka {
+    /// The rclone path of `id` on the remote.
+    pub fn remote_path(&self, id: &str) -> String {
+        remote_path(&self.remote, id)
+    }
+
+    /// The remote `id` is stored in: the archive tier once it's been moved there, otherwise the
+    /// main one.
+    fn document_remote(&self, id: &str) -> Result<&str> {
+        match &self.archive {
+            Some(archive) if self.metadata(id)?.archived => Ok(&archive.remote),
+            _ => Ok(&self.remote),
+        }
+    }
+
+    /// Whether `id` has been moved to cold storage.
+    pub fn is_archived(&self, id: &str) -> Result<bool> {
+        Ok(self.archive.is_some() && self.metadata(id)?.archived)
+    }
+
+    /// Where to view `id`: its copy in `local_archive_dir` or `local_dir` if it has one,
+    /// otherwise `preview_link` with the id filled in. Without a `preview_link`, Dropbox remotes
+    /// link to Dropbox's web viewer, and anything else just gives the rclone path.
+    pub fn preview_link(&self, id: &str) -> String {
+        let local = [&self.local_archive_dir, &self.local_dir]
+            .into_iter()
+            .flatten()
+            .map(|it| it.join(id))
+            .find(|it| it.exists());
+        if let Some(path) = local {
+            return path.display().to_string();
+        }
+
+        match &self.preview_link {
+            Some(template) => template.replace("{id}", id),
+            // the viewer's link is for the app folder itself, not folders in it
+            None if self.remote_type() == Some("dropbox") && !self.remote.contains(':') => {
+                DROPBOX_PREVIEW.replace("{id}", id)
+            }
+            None => self.remote_path(id),
+        }
+    }
+
+    /// The rclone backend `remote` uses, e.g. `dropbox` or `s3`, if rclone knows it.
+    fn remote_type(&self) -> Option<&str> {
+        self.rclone_remotes()
+            .get(remote_name(&self.remote))
+            .map(String::as_str)
+    }
+
+    /// The remotes rclone has been set up with, by name, with their types.
+    pub(crate) fn rclone_remotes(&self) -> &BTreeMap<String, String> {
+        self.rclone_remotes.get_or_init(|| {
+            let Ok(output) = self
+                .tools
+                .output(Command::new("rclone").args(["listremotes", "--long"]))
+            else {
+                return BTreeMap::new();
+            };
+            // lines look like `dropbox:  dropbox`
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|it| it.split_once(':'))
+                .map(|(name, kind)| (name.to_string(), kind.trim().to_string()))
+                .collect()
+        })
+    }
+
+    /// Fails if `remote` or the archive tier's remote isn't one rclone has been set up with.
+    pub fn check_remotes(&self) -> Result<()> {
+        let archive = self.archive.as_ref().map(|it| it.remote.as_str());
+        for remote in std::iter::once(self.remote.as_str()).chain(archive) {
+            let name = remote_name(remote);
+            if !self.rclone_remotes().contains_key(name) {
+                let fix = match self.google_drive {
+                    Some(_) if remote == self.remote => {
+                        "sign in to Google Drive with `kartka google-drive`"
+                    }
+                    _ => "set one up with `rclone config`, or change the remote in kartka.toml",
+                };
+                return Err(KartkaError::Storage {
+                    what: format!("finding {remote}"),
+                    message: format!("rclone has no remote called `{name}` - {fix}"),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves documents older than the archive tier's cutoff into it.
+    pub fn archive(&self, yes: bool) -> Result<()> {
+        let Some(archive) = &self.archive else {
+            bail!("no [archive] remote configured");
+        };
+
+        let cutoff = jiff::Zoned::now()
+            .date()
+            .checked_sub(archive.after_years.years())?;
+        let mut old = vec![];
+        for id in self.documents()? {
+            if document_date(&id).is_some_and(|it| it < cutoff) && !self.is_archived(&id)? {
+                old.push(id);
+            }
+        }
+        if old.is_empty() {
+            info!("nothing older than {cutoff} to archive");
+            return Ok(());
+        }
+
+        for id in &old {
+            println!("{id}");
+        }
+        if !yes
+            && !self.confirm(
+                &format!("Move these {} documents to {}?", old.len(), archive.remote),
+                false,
+            )?
+        {
+            info!("aborted (pass --yes to archive without asking)");
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for (i, id) in old.iter().enumerate() {
+            info!("({} / {}) archiving {id}..", i + 1, old.len());
+            let from = self.remote_path(id);
+            let to = remote_path(&archive.remote, id);
+            let archived = self
+                .rclone(["moveto", &from, &to], &format!("moving {from} to {to}"))
+                .and_then(|_| self.update_metadata(id, |meta| meta.archived = true));
+            if let Err(e) = archived {
+                warn!("failed to archive {id}: {e:#}");
+                failed += 1;
+            }
+        }
+        if failed < old.len() {
+            self.commit_index(&format!("Archive {} documents", old.len() - failed));
+        }
+
+        if failed > 0 {
+            return Err(KartkaError::Partial {
+                failed,
+                total: old.len(),
+            }
+            .into());
+        }
+        info!("done!");
+        Ok(())
+    }
+
+    /// Downloads `id` to `dest`, decrypting it if need be, and returns where it went. `dest`
+    /// can be a directory to download into, and without one it goes in a temporary directory.
+    pub fn get(&self, id: &str, dest: Option<&Path>) -> Result<PathBuf> {
+        validate_id(id)?;
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+        if self.is_archived(id)? && self.local_copy(id).is_none() {
+            bail!("{id} is in cold storage - `kartka open {id}` brings it back");
+        }
+
+        let dest = match dest {
+            Some(dest) if dest.is_dir() => dest.join(id),
+            Some(dest) => dest.to_path_buf(),
+            None => private_dir()?.join(id),
+        };
+        if dest.exists() {
+            bail!("{dest:?} already exists");
+        }
+        if self.dry_run {
+            println!("would download {} to {dest:?}", self.remote_path(id));
+            return Ok(dest);
+        }
+
+        // downloaded alongside, so an interrupted download isn't mistaken for a finished one
+        let partial = dest.with_extension("part");
+        self.fetch(id, &partial)?;
+        fs::rename(&partial, &dest).context(format!("moving {partial:?} to {dest:?}"))?;
+        Ok(dest)
+    }
+
+    /// Opens a document's preview, restoring it from cold storage first if need be.
+    pub fn open(&self, id: &str) -> Result<()> {
+        validate_id(id)?;
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+
+        if let (true, Some(archive)) = (self.is_archived(id)?, &self.archive) {
+            if !self.confirm(
+                &format!("{id} is in cold storage, restore it to {}?", self.remote),
+                true,
+            )? {
+                info!("aborted");
+                return Ok(());
+            }
+
+            let from = remote_path(&archive.remote, id);
+            let to = self.remote_path(id);
+            if let Err(e) = self.rclone(["copyto", &from, &to], &format!("restoring {from}")) {
+                // Glacier-style storage has to thaw objects before they can be read, which
+                // takes hours - kick that off so trying again later works
+                if self
+                    .rclone(
+                        ["backend", "restore", &from, "-o", "priority=Standard"],
+                        &format!("requesting restore of {from}"),
+                    )
+                    .is_ok()
+                {
+                    info!("requested {id} back from cold storage - this can take a few hours, run `kartka open {id}` again later");
+                    return Ok(());
+                }
+                return Err(e);
+            }
+
+            // the cold copy stays put, so the document just counts as live again
+            self.update_metadata(id, |meta| meta.archived = false)?;
+            info!("restored {id}");
+        }
+
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else {
+            "xdg-open"
+        };
+
+        // the remote can't preview what it can't read, so encrypted documents are opened from
+        // a decrypted copy, left where the viewer can still get at it after kartka exits
+        if self.encryption.is_some() {
+            let path = private_dir()?.join(id);
+            if path.exists() {
+                fs::remove_file(&path).context(format!("removing the old copy {path:?}"))?;
+            }
+            self.fetch(id, &path)?;
+            if Command::new(opener).arg(&path).status().is_err() {
+                println!("{}", path.display());
+            }
+            return Ok(());
+        }
+
+        let link = self.preview_link(id);
+        if Command::new(opener).arg(&link).status().is_err() {
+            println!("{link}");
+        }
+        Ok(())
+    }
+
+    /// Names of everything on the remote, apart from kartka's own bookkeeping files.
+    pub fn remote_files(&self) -> Result<Vec<String>> {
+        let output = self.rclone(
+            ["lsf", &self.remote_path("")],
+            &format!("listing {}", self.remote),
+        )?;
+        Ok(String::from_utf8(output.stdout)?
+            .lines()
+            // folders, like the one backups go in, aren't documents
+            .filter(|it| !integrity::is_manifest(it) && !it.ends_with('/'))
+            .map(|it| it.to_string())
+            .collect())
+    }
+
+    /// SHA-256 hashes of everything on the remote, by name. Most remotes don't store these, so
+    /// this downloads every file.
+    pub fn remote_hashes(&self) -> Result<BTreeMap<String, String>> {
+        let output = self.rclone(
+            [
+                "hashsum",
+                "sha256",
+                "--download",
+                "--max-depth",
+                "1",
+                &self.remote_path(""),
+            ],
+            &format!("hashing files in {}", self.remote),
+        )?;
+
+        let mut hashes = integrity::parse_hashes(&String::from_utf8_lossy(&output.stdout));
+        hashes.retain(|name, _| !integrity::is_manifest(name));
+        Ok(hashes)
+    }
+
+    /// Uploads the file at `path` to the remote as `name`.
+    pub fn upload_file(&self, path: &Path, name: &str) -> Result<()> {
+        let remote_path = self.remote_path(name);
+        self.rclone(
+            [
+                OsStr::new("copyto"),
+                path.as_os_str(),
+                OsStr::new(&remote_path),
+            ],
+            &format!("uploading {name} to {}", self.remote),
+        )?;
+        Ok(())
+    }
+
+    /// Downloads `id` from the remote to `dest`.
+    pub fn download(&self, id: &str, dest: &Path) -> Result<()> {
+        let remote_path = self.remote_path(id);
+        self.rclone(
+            [
+                OsStr::new("copyto"),
+                OsStr::new(&remote_path),
+                dest.as_os_str(),
+            ],
+            &format!("downloading {remote_path}"),
+        )?;
+        Ok(())
+    }
+
+    /// [`Kartka::download`], without blocking the thread.
+    pub async fn download_async(&self, id: &str, dest: &Path) -> Result<()> {
+        let remote_path = self.remote_path(id);
+        self.rclone_async(
+            [
+                OsStr::new("copyto"),
+                OsStr::new(&remote_path),
+                dest.as_os_str(),
+            ],
+            &format!("downloading {remote_path}"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `id`'s copy in `local_archive_dir`, if there is one.
+    pub fn local_copy(&self, id: &str) -> Option<PathBuf> {
+        let path = self.local_archive_dir.as_ref()?.join(id);
+        path.exists().then_some(path)
+    }
+
+    /// Copies the (decrypted) PDF at `pdf` into `local_archive_dir` as `id`, if that's set. Not
+    /// having a local copy only means going to the remote for it, so failing is just a warning.
+    pub(crate) fn keep_local_copy(&self, pdf: &Path, id: &str) {
+        let Some(dir) = &self.local_archive_dir else {
+            return;
+        };
+        let dest = dir.join(id);
+        // copied alongside first, so a half-written copy is never mistaken for the real thing
+        let partial = dest.with_extension("part");
+        let copied = fs::create_dir_all(dir)
+            .and_then(|()| fs::copy(pdf, &partial))
+            .and_then(|_| fs::rename(&partial, &dest));
+        if let Err(e) = copied {
+            warn!("couldn't keep a copy of {id} in {dir:?}: {e}");
+        }
+    }
+
+    /// Downloads `id` and renders its first page as a PNG at `out`, at most `width` pixels wide.
+    pub fn first_page(&self, id: &str, width: u32, out: &Path) -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let pdf_path = temp_dir.path().join(id);
+        self.fetch(id, &pdf_path)?;
+        pdf::render_page(self.tools.as_ref(), &pdf_path, 0, width, out)
+    }
+
+    /// Deletes `id` from whichever remote tier it's stored in.
+    pub(crate) fn delete_remote(&self, id: &str) -> Result<()> {
+        let remote_path = remote_path(self.document_remote(id)?, id);
+        self.rclone(
+            ["deletefile", &remote_path],
+            &format!("deleting {remote_path}"),
+        )?;
+        Ok(())
+    }
+
+    /// Renames a document on whichever remote tier it's stored in.
+    pub(crate) fn rclone_moveto(&self, from: &str, to: &str) -> Result<()> {
+        let remote = self.document_remote(from)?;
+        let from_path = remote_path(remote, from);
+        let to_path = remote_path(remote, to);
+        self.rclone(
+            ["moveto", &from_path, &to_path],
+            &format!("moving {from_path} to {to_path}"),
+        )?;
+        Ok(())
+    }
+
+    /// Runs rclone with `args`, retrying with exponential backoff if it fails, and failing with
+    /// its error output once out of attempts.
+    pub(crate) fn rclone<I, S>(&self, args: I, what: &str) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<_> = args.into_iter().collect();
+        let mut delay = Duration::from_millis(self.retry.initial_delay_ms);
+        let mut attempt = 1;
+        let started = Instant::now();
+        loop {
+            // not being able to run rclone at all won't fix itself, so don't retry that
+            let output = self
+                .tools
+                .output(Command::new("rclone").args(&args))
+                .map_err(|e| KartkaError::Storage {
+                    what: what.to_string(),
+                    message: format!("couldn't run rclone: {e}"),
+                })?;
+            if output.status.success() {
+                debug!("{what} took {:?}", started.elapsed());
+                return Ok(output);
+            }
+
+            self.check_failure(&output, attempt, delay, what)?;
+            thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_millis(self.retry.max_delay_ms));
+            attempt += 1;
+        }
+    }
+
+    /// [`Kartka::rclone`] without blocking the thread, so several transfers can be in flight at
+    /// once. rclone is killed if the returned future is dropped before it's done.
+    pub(crate) async fn rclone_async<I, S>(&self, args: I, what: &str) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<_> = args.into_iter().collect();
+        let mut delay = Duration::from_millis(self.retry.initial_delay_ms);
+        let mut attempt = 1;
+        let started = Instant::now();
+        loop {
+            let mut command = tokio::process::Command::new("rclone");
+            command.args(&args);
+            let output =
+                self.tools
+                    .output_async(command)
+                    .await
+                    .map_err(|e| KartkaError::Storage {
+                        what: what.to_string(),
+                        message: format!("couldn't run rclone: {e}"),
+                    })?;
+            if output.status.success() {
+                debug!("{what} took {:?}", started.elapsed());
+                return Ok(output);
+            }
+
+            self.check_failure(&output, attempt, delay, what)?;
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(Duration::from_millis(self.retry.max_delay_ms));
+            attempt += 1;
+        }
+    }
+
+    /// Decides whether rclone's failed `attempt` at `what` is worth retrying after `delay`,
+    /// returning its error if not.
+    fn check_failure(
+        &self,
+        output: &Output,
+        attempt: u32,
+        delay: Duration,
+        what: &str,
+    ) -> Result<()> {
+        // a remote that isn't set up won't appear by retrying
+        if attempt == 1 {
+            self.check_remotes()?;
+        }
+
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if attempt >= self.retry.attempts {
+            return Err(KartkaError::Storage {
+                what: format!("{what} (after {attempt} attempts)"),
+                message,
+            }
+            .into());
+        }
+
+        warn!("{what} failed, retrying in {delay:?}: {message}");
+        Ok(())
+    }
+}
+
+/// How hard to try when talking to the remote fails, configured as `[retry]` in kartka.toml.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Retry {
+    /// How many times to try each operation, including the first.
+    pub attempts: u32,
+    /// How long to wait before the first retry. Each retry after that waits twice as long.
+    pub initial_delay_ms: u64,
+    /// The longest to wait between retries.
+    pub max_delay_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 4,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+/// How long copies of documents are left in [`private_dir`] for something to open them.
+const COPIES_KEPT_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where documents are downloaded to without anywhere else to go, decrypted if need be. It's
+/// only readable by the user, and in their runtime directory where there is one, which is
+/// emptied when they log out. Copies left from over a day ago are cleared out.
+fn private_dir() -> Result<PathBuf> {
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("kartka")
+        .join("copies");
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        builder.mode(0o700);
+        builder.create(&dir).context(format!("creating {dir:?}"))?;
+        // it may have been made before it was kept private
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    builder.create(&dir).context(format!("creating {dir:?}"))?;
+
+    for entry in fs::read_dir(&dir)?.flatten() {
+        let old = entry
+            .metadata()
+            .and_then(|it| it.modified())
+            .is_ok_and(|it| it.elapsed().unwrap_or_default() > COPIES_KEPT_FOR);
+        if old {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                warn!("couldn't remove the old copy {:?}: {e}", entry.path());
+            }
+        }
+    }
+    Ok(dir)
+}
+
+/// The name of the rclone remote in `remote`, without any folder on it.
+pub fn remote_name(remote: &str) -> &str {
+    remote.split_once(':').map_or(remote, |(name, _)| name)
+}
+
+/// The rclone path of `id` in `remote`, which is either just a remote's name or a remote and a
+/// folder on it (`dropbox:documents/kartka`).
+fn remote_path(remote: &str, id: &str) -> String {
+    match remote.split_once(':') {
+        None => format!("{remote}:{id}"),
+        Some((_, "")) => format!("{remote}{id}"),
+        Some(_) => format!("{}/{id}", remote.trim_end_matches('/')),
+    }
+}
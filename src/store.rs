@@ -0,0 +1,38 @@
+//! Keeps the index's bookkeeping - metadata sidecars and the manifest - consistent when several
+//! kartka processes work on the same index at once. Every change happens inside a
+//! [`transaction`], which holds an exclusive lock on `.kartka/lock` from read to write, and files
+//! are replaced atomically, so readers never need the lock and never see half a file.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use eyre::{Context, Result};
+
+const LOCK: &str = ".kartka/lock";
+
+/// Exclusive access to the index's bookkeeping, released when dropped. Don't start one while
+/// already holding one, or it will wait forever.
+pub struct Transaction {
+    _lock: File,
+}
+
+/// Waits for any other change to the index's bookkeeping to finish, then starts one.
+pub fn transaction(index: &Path) -> Result<Transaction> {
+    fs::create_dir_all(index.join(".kartka"))?;
+    let path = index.join(LOCK);
+    let lock = File::create(&path).context(format!("opening {path:?}"))?;
+    lock.lock().context(format!("locking {path:?}"))?;
+    Ok(Transaction { _lock: lock })
+}
+
+/// Replaces `path` with `contents`, writing then renaming so a crash never leaves half a file
+/// behind.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, contents).context(format!("writing {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path).context(format!("writing {path:?}"))?;
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Short summaries of each document written by an LLM, so documents can be told apart in `list`
+//! and search results without opening them.
+
+use eyre::{bail, Context, Result};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{openai::Endpoint, Kartka};
+
+/// How much of a document's text is sent; the start of a letter says what it's about.
+const MAX_CHARS: usize = 12_000;
+
+const PROMPT: &str = "Summarise this scanned letter or document in two or three sentences. \
+Say who it's from, what it's about, and anything the reader needs to do, with any dates or amounts. \
+Reply with just the summary.";
+
+/// Asks `llm` to summarise `text`.
+fn summarise(llm: &Endpoint, text: &str) -> Result<String> {
+    let text: String = text.chars().take(MAX_CHARS).collect();
+    let request = json!({
+        "model": llm.model,
+        "messages": [
+            { "role": "system", "content": PROMPT },
+            { "role": "user", "content": text },
+        ],
+    });
+    let response = llm.post("chat/completions", &request)?;
+
+    let Some(summary) = response["choices"][0]["message"]["content"].as_str() else {
+        bail!("{} didn't send a summary back", llm.endpoint);
+    };
+    Ok(summary.trim().to_string())
+}
+
+impl Kartka {
+    /// Writes a summary of `text` into `id`'s metadata, if `[summaries]` is set up. Not having
+    /// a summary is no reason to fail a scan, so problems are only warned about.
+    pub(crate) fn save_summary(&self, id: &str, text: &str) {
+        let Some(summaries) = &self.summaries else {
+            return;
+        };
+        let saved = summarise(summaries, text)
+            .and_then(|summary| self.update_metadata(id, |meta| meta.summary = Some(summary)));
+        if let Err(e) = saved {
+            warn!("couldn't summarise {id}: {e:#}");
+        }
+    }
+
+    /// Summarises `ids`, or with none given every document that doesn't have a summary yet.
+    pub fn summarise(&self, ids: &[String]) -> Result<()> {
+        let Some(summaries) = &self.summaries else {
+            bail!("no [summaries] configured");
+        };
+
+        let ids = if ids.is_empty() {
+            let mut missing = vec![];
+            for id in self.documents()? {
+                if self.metadata(&id)?.summary.is_none() {
+                    missing.push(id);
+                }
+            }
+            missing
+        } else {
+            ids.to_vec()
+        };
+
+        for (i, id) in ids.iter().enumerate() {
+            if self.dry_run {
+                println!("would summarise {id}");
+                continue;
+            }
+            info!("({} / {}) summarising {id}..", i + 1, ids.len());
+            let summary =
+                summarise(summaries, &self.text(id)?).context(format!("summarising {id}"))?;
+            self.update_metadata(id, |meta| meta.summary = Some(summary))?;
+        }
+        info!("done!");
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! `kartka sync`: bringing the index and the remote back in line with each other in one go.
+
+use std::{collections::HashSet, num::NonZeroUsize};
+
+use eyre::{bail, Result};
+use tracing::{info, warn};
+
+use crate::{error, scan::pages_in, Kartka, KartkaError};
+
+impl Kartka {
+    /// Scans anything left in the scan dir, hydrates whatever is on the remote but not in the
+    /// index (`jobs` at a time), and reports documents only the index has. Kartka doesn't keep
+    /// its own copy of a PDF once it's uploaded, so those can't be put back and have to be
+    /// looked at by hand.
+    ///
+    /// Leftover pages that look like a document already in the index are left alone, so this
+    /// is safe to run again and again.
+    pub fn sync(&self, jobs: NonZeroUsize) -> Result<()> {
+        let mut partial = None;
+
+        if !pages_in(self.scans())?.is_empty() {
+            info!("scanning pages left in {:?}..", self.scans());
+            if let Err(e) = self.scan(true, false) {
+                warn!("couldn't scan the pages in {:?}: {e:#}", self.scans());
+            }
+        }
+
+        info!("hydrating..");
+        // the point is to catch anything the manifest's missing, so the remote gets listed
+        if let Err(e) = self.rehydrate(false, true, jobs) {
+            match error::category(&e) {
+                // carry on to the report, then fail the same way hydrate would have
+                Some(KartkaError::Partial { .. }) => partial = Some(e),
+                _ => return Err(e),
+            }
+        }
+
+        let remote_files: HashSet<_> = self.remote_files()?.into_iter().collect();
+        let mut local_only = 0;
+        for id in self.documents()? {
+            if !remote_files.contains(&id) && !self.is_archived(&id)? {
+                println!("LOCAL ONLY   {id}");
+                local_only += 1;
+            }
+        }
+        if local_only > 0 {
+            println!(
+                "{local_only} documents are in the index but not on {} - restore their PDFs there, or `kartka delete` them",
+                self.remote
+            );
+        }
+
+        if let Some(e) = partial {
+            return Err(e);
+        }
+        if local_only > 0 {
+            bail!("{local_only} documents missing from {}", self.remote);
+        }
+        info!("in sync");
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Spotting an index that lives in a cloud-synced folder. The index is the plaintext of every
+//! letter, so letting Dropbox or iCloud sync it quietly uploads everything kartka is careful
+//! about keeping off the remote.
+
+use std::{
+    fmt, fs,
+    path::{Component, Path},
+    process::Command,
+};
+
+use eyre::{bail, Context, Result};
+
+use crate::Kartka;
+
+/// Written to the index's `.kartka` directory once the sync client has been told to ignore it.
+const IGNORED_MARKER: &str = ".kartka/sync-ignored";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Dropbox,
+    ICloud,
+    OneDrive,
+    GoogleDrive,
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Provider::Dropbox => "Dropbox",
+            Provider::ICloud => "iCloud Drive",
+            Provider::OneDrive => "OneDrive",
+            Provider::GoogleDrive => "Google Drive",
+        })
+    }
+}
+
+impl Provider {
+    /// Whether kartka knows how to tell this provider to leave a folder alone.
+    pub fn can_ignore(self) -> bool {
+        self == Provider::Dropbox
+    }
+
+    /// What to do about an index synced by this provider, for when it can't be ignored
+    /// automatically.
+    pub fn advice(self) -> &'static str {
+        match self {
+            Provider::Dropbox => {
+                "run kartka without `--plain` to have Dropbox ignore it, or move it"
+            }
+            Provider::ICloud => {
+                "rename the folder to end in `.nosync` (and update `index_dir`) so iCloud skips it"
+            }
+            Provider::OneDrive | Provider::GoogleDrive => "move it outside the synced folder",
+        }
+    }
+}
+
+/// Which sync client, if any, looks after `path`, judging by the folders it's in.
+pub fn provider(path: &Path) -> Option<Provider> {
+    // follow symlinks, so an index at ~/kartka -> ~/Dropbox/kartka is still caught
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let names: Vec<_> = path
+        .components()
+        .filter_map(|it| match it {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+
+    if names.iter().any(|it| it.ends_with(".nosync")) {
+        return None;
+    }
+    if names
+        .iter()
+        .any(|it| *it == "Mobile Documents" || *it == "com~apple~CloudDocs")
+    {
+        return Some(Provider::ICloud);
+    }
+    if names.iter().any(|it| it.starts_with("OneDrive")) {
+        return Some(Provider::OneDrive);
+    }
+    if names
+        .iter()
+        .any(|it| *it == "Google Drive" || it.starts_with("GoogleDrive-"))
+    {
+        return Some(Provider::GoogleDrive);
+    }
+    if names.iter().any(|it| it.starts_with("Dropbox"))
+        || path.ancestors().any(|it| it.join(".dropbox").is_file())
+    {
+        return Some(Provider::Dropbox);
+    }
+    None
+}
+
+impl Kartka {
+    /// The sync client uploading the index, unless it's been told not to or the config says
+    /// that's fine with `index_in_synced_folder`.
+    pub fn index_synced_by(&self) -> Option<Provider> {
+        if self.index_in_synced_folder || self.index().join(IGNORED_MARKER).exists() {
+            return None;
+        }
+        provider(self.index())
+    }
+
+    /// Marks the index so its sync client stops uploading it.
+    pub fn ignore_sync(&self, provider: Provider) -> Result<()> {
+        if !provider.can_ignore() {
+            bail!(
+                "kartka can't stop {provider} syncing the index - {}",
+                provider.advice()
+            );
+        }
+
+        let index = self.index();
+        let output = if cfg!(target_os = "macos") {
+            Command::new("xattr")
+                .args(["-w", "com.dropbox.ignored", "1"])
+                .arg(index)
+                .output()
+        } else if cfg!(windows) {
+            Command::new("powershell")
+                .arg("-Command")
+                .arg(format!(
+                    "Set-Content -Path '{}' -Stream com.dropbox.ignored -Value 1",
+                    index.display()
+                ))
+                .output()
+        } else {
+            Command::new("attr")
+                .args(["-s", "com.dropbox.ignored", "-V", "1"])
+                .arg(index)
+                .output()
+        }
+        .context(format!("marking {index:?} as ignored by {provider}"))?;
+        if !output.status.success() {
+            bail!(
+                "marking {index:?} as ignored by {provider} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        fs::create_dir_all(index.join(".kartka"))?;
+        fs::write(index.join(IGNORED_MARKER), provider.to_string())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//! Pulling tables, like the transactions on a bank statement, back out of a page from where
+//! OCR found each word. Words are gathered into lines, lines are split into cells wherever
+//! there's a wide gap, and cells that sit one above another across a run of lines make up a
+//! table's columns. That copes with ruled and plainly aligned tables, but not with cells that
+//! wrap onto more than one line.
+
+use std::{fs, io::Write};
+
+use eyre::{bail, Result};
+use tracing::{debug, info};
+
+use crate::{
+    fields::csv_field,
+    hocr::{self, Word},
+    ocr::{OcrEngine, Settings, Tesseract},
+    pdf, Kartka,
+};
+
+/// How much wider than the words are tall a gap has to be to separate two cells. Spaces
+/// between words are usually well under half a word's height.
+const CELL_GAP: f32 = 1.2;
+
+/// A table found on a page, a row of cells at a time.
+#[derive(Debug)]
+pub struct Table {
+    /// Counting from 1.
+    pub page: usize,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Writes the table to `out` as CSV.
+    pub fn write_csv(&self, out: &mut impl Write) -> Result<()> {
+        for row in &self.rows {
+            let row: Vec<_> = row.iter().map(|it| csv_field(it)).collect();
+            writeln!(out, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Kartka {
+    /// The tables on `id`'s pages, or on just `page` if it's given. The word positions kept
+    /// with `hocr = true` are used if there are any, otherwise the pages are rendered and read
+    /// again with tesseract.
+    pub fn extract_tables(&self, id: &str, page: Option<usize>) -> Result<Vec<Table>> {
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+        let mut pages = self.hocr_pages(id)?;
+        if pages.is_empty() {
+            pages = self.read_layout(id)?;
+        }
+        if let Some(page) = page {
+            pages.retain(|(it, _)| *it == page);
+            if pages.is_empty() {
+                bail!("{id} has no page {page}");
+            }
+        }
+
+        let mut tables = vec![];
+        for (page, hocr) in pages {
+            let (_, words) = hocr::parse(&hocr);
+            for rows in tables_in(words) {
+                debug!("found a {}-row table on page {page} of {id}", rows.len());
+                tables.push(Table { page, rows });
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Reads where every word on `id`'s pages is, as hOCR, without keeping it.
+    fn read_layout(&self, id: &str) -> Result<Vec<(usize, String)>> {
+        info!("{id} has no word positions kept, reading its pages again..");
+        let temp_dir = tempfile::tempdir()?;
+        let pdf_path = temp_dir.path().join(id);
+        self.fetch(id, &pdf_path)?;
+        let pages_dir = temp_dir.path().join("pages");
+        fs::create_dir(&pages_dir)?;
+        pdf::render_pages(self.tools.as_ref(), &pdf_path, &pages_dir, "page")?;
+
+        let language = self
+            .metadata(id)?
+            .language
+            .unwrap_or_else(|| self.ocr_language.clone());
+        let tesseract = Tesseract {
+            language,
+            settings: Settings {
+                hocr: true,
+                ..self.ocr.clone()
+            },
+        };
+        let mut images: Vec<_> = pages_dir
+            .read_dir()?
+            .map(|it| it.map(|it| it.path()))
+            .collect::<Result<_, _>>()?;
+        images.sort();
+        if images.is_empty() {
+            bail!("couldn't render the pages of {id}");
+        }
+        let mut pages = vec![];
+        for (i, image) in images.iter().enumerate() {
+            if let Some(hocr) = tesseract.image_to_page(image)?.hocr {
+                pages.push((i + 1, hocr));
+            }
+        }
+        Ok(pages)
+    }
+}
+
+/// The tables in `words`, as rows of cells. A table is at least two lines in a row with at
+/// least two cells each, that between them have at least two columns.
+fn tables_in(words: Vec<Word>) -> Vec<Vec<Vec<String>>> {
+    let mut tables = vec![];
+    let mut run: Vec<Vec<Word>> = vec![];
+    for line in cells(lines(words)) {
+        if line.len() >= 2 {
+            run.push(line);
+            continue;
+        }
+        tables.extend(table(std::mem::take(&mut run)));
+    }
+    tables.extend(table(run));
+    tables
+}
+
+/// Lays out a run of lines of cells as a table, if it is one.
+fn table(lines: Vec<Vec<Word>>) -> Option<Vec<Vec<String>>> {
+    if lines.len() < 2 {
+        return None;
+    }
+    // a column is wherever cells overlap one another across the lines, which catches left,
+    // right and centre aligned columns alike
+    let mut spans: Vec<(u32, u32)> = lines
+        .iter()
+        .flatten()
+        .map(|it| (it.bbox[0], it.bbox[2]))
+        .collect();
+    spans.sort();
+    let mut columns: Vec<(u32, u32)> = vec![];
+    for (left, right) in spans {
+        match columns.last_mut() {
+            Some(column) if left <= column.1 => column.1 = column.1.max(right),
+            _ => columns.push((left, right)),
+        }
+    }
+    if columns.len() < 2 {
+        return None;
+    }
+
+    let rows = lines
+        .into_iter()
+        .map(|line| {
+            let mut row = vec![String::new(); columns.len()];
+            for cell in line {
+                let i = columns
+                    .iter()
+                    .position(|(_, right)| cell.bbox[0] <= *right)
+                    .unwrap_or(columns.len() - 1);
+                if !row[i].is_empty() {
+                    row[i].push(' ');
+                }
+                row[i].push_str(&cell.text);
+            }
+            row
+        })
+        .collect();
+    Some(rows)
+}
+
+/// `words` gathered into lines, top to bottom, each read left to right. A word is on a line if
+/// its middle is level with the line.
+fn lines(mut words: Vec<Word>) -> Vec<Vec<Word>> {
+    words.sort_by_key(|it| (it.bbox[1], it.bbox[0]));
+    let mut lines: Vec<(u32, u32, Vec<Word>)> = vec![];
+    for word in words {
+        let middle = (word.bbox[1] + word.bbox[3]) / 2;
+        match lines
+            .iter_mut()
+            .rev()
+            .find(|(top, bottom, _)| (*top..=*bottom).contains(&middle))
+        {
+            Some((_, _, line)) => line.push(word),
+            None => lines.push((word.bbox[1], word.bbox[3], vec![word])),
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(_, _, mut line)| {
+            line.sort_by_key(|it| it.bbox[0]);
+            line
+        })
+        .collect()
+}
+
+/// Each of `lines` with its words joined into cells, split wherever the gap between words is
+/// wide enough. A cell is a [`Word`] with the cell's text and a box round all of it.
+fn cells(lines: Vec<Vec<Word>>) -> Vec<Vec<Word>> {
+    lines
+        .into_iter()
+        .map(|line| {
+            let mut heights: Vec<_> = line.iter().map(|it| it.bbox[3] - it.bbox[1]).collect();
+            heights.sort();
+            let height = heights.get(heights.len() / 2).copied().unwrap_or_default();
+            let gap = (height as f32 * CELL_GAP) as u32;
+
+            let mut cells: Vec<Word> = vec![];
+            for word in line {
+                match cells.last_mut() {
+                    Some(cell) if word.bbox[0] <= cell.bbox[2] + gap => {
+                        cell.text.push(' ');
+                        cell.text.push_str(&word.text);
+                        cell.bbox = [
+                            cell.bbox[0].min(word.bbox[0]),
+                            cell.bbox[1].min(word.bbox[1]),
+                            cell.bbox[2].max(word.bbox[2]),
+                            cell.bbox[3].max(word.bbox[3]),
+                        ];
+                    }
+                    _ => cells.push(word),
+                }
+            }
+            cells
+        })
+        .collect()
+}
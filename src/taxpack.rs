@@ -0,0 +1,166 @@
+//! `kartka taxpack`: picking out a year's tax documents by rules and bundling them into one PDF.
+
+use std::{fs, path::Path};
+
+use eyre::{bail, Context, Result};
+use jiff::{civil::Date, ToSpan};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    index::document_date,
+    metadata::{field_matches, Metadata},
+    pdf, Kartka,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Rules {
+    /// When the tax year starts, as `MM-DD`, e.g. `04-06` in the UK. Defaults to January 1st.
+    #[serde(default)]
+    year_start: Option<String>,
+    #[serde(rename = "section")]
+    pub sections: Vec<Section>,
+}
+
+/// A group of documents in the pack, in the order they appear in the rules file.
+#[derive(Debug, Deserialize)]
+pub struct Section {
+    pub title: String,
+    #[serde(default)]
+    doc_type: Option<String>,
+    #[serde(default)]
+    correspondent: Option<String>,
+    /// Documents must have every one of these tags.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Only documents scanned in these months.
+    #[serde(default)]
+    months: Vec<i8>,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).context(format!("reading rules {path:?}"))?;
+        let rules: Rules = toml::from_str(&contents).context(format!("parsing rules {path:?}"))?;
+        if rules.sections.is_empty() {
+            bail!("no [[section]] entries in {path:?}");
+        }
+        Ok(rules)
+    }
+
+    /// The first day of tax year `year`, and the first day of the year after it.
+    pub fn year(&self, year: i16) -> Result<(Date, Date)> {
+        let start = match &self.year_start {
+            Some(year_start) => Date::strptime("%Y-%m-%d", format!("{year}-{year_start}"))
+                .context(format!("invalid year_start {year_start:?}, expected MM-DD"))?,
+            None => Date::new(year, 1, 1)?,
+        };
+        Ok((start, start.checked_add(1.year())?))
+    }
+
+    /// A name for tax year `year`: `2024`, or `2024/25` when it straddles two calendar years.
+    pub fn year_label(&self, year: i16) -> String {
+        match self.year_start.as_deref() {
+            None | Some("01-01") => format!("{year}"),
+            Some(_) => format!("{year}/{:02}", (year + 1) % 100),
+        }
+    }
+
+    /// Sorts `documents` into sections. Documents go in the first section they match, and
+    /// those matching none are left out.
+    pub fn select<'a>(
+        &'a self,
+        documents: &'a [(String, Date, Metadata)],
+    ) -> Vec<(&'a Section, Vec<&'a str>)> {
+        let mut sections: Vec<_> = self.sections.iter().map(|it| (it, vec![])).collect();
+        for (id, date, meta) in documents {
+            if let Some((_, ids)) = sections.iter_mut().find(|(it, _)| it.matches(*date, meta)) {
+                ids.push(id.as_str());
+            }
+        }
+        sections
+    }
+}
+
+impl Section {
+    fn matches(&self, date: Date, meta: &Metadata) -> bool {
+        field_matches(&self.doc_type, &meta.doc_type)
+            && field_matches(&self.correspondent, &meta.correspondent)
+            && self.tags.iter().all(|tag| meta.tags.contains(tag))
+            && (self.months.is_empty() || self.months.contains(&date.month()))
+    }
+}
+
+impl Kartka {
+    /// Writes the documents from tax year `year` picked out by the rules file at `rules` to a
+    /// single PDF at `out`, after an index page.
+    pub fn taxpack(&self, year: i16, rules: &Path, out: &Path) -> Result<()> {
+        let rules = Rules::load(rules)?;
+        let (start, end) = rules.year(year)?;
+        let label = rules.year_label(year);
+
+        let documents: Vec<_> = self
+            .documents()?
+            .into_iter()
+            .filter_map(|id| document_date(&id).map(|date| (id, date)))
+            .filter(|(_, date)| (start..end).contains(date))
+            .map(|(id, date)| Ok((id.clone(), date, self.metadata(&id)?)))
+            .collect::<Result<_>>()?;
+        let sections = rules.select(&documents);
+        if sections.iter().all(|(_, ids)| ids.is_empty()) {
+            bail!("no documents from {label} matched the rules");
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let mut parts = vec![];
+        for (section, ids) in &sections {
+            for id in ids {
+                info!("pulling {id}..");
+                let dest = temp_dir.path().join(id);
+                self.fetch(id, &dest)?;
+                parts.push(pdf::Part::new(
+                    self.tools.as_ref(),
+                    format!("{}: {id}", section.title),
+                    dest,
+                )?);
+            }
+        }
+
+        // the index's own length shifts every page number after it, so render until it settles
+        let index_path = temp_dir.path().join("index.pdf");
+        let mut index_pages = 1;
+        loop {
+            let mut text = format!("Tax pack {label}\n{start} to {}\n", end.yesterday()?);
+            let mut page = index_pages + 1;
+            let mut parts = parts.iter();
+            for (section, ids) in &sections {
+                text.push_str(&format!("\n{}\n", section.title));
+                for (id, part) in ids.iter().zip(parts.by_ref()) {
+                    text.push_str(&format!("  {id:<32} page {page}\n"));
+                    page += part.pages;
+                }
+            }
+
+            pdf::text_pages(self.tools.as_ref(), &text, &index_path)?;
+            let pages = pdf::page_count(self.tools.as_ref(), &index_path)?;
+            if pages == index_pages {
+                break;
+            }
+            index_pages = pages;
+        }
+        parts.insert(
+            0,
+            pdf::Part {
+                title: "Index".to_string(),
+                path: index_path,
+                pages: index_pages,
+            },
+        );
+
+        info!("merging {} documents..", parts.len() - 1);
+        pdf::merge(self.tools.as_ref(), &parts, out)?;
+
+        info!("wrote {out:?}");
+        Ok(())
+    }
+}
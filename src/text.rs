@@ -0,0 +1,166 @@
+//! Tidying up the text that comes out of OCR before it's indexed, and folding away accents and
+//! case for searching it. Tesseract reads lines as they are on the page, and often drops or
+//! mistakes accents, so either would otherwise stop searches from matching.
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Cleans up one page of OCR'd text: normalises it to NFC, so accented letters are always
+/// stored the same way, rejoins words hyphenated across line breaks, collapses runs of spaces,
+/// and squeezes runs of blank lines down to one.
+pub fn clean(page: &str) -> String {
+    let page: String = page.nfc().filter(|it| *it != '\u{ad}').collect();
+    let lines: Vec<String> = page
+        .lines()
+        .map(|it| it.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+
+    let mut cleaned: Vec<String> = vec![];
+    for line in lines {
+        let mut line = line.as_str();
+        let broken = cleaned.last().is_some_and(|it| ends_in_broken_word(it));
+        if broken && !line.is_empty() {
+            // the rest of the word moves up to join its start, and the line carries on below
+            let (rest, after) = line.split_once(' ').unwrap_or((line, ""));
+            if let Some(last) = cleaned.last_mut() {
+                // a capital after the hyphen is more likely a double-barrelled name
+                if rest.starts_with(char::is_lowercase) {
+                    last.pop();
+                }
+                last.push_str(rest);
+            }
+            line = after;
+            if line.is_empty() {
+                continue;
+            }
+        }
+
+        if line.is_empty() && cleaned.last().is_none_or(|it| it.is_empty()) {
+            continue;
+        }
+        cleaned.push(line.to_string());
+    }
+    while cleaned.last().is_some_and(|it| it.is_empty()) {
+        cleaned.pop();
+    }
+
+    let mut page = cleaned.join("\n");
+    page.push('\n');
+    page
+}
+
+/// Whether `line` ends in a word broken off with a hyphen. A lone dash, or a hyphen after a
+/// number, is punctuation instead.
+fn ends_in_broken_word(line: &str) -> bool {
+    line.strip_suffix('-')
+        .and_then(|it| it.chars().last())
+        .is_some_and(char::is_alphabetic)
+}
+
+/// `text` without accents and in lower case, so `Über` and `uber` compare the same. Letters
+/// that are more than a letter with an accent on, like `ł` or `ß`, become their usual ASCII
+/// spelling.
+pub fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|it| !is_combining_mark(*it))
+        .flat_map(char::to_lowercase)
+        .map(|it| match it {
+            'ł' => "l".to_string(),
+            'ø' => "o".to_string(),
+            'đ' => "d".to_string(),
+            'ß' => "ss".to_string(),
+            'æ' => "ae".to_string(),
+            'œ' => "oe".to_string(),
+            it => it.to_string(),
+        })
+        .collect()
+}
+
+/// Rewrites the regex `pattern` so each letter in it also matches that letter with any accent,
+/// e.g. `uber` becomes `[uùúûüũūŭůűų…]ber`. Escapes, classes like `\pL`, the ends of ranges,
+/// group flags and repetitions are left as they are.
+pub fn accent_insensitive(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut in_class = false;
+    let mut chars = pattern.chars().peekable();
+    let mut previous = None;
+    while let Some(c) = chars.next() {
+        out.push(c);
+        // either end of a range in a class has to stay a single letter
+        let in_range = in_class && (previous == Some('-') || chars.peek() == Some(&'-'));
+        previous = Some(c);
+        match c {
+            '\\' => {
+                let Some(escaped) = chars.next() else {
+                    break;
+                };
+                out.push(escaped);
+                // `\p{Greek}`, `\x{e9}` and `\pL` carry on past the escaped letter
+                if matches!(escaped, 'p' | 'P' | 'x' | 'u' | 'U') {
+                    if chars.peek() == Some(&'{') {
+                        copy_through(&mut chars, &mut out, &['}']);
+                    } else if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                }
+            }
+            '[' if in_class && chars.peek() == Some(&':') => {
+                copy_through(&mut chars, &mut out, &[']']);
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class && chars.peek() == Some(&'?') => {
+                copy_through(&mut chars, &mut out, &[')', ':', '>']);
+            }
+            '{' if !in_class => copy_through(&mut chars, &mut out, &['}']),
+            _ if in_range => {}
+            c => {
+                let variants = fold(&c.to_string())
+                    .chars()
+                    .next()
+                    .and_then(|base| accented().get(&base));
+                if let Some(variants) = variants {
+                    out.pop();
+                    if in_class {
+                        out.push_str(variants);
+                    } else {
+                        out.push_str(&format!("[{variants}]"));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Copies `chars` into `out` up to and including the first of `ends`.
+fn copy_through(chars: &mut impl Iterator<Item = char>, out: &mut String, ends: &[char]) {
+    for c in chars.by_ref() {
+        out.push(c);
+        if ends.contains(&c) {
+            break;
+        }
+    }
+}
+
+/// The ASCII letters with accented versions, along with every Latin letter that folds to them.
+fn accented() -> &'static BTreeMap<char, String> {
+    static ACCENTED: OnceLock<BTreeMap<char, String>> = OnceLock::new();
+    ACCENTED.get_or_init(|| {
+        let mut accented: BTreeMap<char, String> =
+            ('a'..='z').map(|it| (it, it.to_string())).collect();
+        // Latin-1 Supplement through Latin Extended-B
+        for c in ('\u{c0}'..='\u{24f}').filter(|it| it.is_lowercase()) {
+            let folded = fold(&c.to_string());
+            let mut folded = folded.chars();
+            if let (Some(base), None) = (folded.next(), folded.next()) {
+                if let Some(variants) = accented.get_mut(&base) {
+                    variants.push(c);
+                }
+            }
+        }
+        accented.retain(|_, variants| variants.chars().count() > 1);
+        accented
+    })
+}
@@ -0,0 +1,130 @@
+//! Small JPEG thumbnails of each document's first page, cached in the index's `.kartka`
+//! directory so documents can be recognised at a glance without downloading them.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{Context, Result};
+use tracing::warn;
+
+use crate::{pdf, tools::ToolRunner, Kartka};
+
+const THUMBNAIL_DIR: &str = ".kartka/thumbnails";
+/// How wide thumbnails are, in pixels.
+const WIDTH: u32 = 200;
+
+/// The first page image in `dir`, going by name.
+pub fn first_image(dir: &Path) -> Result<Option<PathBuf>> {
+    let mut images = vec![];
+    for entry in dir.read_dir().context(format!("reading {dir:?}"))? {
+        let path = entry?.path();
+        let is_image = path
+            .extension()
+            .and_then(|it| it.to_str())
+            .is_some_and(|it| matches!(it.to_ascii_lowercase().as_str(), "png" | "jpg" | "jpeg"));
+        if is_image {
+            images.push(path);
+        }
+    }
+    Ok(images.into_iter().min())
+}
+
+/// Shrinks the image at `page` down to a thumbnail at `out`.
+fn make_thumbnail(tools: &dyn ToolRunner, page: &Path, out: &Path) -> Result<()> {
+    tools.run(
+        Command::new("magick")
+            .arg(page)
+            .arg("-thumbnail")
+            .arg(format!("{WIDTH}x"))
+            .arg("-background")
+            .arg("white")
+            .arg("-flatten")
+            .arg("-quality")
+            .arg("80")
+            .arg(out),
+        &format!("making thumbnail of {page:?}"),
+    )?;
+    Ok(())
+}
+
+impl Kartka {
+    /// Where `id`'s thumbnail is cached.
+    pub fn thumbnail_path(&self, id: &str) -> PathBuf {
+        self.index().join(THUMBNAIL_DIR).join(format!("{id}.jpg"))
+    }
+
+    /// Caches a thumbnail for `id` made from the first page image in `pages`. A missing
+    /// thumbnail is no reason to fail a scan, so problems are only warned about.
+    pub(crate) fn save_thumbnail(&self, id: &str, pages: &Path) {
+        let saved = first_image(pages).and_then(|page| {
+            let Some(page) = page else {
+                return Ok(());
+            };
+            fs::create_dir_all(self.index().join(THUMBNAIL_DIR))?;
+            make_thumbnail(self.tools.as_ref(), &page, &self.thumbnail_path(id))
+        });
+        if let Err(e) = saved {
+            warn!("couldn't make a thumbnail for {id}: {e:#}");
+        }
+    }
+
+    /// Caches a thumbnail for `id` from the PDF at `pdf`, using the first page in `pages` if it
+    /// has already been rendered there. Problems are only warned about, as for
+    /// [`Kartka::save_thumbnail`].
+    pub(crate) fn save_pdf_thumbnail(&self, id: &str, pdf: &Path, pages: &Path) {
+        if matches!(first_image(pages), Ok(Some(_))) {
+            return self.save_thumbnail(id, pages);
+        }
+
+        let rendered = tempfile::tempdir()
+            .map_err(eyre::Report::from)
+            .and_then(|dir| {
+                pdf::render_page(
+                    self.tools.as_ref(),
+                    pdf,
+                    0,
+                    WIDTH,
+                    &dir.path().join("page.png"),
+                )?;
+                Ok(dir)
+            });
+        match rendered {
+            Ok(dir) => self.save_thumbnail(id, dir.path()),
+            Err(e) => warn!("couldn't make a thumbnail for {id}: {e:#}"),
+        }
+    }
+
+    /// `id`'s thumbnail, downloading the document to make one if it isn't cached yet.
+    pub fn thumbnail(&self, id: &str) -> Result<PathBuf> {
+        let path = self.thumbnail_path(id);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let page = temp_dir.path().join("page.png");
+        self.first_page(id, WIDTH, &page)?;
+        fs::create_dir_all(self.index().join(THUMBNAIL_DIR))?;
+        make_thumbnail(self.tools.as_ref(), &page, &path)?;
+        Ok(path)
+    }
+
+    pub(crate) fn remove_thumbnail(&self, id: &str) -> Result<()> {
+        let path = self.thumbnail_path(id);
+        if path.exists() {
+            fs::remove_file(&path).context(format!("removing {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rename_thumbnail(&self, id: &str, new_id: &str) -> Result<()> {
+        let path = self.thumbnail_path(id);
+        if path.exists() {
+            fs::rename(&path, self.thumbnail_path(new_id)).context(format!("renaming {path:?}"))?;
+        }
+        Ok(())
+    }
+}
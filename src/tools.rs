@@ -0,0 +1,187 @@
+//! Running the tools the scan, hydrate and search pipelines lean on - rclone, ImageMagick,
+//! ghostscript, ripgrep, zbar and OCRmyPDF - through a [`ToolRunner`], so tests can stand in
+//! for them. [`System`] runs them for real; [`Recording`] answers every call itself and keeps a
+//! note of what was asked, so the pipelines can be tested without the tools installed or a
+//! remote to talk to. Tools only used around the edges, like git, age and fzf, are run
+//! directly.
+
+use std::{
+    fmt, io,
+    path::PathBuf,
+    process::{Command, ExitStatus, Output},
+    sync::{Arc, Mutex},
+};
+
+use eyre::{bail, Context, Result};
+use futures_util::future::BoxFuture;
+
+/// Runs external commands for [`Kartka`](crate::Kartka), set as its `tools`.
+pub trait ToolRunner: fmt::Debug + Send + Sync {
+    /// Runs `command` to completion, returning what it printed.
+    fn output(&self, command: &mut Command) -> io::Result<Output>;
+
+    /// [`ToolRunner::output`] without blocking the thread. The command is killed if the future
+    /// is dropped before it's done.
+    fn output_async(&self, command: tokio::process::Command) -> BoxFuture<'_, io::Result<Output>>;
+
+    /// Runs `command`, failing with what it printed to stderr if it doesn't succeed. `what` says
+    /// what it was for, for the error.
+    fn run(&self, command: &mut Command, what: &str) -> Result<Output> {
+        checked(self.output(command), what)
+    }
+}
+
+/// `output` if it's from a command that ran and succeeded, otherwise an error saying `what`
+/// failed.
+pub(crate) fn checked(output: io::Result<Output>, what: &str) -> Result<Output> {
+    let output = output.context(what.to_string())?;
+    if !output.status.success() {
+        bail!(
+            "{what} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output)
+}
+
+/// Runs commands for real.
+#[derive(Debug, Default)]
+pub struct System;
+
+impl ToolRunner for System {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        command.output()
+    }
+
+    fn output_async(
+        &self,
+        mut command: tokio::process::Command,
+    ) -> BoxFuture<'_, io::Result<Output>> {
+        Box::pin(async move { command.kill_on_drop(true).output().await })
+    }
+}
+
+/// The runner kartka uses unless told otherwise.
+pub fn system() -> Arc<dyn ToolRunner> {
+    Arc::new(System)
+}
+
+/// A command as it was asked to be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Where it was run from, if not the current directory.
+    pub dir: Option<PathBuf>,
+}
+
+impl Call {
+    fn new(command: &Command) -> Self {
+        Call {
+            program: command.get_program().to_string_lossy().into_owned(),
+            args: command
+                .get_args()
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect(),
+            dir: command.get_current_dir().map(|it| it.to_path_buf()),
+        }
+    }
+
+    /// The first argument, which is rclone's and git's subcommand.
+    pub fn subcommand(&self) -> Option<&str> {
+        self.args.first().map(String::as_str)
+    }
+
+    /// The last argument, which is where most of the tools write their output.
+    pub fn last_arg(&self) -> Option<&str> {
+        self.args.last().map(String::as_str)
+    }
+}
+
+type Reply = dyn Fn(&Call) -> io::Result<Output> + Send + Sync;
+
+/// Stands in for the tools in tests. Every call is noted down and answered by `reply`, which
+/// can write whatever files the real tool would have, and say what it printed with
+/// [`succeeded`] or [`failed`].
+pub struct Recording {
+    calls: Mutex<Vec<Call>>,
+    reply: Box<Reply>,
+}
+
+impl Recording {
+    pub fn new(reply: impl Fn(&Call) -> io::Result<Output> + Send + Sync + 'static) -> Self {
+        Recording {
+            calls: Mutex::default(),
+            reply: Box::new(reply),
+        }
+    }
+
+    /// Every call so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().expect("calls lock poisoned").clone()
+    }
+
+    /// The calls so far to `program`, in order.
+    pub fn calls_to(&self, program: &str) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|it| it.program == program)
+            .collect()
+    }
+
+    fn answer(&self, call: Call) -> io::Result<Output> {
+        let output = (self.reply)(&call);
+        self.calls.lock().expect("calls lock poisoned").push(call);
+        output
+    }
+}
+
+impl fmt::Debug for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recording")
+            .field("calls", &self.calls())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ToolRunner for Recording {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        self.answer(Call::new(command))
+    }
+
+    fn output_async(&self, command: tokio::process::Command) -> BoxFuture<'_, io::Result<Output>> {
+        let output = self.answer(Call::new(command.as_std()));
+        Box::pin(async move { output })
+    }
+}
+
+/// What a command that succeeded and printed `stdout` gives back.
+pub fn succeeded(stdout: impl Into<Vec<u8>>) -> io::Result<Output> {
+    Ok(Output {
+        status: exit_status(0),
+        stdout: stdout.into(),
+        stderr: vec![],
+    })
+}
+
+/// What a command that failed and printed `stderr` gives back.
+pub fn failed(stderr: impl Into<Vec<u8>>) -> io::Result<Output> {
+    Ok(Output {
+        status: exit_status(1),
+        stdout: vec![],
+        stderr: stderr.into(),
+    })
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    // a wait status, which has the exit code in its second byte
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
@@ -0,0 +1,401 @@
+//! Checking the config makes sense before anything is done with it: that the folders it names
+//! are there or can be made, rclone knows the remote, and templates and patterns are written
+//! right. Everything that's wrong is reported at once, each with what to do about it, as an
+//! [`Invalid`] inside a [`KartkaError::Config`], rather than one at a time as each is tripped
+//! over.
+
+use std::{fmt, path::Path};
+
+use regex::Regex;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::{fields, index::document_date, storage::remote_name, Kartka, KartkaError};
+
+/// Something wrong with one setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Where it is, like `scan_dir` or `extraction.rules[2].pattern`.
+    pub setting: String,
+    pub message: String,
+    /// What to do about it, if there's more to say than the message does.
+    pub fix: Option<String>,
+}
+
+impl Problem {
+    fn new(setting: &str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Problem {
+            setting: setting.to_string(),
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` {}", self.setting, self.message)?;
+        if let Some(fix) = &self.fix {
+            write!(f, " - {fix}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything wrong with a config.
+#[derive(Debug)]
+pub struct Invalid(pub Vec<Problem>);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.len() {
+            1 => write!(f, "found a problem:")?,
+            n => write!(f, "found {n} problems:")?,
+        }
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+impl Kartka {
+    /// Fails with every [`Problem`] there is with the config at `path` this was loaded from.
+    pub fn validate(&self, path: &Path) -> eyre::Result<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(KartkaError::Config {
+            path: path.to_path_buf(),
+            source: Invalid(problems).into(),
+        }
+        .into())
+    }
+
+    /// What's wrong with the settings, beyond what loading them catches.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        let dirs = [
+            ("scan_dir", Some(&self.scan_dir)),
+            ("index_dir", Some(&self.index_dir)),
+            ("local_dir", self.local_dir.as_ref()),
+            ("local_archive_dir", self.local_archive_dir.as_ref()),
+        ];
+        for (setting, dir) in dirs {
+            problems.extend(dir.and_then(|it| dir_problem(setting, it)));
+        }
+
+        let keys = [
+            ("signing_key", &self.signing_key),
+            ("verify_key", &self.verify_key),
+        ];
+        for (setting, key) in keys {
+            if let Some(key) = key.as_ref().filter(|it| !it.exists()) {
+                problems.push(Problem::new(
+                    setting,
+                    format!("{key:?} doesn't exist"),
+                    "point it at your minisign key, or make one with `minisign -G`",
+                ));
+            }
+        }
+
+        if let Some(profile) = self.pdf.icc_profile.as_ref().filter(|it| !it.is_file()) {
+            problems.push(Problem::new(
+                "pdf.icc_profile",
+                format!("{profile:?} doesn't exist"),
+                "point it at an .icc file, or leave it out to use ghostscript's sRGB profile",
+            ));
+        }
+
+        if let Some(template) = &self.preview_link {
+            problems.extend(template_problem("preview_link", template));
+        }
+        if let Some(extraction) = &self.extraction {
+            for (i, rule) in extraction.rules.iter().enumerate() {
+                if let Err(e) = fields::compile(&rule.pattern) {
+                    // regex's own errors draw where the mistake is over several lines, and
+                    // end with what it is
+                    let error = e.root_cause().to_string();
+                    let error = error.lines().last().unwrap_or_default();
+                    problems.push(Problem::new(
+                        &format!("extraction.rules[{i}].pattern"),
+                        format!(
+                            "isn't a valid regex ({})",
+                            error.trim_start_matches("error: ")
+                        ),
+                        "in TOML, writing it in 'single quotes' saves doubling every backslash",
+                    ));
+                }
+            }
+        }
+
+        problems.extend(self.ids_problems());
+
+        if self.retry.attempts == 0 {
+            problems.push(Problem::new(
+                "retry.attempts",
+                "is 0, so nothing would ever be sent to the remote",
+                "set it to at least 1",
+            ));
+        }
+
+        problems.extend(self.remote_problems());
+        problems
+    }
+
+    /// What's wrong with `[ids]`: ids it makes have to be file names, and start with the date
+    /// so they sort by it and it can be read back.
+    fn ids_problems(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        if self.ids.time_zone().is_err() {
+            problems.push(Problem::new(
+                "ids.timezone",
+                format!("is {:?}, which isn't a time zone", self.ids.timezone),
+                "use `UTC`, `local`, or a name like `Europe/London`",
+            ));
+        }
+
+        let sample: jiff::Timestamp = "2024-11-05T06:07:08Z".parse().expect("sample is a time");
+        let zoned = sample.to_zoned(jiff::tz::TimeZone::UTC);
+        let problem = match jiff::fmt::strtime::format(&self.ids.format, &zoned) {
+            Err(e) => Some(format!("isn't a strftime pattern ({e})")),
+            Ok(stamp) if stamp.contains(['/', '\\']) => {
+                Some("would put a `/` in ids, which have to be file names".to_string())
+            }
+            Ok(stamp) if document_date(&stamp) != Some(zoned.date()) => Some(format!(
+                "makes ids like `{stamp}.pdf`, which don't start with the date, year first"
+            )),
+            Ok(_) => None,
+        };
+        problems.extend(
+            problem.map(|it| {
+                Problem::new("ids.format", it, "start it with `%Y_%m_%d`, or `%Y-%m-%d`")
+            }),
+        );
+        problems
+    }
+
+    /// Remotes rclone doesn't know about. Whether they can be reached is left to when they're
+    /// used, as that means going over the network.
+    fn remote_problems(&self) -> Vec<Problem> {
+        let known = self.rclone_remotes();
+        // rclone isn't installed or has no remotes yet, which `kartka doctor` says how to sort
+        // out, and which isn't the config's fault
+        if known.is_empty() {
+            return vec![];
+        }
+
+        let archive = self
+            .archive
+            .as_ref()
+            .map(|it| ("archive.remote", &it.remote));
+        // `kartka google-drive` sets the remote up, so it can't need to be already
+        let remote = Some(("remote", &self.remote)).filter(|_| self.google_drive.is_none());
+        remote
+            .into_iter()
+            .chain(archive)
+            .filter(|(_, remote)| !known.contains_key(remote_name(remote)))
+            .map(|(setting, remote)| {
+                let name = remote_name(remote);
+                let fix = match closest(name, known.keys().map(String::as_str)) {
+                    Some(it) => {
+                        format!("did you mean `{it}`? Otherwise set it up with `rclone config`")
+                    }
+                    None => "set it up with `rclone config`".to_string(),
+                };
+                Problem::new(
+                    setting,
+                    format!("is on `{name}`, which rclone has no remote called"),
+                    fix,
+                )
+            })
+            .collect()
+    }
+}
+
+/// What's wrong with `dir` as a folder kartka keeps things in. Folders that aren't there yet
+/// are fine as long as they can be made when they're first needed.
+fn dir_problem(setting: &str, dir: &Path) -> Option<Problem> {
+    if dir.is_dir() {
+        return tempfile::tempfile_in(dir).is_err().then(|| {
+            Problem::new(
+                setting,
+                format!("{dir:?} isn't writable"),
+                "check its permissions, or point it somewhere else",
+            )
+        });
+    }
+    if dir.exists() {
+        return Some(Problem::new(
+            setting,
+            format!("{dir:?} is a file, not a folder"),
+            "point it at a folder",
+        ));
+    }
+
+    let parent = dir
+        .ancestors()
+        .skip(1)
+        .map(|it| match it.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => it,
+        })
+        .find(|it| it.exists())?;
+    if parent.is_dir() && tempfile::tempfile_in(parent).is_ok() {
+        return None;
+    }
+    Some(Problem::new(
+        setting,
+        format!("{dir:?} doesn't exist, and can't be made in {parent:?}"),
+        format!(
+            "create it with `mkdir -p {}`, or point it somewhere else",
+            dir.display()
+        ),
+    ))
+}
+
+/// What's wrong with `template`, where the only placeholder is `{id}`.
+fn template_problem(setting: &str, template: &str) -> Option<Problem> {
+    let placeholder = Regex::new(r"\{([^{}]*)\}").expect("placeholder regex is valid");
+    if let Some(unknown) = placeholder
+        .captures_iter(template)
+        .map(|it| it[1].to_string())
+        .find(|it| it != "id")
+    {
+        let fix = match closest(&unknown, ["id"]) {
+            Some(_) => "did you mean `{id}`?",
+            None => "`{id}` is the only placeholder there is",
+        };
+        return Some(Problem::new(
+            setting,
+            format!("has `{{{unknown}}}` in it, which isn't a placeholder"),
+            fix,
+        ));
+    }
+    if placeholder.replace_all(template, "").contains(['{', '}']) {
+        return Some(Problem::new(
+            setting,
+            "has a `{` or `}` without its other half",
+            "write the document's id as `{id}`",
+        ));
+    }
+    if !template.contains("{id}") {
+        return Some(Problem::new(
+            setting,
+            "has no `{id}` in it, so every document would link to the same place",
+            "put `{id}` where the document's id goes",
+        ));
+    }
+    None
+}
+
+/// Settings in `config` that `T` hasn't got, which would otherwise be quietly ignored.
+pub(crate) fn unknown_settings<'de, T: Deserialize<'de>>(config: &toml::Table) -> Vec<Problem> {
+    let known = settings_of::<T>();
+    if known.is_empty() {
+        return vec![];
+    }
+    config
+        .keys()
+        .filter(|it| !known.contains(&it.as_str()))
+        .map(|it| Problem {
+            setting: it.clone(),
+            message: "isn't a setting".to_string(),
+            fix: Some(match closest(it, known.iter().copied()) {
+                Some(known) => format!("did you mean `{known}`?"),
+                None => "remove it".to_string(),
+            }),
+        })
+        .collect()
+}
+
+/// The candidate `name` is most likely a typo of, if any are close enough.
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|it| (strsim::jaro_winkler(name, it), it))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, it)| it)
+}
+
+/// The top-level settings `T` takes. Its `Deserialize` impl hands them over as soon as it's
+/// asked for a struct, which is as far as it gets.
+fn settings_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    match T::deserialize(FieldNames) {
+        Err(FoundFields(Some(fields))) => fields,
+        _ => &[],
+    }
+}
+
+struct FieldNames;
+
+#[derive(Debug)]
+struct FoundFields(Option<&'static [&'static str]>);
+
+impl fmt::Display for FoundFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "only looking for field names")
+    }
+}
+
+impl std::error::Error for FoundFields {}
+
+impl de::Error for FoundFields {
+    fn custom<M: fmt::Display>(_: M) -> Self {
+        FoundFields(None)
+    }
+}
+
+impl<'de> Deserializer<'de> for FieldNames {
+    type Error = FoundFields;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, FoundFields> {
+        Err(FoundFields(None))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, FoundFields> {
+        Err(FoundFields(Some(fields)))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+/// The [`Problem`] with a config that wouldn't load as `T`.
+pub(crate) fn load_problem(e: serde_path_to_error::Error<toml::de::Error>) -> Problem {
+    let message = e.inner().message().trim().to_string();
+    let path = e.path().to_string();
+    let missing = message
+        .strip_prefix("missing field `")
+        .and_then(|it| it.strip_suffix('`'));
+    match missing {
+        Some(field) => Problem::new(
+            &match path.as_str() {
+                "." => field.to_string(),
+                _ => format!("{path}.{field}"),
+            },
+            "isn't set",
+            "add it to the config, or run `kartka init` to write one",
+        ),
+        None => Problem {
+            setting: path,
+            message: format!("can't be read: {message}"),
+            fix: None,
+        },
+    }
+}
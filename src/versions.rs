@@ -0,0 +1,97 @@
+//! Scanning a corrected copy of a document as a new version of it. The new version takes over
+//! the document's id, and the one it replaces is kept, text and all, under the same id with a
+//! `.v1`, `.v2`.. on the end.
+
+use eyre::{bail, Context, Result};
+use tracing::{info, warn};
+
+use crate::{
+    audit::Operation, import::pdfs_in, index::validate_id, remote_manifest, scan::pages_in, Kartka,
+};
+
+/// The id the `n`th version of `id` is kept under once it's been replaced.
+pub fn version_id(id: &str, n: usize) -> String {
+    let stem = id.trim_end_matches(".pdf");
+    format!("{stem}.v{n}.pdf")
+}
+
+impl Kartka {
+    /// Adds what's in the scan dir as a new version of `id`: the current version is moved
+    /// aside and kept, and the new one is added in its place with the same type, correspondent
+    /// and tags. The scan dir has to hold either page images or a single PDF. Afterwards it
+    /// offers to clear the scan dir as [`Kartka::scan`] does, with `purge` working the same.
+    pub fn rescan(&self, id: &str, purge: bool) -> Result<()> {
+        validate_id(id)?;
+        if !self.contains(id)? {
+            bail!("{id} not found in index");
+        }
+        let current = self.metadata(id)?;
+        if let Some(newer) = &current.superseded_by {
+            bail!("{id} is an earlier version of {newer}, rescan that instead");
+        }
+
+        let pages = pages_in(self.scans())?;
+        let pdfs = pdfs_in(&[self.scans().to_path_buf()])?;
+        let pdf = match (pages.is_empty(), pdfs.as_slice()) {
+            (true, []) => bail!("nothing to scan in {:?}", self.scans()),
+            (false, []) => None,
+            (true, [pdf]) => Some(pdf),
+            _ => bail!(
+                "a new version has to be one document, but {:?} has {} PDFs and {} page images",
+                self.scans(),
+                pdfs.len(),
+                pages.len()
+            ),
+        };
+
+        let old_id = version_id(id, current.versions.len() + 1);
+        if self.contains(&old_id)? {
+            bail!("{old_id} already exists in index");
+        }
+        if self.dry_run {
+            println!("would keep the current version of {id} as {old_id}");
+            println!("would add the new version as {id}");
+            return Ok(());
+        }
+
+        info!("keeping the current version as {old_id}..");
+        self.rename(id, &old_id)?;
+        let added = match pdf {
+            Some(pdf) => self.add_pdf_as(pdf, id, false),
+            None => self.add_pages_as(self.scans(), id, false),
+        };
+        let detail = format!("new version, with the old one kept as {old_id}");
+        self.audit(Operation::Scan, id, Some(detail), added.as_ref().err());
+        if let Err(e) = added {
+            warn!("couldn't add the new version, putting the old one back");
+            self.rename(&old_id, id)
+                .context(format!("restoring {id} from {old_id}"))?;
+            return Err(e);
+        }
+
+        self.update_metadata(&old_id, |meta| {
+            meta.versions.clear();
+            meta.superseded_by = Some(id.to_string());
+        })?;
+        self.update_metadata(id, |meta| {
+            remote_manifest::restore(meta, &current);
+            meta.versions = current.versions.clone();
+            meta.versions.push(old_id.clone());
+        })?;
+        self.publish_manifest()?;
+        self.commit_index(&format!("Rescan {id}, keeping the old version as {old_id}"));
+
+        self.clear_scans(&[id.to_string()], purge)?;
+        info!("done!");
+        Ok(())
+    }
+
+    /// Whether `id` is an earlier version of a document that's still in the index, and so
+    /// is shown as part of that document's history rather than on its own.
+    pub fn is_old_version(&self, id: &str) -> Result<bool> {
+        match self.metadata(id)?.superseded_by {
+            Some(newer) => self.contains(&newer),
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! The scan, hydrate and search pipelines end to end, with the tools they run stood in for by a
+//! [`Recording`], so they run without rclone, ghostscript or ripgrep installed or a remote to
+//! talk to. The remote is a directory, and a "PDF" is just its text.
+
+use std::{
+    fs, io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Output,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use kartka::{
+    config, import, scan,
+    tools::{failed, succeeded, Call, Recording},
+    Kartka,
+};
+use tempfile::TempDir;
+
+/// What every call to the remote looks like, as `remote:name`.
+const REMOTE: &str = "fake";
+
+/// Long enough to be taken as the PDF's own text rather than OCRed.
+const BILL: &str = "Council tax bill 2024/25\nBand D, £2,065.14 a year\n";
+const PAYSLIP: &str = "Payslip for January 2024\nNet pay £2,310.42\n";
+
+struct Setup {
+    kartka: Kartka,
+    tools: Arc<Recording>,
+    remote: PathBuf,
+    scans: PathBuf,
+    _dir: TempDir,
+}
+
+/// A kartka with everything in a temporary directory, and `extra` added to its config.
+fn setup(extra: &str) -> Setup {
+    setup_with(extra, |_| None)
+}
+
+/// [`setup`], with `reply` answering any call it wants to before the fake tools do.
+fn setup_with(
+    extra: &str,
+    reply: impl Fn(&Call) -> Option<io::Result<Output>> + Send + Sync + 'static,
+) -> Setup {
+    let dir = tempfile::tempdir().unwrap();
+    let remote = dir.path().join("remote");
+    let scans = dir.path().join("scans");
+    let index = dir.path().join("index");
+    for it in [&remote, &scans, &index] {
+        fs::create_dir(it).unwrap();
+    }
+
+    let config = dir.path().join("kartka.toml");
+    fs::write(
+        &config,
+        format!(
+            "scan_dir = {scans:?}\nindex_dir = {index:?}\nremote = \"{REMOTE}:\"\n{extra}\n\n\
+             [retry]\nattempts = 1\n"
+        ),
+    )
+    .unwrap();
+    let mut kartka: Kartka = config::load(&config, None).unwrap();
+    kartka.plain = true;
+
+    let fake_remote = remote.clone();
+    let tools = Arc::new(Recording::new(move |call| {
+        reply(call).unwrap_or_else(|| fake(call, &fake_remote))
+    }));
+    kartka.tools = tools.clone();
+
+    Setup {
+        kartka,
+        tools,
+        remote,
+        scans,
+        _dir: dir,
+    }
+}
+
+/// Answers `call` as the real tool would, with `remote` as the remote.
+fn fake(call: &Call, remote: &Path) -> io::Result<Output> {
+    match call.program.as_str() {
+        "rclone" => fake_rclone(call, remote),
+        "gs" => fake_gs(call),
+        "rg" => fake_rg(call),
+        // thumbnails and the like, which are only warned about when they're missing
+        _ => succeeded(""),
+    }
+}
+
+fn fake_rclone(call: &Call, remote: &Path) -> io::Result<Output> {
+    let local = |path: &str| match path.strip_prefix(&format!("{REMOTE}:")) {
+        Some(name) => remote.join(name),
+        None => PathBuf::from(path),
+    };
+    match (call.subcommand(), &call.args[1..]) {
+        (Some("listremotes"), _) => succeeded(format!("{REMOTE}: local\n")),
+        (Some("lsf"), _) => {
+            let mut names: Vec<_> = fs::read_dir(remote)?
+                .map(|it| it.map(|it| it.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<_, _>>()?;
+            names.sort();
+            succeeded(names.join("\n"))
+        }
+        (Some("copyto"), [from, to]) => match fs::copy(local(from), local(to)) {
+            Ok(_) => succeeded(""),
+            Err(e) => failed(format!("copying {from}: {e}")),
+        },
+        (Some("moveto"), [from, to]) => match fs::rename(local(from), local(to)) {
+            Ok(()) => succeeded(""),
+            Err(e) => failed(format!("moving {from}: {e}")),
+        },
+        (Some("deletefile"), [path]) => match fs::remove_file(local(path)) {
+            Ok(()) => succeeded(""),
+            Err(e) => failed(format!("deleting {path}: {e}")),
+        },
+        _ => failed(format!("the fake rclone can't {:?}", call.args)),
+    }
+}
+
+/// Every PDF has one page, and its text is the whole file.
+fn fake_gs(call: &Call) -> io::Result<Output> {
+    let script = call.args.iter().find(|it| it.contains("runpdfbegin"));
+    match script {
+        Some(script) if script.contains("pdfpagecount") => succeeded("1\n"),
+        // no creation date
+        Some(_) => succeeded(""),
+        None if call.args.iter().any(|it| it == "-sDEVICE=txtwrite") => {
+            succeeded(fs::read(call.last_arg().unwrap())?)
+        }
+        None => failed(format!("the fake gs can't {:?}", call.args)),
+    }
+}
+
+/// Finds the pattern after `-e` in the files of the directory it's run in, or the one file on
+/// the end, printing matches as `rg --json` does.
+fn fake_rg(call: &Call) -> io::Result<Output> {
+    let dir = call.dir.clone().unwrap();
+    let at = call.args.iter().position(|it| it == "-e").unwrap();
+    let pattern = regex::RegexBuilder::new(&call.args[at + 1])
+        .case_insensitive(call.args.iter().any(|it| it == "-i"))
+        .build()
+        .unwrap();
+    let files = match call.args.get(at + 2) {
+        Some(file) => vec![file.clone()],
+        None => fs::read_dir(&dir)?
+            .map(|it| it.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|it| !it.starts_with('.'))
+            .collect(),
+    };
+
+    let mut out = String::new();
+    for file in files {
+        let text = fs::read_to_string(dir.join(&file))?;
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            if pattern.is_match(line) {
+                let message = serde_json::json!({
+                    "type": "match",
+                    "data": {
+                        "path": { "text": file },
+                        "lines": { "text": line },
+                        "absolute_offset": offset,
+                    },
+                });
+                out.push_str(&format!("{message}\n"));
+            }
+            offset += line.len();
+        }
+    }
+    succeeded(out)
+}
+
+#[test]
+fn scanning_a_pdf_indexes_and_uploads_it() {
+    let setup = setup("");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+
+    let added = setup.kartka.scan(false, false).unwrap();
+
+    let [id] = added.as_slice() else {
+        panic!("expected one document, got {added:?}");
+    };
+    assert!(setup.kartka.text(id).unwrap().contains("Council tax bill"));
+    assert_eq!(fs::read_to_string(setup.remote.join(id)).unwrap(), BILL);
+    // plain mode says no to clearing the scan dir
+    assert!(setup.scans.join("bill.pdf").exists());
+}
+
+#[test]
+fn a_failed_upload_leaves_nothing_in_the_index() {
+    let setup = setup_with("", |call| {
+        let uploading = call.subcommand() == Some("copyto")
+            && call.last_arg().is_some_and(|it| it.starts_with(REMOTE));
+        uploading.then(|| failed("no space left"))
+    });
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+
+    assert!(setup.kartka.scan(false, false).is_err());
+
+    assert!(setup.kartka.documents().unwrap().is_empty());
+    assert_eq!(fs::read_dir(&setup.remote).unwrap().count(), 0);
+    assert!(setup.scans.join("bill.pdf").exists());
+}
+
+#[test]
+fn hydrating_indexes_whats_on_the_remote() {
+    let setup = setup("");
+    let id = "2024_01_31_09_15_00.pdf";
+    fs::write(setup.remote.join(id), PAYSLIP).unwrap();
+
+    let hydrated = setup
+        .kartka
+        .rehydrate(false, true, NonZeroUsize::new(2).unwrap())
+        .unwrap();
+
+    assert_eq!(hydrated, 1);
+    assert!(setup.kartka.contains(id).unwrap());
+    assert!(setup
+        .kartka
+        .text(id)
+        .unwrap()
+        .contains("Payslip for January"));
+    let downloads: Vec<_> = setup
+        .tools
+        .calls_to("rclone")
+        .into_iter()
+        .filter(|it| it.args.first().is_some_and(|it| it == "copyto"))
+        .filter(|it| it.args.get(1).is_some_and(|it| it.ends_with(id)))
+        .collect();
+    assert_eq!(downloads.len(), 1);
+}
+
+#[test]
+fn hydrating_again_downloads_nothing_new() {
+    let setup = setup("");
+    fs::write(setup.remote.join("2024_01_31_09_15_00.pdf"), PAYSLIP).unwrap();
+    let jobs = NonZeroUsize::new(1).unwrap();
+    setup.kartka.rehydrate(false, true, jobs).unwrap();
+    let calls = setup.tools.calls_to("gs").len();
+
+    assert_eq!(setup.kartka.rehydrate(false, true, jobs).unwrap(), 0);
+    assert_eq!(setup.tools.calls_to("gs").len(), calls);
+}
+
+#[test]
+fn searching_finds_what_was_scanned() {
+    let setup = setup("");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    fs::write(setup.scans.join("payslip.pdf"), PAYSLIP).unwrap();
+    let added = setup.kartka.scan(false, false).unwrap();
+    assert_eq!(added.len(), 2);
+
+    let hits = setup.kartka.search("council").unwrap();
+
+    assert_eq!(hits.len(), 1);
+    assert!(setup.kartka.text(&hits[0]).unwrap().contains("Council tax"));
+    let searches = setup.tools.calls_to("rg");
+    assert!(searches
+        .iter()
+        .all(|it| it.args.contains(&"-i".to_string())));
+}
+
+#[test]
+fn searching_the_sqlite_index_needs_no_ripgrep() {
+    let setup = setup("index_backend = \"sqlite\"");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    setup.kartka.scan(false, false).unwrap();
+
+    assert_eq!(setup.kartka.search("council").unwrap().len(), 1);
+    assert!(setup.tools.calls_to("rg").is_empty());
+}
+
+#[test]
+fn searching_by_field_narrows_down_what_the_text_finds() {
+    let setup = setup("");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    fs::write(setup.scans.join("payslip.pdf"), PAYSLIP).unwrap();
+    let added = setup.kartka.scan(false, false).unwrap();
+    let bill = added
+        .iter()
+        .find(|it| setup.kartka.text(it).unwrap().contains("Council"))
+        .unwrap();
+    setup
+        .kartka
+        .update_metadata(bill, |it| it.tags.push("tax".to_string()))
+        .unwrap();
+
+    assert_eq!(
+        setup.kartka.search("tag:tax 2024").unwrap(),
+        [bill.as_str()]
+    );
+    assert_eq!(setup.kartka.search("tag:TAX").unwrap(), [bill.as_str()]);
+    assert!(setup.kartka.search("tag:tax payslip").unwrap().is_empty());
+    assert_eq!(setup.kartka.search("date:>2000").unwrap().len(), 2);
+}
+
+#[test]
+fn photos_are_dated_from_when_they_were_taken() {
+    let setup = setup("");
+    let mut writer = exif::experimental::Writer::new();
+    let fields = [
+        (exif::Tag::DateTimeOriginal, "2019:03:04 10:00:00"),
+        (exif::Tag::OffsetTimeOriginal, "+02:00"),
+    ]
+    .map(|(tag, value)| exif::Field {
+        tag,
+        ifd_num: exif::In::PRIMARY,
+        value: exif::Value::Ascii(vec![value.as_bytes().to_vec()]),
+    });
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = io::Cursor::new(vec![]);
+    writer.write(&mut tiff, false).unwrap();
+    // just enough of a JPEG to carry it
+    let tiff = tiff.into_inner();
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+    jpeg.extend(u16::try_from(tiff.len() + 8).unwrap().to_be_bytes());
+    jpeg.extend(b"Exif\0\0");
+    jpeg.extend(tiff);
+    jpeg.extend([0xff, 0xd9]);
+    let photo = setup.scans.join("photo.jpg");
+    fs::write(&photo, jpeg).unwrap();
+
+    let taken = import::Source::Files
+        .captured(setup.tools.as_ref(), &photo)
+        .unwrap();
+
+    assert_eq!(taken, "2019-03-04T08:00:00Z".parse().unwrap());
+}
+
+#[test]
+fn ids_are_written_as_configured_and_still_dated() {
+    let setup = setup("[ids]\nformat = \"%Y-%m-%dT%H%M%S\"\ntimezone = \"Asia/Tokyo\"");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+
+    let id = setup.kartka.scan(false, false).unwrap().remove(0);
+
+    let written = regex::Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{6}\.pdf$").unwrap();
+    assert!(written.is_match(&id), "{id}");
+    assert!(setup.kartka.ids.scanned(&id).is_some());
+    let today = format!("date:{}", kartka::index::document_date(&id).unwrap());
+    assert_eq!(setup.kartka.search(&today).unwrap(), [id.as_str()]);
+}
+
+#[test]
+fn splitting_a_scan_by_hand_leaves_no_page_behind() {
+    let mut setup = setup("");
+    for page in ["page-1.png", "page-2.png", "page-3.png"] {
+        fs::write(setup.scans.join(page), "").unwrap();
+    }
+    setup.kartka.dry_run = true;
+    setup.kartka.split = Some(scan::Split::Parts(vec![scan::Part {
+        pages: 1..=2,
+        title: Some("gas bill".to_string()),
+    }]));
+
+    let e = setup.kartka.scan(false, false).unwrap_err();
+
+    assert_eq!(e.to_string(), "page 3 is not in any document");
+    assert!(setup.tools.calls().is_empty());
+}
+
+#[test]
+fn settings_from_the_environment_are_read_as_toml_unless_strings_are_wanted() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("kartka.toml");
+    fs::write(&config, "scan_dir = \"scans\"\nindex_dir = \"index\"\n").unwrap();
+    let env = [
+        ("KARTKA_NOTIFY", "true"),
+        ("KARTKA_QUARANTINE_AFTER", "5"),
+        ("KARTKA_REMOTE", "2024"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+
+    let kartka: Kartka = config::load_with_env(&config, None, env).unwrap();
+
+    assert!(kartka.notify);
+    assert_eq!(kartka.quarantine_after, 5);
+    assert_eq!(kartka.remote, "2024");
+}
+
+#[test]
+fn rebuilding_from_the_remote_reads_everything_again_keeping_tags_and_when_it_was_added() {
+    let setup = setup("");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    let added = setup.kartka.scan(false, false).unwrap();
+    let id = &added[0];
+    setup
+        .kartka
+        .update_metadata(id, |it| it.tags.push("tax".to_string()))
+        .unwrap();
+    fs::write(
+        setup.remote.join(id),
+        "Council tax bill 2025/26, read better\n",
+    )
+    .unwrap();
+    let added_at = setup.kartka.recent(1).unwrap()[0].1;
+    thread::sleep(Duration::from_millis(10));
+
+    let rebuilt = setup
+        .kartka
+        .rebuild_index(true, NonZeroUsize::new(1).unwrap(), true)
+        .unwrap();
+
+    assert_eq!(rebuilt, 1);
+    assert!(setup.kartka.text(id).unwrap().contains("read better"));
+    assert_eq!(setup.kartka.metadata(id).unwrap().tags, ["tax"]);
+    assert_eq!(setup.kartka.recent(1).unwrap(), [(id.clone(), added_at)]);
+}
+
+#[test]
+fn protected_pdfs_keep_their_text_through_a_rebuild() {
+    let mut setup = setup_with("", |call| {
+        // a protected PDF's text can't be read back out of it
+        (call.program == "qpdf").then(|| {
+            fs::write(call.last_arg().unwrap(), "")?;
+            succeeded("")
+        })
+    });
+    setup.kartka.pdf.protect_with = Some("hunter2".to_string());
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    let id = setup.kartka.scan(false, false).unwrap().remove(0);
+
+    let rebuilt = setup
+        .kartka
+        .rebuild_index(true, NonZeroUsize::new(1).unwrap(), true)
+        .unwrap();
+
+    assert_eq!(rebuilt, 0);
+    assert_eq!(fs::read(setup.remote.join(&id)).unwrap(), b"");
+    assert!(setup.kartka.metadata(&id).unwrap().protected);
+    assert!(setup.kartka.text(&id).unwrap().contains("Council tax"));
+}
+
+#[test]
+fn hydrating_by_listing_the_remote_leaves_protected_pdfs_be() {
+    let mut setup = setup_with("", |call| {
+        (call.program == "qpdf").then(|| {
+            fs::write(call.last_arg().unwrap(), "")?;
+            succeeded("")
+        })
+    });
+    setup.kartka.pdf.protect_with = Some("hunter2".to_string());
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    setup.kartka.scan(false, false).unwrap();
+    // as on another machine, with an index of its own
+    fs::remove_dir_all(setup.kartka.index()).unwrap();
+    fs::create_dir(setup.kartka.index()).unwrap();
+
+    let hydrated = setup
+        .kartka
+        .rehydrate(false, true, NonZeroUsize::new(1).unwrap())
+        .unwrap();
+
+    assert_eq!(hydrated, 0);
+    assert!(setup.kartka.documents().unwrap().is_empty());
+}
+
+#[test]
+fn gc_puts_the_index_and_remote_back_in_step() {
+    let setup = setup("");
+    fs::write(setup.scans.join("bill.pdf"), BILL).unwrap();
+    let deleted = setup.kartka.scan(false, false).unwrap().remove(0);
+    fs::remove_file(setup.remote.join(&deleted)).unwrap();
+    let added = "2024_01_31_09_15_00.pdf";
+    fs::write(setup.remote.join(added), PAYSLIP).unwrap();
+
+    let collected = setup.kartka.gc(true).unwrap();
+
+    assert_eq!((collected.found, collected.fixed), (2, 2));
+    assert_eq!(setup.kartka.documents().unwrap(), [added]);
+    assert!(setup.kartka.search("council").unwrap().is_empty());
+}